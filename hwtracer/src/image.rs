@@ -0,0 +1,89 @@
+//! Access to the code that a trace was recorded from.
+//!
+//! Decoding a trace requires knowing which object each virtual address belongs to, the static
+//! control flow information that ykllvm embeds in the main object (the "blockmap"), and sometimes
+//! the machine code itself. This information normally comes from the currently running process
+//! (see [SelfImage]), but it can also come from a binary on disk (see
+//! [crate::offline::OfflineImage]).
+
+use crate::llvm_blockmap::{BlockMap, LLVM_BLOCK_MAP};
+use intervaltree::IntervalTree;
+use std::{ffi::CStr, fmt::Debug, ops::Range, slice, sync::LazyLock};
+use ykaddr::{
+    addr::{vaddr_to_obj_and_off, vaddr_to_sym_and_obj},
+    obj::{PHDR_OBJECT_CACHE, SELF_BIN_PATH},
+};
+
+/// The virtual address range of and memory slice of one non-writable ELF segment.
+pub struct Segment<'a> {
+    /// The virtual address range of the segment.
+    pub vaddrs: Range<usize>,
+    /// The contents of the segment.
+    pub slice: &'a [u8],
+}
+
+/// The code that a trace was recorded from.
+pub trait Image: Debug + Send + Sync {
+    /// The blockmap of the main object.
+    fn blockmap(&self) -> &BlockMap;
+
+    /// Return `Some(true)` if `vaddr` is in the main object, `Some(false)` if it is in another
+    /// loaded object, or `None` if no loaded object contains `vaddr`.
+    fn in_main_obj(&self, vaddr: usize) -> Option<bool>;
+
+    /// Return the name of the symbol in the main object which `vaddr` falls within, if there is
+    /// one.
+    fn sym_name(&self, vaddr: usize) -> Option<&CStr>;
+
+    /// Return the non-writable segment containing `vaddr`, if there is one.
+    fn code_seg(&self, vaddr: usize) -> Option<Segment<'_>>;
+}
+
+/// The virtual address ranges of segments of the current process that we may need to
+/// disassemble.
+///
+/// FIXME: For now this assumes that no dlopen()/dlclose() is happening.
+static CODE_SEGS: LazyLock<IntervalTree<usize, ()>> = LazyLock::new(|| {
+    let mut segs = Vec::new();
+    for obj in PHDR_OBJECT_CACHE.iter() {
+        let obj_base = obj.addr();
+        for hdr in obj.phdrs() {
+            if (hdr.flags() & libc::PF_W) == 0 {
+                let vaddr = usize::try_from(obj_base + hdr.vaddr()).unwrap();
+                let memsz = usize::try_from(hdr.memsz()).unwrap();
+                segs.push((vaddr..(vaddr + memsz), ()));
+            }
+        }
+    }
+    segs.into_iter().collect()
+});
+
+/// The code of the currently running process.
+#[derive(Debug)]
+pub struct SelfImage;
+
+impl Image for SelfImage {
+    fn blockmap(&self) -> &BlockMap {
+        &LLVM_BLOCK_MAP
+    }
+
+    fn in_main_obj(&self, vaddr: usize) -> Option<bool> {
+        vaddr_to_obj_and_off(vaddr).map(|(obj, _)| obj == *SELF_BIN_PATH)
+    }
+
+    fn sym_name(&self, vaddr: usize) -> Option<&CStr> {
+        vaddr_to_sym_and_obj(vaddr).and_then(|x| x.dli_sname())
+    }
+
+    fn code_seg(&self, vaddr: usize) -> Option<Segment<'_>> {
+        let mut hits = CODE_SEGS.query(vaddr..(vaddr + 1));
+        let x = hits.next()?;
+        // Segments can't overlap.
+        debug_assert!(hits.next().is_none());
+        let slice = unsafe { slice::from_raw_parts(x.range.start as *const u8, x.range.len()) };
+        Some(Segment {
+            vaddrs: x.range.clone(),
+            slice,
+        })
+    }
+}
@@ -6,7 +6,10 @@
 mod block;
 pub use block::Block;
 pub mod errors;
+pub mod image;
 pub mod llvm_blockmap;
+#[cfg(ykpt)]
+pub mod offline;
 #[cfg(linux_perf)]
 mod perf;
 #[cfg(pt)]
@@ -95,6 +98,9 @@ pub trait Trace: Debug + Send {
         self: Box<Self>,
     ) -> Box<dyn Iterator<Item = Result<Block, BlockIteratorError>> + Send>;
 
+    /// The code that the trace was recorded from.
+    fn image(&self) -> &'static dyn image::Image;
+
     #[cfg(test)]
     fn bytes(&self) -> &[u8];
 
@@ -108,9 +108,8 @@ pub struct BlockMap {
 }
 
 impl BlockMap {
-    /// Parse the LLVM blockmap section of the current executable and return a struct holding the
-    /// mappings.
-    pub fn new(data: &'static [u8]) -> Self {
+    /// Parse the LLVM blockmap section `data` and return a struct holding the mappings.
+    pub fn new(data: &[u8]) -> Self {
         // Keep reading blockmap records until we fall outside of the section's bounds.
         let mut elems = Vec::new();
         let mut crsr = Cursor::new(data);
@@ -0,0 +1,335 @@
+//! Decoding Intel PT traces offline.
+//!
+//! Normally a trace is decoded by the process that recorded it, using that process's own memory
+//! to find out about the code being traced. This module instead decodes a trace that was saved by
+//! a previous run, using the traced binary (and, optionally, the other objects it had loaded) as
+//! found on disk. This allows decoder bugs to be reproduced, and tested for, on machines without
+//! PT hardware.
+//!
+//! The main object's blockmap contains the addresses that the main object was linked at, so the
+//! main object must have been loaded at those addresses when it was traced. Other objects may be
+//! loaded at any address (see [OfflineImage::add_object]).
+
+use crate::{
+    errors::HWTracerError,
+    image::{Image, Segment},
+    llvm_blockmap::BlockMap,
+    perf::collect::PerfTrace,
+    Block, BlockIteratorError, Trace,
+};
+use byteorder::{NativeEndian, ReadBytesExt};
+use memmap2::Mmap;
+use object::{Object, ObjectSection, ObjectSegment, SegmentFlags};
+use std::{
+    ffi::{CStr, CString},
+    fs::{self, File},
+    io::{Cursor, Seek, SeekFrom},
+    ops::Range,
+    path::Path,
+};
+
+/// The magic number at the start of a `perf.data` file.
+const PERF_MAGIC: &[u8; 8] = b"PERFILE2";
+/// The `perf_event_header` type of a `PERF_RECORD_AUXTRACE` record.
+const PERF_RECORD_AUXTRACE: u32 = 71;
+
+/// One loadable segment of an [LoadedObj].
+#[derive(Debug)]
+struct LoadedSeg {
+    /// The virtual address range the segment was loaded at.
+    vaddrs: Range<usize>,
+    /// If the segment is not writable, the range of file offsets of its contents.
+    code: Option<Range<usize>>,
+}
+
+/// An object file, as it was loaded into the traced process.
+#[derive(Debug)]
+struct LoadedObj {
+    mmap: Mmap,
+    segs: Vec<LoadedSeg>,
+}
+
+impl LoadedObj {
+    /// Map the object file at `path`, which was loaded with the virtual address `base` added to
+    /// each of its segments' addresses.
+    fn new(path: &Path, base: usize) -> Result<Self, HWTracerError> {
+        let err = |e: &dyn std::fmt::Display| {
+            HWTracerError::Unrecoverable(format!("{}: {e}", path.display()))
+        };
+        let file = File::open(path).map_err(|e| err(&e))?;
+        let mmap = unsafe { Mmap::map(&file) }.map_err(|e| err(&e))?;
+        let obj = object::File::parse(&*mmap).map_err(|e| err(&e))?;
+        let mut segs = Vec::new();
+        for seg in obj.segments() {
+            let vaddr = base + usize::try_from(seg.address()).unwrap();
+            let vaddrs = vaddr..(vaddr + usize::try_from(seg.size()).unwrap());
+            let writable = match seg.flags() {
+                SegmentFlags::Elf { p_flags } => (p_flags & libc::PF_W) != 0,
+                _ => unreachable!(),
+            };
+            let code = if writable {
+                None
+            } else {
+                let (off, len) = seg.file_range();
+                let off = usize::try_from(off).unwrap();
+                Some(off..(off + usize::try_from(len).unwrap()))
+            };
+            segs.push(LoadedSeg { vaddrs, code });
+        }
+        Ok(Self { mmap, segs })
+    }
+
+    /// Does this object contain `vaddr`?
+    fn contains(&self, vaddr: usize) -> bool {
+        self.segs.iter().any(|x| x.vaddrs.contains(&vaddr))
+    }
+
+    /// Return the non-writable segment containing `vaddr`, if there is one.
+    fn code_seg(&self, vaddr: usize) -> Option<Segment<'_>> {
+        self.segs.iter().find_map(|seg| {
+            let code = seg.code.as_ref()?;
+            let vaddrs = seg.vaddrs.start..(seg.vaddrs.start + code.len());
+            vaddrs.contains(&vaddr).then(|| Segment {
+                vaddrs,
+                slice: &self.mmap[code.clone()],
+            })
+        })
+    }
+}
+
+/// The code of a traced process, loaded from the object files on disk.
+#[derive(Debug)]
+pub struct OfflineImage {
+    /// The main object, i.e. the traced binary.
+    main: LoadedObj,
+    /// Other objects (e.g. shared objects) loaded into the traced process.
+    others: Vec<LoadedObj>,
+    blockmap: BlockMap,
+    /// The main object's symbols, sorted by address.
+    syms: Vec<(usize, CString)>,
+}
+
+impl OfflineImage {
+    /// Load the traced binary `exe`, which must have been built by ykllvm.
+    pub fn new(exe: &Path) -> Result<Self, HWTracerError> {
+        let main = LoadedObj::new(exe, 0)?;
+        let obj = object::File::parse(&*main.mmap).unwrap();
+        let blockmap = match obj.section_by_name(".llvm_bb_addr_map") {
+            Some(sec) => BlockMap::new(sec.data().unwrap()),
+            None => {
+                return Err(HWTracerError::Unrecoverable(format!(
+                    "{}: no .llvm_bb_addr_map section",
+                    exe.display()
+                )))
+            }
+        };
+        let syms = obj
+            .symbol_map()
+            .symbols()
+            .iter()
+            .map(|x| {
+                (
+                    usize::try_from(x.address()).unwrap(),
+                    CString::new(x.name()).unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        Ok(Self {
+            main,
+            others: Vec::new(),
+            blockmap,
+            syms,
+        })
+    }
+
+    /// Add the object file `path` (e.g. a shared object) which was loaded into the traced process
+    /// with the virtual address `base` added to each of its segments' addresses. Only objects
+    /// whose code appears in the trace need to be added.
+    pub fn add_object(&mut self, path: &Path, base: usize) -> Result<(), HWTracerError> {
+        self.others.push(LoadedObj::new(path, base)?);
+        Ok(())
+    }
+
+    /// Create a trace from `bytes`, a stream of Intel PT packets recorded from this image. Since
+    /// the blocks of a trace refer to its image, the image must never be dropped: use e.g.
+    /// [Box::leak] to obtain a `'static` reference to it.
+    pub fn trace(&'static self, bytes: &[u8]) -> Result<Box<dyn Trace>, HWTracerError> {
+        Ok(Box::new(OfflineTrace {
+            trace: Box::new(PerfTrace::from_bytes(bytes)?),
+            image: self,
+        }))
+    }
+}
+
+impl Image for OfflineImage {
+    fn blockmap(&self) -> &BlockMap {
+        &self.blockmap
+    }
+
+    fn in_main_obj(&self, vaddr: usize) -> Option<bool> {
+        if self.main.contains(vaddr) {
+            Some(true)
+        } else if self.others.iter().any(|x| x.contains(vaddr)) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    fn sym_name(&self, vaddr: usize) -> Option<&CStr> {
+        // As with `dladdr`, the symbol is the closest one at or before `vaddr`.
+        match self.syms.partition_point(|(x, _)| *x <= vaddr) {
+            0 => None,
+            i => Some(self.syms[i - 1].1.as_c_str()),
+        }
+    }
+
+    fn code_seg(&self, vaddr: usize) -> Option<Segment<'_>> {
+        std::iter::once(&self.main)
+            .chain(&self.others)
+            .find_map(|x| x.code_seg(vaddr))
+    }
+}
+
+/// A trace decoded offline.
+#[derive(Debug)]
+struct OfflineTrace {
+    trace: Box<PerfTrace>,
+    image: &'static OfflineImage,
+}
+
+impl Trace for OfflineTrace {
+    fn iter_blocks(
+        self: Box<Self>,
+    ) -> Box<dyn Iterator<Item = Result<Block, BlockIteratorError>> + Send> {
+        (*self.trace).iter_image_blocks(self.image)
+    }
+
+    fn image(&self) -> &'static dyn Image {
+        self.image
+    }
+
+    #[cfg(test)]
+    fn bytes(&self) -> &[u8] {
+        self.trace.bytes()
+    }
+
+    #[cfg(test)]
+    fn capacity(&self) -> usize {
+        self.trace.capacity()
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.trace.len()
+    }
+}
+
+/// Read the Intel PT packets saved in the file at `path`. The file can either contain just the
+/// packets themselves, or be a `perf.data` file (e.g. from `perf record -e intel_pt//u`) which
+/// contains the packets for a single thread.
+pub fn read_trace(path: &Path) -> Result<Vec<u8>, HWTracerError> {
+    let buf = fs::read(path)
+        .map_err(|e| HWTracerError::Unrecoverable(format!("{}: {e}", path.display())))?;
+    if buf.starts_with(PERF_MAGIC) {
+        perf_aux_data(&buf)
+            .map_err(|e| HWTracerError::Unrecoverable(format!("{}: {e}", path.display())))
+    } else {
+        Ok(buf)
+    }
+}
+
+/// Extract the Intel PT packets from the `PERF_RECORD_AUXTRACE` records in the `perf.data` file
+/// `buf`.
+fn perf_aux_data(buf: &[u8]) -> Result<Vec<u8>, String> {
+    let truncated = |_| "truncated perf.data file".to_owned();
+    let mut crsr = Cursor::new(buf);
+    // In a `struct perf_file_header`, the data section's offset and size follow the magic number,
+    // the size of the header, the size of an attribute, and the attribute section's offset and
+    // size.
+    crsr.seek(SeekFrom::Start(40)).map_err(truncated)?;
+    let data_off = crsr.read_u64::<NativeEndian>().map_err(truncated)?;
+    let data_end = data_off + crsr.read_u64::<NativeEndian>().map_err(truncated)?;
+
+    let mut aux = Vec::new();
+    let mut aux_idx = None;
+    let mut off = data_off;
+    while off < data_end {
+        // Each record starts with a `struct perf_event_header`.
+        crsr.seek(SeekFrom::Start(off)).map_err(truncated)?;
+        let type_ = crsr.read_u32::<NativeEndian>().map_err(truncated)?;
+        let _misc = crsr.read_u16::<NativeEndian>().map_err(truncated)?;
+        let size = crsr.read_u16::<NativeEndian>().map_err(truncated)?;
+        if size < 8 {
+            return Err("corrupt perf.data file".into());
+        }
+        off += u64::from(size);
+        if type_ == PERF_RECORD_AUXTRACE {
+            // The rest of a `struct perf_record_auxtrace` is the size, offset, and reference of
+            // the AUX data, then the index of the buffer it came from. The AUX data follows the
+            // record, but isn't included in the record's size.
+            let aux_size = crsr.read_u64::<NativeEndian>().map_err(truncated)?;
+            crsr.seek(SeekFrom::Current(16)).map_err(truncated)?;
+            let idx = crsr.read_u32::<NativeEndian>().map_err(truncated)?;
+            if *aux_idx.get_or_insert(idx) != idx {
+                return Err("perf.data contains more than one trace buffer".into());
+            }
+            let data = usize::try_from(off).unwrap()..usize::try_from(off + aux_size).unwrap();
+            aux.extend_from_slice(buf.get(data).ok_or("truncated perf.data file")?);
+            off += aux_size;
+        }
+    }
+    Ok(aux)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make a `perf.data` file whose data section contains `records`.
+    fn perf_data(records: &[u8]) -> Vec<u8> {
+        let mut buf = PERF_MAGIC.to_vec();
+        // The header size, attribute size, and attribute section.
+        buf.resize(40, 0);
+        buf.extend_from_slice(&64u64.to_ne_bytes());
+        buf.extend_from_slice(&u64::try_from(records.len()).unwrap().to_ne_bytes());
+        buf.resize(64, 0);
+        buf.extend_from_slice(records);
+        buf
+    }
+
+    /// Make a `PERF_RECORD_AUXTRACE` record for the AUX data `data` from buffer `idx`.
+    fn auxtrace(idx: u32, data: &[u8]) -> Vec<u8> {
+        let mut buf = PERF_RECORD_AUXTRACE.to_ne_bytes().to_vec();
+        buf.extend_from_slice(&0u16.to_ne_bytes());
+        buf.extend_from_slice(&48u16.to_ne_bytes());
+        buf.extend_from_slice(&u64::try_from(data.len()).unwrap().to_ne_bytes());
+        buf.resize(32, 0);
+        buf.extend_from_slice(&idx.to_ne_bytes());
+        buf.resize(48, 0);
+        buf.extend_from_slice(data);
+        buf
+    }
+
+    #[test]
+    fn perf_aux() {
+        // A `PERF_RECORD_COMM` record, which must be skipped.
+        let mut recs = vec![3, 0, 0, 0, 0, 0, 16, 0];
+        recs.extend_from_slice(b"interp\0\0");
+        recs.extend(auxtrace(0, &[1, 2, 3]));
+        recs.extend(auxtrace(0, &[4, 5]));
+        assert_eq!(perf_aux_data(&perf_data(&recs)), Ok(vec![1, 2, 3, 4, 5]));
+
+        recs.extend(auxtrace(1, &[6]));
+        assert_eq!(
+            perf_aux_data(&perf_data(&recs)),
+            Err("perf.data contains more than one trace buffer".to_owned())
+        );
+
+        let buf = perf_data(&auxtrace(0, &[1, 2, 3]));
+        assert_eq!(
+            perf_aux_data(&buf[..buf.len() - 1]),
+            Err("truncated perf.data file".to_owned())
+        );
+    }
+}
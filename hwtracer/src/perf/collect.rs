@@ -7,6 +7,7 @@ use crate::pt::c_errors::PerfPTCError;
 use crate::pt::ykpt::YkPTBlockIterator;
 use crate::{
     errors::{HWTracerError, TemporaryErrorKind},
+    image::{Image, SelfImage},
     Block, BlockIteratorError, ThreadTracer, Trace, Tracer,
 };
 use libc::{c_void, free, geteuid, malloc, size_t, PF_R, PF_X, PT_LOAD};
@@ -223,12 +224,22 @@ impl PerfTrace {
             capacity: capacity as u64,
         })
     }
-}
 
-impl Trace for PerfTrace {
+    /// Makes a new trace containing a copy of the Intel PT packets `bytes`.
     #[cfg(ykpt)]
-    fn iter_blocks(
-        mut self: Box<Self>,
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, HWTracerError> {
+        // `malloc(0)` may return a null pointer, so always allocate at least one byte.
+        let mut trace = Self::new(bytes.len().max(1))?;
+        unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), trace.buf.0, bytes.len()) };
+        trace.len = bytes.len() as u64;
+        Ok(trace)
+    }
+
+    /// Iterate over the blocks of the trace, which was recorded from `image`.
+    #[cfg(ykpt)]
+    pub(crate) fn iter_image_blocks(
+        mut self,
+        image: &'static dyn Image,
     ) -> Box<dyn Iterator<Item = Result<Block, BlockIteratorError>> + Send> {
         // We hand ownership for self.buf over to `YkPTBlockIterator` so we need to make sure that
         // we don't try and free it.
@@ -236,8 +247,22 @@ impl Trace for PerfTrace {
         Box::new(YkPTBlockIterator::new(
             buf,
             usize::try_from(self.len).unwrap(),
+            image,
         ))
     }
+}
+
+impl Trace for PerfTrace {
+    #[cfg(ykpt)]
+    fn iter_blocks(
+        self: Box<Self>,
+    ) -> Box<dyn Iterator<Item = Result<Block, BlockIteratorError>> + Send> {
+        (*self).iter_image_blocks(&SelfImage)
+    }
+
+    fn image(&self) -> &'static dyn Image {
+        &SelfImage
+    }
 
     #[cfg(test)]
     fn bytes(&self) -> &[u8] {
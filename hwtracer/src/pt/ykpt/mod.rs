@@ -42,87 +42,26 @@ mod parser;
 
 use crate::{
     errors::{HWTracerError, TemporaryErrorKind},
-    llvm_blockmap::{BlockMapEntry, SuccessorKind, LLVM_BLOCK_MAP},
+    image::{Image, Segment},
+    llvm_blockmap::{BlockMapEntry, SuccessorKind},
     perf::collect::PerfTraceBuf,
     Block, BlockIteratorError,
 };
-use intervaltree::IntervalTree;
 use std::{
     collections::VecDeque,
     fmt::{self, Debug},
-    ops::Range,
-    path::PathBuf,
     slice,
-    sync::LazyLock,
 };
 use thiserror::Error;
-use ykaddr::obj::{PHDR_OBJECT_CACHE, SELF_BIN_PATH};
 
 use packets::{Bitness, Packet, PacketKind};
 use parser::PacketParser;
 
-/// The virtual address ranges of segments that we may need to disassemble.
-static CODE_SEGS: LazyLock<CodeSegs> = LazyLock::new(|| {
-    let mut segs = Vec::new();
-    for obj in PHDR_OBJECT_CACHE.iter() {
-        let obj_base = obj.addr();
-        for hdr in obj.phdrs() {
-            if (hdr.flags() & libc::PF_W) == 0 {
-                let vaddr = usize::try_from(obj_base + hdr.vaddr()).unwrap();
-                let memsz = usize::try_from(hdr.memsz()).unwrap();
-                let key = vaddr..(vaddr + memsz);
-                segs.push((key.clone(), ()));
-            }
-        }
-    }
-    let tree = segs.into_iter().collect::<IntervalTree<usize, ()>>();
-    CodeSegs { tree }
-});
-
 /// The number of compressed returns that a CPU implementing Intel Processor Trace can keep track
 /// of. This is a bound baked into the hardware, but the decoder needs to be aware of it for its
 /// compressed return stack.
 const PT_MAX_COMPRETS: usize = 64;
 
-/// A data structure providing convenient access to virtual address ranges and memory slices for
-/// segments.
-///
-/// FIXME: For now this assumes that no dlopen()/dlclose() is happening.
-struct CodeSegs {
-    tree: IntervalTree<usize, ()>,
-}
-
-impl CodeSegs {
-    /// Obtain the virtual address range and a slice of memory for the segment containing the
-    /// specified virtual address.
-    fn seg<'s: 'a, 'a>(&'s self, vaddr: usize) -> Segment<'a> {
-        let mut hits = self.tree.query(vaddr..(vaddr + 1));
-        match hits.next() {
-            Some(x) => {
-                // Segments can't overlap.
-                debug_assert_eq!(hits.next(), None);
-
-                let slice = unsafe {
-                    slice::from_raw_parts(x.range.start as *const u8, x.range.end - x.range.start)
-                };
-                Segment {
-                    vaddrs: &x.range,
-                    slice,
-                }
-            }
-            None => todo!(), // Has an object been loaded or unloaded at runtime?
-        }
-    }
-}
-
-/// The virtual address range of and memory slice of one ELF segment.
-struct Segment<'a> {
-    /// The virtual address range of the segment.
-    vaddrs: &'a Range<usize>,
-    /// A memory slice for the segment.
-    slice: &'a [u8],
-}
-
 /// Represents a location in the instruction stream of the traced binary.
 #[derive(Eq, PartialEq)]
 enum ObjLoc {
@@ -218,6 +157,8 @@ impl CompressedReturns {
 /// Iterate over the blocks of an Intel PT trace using the fast Yk PT decoder.
 pub(crate) struct YkPTBlockIterator<'t> {
     trace: PerfTraceBuf,
+    /// The code that the trace was recorded from.
+    image: &'static dyn Image,
     /// The packet iterator used to drive the decoding process.
     parser: PacketParser<'t>,
     /// Keeps track of where we are in the traced binary.
@@ -232,11 +173,12 @@ pub(crate) struct YkPTBlockIterator<'t> {
 }
 
 impl YkPTBlockIterator<'_> {
-    pub(crate) fn new(trace: PerfTraceBuf, trace_len: usize) -> Self {
+    pub(crate) fn new(trace: PerfTraceBuf, trace_len: usize, image: &'static dyn Image) -> Self {
         // We must keep `self.trace` alive at least as long as `self.parser`
         let bytes = unsafe { slice::from_raw_parts(trace.0, trace_len) };
         Self {
             trace,
+            image,
             parser: PacketParser::new(bytes),
             cur_loc: ObjLoc::OtherObjOrUnknown(None),
             tnts: VecDeque::new(),
@@ -245,9 +187,19 @@ impl YkPTBlockIterator<'_> {
         }
     }
 
-    /// Convert a virtual address to a file offset.
-    fn vaddr_to_off(&self, vaddr: usize) -> Result<(PathBuf, u64), IteratorError> {
-        ykaddr::addr::vaddr_to_obj_and_off(vaddr).ok_or(IteratorError::NoSuchVAddr)
+    /// Determine whether a virtual address is in the main object.
+    fn in_main_obj(&self, vaddr: usize) -> Result<bool, IteratorError> {
+        self.image
+            .in_main_obj(vaddr)
+            .ok_or(IteratorError::NoSuchVAddr)
+    }
+
+    /// Obtain the virtual address range and a slice of memory for the segment containing the
+    /// specified virtual address. Fails if no object in the image has a code segment containing
+    /// `vaddr` (e.g. because an object was loaded after the image was created, or, for an offline
+    /// image, because an object the trace passed through was not added to it).
+    fn code_seg(&self, vaddr: usize) -> Result<Segment<'static>, IteratorError> {
+        self.image.code_seg(vaddr).ok_or(IteratorError::NoSuchVAddr)
     }
 
     /// Looks up the blockmap entry for the given virtual address.
@@ -255,7 +207,7 @@ impl YkPTBlockIterator<'_> {
         &self,
         vaddr: usize,
     ) -> Option<&'static intervaltree::Element<usize, BlockMapEntry>> {
-        let mut ents = LLVM_BLOCK_MAP.query(vaddr, vaddr + 1);
+        let mut ents = self.image.blockmap().query(vaddr, vaddr + 1);
         if let Some(ent) = ents.next() {
             // A single-address range cannot span multiple blocks.
             debug_assert!(ents.next().is_none());
@@ -477,7 +429,7 @@ impl YkPTBlockIterator<'_> {
     }
 
     fn disassemble(&mut self, start_vaddr: usize) -> Result<Block, IteratorError> {
-        let mut seg = CODE_SEGS.seg(start_vaddr);
+        let mut seg = self.code_seg(start_vaddr)?;
         let mut dis =
             iced_x86::Decoder::with_ip(64, seg.slice, u64::try_from(seg.vaddrs.start).unwrap(), 0);
         dis.set_ip(u64::try_from(start_vaddr).unwrap());
@@ -486,9 +438,7 @@ impl YkPTBlockIterator<'_> {
 
         loop {
             let vaddr = usize::try_from(dis.ip()).unwrap();
-            let (obj, _) = self.vaddr_to_off(vaddr)?;
-
-            if obj == *SELF_BIN_PATH {
+            if self.in_main_obj(vaddr)? {
                 let block = self.lookup_block_by_vaddr(vaddr)?;
                 if !block.is_unknown() {
                     // We are back to "native code" and can resume compiler-assisted decoding.
@@ -500,7 +450,7 @@ impl YkPTBlockIterator<'_> {
             if !seg.vaddrs.contains(&vaddr) {
                 // The next instruction is outside of the current segment. Switch segment and make
                 // a new decoder for it.
-                seg = CODE_SEGS.seg(vaddr);
+                seg = self.code_seg(vaddr)?;
                 let seg_start_u64 = u64::try_from(seg.vaddrs.start).unwrap();
                 dis = iced_x86::Decoder::with_ip(64, seg.slice, seg_start_u64, 0);
                 dis.set_ip(u64::try_from(vaddr).unwrap());
@@ -799,9 +749,10 @@ impl YkPTBlockIterator<'_> {
 
             // Update `self.target_ip` if necessary.
             if let Some(vaddr) = pkt.target_ip() {
-                self.cur_loc = match self.vaddr_to_off(vaddr)? {
-                    (obj, _) if obj == *SELF_BIN_PATH => ObjLoc::MainObj(vaddr),
-                    _ => ObjLoc::OtherObjOrUnknown(Some(vaddr)),
+                self.cur_loc = if self.in_main_obj(vaddr)? {
+                    ObjLoc::MainObj(vaddr)
+                } else {
+                    ObjLoc::OtherObjOrUnknown(Some(vaddr))
                 };
             }
 
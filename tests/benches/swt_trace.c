@@ -0,0 +1,49 @@
+// Software tracer benchmark: record traces of differing lengths.
+//
+// Criterion forks executions of this code.
+
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+__attribute__((noinline)) uint64_t step(uint64_t x) {
+  if (x % 3 == 0)
+    return x / 3;
+  return x + 1;
+}
+
+// Executes a number of basic blocks, across two functions, proportional to
+// `len`. Since this function is outlined, the trace which calls it is small no
+// matter how many blocks the software tracer has to record.
+__attribute__((yk_outline)) uint64_t work(uint64_t len) {
+  uint64_t sum = 0;
+  for (uint64_t i = 0; i < len; i++)
+    sum += step(i);
+  return sum;
+}
+
+int main(int argc, char **argv) {
+  if (argc != 3) {
+    fprintf(stderr, "usage: swt_trace <hot-threshold> <len>\n");
+    exit(EXIT_FAILURE);
+  }
+
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, atoi(argv[1]));
+  YkLocation loc = yk_location_new();
+
+  uint64_t len = strtoull(argv[2], NULL, 10);
+  uint64_t sum = 0;
+  NOOPT_VAL(len);
+  for (int i = 0; i < 4; i++) {
+    yk_mt_control_point(mt, &loc);
+    sum += work(len);
+  }
+  NOOPT_VAL(sum);
+
+  yk_location_drop(loc);
+  yk_mt_shutdown(mt);
+  return (EXIT_SUCCESS);
+}
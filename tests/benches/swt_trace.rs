@@ -0,0 +1,80 @@
+//! Critereon benchmarks for measuring the overhead of recording traces with the software tracer.
+//!
+//! These benchmarks are only meaningful if yk was built with `YKB_TRACER=swt`.
+
+use criterion::{
+    criterion_group, criterion_main, measurement::Measurement, BenchmarkGroup, BenchmarkId,
+    Criterion, SamplingMode,
+};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+use tempfile::TempDir;
+use tests::{check_output, mk_compiler};
+use ykbuild::ykllvm_bin;
+
+const SAMPLE_SIZE: usize = 30;
+const MEASUREMENT_TIME: Duration = Duration::from_secs(20);
+
+fn compile_bench(tempdir: &TempDir) -> PathBuf {
+    let md = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let src = [&md, "benches", "swt_trace.c"].iter().collect::<PathBuf>();
+
+    let mut exe = PathBuf::new();
+    exe.push(tempdir);
+    exe.push(src.file_stem().unwrap());
+
+    let mut compiler = mk_compiler(&ykllvm_bin("clang"), &exe, &src, &[], true, None);
+    compiler.arg("-ltests");
+    let out = compiler.output().unwrap();
+    check_output(&out);
+
+    exe
+}
+
+fn setup<'c, M: Measurement>(
+    c: &'c mut Criterion<M>,
+    group_name: &str,
+) -> (TempDir, PathBuf, BenchmarkGroup<'c, M>) {
+    let tempdir = TempDir::new().unwrap();
+    let bin = compile_bench(&tempdir);
+
+    let mut group = c.benchmark_group(group_name);
+    group.sample_size(SAMPLE_SIZE);
+    group.measurement_time(MEASUREMENT_TIME);
+    group.sampling_mode(SamplingMode::Flat);
+
+    (tempdir, bin, group)
+}
+
+fn run_bench(bench_bin: &Path, hot_threshold: usize, len: usize) {
+    assert!(bench_bin.exists());
+    let out = Command::new(bench_bin)
+        .arg(format!("{hot_threshold}"))
+        .arg(format!("{len}"))
+        .env("YKD_SERIALISE_COMPILATION", "1")
+        .output()
+        .unwrap();
+    check_output(&out);
+}
+
+fn bench_swt_trace(c: &mut Criterion) {
+    let (_tempdir, bin, mut group) = setup(c, "swt-trace");
+
+    for len in [1000, 10000, 100000] {
+        // Benchmark the program without ever tracing it, as a baseline.
+        group.bench_function(BenchmarkId::new("without tracing", format!("{len}")), |b| {
+            b.iter(|| run_bench(&bin, 1000, len))
+        });
+        // Benchmark the program when one loop iteration (i.e. a call to `work(len)`) is traced.
+        group.bench_function(BenchmarkId::new("with tracing", format!("{len}")), |b| {
+            b.iter(|| run_bench(&bin, 0, len))
+        });
+    }
+}
+
+criterion_group!(swt_trace_benchmarks, bench_swt_trace);
+criterion_main!(swt_trace_benchmarks);
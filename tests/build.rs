@@ -9,6 +9,7 @@ pub fn main() {
         "c",
         "extra_linkage",
         "lua",
+        "pt_fixtures",
         "trace_compiler",
         "benches/*.c",
         "yklua",
@@ -21,4 +22,21 @@ pub fn main() {
     println!(
         r#"cargo::rustc-check-cfg=cfg(cargo_profile, values("debug", "release", "release-with-debug"))"#
     );
+
+    // Mirror ykrt's choice of tracers, so that tests can tell which are available. ykrt itself
+    // rejects invalid values of `YKB_TRACER`.
+    println!("cargo::rerun-if-env-changed=YKB_TRACER");
+    println!("cargo::rustc-check-cfg=cfg(tracer_hwt)");
+    println!("cargo::rustc-check-cfg=cfg(tracer_swt)");
+    match env::var("YKB_TRACER") {
+        Ok(ref tracer) => {
+            for t in tracer.split(',') {
+                println!("cargo::rustc-cfg=tracer_{t}");
+            }
+        }
+        #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+        Err(_) => println!("cargo::rustc-cfg=tracer_hwt"),
+        #[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+        Err(_) => println!("cargo::rustc-cfg=tracer_swt"),
+    }
 }
@@ -0,0 +1,69 @@
+// Run-time:
+//   env-var: YKD_LOG_IR=jit-pre-opt
+//   env-var: YKD_SERIALISE_COMPILATION=1
+//   env-var: YKD_LOG=4
+//   stderr:
+//     yk-tracing: start-tracing
+//     4: 10
+//     yk-tracing: stop-tracing
+//     --- Begin jit-pre-opt ---
+//     ...
+//     --- End jit-pre-opt ---
+//     3: 10
+//     yk-execution: enter-jit-code
+//     2: 10
+//     1: 10
+//     yk-execution: deoptimise ...
+//     yk-tracing: trace-invalidated: ...
+//     yk-tracing: start-tracing
+//     4: 20
+//     yk-tracing: stop-tracing
+//     --- Begin jit-pre-opt ---
+//     ...
+//     --- End jit-pre-opt ---
+//     3: 20
+//     yk-execution: enter-jit-code
+//     2: 20
+//     1: 20
+//     yk-execution: deoptimise ...
+//     exit
+
+// Check that quasi-constants are baked into traces and that changing them
+// causes traces that depend on them to be invalidated.
+
+#include <assert.h>
+#include <inttypes.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+uintptr_t qconst = 10;
+
+__attribute__((noinline)) void loop(YkMT *mt, YkLocation *loc) {
+  int i = 4;
+  NOOPT_VAL(i);
+  while (i > 0) {
+    yk_mt_control_point(mt, loc);
+    fprintf(stderr, "%d: %" PRIuPTR "\n", i, yk_assume_constant(&qconst));
+    i--;
+  }
+}
+
+int main(int argc, char **argv) {
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, 0);
+  YkLocation loc = yk_location_new();
+
+  NOOPT_VAL(loc);
+  loop(mt, &loc);
+  qconst = 20;
+  yk_assumption_changed(mt, &qconst);
+  loop(mt, &loc);
+  fprintf(stderr, "exit\n");
+  yk_location_drop(loc);
+  yk_mt_shutdown(mt);
+  return (EXIT_SUCCESS);
+}
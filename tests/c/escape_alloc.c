@@ -0,0 +1,63 @@
+// Run-time:
+//   env-var: YK_ALLOCATORS=box_new
+//   env-var: YKD_SERIALISE_COMPILATION=1
+//   env-var: YKD_LOG=4
+//   stderr:
+//     yk-tracing: start-tracing
+//     12
+//     yk-tracing: stop-tracing
+//     21
+//     yk-execution: enter-jit-code
+//     27
+//     yk-execution: deoptimise ...
+//     last: 1 2
+//     30
+//     ...
+
+// Check that allocations which don't escape a trace are removed from it, and
+// are correctly materialised when a guard fails.
+
+#include <assert.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+struct pair {
+  int a;
+  int b;
+};
+
+__attribute__((yk_outline, noinline)) void *box_new(size_t n) {
+  void *p = malloc(n);
+  assert(p != NULL);
+  return p;
+}
+
+int main(int argc, char **argv) {
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, 0);
+  YkLocation loc = yk_location_new();
+
+  int res = 0;
+  int i = 4;
+  NOOPT_VAL(loc);
+  NOOPT_VAL(res);
+  NOOPT_VAL(i);
+  while (i > 0) {
+    yk_mt_control_point(mt, &loc);
+    struct pair *p = box_new(sizeof(struct pair));
+    p->a = i;
+    p->b = i * 2;
+    if (i == 1)
+      fprintf(stderr, "last: %d %d\n", p->a, p->b);
+    res += p->a + p->b;
+    fprintf(stderr, "%d\n", res);
+    i--;
+  }
+  NOOPT_VAL(res);
+  yk_location_drop(loc);
+  yk_mt_shutdown(mt);
+  return (EXIT_SUCCESS);
+}
@@ -0,0 +1,47 @@
+// Run-time:
+//   env-var: YKD_SERIALISE_COMPILATION=1
+//   stderr:
+//     4: fdbac097c8dc5ad0 da5f57397d9b0940 1 -19546873383 -4777216 ba9876543214
+//     3: fdbac097c8dc5acf db829ca10746d730 1 -14660155038 -7777216 ba9876543213
+//     2: fdbac097c8dc5ace dca5e20890f2a520 1 -9773436692 6000000 ba9876543212
+//     1: fdbac097c8dc5acd ddc927701a9e7310 1 -4886718346 3000000 ba9876543211
+//     exit
+
+// Check that integers of unusual widths (which LLVM represents as e.g. `i24`,
+// `i48`, and `i128`) work.
+
+#include <inttypes.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+int main(int argc, char **argv) {
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, 0);
+  YkLocation loc = yk_location_new();
+
+  int i = 4;
+  uint64_t x = 0xFEDCBA9876543210;
+  NOOPT_VAL(loc);
+  NOOPT_VAL(i);
+  NOOPT_VAL(x);
+  while (i > 0) {
+    yk_mt_control_point(mt, &loc);
+    unsigned __int128 p = (unsigned __int128)x * (x + i);
+    __int128 s = (__int128)(int64_t)x * ((int64_t)i << 40);
+    _BitInt(24) b = (_BitInt(24))(i * 3000000);
+    unsigned _BitInt(48) u = (unsigned _BitInt(48))x + i;
+    fprintf(stderr,
+            "%d: %016" PRIx64 " %016" PRIx64 " %d %" PRId64 " %d %" PRIx64 "\n",
+            i, (uint64_t)(p >> 64), (uint64_t)p,
+            p > ((unsigned __int128)1 << 127), (int64_t)(s >> 64), (int)b,
+            (uint64_t)u);
+    i--;
+  }
+  fprintf(stderr, "exit\n");
+  yk_location_drop(loc);
+  yk_mt_shutdown(mt);
+  return (EXIT_SUCCESS);
+}
@@ -0,0 +1,45 @@
+// Run-time:
+//   env-var: YKD_SERIALISE_COMPILATION=1
+//   stderr:
+//     4: 28 2 c000000 6.0 -6.0 aabcefg
+//     3: 28 0 9000000 4.5 -4.0 aabcefg
+//     2: 29 1 6000000 3.0 -3.0 aabcefg
+//     1: 30 0 3000000 1.5 -1.0 aabcefg
+//     exit
+
+// Check that intrinsics which are lowered inline by the code generator work.
+
+#include <assert.h>
+#include <math.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+int main(int argc, char **argv) {
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, 0);
+  YkLocation loc = yk_location_new();
+
+  int i = 4;
+  char buf[8];
+  NOOPT_VAL(loc);
+  NOOPT_VAL(i);
+  while (i > 0) {
+    yk_mt_control_point(mt, &loc);
+    uint32_t x = i * 3;
+    NOOPT_VAL(x);
+    double d = -(double)x / 2.0;
+    strcpy(buf, "abcdefg");
+    memmove(buf + 1, buf, 3);
+    fprintf(stderr, "%d: %d %d %x %.1f %.1f %s\n", i, __builtin_clz(x),
+            __builtin_ctzll(x), __builtin_bswap32(x), fabs(d), ceil(d), buf);
+    i--;
+  }
+  fprintf(stderr, "exit\n");
+  yk_location_drop(loc);
+  yk_mt_shutdown(mt);
+  return (EXIT_SUCCESS);
+}
@@ -0,0 +1,56 @@
+// Run-time:
+//   env-var: YKD_SERIALISE_COMPILATION=1
+//   env-var: YKD_LOG=4
+//   stderr:
+//     yk-tracing: start-tracing
+//     4
+//     yk-tracing: stop-tracing
+//     3
+//     yk-execution: enter-jit-code
+//     2
+//     1
+//     yk-execution: deoptimise ...
+//     yk-tracing: trace-invalidated: ...
+//     yk-tracing: start-tracing
+//     4
+//     yk-tracing: stop-tracing
+//     3
+//     yk-execution: enter-jit-code
+//     2
+//     1
+//     yk-execution: deoptimise ...
+//     exit
+
+// Check that invalidating a location causes it to be retraced.
+
+#include <assert.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+__attribute__((noinline)) void loop(YkMT *mt, YkLocation *loc) {
+  int i = 4;
+  NOOPT_VAL(i);
+  while (i > 0) {
+    yk_mt_control_point(mt, loc);
+    fprintf(stderr, "%d\n", i);
+    i--;
+  }
+}
+
+int main(int argc, char **argv) {
+  YkMT *mt = yk_mt_new(NULL);
+  yk_mt_hot_threshold_set(mt, 0);
+  YkLocation loc = yk_location_new();
+
+  NOOPT_VAL(loc);
+  loop(mt, &loc);
+  yk_location_invalidate(&loc);
+  loop(mt, &loc);
+  fprintf(stderr, "exit\n");
+  yk_location_drop(loc);
+  yk_mt_shutdown(mt);
+  return (EXIT_SUCCESS);
+}
@@ -0,0 +1,43 @@
+// Run-time:
+//   env-var: YKD_SERIALISE_COMPILATION=1
+//   stderr:
+//     yk-tracing: start-tracing
+//     4
+//     yk-tracing: stop-tracing
+//     3
+//     yk-execution: enter-jit-code
+//     2
+//     1
+//     yk-execution: deoptimise ...
+//     exit
+
+// Check that a `YkMT` can be configured with a `YkMTConfig`, and that the
+// config's settings override the environment.
+
+#include <assert.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <yk.h>
+#include <yk_testing.h>
+
+int main(int argc, char **argv) {
+  YkMTConfig *config = yk_mtconfig_new(NULL);
+  yk_mtconfig_hot_threshold_set(config, 0);
+  yk_mtconfig_log_set(config, "-", 4);
+  YkMT *mt = yk_mt_new_with_config(config, NULL);
+  YkLocation loc = yk_location_new();
+
+  int i = 4;
+  NOOPT_VAL(loc);
+  NOOPT_VAL(i);
+  while (i > 0) {
+    yk_mt_control_point(mt, &loc);
+    fprintf(stderr, "%d\n", i);
+    i--;
+  }
+  fprintf(stderr, "exit\n");
+  yk_location_drop(loc);
+  yk_mt_shutdown(mt);
+  return (EXIT_SUCCESS);
+}
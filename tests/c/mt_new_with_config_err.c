@@ -0,0 +1,35 @@
+// Run-time:
+//   stderr:
+//     The number of compiler threads must be >= 1
+//     Invalid tracer: ...
+//     exit
+
+// Check that invalid `YkMTConfig` settings are reported by
+// `yk_mt_new_with_config` rather than aborting the process.
+
+#include <assert.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <yk.h>
+
+int main(int argc, char **argv) {
+  char *err_msg = NULL;
+  YkMTConfig *config = yk_mtconfig_new(NULL);
+  yk_mtconfig_compiler_threads_set(config, 0);
+  // Only the first error is reported.
+  yk_mtconfig_swt_max_trace_len_set(config, 0);
+  YkMT *mt = yk_mt_new_with_config(config, &err_msg);
+  assert(mt == NULL);
+  fprintf(stderr, "%s\n", err_msg);
+  free(err_msg);
+
+  config = yk_mtconfig_new(NULL);
+  yk_mtconfig_tracer_set(config, "\xff");
+  mt = yk_mt_new_with_config(config, &err_msg);
+  assert(mt == NULL);
+  fprintf(stderr, "%s\n", err_msg);
+  free(err_msg);
+
+  fprintf(stderr, "exit\n");
+  return (EXIT_SUCCESS);
+}
@@ -5,8 +5,10 @@
 //     {
 //       ...
 //       "trace_executions": 1,
+//       "traces_compiled_cancelled": 0,
 //       "traces_compiled_err": 0,
 //       "traces_compiled_ok": 1,
+//       "traces_invalidated": 0,
 //       "traces_recorded_err": 0,
 //       "traces_recorded_ok": 1
 //       ...
@@ -7,8 +7,10 @@
 //     {
 //       ...
 //       "trace_executions": 0,
+//       "traces_compiled_cancelled": 0,
 //       "traces_compiled_err": 1,
 //       "traces_compiled_ok": 0,
+//       "traces_invalidated": 0,
 //       "traces_recorded_err": 0,
 //       "traces_recorded_ok": 1
 //       ...
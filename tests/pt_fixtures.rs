@@ -0,0 +1,55 @@
+//! Decode the saved Intel PT traces in `pt_fixtures/` and check that the decoder's output hasn't
+//! changed.
+//!
+//! Each directory `pt_fixtures/<name>` contains a binary `<name>`, a trace `<name>.pt` recorded
+//! from it, and the expected output of `decode_pt` for that trace: `blocks` (with `--blocks`) and
+//! `actions` (without). See `pt_fixtures/README.md`.
+
+use std::{fs, path::Path, process::Command};
+
+/// Run `decode_pt` on the fixture in `dir`, returning its stdout.
+fn decode(dir: &Path, extra_args: &[&str]) -> String {
+    let name = dir.file_name().unwrap();
+    let out = Command::new(env!("CARGO_BIN_EXE_decode_pt"))
+        .args(extra_args)
+        .arg(dir.join(name))
+        .arg(dir.join(name).with_extension("pt"))
+        .output()
+        .unwrap();
+    if !out.status.success() {
+        panic!(
+            "decode_pt failed on {}:\n{}",
+            dir.display(),
+            String::from_utf8_lossy(&out.stderr)
+        );
+    }
+    String::from_utf8(out.stdout).unwrap()
+}
+
+fn fixtures() -> Vec<std::path::PathBuf> {
+    let mut dirs = fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("pt_fixtures"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.is_dir())
+        .collect::<Vec<_>>();
+    dirs.sort();
+    assert!(!dirs.is_empty());
+    dirs
+}
+
+#[test]
+fn blocks() {
+    for dir in fixtures() {
+        let expected = fs::read_to_string(dir.join("blocks")).unwrap();
+        assert_eq!(decode(&dir, &["--blocks"]), expected, "{}", dir.display());
+    }
+}
+
+#[cfg(tracer_hwt)]
+#[test]
+fn actions() {
+    for dir in fixtures() {
+        let expected = fs::read_to_string(dir.join("actions")).unwrap();
+        assert_eq!(decode(&dir, &[]), expected, "{}", dir.display());
+    }
+}
@@ -0,0 +1,109 @@
+# A hand-written stand-in for a binary built by ykllvm: `f` loops twice,
+# calls `g`, and then calls `stop_tracing` (which has no blockmap entry, so is
+# "foreign" code). The `.llvm_bb_addr_map` section describes `f` and `g` in the
+# format that ykllvm emits.
+#
+# Build with:
+#   as loop.s -o loop.o && ld -o loop loop.o && rm loop.o
+
+	.text
+	.globl _start
+_start:
+	call f
+	mov $60, %eax
+	xor %edi, %edi
+	syscall
+
+	.type f, @function
+f:
+.Lf0:
+	mov $2, %ecx
+.Lf1:
+	dec %ecx
+.Lf2:
+	test %ecx, %ecx
+	jnz .Lf1
+.Lf3:
+.Lf3_call:
+	call g
+.Lf3_ret:
+	jmp .Lf4
+.Lf4:
+.Lf4_call:
+	call stop_tracing
+.Lf4_ret:
+	ret
+.Lf_end:
+	.size f, .-f
+
+	.type g, @function
+g:
+.Lg0:
+	ret
+.Lg_end:
+	.size g, .-g
+
+	.type stop_tracing, @function
+stop_tracing:
+	nop
+	ret
+	.size stop_tracing, .-stop_tracing
+
+# Each function's entry is: version, features, function address, number of
+# blocks. Each block's entry is: ID, offset from the end of the previous block,
+# size, metadata, the indices of the corresponding IR blocks, the calls in the
+# block (call address, return address, target address, is-direct), and the
+# kind of successor (0: unconditional; 1: conditional; 2: return; 3: dynamic)
+# followed by its target address(es).
+	.section .llvm_bb_addr_map, "", @progbits
+	.byte 2, 0
+	.quad f
+	.uleb128 5
+	# .Lf0: falls through to .Lf1.
+	.uleb128 0, .Lf0-f, .Lf1-.Lf0
+	.byte 0
+	.uleb128 1, 0
+	.uleb128 0
+	.byte 0
+	.quad .Lf1
+	# .Lf1: falls through to .Lf2.
+	.uleb128 1, 0, .Lf2-.Lf1
+	.byte 0
+	.uleb128 1, 1
+	.uleb128 0
+	.byte 0
+	.quad .Lf2
+	# .Lf2: branches back to .Lf1 or falls through to .Lf3.
+	.uleb128 2, 0, .Lf3-.Lf2
+	.byte 0
+	.uleb128 1, 2
+	.uleb128 0
+	.byte 1, 1
+	.quad .Lf1, .Lf3
+	# .Lf3: calls `g` then jumps to .Lf4.
+	.uleb128 3, 0, .Lf4-.Lf3
+	.byte 0
+	.uleb128 1, 3
+	.uleb128 1
+	.quad .Lf3_call, .Lf3_ret, g
+	.byte 1
+	.byte 0
+	.quad .Lf4
+	# .Lf4: calls `stop_tracing` then returns.
+	.uleb128 4, 0, .Lf_end-.Lf4
+	.byte 0
+	.uleb128 1, 4
+	.uleb128 1
+	.quad .Lf4_call, .Lf4_ret, stop_tracing
+	.byte 1
+	.byte 2
+
+	.byte 2, 0
+	.quad g
+	.uleb128 1
+	# .Lg0: returns.
+	.uleb128 0, 0, .Lg_end-.Lg0
+	.byte 0
+	.uleb128 1, 0
+	.uleb128 0
+	.byte 2
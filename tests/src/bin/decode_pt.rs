@@ -0,0 +1,79 @@
+//! A tool to decode an Intel PT trace saved by a previous run.
+
+use clap::Parser;
+use hwtracer::{
+    offline::{read_trace, OfflineImage},
+    Trace,
+};
+use std::{error::Error, path::PathBuf, process::exit};
+
+/// Decode an Intel PT trace saved by a previous run, printing one item per line. This does not
+/// need PT hardware, so decoder bugs can be reproduced on any machine.
+#[derive(Parser, Debug)]
+#[command(about, long_about = None)]
+struct Args {
+    /// The binary which recorded the trace. It must have been loaded at the addresses it was
+    /// linked at.
+    exe: PathBuf,
+
+    /// The trace: either a `perf.data` file recorded with `perf record -e intel_pt//u` or a raw
+    /// stream of PT packets.
+    trace: PathBuf,
+
+    /// Another object which was loaded at virtual address `BASE` (in hex) when the trace was
+    /// recorded. May be repeated.
+    #[arg(long = "object", value_name = "PATH@BASE", value_parser = parse_object)]
+    objects: Vec<(PathBuf, usize)>,
+
+    /// Print the machine blocks from the decoder rather than the trace actions they map to.
+    #[arg(long)]
+    blocks: bool,
+}
+
+fn parse_object(s: &str) -> Result<(PathBuf, usize), String> {
+    let (path, base) = s
+        .rsplit_once('@')
+        .ok_or_else(|| format!("expected PATH@BASE, got '{s}'"))?;
+    let base = usize::from_str_radix(base.trim_start_matches("0x"), 16)
+        .map_err(|e| format!("invalid base '{base}': {e}"))?;
+    Ok((PathBuf::from(path), base))
+}
+
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = decode(&args) {
+        eprintln!("{e}");
+        exit(1);
+    }
+}
+
+fn decode(args: &Args) -> Result<(), Box<dyn Error>> {
+    let mut image = OfflineImage::new(&args.exe)?;
+    for (path, base) in &args.objects {
+        image.add_object(path, *base)?;
+    }
+    // Traces and the items decoded from them borrow from the image for their whole lifetime.
+    let image = Box::leak(Box::new(image));
+    let trace = image.trace(&read_trace(&args.trace)?)?;
+    if args.blocks {
+        for b in trace.iter_blocks() {
+            println!("{:?}", b?);
+        }
+        Ok(())
+    } else {
+        print_actions(trace)
+    }
+}
+
+#[cfg(tracer_hwt)]
+fn print_actions(trace: Box<dyn Trace>) -> Result<(), Box<dyn Error>> {
+    for ta in ykrt::trace::map_hwt_trace(trace)? {
+        println!("{ta:?}");
+    }
+    Ok(())
+}
+
+#[cfg(not(tracer_hwt))]
+fn print_actions(_trace: Box<dyn Trace>) -> Result<(), Box<dyn Error>> {
+    Err("this build of yk has no hardware tracer to map the trace with: try --blocks".into())
+}
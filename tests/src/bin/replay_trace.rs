@@ -0,0 +1,50 @@
+//! A tool to compile a trace dumped with `YKD_TRACE_DUMP` again.
+
+use clap::Parser;
+use std::{
+    env,
+    ffi::OsString,
+    path::PathBuf,
+    process::{exit, Command},
+};
+use tests::replay_trace::REPLAY_TRACE_ENV;
+
+/// Compile a trace dumped with `YKD_TRACE_DUMP` again, without running the interpreter program
+/// that recorded it. `YKD_*` environment variables (e.g. `YKD_LOG_IR`) are honoured.
+#[derive(Parser, Debug)]
+#[command(about, long_about = None)]
+struct Args {
+    /// The interpreter binary which recorded the trace.
+    exe: PathBuf,
+
+    /// The dumped trace.
+    trace: PathBuf,
+}
+
+fn main() {
+    let args = Args::parse();
+    // The trace is compiled by `libtests.so` (see `tests::replay_trace`), which cargo builds
+    // alongside this binary.
+    let lib = env::current_exe()
+        .unwrap()
+        .with_file_name(format!("libtests{}", env::consts::DLL_SUFFIX));
+    if !lib.exists() {
+        eprintln!("Can't find {}", lib.display());
+        exit(1);
+    }
+    let mut preload = OsString::from(lib);
+    if let Some(x) = env::var_os("LD_PRELOAD") {
+        preload.push(":");
+        preload.push(x);
+    }
+    let status = Command::new(&args.exe)
+        .env("LD_PRELOAD", preload)
+        .env(REPLAY_TRACE_ENV, &args.trace)
+        .env_remove("YKD_TRACE_DUMP")
+        .status()
+        .unwrap_or_else(|e| {
+            eprintln!("Can't run {}: {e}", args.exe.display());
+            exit(1);
+        });
+    exit(status.code().unwrap_or(1));
+}
@@ -1,4 +1,5 @@
 mod hwtracer_ykpt;
+pub mod replay_trace;
 
 use std::{
     collections::HashMap,
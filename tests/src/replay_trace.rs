@@ -0,0 +1,40 @@
+#![cfg(target_os = "linux")]
+
+//! Support for the `replay_trace` tool.
+//!
+//! A dumped trace can only be compiled inside the interpreter binary which recorded it, since
+//! compilation needs that binary's AOT IR and symbols. `replay_trace` therefore runs the
+//! interpreter with this library preloaded (via `LD_PRELOAD`) and [REPLAY_TRACE_ENV] set to the
+//! dumped trace. The constructor below then compiles the trace with [MT::replay_trace] and exits
+//! before any of the interpreter program is run.
+
+use std::{env, path::Path, process};
+use ykrt::MT;
+
+/// The environment variable which tells [replay_trace] which dumped trace to compile.
+pub const REPLAY_TRACE_ENV: &str = "__YK_REPLAY_TRACE";
+
+#[used]
+#[link_section = ".init_array"]
+static REPLAY_TRACE: extern "C" fn() = replay_trace;
+
+/// If [REPLAY_TRACE_ENV] is set, compile the trace it names and exit, with a non-zero exit code if
+/// the trace could not be compiled.
+extern "C" fn replay_trace() {
+    let Some(path) = env::var_os(REPLAY_TRACE_ENV) else {
+        return;
+    };
+    let path = Path::new(&path);
+    let r = MT::new().and_then(|mt| {
+        let r = mt.replay_trace(path);
+        mt.shutdown();
+        r
+    });
+    match r {
+        Ok(()) => process::exit(0),
+        Err(e) => {
+            eprintln!("Can't replay trace {}: {e}", path.display());
+            process::exit(1);
+        }
+    }
+}
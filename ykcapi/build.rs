@@ -5,6 +5,10 @@ pub fn main() {
     match env::var("YKB_TRACER") {
         Ok(ref tracer) if tracer == "swt" => println!("cargo::rustc-cfg=tracer_swt"),
         Ok(ref tracer) if tracer == "hwt" => println!("cargo::rustc-cfg=tracer_hwt"),
+        Ok(ref tracer) if tracer == "hwt,swt" => {
+            println!("cargo::rustc-cfg=tracer_hwt");
+            println!("cargo::rustc-cfg=tracer_swt");
+        }
         #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
         Err(env::VarError::NotPresent) => println!("cargo::rustc-cfg=tracer_hwt"),
         #[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
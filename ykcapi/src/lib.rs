@@ -14,36 +14,232 @@
 
 #![allow(clippy::missing_safety_doc)]
 
-#[cfg(feature = "ykd")]
-use std::ffi::CStr;
 use std::{
-    ffi::{c_char, c_void, CString},
+    error::Error,
+    ffi::{c_char, c_void, CStr, CString},
     mem::forget,
     ptr,
     sync::Arc,
 };
-use ykrt::{HotThreshold, Location, MT};
+use ykrt::{HotThreshold, Location, MTConfig, MT};
 
 #[no_mangle]
 pub unsafe extern "C" fn yk_mt_new(err_msg: *mut *const c_char) -> *const MT {
     match MT::new() {
         Ok(mt) => Arc::into_raw(mt),
+        Err(e) => unsafe { set_err_msg(err_msg, e) },
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn yk_mt_new_with_config(
+    config: *mut YkMTConfig,
+    err_msg: *mut *const c_char,
+) -> *const MT {
+    let config = unsafe { Box::from_raw(config) };
+    if let Some(e) = config.err {
+        return unsafe { set_err_msg(err_msg, e.into()) };
+    }
+    match MT::with_config(config.config) {
+        Ok(mt) => Arc::into_raw(mt),
+        Err(e) => unsafe { set_err_msg(err_msg, e) },
+    }
+}
+
+/// If `err_msg` is null, panic with `e`; otherwise store a `malloc`d copy of `e` in `*err_msg`.
+/// Always returns a null pointer.
+unsafe fn set_err_msg<T>(err_msg: *mut *const c_char, e: Box<dyn Error>) -> *mut T {
+    if err_msg.is_null() {
+        panic!("{}", e);
+    }
+    let s = CString::new(e.to_string()).unwrap();
+    let b = s.to_bytes_with_nul();
+    let buf = unsafe { libc::malloc(b.len()) as *mut i8 };
+    unsafe {
+        buf.copy_from(b.as_ptr() as *const i8, b.len());
+    }
+    unsafe { *err_msg = buf };
+    ptr::null_mut()
+}
+
+/// The C API's view of an [MTConfig]. The `yk_mtconfig_*` setters can't report errors directly
+/// (and must not panic), so the first error they encounter is stored here and reported by
+/// [yk_mt_new_with_config].
+pub struct YkMTConfig {
+    config: MTConfig,
+    err: Option<String>,
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn yk_mtconfig_new(err_msg: *mut *const c_char) -> *mut YkMTConfig {
+    match MTConfig::from_env() {
+        Ok(config) => Box::into_raw(Box::new(YkMTConfig { config, err: None })),
+        Err(e) => unsafe { set_err_msg(err_msg, e) },
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn yk_mtconfig_drop(config: *mut YkMTConfig) {
+    drop(unsafe { Box::from_raw(config) });
+}
+
+/// Replace the [MTConfig] pointed to by `config` with the result of calling `f` on it. If `f`
+/// returns `Err`, the [MTConfig] is left unchanged and the error is reported by
+/// [yk_mt_new_with_config].
+unsafe fn mtconfig_update(
+    config: *mut YkMTConfig,
+    f: impl FnOnce(MTConfig) -> Result<MTConfig, String>,
+) {
+    let config = unsafe { &mut *config };
+    match f(config.config.clone()) {
+        Ok(new) => config.config = new,
         Err(e) => {
-            if err_msg.is_null() {
-                panic!("{}", e);
-            }
-            let s = CString::new(e.to_string()).unwrap();
-            let b = s.to_bytes_with_nul();
-            let buf = unsafe { libc::malloc(b.len()) as *mut i8 };
-            unsafe {
-                buf.copy_from(b.as_ptr() as *const i8, b.len());
-            }
-            unsafe { *err_msg = buf };
-            ptr::null_mut()
+            config.err.get_or_insert(e);
         }
     }
 }
 
+/// Convert the C string `s`, the `what` argument of a setter, to a `&str`, or `None` if `s` is
+/// null.
+unsafe fn opt_str_arg<'a>(s: *const c_char, what: &str) -> Result<Option<&'a str>, String> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    unsafe { CStr::from_ptr(s) }
+        .to_str()
+        .map(Some)
+        .map_err(|e| format!("Invalid {what}: {e}"))
+}
+
+/// Convert the C string `s`, the `what` argument of a setter, to a `&str`.
+unsafe fn str_arg<'a>(s: *const c_char, what: &str) -> Result<&'a str, String> {
+    unsafe { opt_str_arg(s, what) }?.ok_or_else(|| format!("Invalid {what}: NULL"))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn yk_mtconfig_hot_threshold_set(
+    config: *mut YkMTConfig,
+    hot_threshold: HotThreshold,
+) {
+    unsafe { mtconfig_update(config, |c| Ok(c.hot_threshold(hot_threshold))) };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn yk_mtconfig_sidetrace_threshold_set(
+    config: *mut YkMTConfig,
+    hot_threshold: HotThreshold,
+) {
+    unsafe { mtconfig_update(config, |c| Ok(c.sidetrace_threshold(hot_threshold))) };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn yk_mtconfig_compiler_threads_set(config: *mut YkMTConfig, threads: usize) {
+    unsafe {
+        mtconfig_update(config, |c| {
+            if threads < 1 {
+                return Err("The number of compiler threads must be >= 1".to_owned());
+            }
+            Ok(c.compiler_threads(threads))
+        })
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn yk_mtconfig_optimise_set(config: *mut YkMTConfig, opt: bool) {
+    unsafe { mtconfig_update(config, |c| Ok(c.optimise(opt))) };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn yk_mtconfig_tracer_set(config: *mut YkMTConfig, tracer: *const c_char) {
+    unsafe { mtconfig_update(config, |c| Ok(c.tracer(opt_str_arg(tracer, "tracer")?))) };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn yk_mtconfig_swt_max_trace_len_set(config: *mut YkMTConfig, len: usize) {
+    unsafe {
+        mtconfig_update(config, |c| {
+            if len < 1 {
+                return Err("The software tracer's maximum trace length must be >= 1".to_owned());
+            }
+            Ok(c.swt_max_trace_len(len))
+        })
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn yk_mtconfig_allocator_add(config: *mut YkMTConfig, name: *const c_char) {
+    unsafe {
+        mtconfig_update(
+            config,
+            |c| Ok(c.allocator(str_arg(name, "allocator name")?)),
+        )
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn yk_mtconfig_foreground_compilation_set(
+    config: *mut YkMTConfig,
+    foreground_compilation: bool,
+) {
+    unsafe {
+        mtconfig_update(config, |c| {
+            Ok(c.foreground_compilation(foreground_compilation))
+        })
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn yk_mtconfig_log_set(
+    config: *mut YkMTConfig,
+    path: *const c_char,
+    level: u8,
+) {
+    unsafe { mtconfig_update(config, |c| Ok(c.log(str_arg(path, "log path")?, level))) };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn yk_mtconfig_log_ir_set(
+    config: *mut YkMTConfig,
+    path: *const c_char,
+    phases: *const c_char,
+) {
+    unsafe {
+        mtconfig_update(config, |c| {
+            Ok(c.log_ir(
+                str_arg(path, "IR log path")?,
+                str_arg(phases, "IR log phases")?,
+            ))
+        })
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn yk_mtconfig_log_stats_set(config: *mut YkMTConfig, path: *const c_char) {
+    unsafe {
+        mtconfig_update(config, |c| {
+            Ok(c.log_stats(opt_str_arg(path, "statistics path")?))
+        })
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn yk_mtconfig_trace_cache_set(config: *mut YkMTConfig, dir: *const c_char) {
+    unsafe {
+        mtconfig_update(config, |c| {
+            Ok(c.trace_cache(opt_str_arg(dir, "trace cache directory")?))
+        })
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn yk_mtconfig_trace_dump_set(config: *mut YkMTConfig, dir: *const c_char) {
+    unsafe {
+        mtconfig_update(config, |c| {
+            Ok(c.trace_dump(opt_str_arg(dir, "trace dump directory")?))
+        })
+    };
+}
+
 /// Shutdown this MT instance. Will panic if an error is detected when doing so.
 #[no_mangle]
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
@@ -150,6 +346,35 @@ pub unsafe extern "C" fn yk_mt_sidetrace_threshold_set(mt: *const MT, hot_thresh
     forget(arc);
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn yk_mt_compiler_threads_set(mt: *const MT, compiler_threads: usize) {
+    let arc = unsafe { Arc::from_raw(mt) };
+    arc.set_compiler_threads(compiler_threads);
+    forget(arc);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn yk_mt_compile_queue_len(mt: *const MT) -> usize {
+    let arc = unsafe { Arc::from_raw(mt) };
+    let len = arc.compile_queue_len();
+    forget(arc);
+    len
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn yk_mt_invalidate_all(mt: *const MT) {
+    let arc = unsafe { Arc::from_raw(mt) };
+    arc.invalidate_all();
+    forget(arc);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn yk_assumption_changed(mt: *const MT, ptr: *const c_void) {
+    let arc = unsafe { Arc::from_raw(mt) };
+    arc.assumption_changed(ptr);
+    forget(arc);
+}
+
 #[no_mangle]
 pub extern "C" fn yk_location_new() -> Location {
     Location::new()
@@ -164,6 +389,14 @@ pub unsafe extern "C" fn yk_location_set_debug_str(loc: *mut Location, s: *const
     loc.set_hl_debug_str(s);
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn yk_location_invalidate(loc: *mut Location) {
+    let loc = unsafe { &*loc };
+    if !loc.is_null() {
+        loc.invalidate();
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn yk_location_null() -> Location {
     Location::null()
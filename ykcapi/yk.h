@@ -39,6 +39,80 @@ typedef struct YkMT YkMT;
 //       2. `yk_mt_new` will return `NULL`.
 YkMT *yk_mt_new(char **err_msg);
 
+// The configuration of a `YkMT` instance.
+typedef struct YkMTConfig YkMTConfig;
+
+// Create a new `YkMTConfig` whose settings are taken from the `YK_*` and
+// `YKD_*` environment variables, or yk's defaults for those environment
+// variables which are not set. Each setting can then be overridden with the
+// `yk_mtconfig_*_set` functions. `err_msg` is handled as in `yk_mt_new`.
+//
+// The `YkMTConfig` must either be passed to `yk_mt_new_with_config` or freed
+// with `yk_mtconfig_drop`.
+YkMTConfig *yk_mtconfig_new(char **err_msg);
+
+// Free a `YkMTConfig` which has not been passed to `yk_mt_new_with_config`.
+void yk_mtconfig_drop(YkMTConfig *);
+
+// Set the threshold at which `YkLocation`'s are considered hot (as
+// `YK_HOT_THRESHOLD`).
+void yk_mtconfig_hot_threshold_set(YkMTConfig *, YkHotThreshold);
+
+// Set the threshold at which guard failures are considered hot (as
+// `YK_SIDETRACE_THRESHOLD`).
+void yk_mtconfig_sidetrace_threshold_set(YkMTConfig *, YkHotThreshold);
+
+// Set the maximum number of threads used to compile traces. Must be at least
+// 1.
+void yk_mtconfig_compiler_threads_set(YkMTConfig *, size_t);
+
+// Turn trace optimisation on or off (as `YKD_OPT`).
+void yk_mtconfig_optimise_set(YkMTConfig *, bool);
+
+// Record traces with the tracer `tracer` (`hwt` or `swt`) (as `YK_TRACER`). If
+// `tracer` is `NULL`, the hardware tracer is used if it is available, and the
+// software tracer otherwise.
+void yk_mtconfig_tracer_set(YkMTConfig *, const char *tracer);
+
+// Set the maximum number of basic blocks the software tracer will record in a
+// single trace (as `YK_SWT_MAX_TRACE_LEN`). Must be at least 1.
+void yk_mtconfig_swt_max_trace_len_set(YkMTConfig *, size_t);
+
+// Treat the function `name` as an allocator (as `YK_ALLOCATORS`).
+void yk_mtconfig_allocator_add(YkMTConfig *, const char *name);
+
+// If `true`, compile traces on the thread which recorded them, before
+// `yk_mt_control_point` returns (as `YK_FOREGROUND_COMPILATION`).
+void yk_mtconfig_foreground_compilation_set(YkMTConfig *, bool);
+
+// Log to `path` (`-` for stderr) at verbosity `level` (as `YKD_LOG`).
+void yk_mtconfig_log_set(YkMTConfig *, const char *path, uint8_t level);
+
+// Log the comma-separated IR phases `phases` to `path` (`-` for stderr) (as
+// `YKD_LOG_IR`). An empty `phases` turns IR logging off. IR is only logged if
+// yk was built with the "ykd" feature.
+void yk_mtconfig_log_ir_set(YkMTConfig *, const char *path,
+                            const char *phases);
+
+// Output statistics to `path` (`-` for stderr) (as `YKD_LOG_STATS`). If
+// `path` is `NULL`, statistics are not collected.
+void yk_mtconfig_log_stats_set(YkMTConfig *, const char *path);
+
+// Cache optimised traces in the directory `dir` (as `YK_TRACE_CACHE`). If
+// `dir` is `NULL`, traces are not cached.
+void yk_mtconfig_trace_cache_set(YkMTConfig *, const char *dir);
+
+// Dump recorded traces to the directory `dir` (as `YKD_TRACE_DUMP`). If `dir`
+// is `NULL`, traces are not dumped.
+void yk_mtconfig_trace_dump_set(YkMTConfig *, const char *dir);
+
+// Create a new `YkMT` instance configured by `config`. `config` is consumed
+// by this call (whether it succeeds or not) and must not be used again.
+// `err_msg` is handled as in `yk_mt_new`. If any of the `yk_mtconfig_*`
+// functions was passed an invalid value (e.g. a string which is not valid
+// UTF-8), the first such error is reported here.
+YkMT *yk_mt_new_with_config(YkMTConfig *config, char **err_msg);
+
 // Shutdown this MT instance. Will panic if an error is detected when doing so.
 // This function can be called more than once, but only the first call will
 // have observable behaviour.
@@ -57,6 +131,23 @@ void yk_mt_hot_threshold_set(YkMT *, YkHotThreshold);
 // Set the threshold at which guard failures are considered hot.
 void yk_mt_sidetrace_threshold_set(YkMT *, YkHotThreshold);
 
+// Set the maximum number of threads used to compile traces. Must be at least
+// 1. If this is lower than the number of compiler threads currently running,
+// the excess threads exit once they have finished their current compilation
+// job.
+void yk_mt_compiler_threads_set(YkMT *, size_t);
+
+// Return the number of compilation jobs waiting for a compiler thread. Note
+// that other threads may change this value at any time.
+size_t yk_mt_compile_queue_len(YkMT *);
+
+// Invalidate all of this `YkMT`'s compiled traces. Subsequent calls to
+// `yk_mt_control_point` will fall back to the interpreter until new traces
+// have been recorded and compiled. Traces which are currently being recorded
+// or compiled are discarded. Threads currently executing a compiled trace will
+// continue to do so until they next leave it (e.g. because a guard fails).
+void yk_mt_invalidate_all(YkMT *);
+
 // Create a new `YkLocation`.
 //
 // Note that a `YkLocation` created by this call must not simply be discarded:
@@ -74,6 +165,17 @@ YkLocation yk_location_new(void);
 // This function is only avaliable if yk was built with the "ykd" feature.
 YkLocation yk_location_set_debug_str(YkLocation *, char *);
 
+// Invalidate the compiled trace (if any) associated with a `YkLocation`, as
+// well as any traces that depend on it. This should be called when the
+// assumptions baked into that trace are no longer true (e.g. a global that the
+// trace relies upon has been redefined). Subsequent calls to
+// `yk_mt_control_point` with this `YkLocation` will fall back to the
+// interpreter and, in time, retrace it. If a trace starting at this
+// `YkLocation` is currently being recorded or compiled, it is discarded.
+// Calling this function on a `YkLocation` without a compiled or in-flight
+// trace, or on a `yk_location_null` value, is a no-op.
+void yk_location_invalidate(YkLocation *);
+
 // Create a new NULL-equivalent `Location`. Such a `YkLocation` denotes a point
 // in a program which can never contribute to a trace.
 YkLocation yk_location_null(void);
@@ -105,6 +207,21 @@ long long __yk_promote_c_long_long(long long);
 uintptr_t __yk_promote_usize(uintptr_t);
 void *__yk_promote_ptr(void *);
 
+// Read the "quasi-constant" memory cell `ptr`. A quasi-constant is a value
+// which is rarely, if ever, changed (e.g. an interpreter global that is not
+// normally redefined). When this function is called during trace recording,
+// the value read is baked into the trace as a constant without a guard:
+// instead, the trace depends upon `ptr`. Every time the interpreter writes to
+// `ptr`, it must then call `yk_assumption_changed`, which invalidates all
+// traces which depend upon `ptr`. Failing to do so is undefined behaviour.
+uintptr_t yk_assume_constant(const uintptr_t *ptr);
+
+// Inform yk that the quasi-constant memory cell `ptr` (see
+// `yk_assume_constant`) has been written to, invalidating all traces which
+// depend upon it. Threads currently executing such a trace will continue to
+// do so until they next leave it (e.g. because a guard fails).
+void yk_assumption_changed(YkMT *, const void *ptr);
+
 /// Associate a UTF-8 compatible string to the next instruction to be traced.
 /// The string will be copied by this function, so callers can safely reuse the
 /// memory after this call has completed.
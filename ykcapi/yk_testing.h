@@ -16,6 +16,10 @@ typedef struct {
   uint64_t traces_compiled_ok;
   /// How many traces were compiled unsuccessfully?
   uint64_t traces_compiled_err;
+  /// How many trace compilations were cancelled?
+  uint64_t traces_compiled_cancelled;
+  /// How many compiled traces have been invalidated?
+  uint64_t traces_invalidated;
   /// How many times have traces been executed? Note that the same trace can
   /// count arbitrarily many times to this.
   uint64_t trace_executions;
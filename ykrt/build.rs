@@ -21,6 +21,11 @@ pub fn main() {
         #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
         Ok(ref tracer) if tracer == "hwt" => println!("cargo::rustc-cfg=tracer_hwt"),
         #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+        Ok(ref tracer) if tracer == "hwt,swt" => {
+            println!("cargo::rustc-cfg=tracer_hwt");
+            println!("cargo::rustc-cfg=tracer_swt");
+        }
+        #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
         Err(env::VarError::NotPresent) => println!("cargo::rustc-cfg=tracer_hwt"),
         #[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
         Err(env::VarError::NotPresent) => println!("cargo::rustc-cfg=tracer_swt"),
@@ -0,0 +1,176 @@
+//! Quasi-constant assumptions.
+//!
+//! An interpreter can tell yk that a memory cell is "quasi-constant" (i.e. it is rarely, if ever,
+//! written to) via `yk_assume_constant`. When such a call is recorded in a trace, the value of the
+//! cell at recording time is baked into the compiled trace as a constant *without* a guard.
+//! Instead, the trace is recorded as depending on that cell: when the interpreter later signals
+//! (via `yk_assumption_changed`) that the cell has been written to, all traces that depend on it
+//! are invalidated.
+//!
+//! Since a cell may be written to while a trace that depends on it is being recorded or compiled,
+//! we keep a global "epoch" which is incremented on every change. A trace records the epoch when
+//! it starts being recorded: if any of the cells it depends upon have changed since then, the
+//! trace is discarded rather than being made available for execution. The epoch is also
+//! incremented when all traces are invalidated (see [crate::mt::MT::invalidate_all]), which
+//! discards every trace that started being recorded before then.
+
+use crate::mt::TraceId;
+use parking_lot::Mutex;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+pub(crate) struct Assumptions {
+    inner: Mutex<AssumptionsInner>,
+}
+
+struct AssumptionsInner {
+    /// Incremented every time a quasi-constant cell changes or all traces are invalidated.
+    epoch: u64,
+    /// The epoch of the most recent call to [Assumptions::invalidate_all].
+    all_changed: u64,
+    /// Maps quasi-constant cells to the epoch of their most recent change. Only changes which
+    /// happened after the oldest [RecordingEpoch] still alive can affect [Assumptions::register],
+    /// so older changes are forgotten.
+    changed: HashMap<usize, u64>,
+    /// Maps each quasi-constant cell to the traces which assume that its value will not change.
+    dependents: HashMap<usize, Vec<TraceId>>,
+    /// Maps each trace registered with [Assumptions::register] to the cells it assumes will not
+    /// change, so that [Assumptions::remove] can remove it from `dependents`.
+    assumed: HashMap<TraceId, Vec<usize>>,
+    /// How many [RecordingEpoch]s are alive for each epoch.
+    in_flight: BTreeMap<u64, usize>,
+}
+
+impl Assumptions {
+    pub(crate) fn new() -> Self {
+        Self {
+            inner: Mutex::new(AssumptionsInner {
+                epoch: 0,
+                all_changed: 0,
+                changed: HashMap::new(),
+                dependents: HashMap::new(),
+                assumed: HashMap::new(),
+                in_flight: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Return the current epoch. This should be called before a trace starts being recorded, and
+    /// passed to [Self::register] when that trace has been compiled: until the returned value is
+    /// dropped, changes made since that epoch are remembered.
+    pub(crate) fn start_recording(self: &Arc<Self>) -> RecordingEpoch {
+        let mut lk = self.inner.lock();
+        let epoch = lk.epoch;
+        *lk.in_flight.entry(epoch).or_default() += 1;
+        RecordingEpoch {
+            assumptions: Arc::clone(self),
+            epoch,
+        }
+    }
+
+    /// Record that the trace `ctrid` assumes that the cells `ptrs` have not changed since `epoch`.
+    /// Returns `false` (and records nothing) if any of `ptrs` have changed, or
+    /// [Self::invalidate_all] has been called, since `epoch`, in which case the trace must be
+    /// discarded.
+    pub(crate) fn register(&self, ctrid: TraceId, ptrs: &[usize], epoch: &RecordingEpoch) -> bool {
+        let epoch = epoch.epoch;
+        let mut lk = self.inner.lock();
+        if lk.all_changed > epoch
+            || ptrs
+                .iter()
+                .any(|x| lk.changed.get(x).map(|x| *x > epoch).unwrap_or(false))
+        {
+            return false;
+        }
+        if ptrs.is_empty() {
+            return true;
+        }
+        for ptr in ptrs {
+            let deps = lk.dependents.entry(*ptr).or_default();
+            if !deps.contains(&ctrid) {
+                deps.push(ctrid);
+            }
+        }
+        lk.assumed.insert(ctrid, ptrs.to_vec());
+        true
+    }
+
+    /// Forget the assumptions of the trace `ctrid`, which will never be executed again.
+    pub(crate) fn remove(&self, ctrid: TraceId) {
+        let mut lk = self.inner.lock();
+        for ptr in lk.assumed.remove(&ctrid).unwrap_or_default() {
+            if let Some(deps) = lk.dependents.get_mut(&ptr) {
+                deps.retain(|x| *x != ctrid);
+                if deps.is_empty() {
+                    lk.dependents.remove(&ptr);
+                }
+            }
+        }
+    }
+
+    /// Record that the cell `ptr` has changed, returning the [TraceId]s of the traces which
+    /// assumed that it would not (and which therefore must be invalidated).
+    pub(crate) fn changed(&self, ptr: usize) -> Vec<TraceId> {
+        let mut lk = self.inner.lock();
+        lk.epoch += 1;
+        let epoch = lk.epoch;
+        // If no trace is being recorded or compiled, no one can care about this change.
+        if !lk.in_flight.is_empty() {
+            lk.changed.insert(ptr, epoch);
+        }
+        lk.dependents.remove(&ptr).unwrap_or_default()
+    }
+
+    /// Record that all traces are being invalidated, so that traces which started being recorded
+    /// before now are discarded by [Self::register].
+    pub(crate) fn invalidate_all(&self) {
+        let mut lk = self.inner.lock();
+        lk.epoch += 1;
+        lk.all_changed = lk.epoch;
+    }
+
+    /// Called when a [RecordingEpoch] for `epoch` is dropped.
+    fn finish_recording(&self, epoch: u64) {
+        let mut lk = self.inner.lock();
+        let oldest = *lk.in_flight.first_key_value().unwrap().0;
+        let cnt = lk.in_flight.get_mut(&epoch).unwrap();
+        *cnt -= 1;
+        if *cnt > 0 {
+            return;
+        }
+        lk.in_flight.remove(&epoch);
+        if epoch == oldest {
+            // Changes at or before the oldest epoch still in flight can no longer cause a trace to
+            // be discarded.
+            match lk.in_flight.first_key_value().map(|(x, _)| *x) {
+                Some(oldest) => lk.changed.retain(|_, x| *x > oldest),
+                None => lk.changed.clear(),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn num_changed(&self) -> usize {
+        self.inner.lock().changed.len()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn num_dependents(&self) -> usize {
+        self.inner.lock().dependents.len()
+    }
+}
+
+/// The epoch at which a trace started being recorded (see [Assumptions::start_recording]). While
+/// this is alive, [Assumptions] remembers every change made since `epoch`.
+pub(crate) struct RecordingEpoch {
+    assumptions: Arc<Assumptions>,
+    epoch: u64,
+}
+
+impl Drop for RecordingEpoch {
+    fn drop(&mut self) {
+        self.assumptions.finish_recording(self.epoch);
+    }
+}
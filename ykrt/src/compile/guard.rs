@@ -2,10 +2,14 @@
 
 use crate::{
     compile::CompiledTrace,
+    job_queue::Hotness,
     mt::{AtomicTraceCompilationErrorThreshold, HotThreshold, MT},
 };
 use parking_lot::Mutex;
-use std::sync::{atomic::Ordering, Arc};
+use std::{
+    assert_matches::{assert_matches, debug_assert_matches},
+    sync::{atomic::Ordering, Arc},
+};
 
 /// Responsible for tracking how often a guard in a `CompiledTrace` fails. A hotness counter is
 /// incremented each time the matching guard failure in a `CompiledTrace` is triggered. Also stores
@@ -18,11 +22,12 @@ pub(crate) struct Guard {
     errors: AtomicTraceCompilationErrorThreshold,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 enum GuardState {
     Counting(HotThreshold),
-    /// We are either creating a trace or waiting for that trace to compile.
-    SideTracing,
+    /// We are either creating a trace or waiting for that trace to compile. The [Hotness] records
+    /// how often this guard has failed since then.
+    SideTracing(Hotness),
     Compiled,
     /// This guard encountered errors sufficiently often when tracing or compiling that we don't
     /// want to try again.
@@ -37,6 +42,14 @@ impl Guard {
         }
     }
 
+    /// Create a guard which will never be side-traced.
+    pub(crate) fn dont_trace() -> Self {
+        Self {
+            kind: Mutex::new(GuardState::DontTrace),
+            errors: AtomicTraceCompilationErrorThreshold::new(0),
+        }
+    }
+
     /// This guard has failed (i.e. evaluated to true/false when false/true was expected). Returns
     /// `true` if this guard has failed often enough to be worth side-tracing.
     pub fn inc_failed(&self, mt: &Arc<MT>) -> bool {
@@ -44,24 +57,40 @@ impl Guard {
         match &*lk {
             GuardState::Counting(x) => {
                 if x + 1 >= mt.sidetrace_threshold() {
-                    *lk = GuardState::SideTracing;
+                    *lk = GuardState::SideTracing(Hotness::default());
                     true
                 } else {
                     *lk = GuardState::Counting(x + 1);
                     false
                 }
             }
-            GuardState::SideTracing | GuardState::Compiled | GuardState::DontTrace => false,
+            GuardState::SideTracing(hotness) => {
+                hotness.inc();
+                false
+            }
+            GuardState::Compiled | GuardState::DontTrace => false,
+        }
+    }
+
+    /// Return the [Hotness] of the side-trace being created from this guard.
+    ///
+    /// # Panics
+    ///
+    /// If this guard is not currently side-tracing.
+    pub fn hotness(&self) -> Hotness {
+        match &*self.kind.lock() {
+            GuardState::SideTracing(hotness) => hotness.clone(),
+            _ => panic!(),
         }
     }
 
     /// Inform this guard that a trace started from it failed (either in tracing or compiling).
     pub fn trace_or_compile_failed(&self, mt: &Arc<MT>) {
         let mut lk = self.kind.lock();
-        if let GuardState::SideTracing = &*lk {
+        if let GuardState::SideTracing(_) = &*lk {
             let failures = self.errors.fetch_add(1, Ordering::Relaxed);
             if failures >= mt.trace_failure_threshold() {
-                assert_eq!(*lk, GuardState::SideTracing);
+                assert_matches!(*lk, GuardState::SideTracing(_));
                 *lk = GuardState::DontTrace;
             } else {
                 *lk = GuardState::Counting(0);
@@ -71,6 +100,14 @@ impl Guard {
         }
     }
 
+    /// Inform this guard that the compilation of a trace started from it was cancelled. Unlike
+    /// [Self::trace_or_compile_failed], this is not counted as an error.
+    pub fn trace_or_compile_cancelled(&self) {
+        let mut lk = self.kind.lock();
+        assert_matches!(*lk, GuardState::SideTracing(_));
+        *lk = GuardState::Counting(0);
+    }
+
     /// Stores a compiled side-trace inside this guard while patching a jump to the side-trace
     /// directly into the parent trace.
     /// * `ctr`: The compiled side-trace.
@@ -84,8 +121,15 @@ impl Guard {
     ) {
         let mut lk = self.kind.lock();
         let addr = ctr.entry();
+        if ctr.is_invalidated() {
+            // The side-trace was invalidated before we could link it in: since invalidation only
+            // unlinks traces from guards in the `Compiled` state, we mustn't patch the parent.
+            debug_assert_matches!(*lk, GuardState::SideTracing(_));
+            *lk = GuardState::Counting(0);
+            return;
+        }
         match &*lk {
-            GuardState::SideTracing => *lk = GuardState::Compiled,
+            GuardState::SideTracing(_) => *lk = GuardState::Compiled,
             _ => panic!(),
         }
         // It's important to patch the parent only after we've updated the `GuardState` to avoid a
@@ -94,6 +138,19 @@ impl Guard {
         // `set_ctr` which sets information required by deopt.
         parent.patch_guard(gidx, addr);
     }
+
+    /// The side-trace previously stored in this guard by [Self::set_ctr] has been invalidated:
+    /// patch the parent trace so that this guard deoptimises again, and start counting failures
+    /// from scratch so that a new side-trace can later be created.
+    /// * `parent`: The immediate parent of the side-trace.
+    /// * `gidx`: The guard id of the side-trace.
+    pub fn unset_ctr(&self, parent: &Arc<dyn CompiledTrace>, gidx: GuardIdx) {
+        let mut lk = self.kind.lock();
+        if let GuardState::Compiled = &*lk {
+            parent.unpatch_guard(gidx);
+            *lk = GuardState::Counting(0);
+        }
+    }
 }
 
 /// Identify a [Guard] within a trace.
@@ -26,7 +26,7 @@
 //! and/or `display()`). The same naming conventions are used in the textual AOT IR as in the
 //! textual JIT IR. See the docstring for the [super::jit_ir] module.
 
-use byteorder::{NativeEndian, ReadBytesExt};
+use super::int_signs::{SignExtend, Truncate};
 use deku::prelude::*;
 use std::{
     borrow::Cow,
@@ -127,7 +127,7 @@ impl Module {
         self.funcs
             .iter()
             .enumerate()
-            .find(|(_, f)| f.name == find_func)
+            .find(|(_, f)| f.name.as_bytes() == find_func.as_bytes())
             .map(|(f_idx, _)| FuncIdx(f_idx))
             .unwrap()
     }
@@ -175,9 +175,13 @@ impl Module {
         &self.funcs[idx]
     }
 
-    #[cfg(tracer_swt)]
-    pub(crate) fn funcs(&self) -> &TiVec<FuncIdx, Func> {
-        &self.funcs
+    /// Iterate over all of the [DeoptSafepoint]s in the module.
+    pub(crate) fn safepoints(&'static self) -> impl Iterator<Item = &'static DeoptSafepoint> {
+        self.funcs
+            .iter()
+            .flat_map(|func| func.bblocks.iter())
+            .flat_map(|bb| bb.insts.iter())
+            .filter_map(|inst| inst.safepoint())
     }
 
     /// Lookup a global variable declaration by its index.
@@ -366,6 +370,17 @@ fn map_to_string(v: Vec<u8>) -> Result<String, DekuError> {
     Err(DekuError::Parse(Cow::Borrowed("Couldn't map string")))
 }
 
+/// Helper function for deku `map` attribute: as [map_to_string], but keeping the string
+/// null-terminated.
+fn map_to_cstring(v: Vec<u8>) -> Result<CString, DekuError> {
+    if let Ok(x) = CString::from_vec_with_nul(v)
+        && x.to_str().is_ok()
+    {
+        return Ok(x);
+    }
+    Err(DekuError::Parse(Cow::Borrowed("Couldn't map string")))
+}
+
 /// Convert the bytes of a null-terminated string into a PathBuf.
 fn map_to_pathbuf(v: Vec<u8>) -> Result<PathBuf, DekuError> {
     Ok(PathBuf::from(map_to_string(v)?))
@@ -598,6 +613,74 @@ impl Predicate {
             | Self::SignedLessEqual => true,
         }
     }
+
+    /// Returns the predicate `p` such that `x self y` is equivalent to `y p x`.
+    pub(crate) fn swapped(&self) -> Self {
+        match self {
+            Self::Equal => Self::Equal,
+            Self::NotEqual => Self::NotEqual,
+            Self::UnsignedGreater => Self::UnsignedLess,
+            Self::UnsignedGreaterEqual => Self::UnsignedLessEqual,
+            Self::UnsignedLess => Self::UnsignedGreater,
+            Self::UnsignedLessEqual => Self::UnsignedGreaterEqual,
+            Self::SignedGreater => Self::SignedLess,
+            Self::SignedGreaterEqual => Self::SignedLessEqual,
+            Self::SignedLess => Self::SignedGreater,
+            Self::SignedLessEqual => Self::SignedGreaterEqual,
+        }
+    }
+
+    /// Returns the predicate `p` such that `x self y` is equivalent to `!(x p y)`.
+    pub(crate) fn inverted(&self) -> Self {
+        match self {
+            Self::Equal => Self::NotEqual,
+            Self::NotEqual => Self::Equal,
+            Self::UnsignedGreater => Self::UnsignedLessEqual,
+            Self::UnsignedGreaterEqual => Self::UnsignedLess,
+            Self::UnsignedLess => Self::UnsignedGreaterEqual,
+            Self::UnsignedLessEqual => Self::UnsignedGreater,
+            Self::SignedGreater => Self::SignedLessEqual,
+            Self::SignedGreaterEqual => Self::SignedLess,
+            Self::SignedLess => Self::SignedGreaterEqual,
+            Self::SignedLessEqual => Self::SignedGreater,
+        }
+    }
+
+    /// Returns whether, for all `x` and `y`, `x self y` being true implies that `x other y` is
+    /// also true.
+    pub(crate) fn implies(&self, other: Self) -> bool {
+        match self {
+            Self::Equal => matches!(
+                other,
+                Self::Equal
+                    | Self::UnsignedGreaterEqual
+                    | Self::UnsignedLessEqual
+                    | Self::SignedGreaterEqual
+                    | Self::SignedLessEqual
+            ),
+            Self::UnsignedGreater => matches!(
+                other,
+                Self::UnsignedGreater | Self::UnsignedGreaterEqual | Self::NotEqual
+            ),
+            Self::UnsignedLess => matches!(
+                other,
+                Self::UnsignedLess | Self::UnsignedLessEqual | Self::NotEqual
+            ),
+            Self::SignedGreater => matches!(
+                other,
+                Self::SignedGreater | Self::SignedGreaterEqual | Self::NotEqual
+            ),
+            Self::SignedLess => matches!(
+                other,
+                Self::SignedLess | Self::SignedLessEqual | Self::NotEqual
+            ),
+            Self::NotEqual
+            | Self::UnsignedGreaterEqual
+            | Self::UnsignedLessEqual
+            | Self::SignedGreaterEqual
+            | Self::SignedLessEqual => *self == other,
+        }
+    }
 }
 
 impl Display for Predicate {
@@ -1120,7 +1203,7 @@ impl Inst {
         // reconstitute a string from constant bytes though.
         match self {
             Self::Call { callee, .. } => {
-                m.func(*callee).name == "llvm.experimental.patchpoint.void"
+                m.func(*callee).name.as_bytes() == b"llvm.experimental.patchpoint.void"
             }
             _ => false,
         }
@@ -1136,7 +1219,9 @@ impl Inst {
 
     pub(crate) fn is_debug_call(&self, m: &Module) -> bool {
         match self {
-            Self::Call { callee, .. } => m.func(*callee).name == LLVM_DEBUG_CALL_NAME,
+            Self::Call { callee, .. } => {
+                m.func(*callee).name.as_bytes() == LLVM_DEBUG_CALL_NAME.as_bytes()
+            }
             _ => false,
         }
     }
@@ -1475,8 +1560,8 @@ impl fmt::Display for DisplayableBBlock<'_> {
 #[deku_derive(DekuRead)]
 #[derive(Debug)]
 pub(crate) struct Func {
-    #[deku(until = "|v: &u8| *v == 0", map = "map_to_string")]
-    name: String,
+    #[deku(until = "|v: &u8| *v == 0", map = "map_to_cstring")]
+    name: CString,
     tyidx: TyIdx,
     flags: u8,
     #[deku(temp)]
@@ -1512,6 +1597,13 @@ impl Func {
 
     /// Return the name of the function.
     pub(crate) fn name(&self) -> &str {
+        // Can't fail: [map_to_cstring] checked that the name is valid UTF-8.
+        self.name.to_str().unwrap()
+    }
+
+    /// Return the name of the function as a C string.
+    #[cfg(tracer_swt)]
+    pub(crate) fn name_cstr(&self) -> &std::ffi::CStr {
         &self.name
     }
 
@@ -1576,7 +1668,7 @@ impl fmt::Display for DisplayableFunc<'_> {
                 f,
                 "{}func {}({}",
                 attrs,
-                self.func_.name,
+                self.func_.name(),
                 fty.arg_tyidxs
                     .iter()
                     .enumerate()
@@ -1614,27 +1706,48 @@ impl fmt::Display for DisplayableFunc<'_> {
     }
 }
 
+/// Return the zero extended value of the constant integer obtained by interpreting `bytes` as a
+/// `num_bits`-wide constant integer, or `None` if the integer is more than 128 bits wide.
+pub(crate) fn const_int_bytes_to_u128(num_bits: u32, bytes: &[u8]) -> Option<u128> {
+    debug_assert!(bytes.len() * 8 >= usize::try_from(num_bits).unwrap());
+    if num_bits > 128 {
+        return None;
+    }
+    // LLVM stores the integer, in the target's byte order, in the smallest number of bytes that
+    // can hold it, so we may have fewer than 16 bytes (e.g. 3 bytes for an `i24`).
+    let mut buf = [0; 16];
+    let len = bytes.len().min(buf.len());
+    #[cfg(target_endian = "little")]
+    let v = {
+        buf[..len].copy_from_slice(&bytes[..len]);
+        u128::from_le_bytes(buf)
+    };
+    #[cfg(target_endian = "big")]
+    let v = {
+        buf[16 - len..].copy_from_slice(&bytes[bytes.len() - len..]);
+        u128::from_be_bytes(buf)
+    };
+    Some(v.truncate(num_bits))
+}
+
 /// Return the stringified constant integer obtained by interpreting `bytes` as `num-bits`-wide
 /// constant integer.
 ///
-/// FIXME: For now we just handle common integer types, but eventually we will need to
-/// implement printing of aribitrarily-sized (in bits) integers. Consider using a bigint
-/// library so we don't have to do it ourself?
-///
-/// This discussion may help:
-/// https://rust-lang.zulipchat.com/#narrow/stream/122651-general/topic/.E2.9C.94.20Big.20Integer.20library.20with.20bit.20granularity/near/393733327
+/// Integers of up to 128 bits are printed as signed decimals; wider integers are printed in
+/// hexadecimal.
 pub(crate) fn const_int_bytes_to_string(num_bits: u32, bytes: &[u8]) -> String {
-    // All of the unwraps below are safe due to:
-    debug_assert!(bytes.len() * 8 >= usize::try_from(num_bits).unwrap());
-
-    let mut bytes = bytes;
-    match num_bits {
-        1 => format!("{}i1", bytes.read_i8().unwrap() & 1),
-        8 => format!("{}i8", bytes.read_i8().unwrap()),
-        16 => format!("{}i16", bytes.read_i16::<NativeEndian>().unwrap()),
-        32 => format!("{}i32", bytes.read_i32::<NativeEndian>().unwrap()),
-        64 => format!("{}i64", bytes.read_i64::<NativeEndian>().unwrap()),
-        _ => todo!("{}", num_bits),
+    match const_int_bytes_to_u128(num_bits, bytes) {
+        Some(v) if num_bits == 1 => format!("{v}i1"),
+        Some(v) => format!("{}i{num_bits}", v.sign_extend(num_bits, 128) as i128),
+        None => {
+            // Print the most significant byte first.
+            #[cfg(target_endian = "little")]
+            let bytes = bytes.iter().rev();
+            #[cfg(target_endian = "big")]
+            let bytes = bytes.iter();
+            let hex = bytes.map(|b| format!("{b:02x}")).collect::<String>();
+            format!("0x{hex}i{num_bits}")
+        }
     }
 }
 
@@ -1789,6 +1902,21 @@ pub(crate) struct StructTy {
 }
 
 impl StructTy {
+    /// Returns the number of fields in the struct.
+    pub(crate) fn num_fields(&self) -> usize {
+        self.field_tyidxs.len()
+    }
+
+    /// Returns the type index of the field at `idx`.
+    pub(crate) fn field_tyidx(&self, idx: usize) -> TyIdx {
+        self.field_tyidxs[idx]
+    }
+
+    /// Returns the bit offset of the field at `idx` from the start of the struct.
+    pub(crate) fn field_bit_off(&self, idx: usize) -> usize {
+        self.field_bit_offs[idx]
+    }
+
     pub(crate) fn display<'a>(&'a self, m: &'a Module) -> DisplayableStructTy<'a> {
         DisplayableStructTy {
             struct_type: self,
@@ -2113,6 +2241,29 @@ mod tests {
         check(64, u64::MAX, "-1i64");
         check(64, 12345678u64, "12345678i64");
         check(64, i64::MIN as u64, &format!("{}i64", i64::MIN));
+
+        check(24, 0x7fffffu32, "8388607i24");
+        check(24, 0xffffffu32, "-1i24");
+        check(24, 0xff800000u32, "-8388608i24");
+        check(48, 0x123456789abcu64, "20015998343868i48");
+        check(48, 0xffff800000000000u64, "-140737488355328i48");
+
+        check(128, 0u128, "0i128");
+        check(128, u128::MAX, "-1i128");
+        check(128, 1u128 << 64, "18446744073709551616i128");
+        check(128, i128::MIN as u128, &format!("{}i128", i128::MIN));
+
+        // Integers wider than 128 bits are printed in hex.
+        let it = IntegerTy { bitw: 136 };
+        let c = ConstVal {
+            tyidx: TyIdx::new(0),
+            bytes: (1..=17).collect(),
+        };
+        #[cfg(target_endian = "little")]
+        assert_eq!(
+            it.const_to_string(&c),
+            "0x11100f0e0d0c0b0a090807060504030201i136"
+        );
     }
 
     #[test]
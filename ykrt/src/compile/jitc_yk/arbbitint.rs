@@ -4,7 +4,7 @@
 //! want to zero or sign extend the underlying the integer whenever they want access to a
 //! Rust-level integer.
 //!
-//! Currently only up to 64 bits are supported, though the API is flexible enough to transparently
+//! Currently only up to 128 bits are supported, though the API is flexible enough to transparently
 //! support greater bit widths in the future.
 
 use super::int_signs::{SignExtend, Truncate};
@@ -15,16 +15,16 @@ use std::{
 
 /// An integer of an arbitrary, dynamic, bit width.
 ///
-/// Currently can only represent a max of 64 bits: this could be extended in the future.
+/// Currently can only represent a max of 128 bits: this could be extended in the future.
 #[derive(Clone, Debug)]
 pub(crate) struct ArbBitInt {
     bitw: u32,
     /// The underlying value. Any bits above `self.bitw` have an undefined value: they may be set
     /// or unset.
     ///
-    /// Currently we can only store ints that can fit in 64 bits: in the future we could use
+    /// Currently we can only store ints that can fit in 128 bits: in the future we could use
     /// another scheme to e.g `Box` bigger integers.
-    val: u64,
+    val: u128,
 }
 
 impl ArbBitInt {
@@ -32,7 +32,18 @@ impl ArbBitInt {
     /// `width` bits are ignored (i.e. it is safe for those bits to be set or unset when calling
     /// this function).
     pub(crate) fn from_u64(bitw: u32, val: u64) -> Self {
-        debug_assert!(bitw <= 64);
+        debug_assert!(bitw <= 128);
+        Self {
+            bitw,
+            val: u128::from(val),
+        }
+    }
+
+    /// Create a new `ArbBitInt` that is `width` bits wide and has a value `val`. Any bits above
+    /// `width` bits are ignored (i.e. it is safe for those bits to be set or unset when calling
+    /// this function).
+    pub(crate) fn from_u128(bitw: u32, val: u128) -> Self {
+        debug_assert!(bitw <= 128);
         Self { bitw, val }
     }
 
@@ -43,19 +54,30 @@ impl ArbBitInt {
     pub(crate) fn from_usize(val: usize) -> Self {
         Self {
             bitw: 64,
-            val: val as u64,
+            val: val as u128,
         }
     }
 
     /// Create a new `ArbBitInt` that is `width` bits wide and has a value `val`. Any bits above
     /// `width` bits are ignored (i.e. it is safe for those bits to be set or unset when calling
     /// this function).
-    #[cfg(test)]
     pub(crate) fn from_i64(bitw: u32, val: i64) -> Self {
-        debug_assert!(bitw <= 64);
+        debug_assert!(bitw <= 128);
         Self {
             bitw,
-            val: val as u64,
+            val: i128::from(val) as u128,
+        }
+    }
+
+    /// Create a new `ArbBitInt` that is `width` bits wide and has a value `val`. Any bits above
+    /// `width` bits are ignored (i.e. it is safe for those bits to be set or unset when calling
+    /// this function).
+    #[cfg(test)]
+    pub(crate) fn from_i128(bitw: u32, val: i128) -> Self {
+        debug_assert!(bitw <= 128);
+        Self {
+            bitw,
+            val: val as u128,
         }
     }
 
@@ -64,7 +86,7 @@ impl ArbBitInt {
     pub(crate) fn all_bits_set(bitw: u32) -> Self {
         Self {
             bitw,
-            val: u64::MAX,
+            val: u128::MAX,
         }
     }
 
@@ -79,7 +101,7 @@ impl ArbBitInt {
     ///
     /// If `to_bitw` is smaller than `self.bitw()`.
     pub(crate) fn sign_extend(&self, to_bitw: u32) -> Self {
-        debug_assert!(to_bitw >= self.bitw && to_bitw <= 64);
+        debug_assert!(to_bitw >= self.bitw && to_bitw <= 128);
         Self {
             bitw: to_bitw,
             val: self.val.sign_extend(self.bitw, to_bitw),
@@ -92,7 +114,7 @@ impl ArbBitInt {
     ///
     /// If `to_bitw` is smaller than `self.bitw()`.
     pub(crate) fn zero_extend(&self, to_bitw: u32) -> Self {
-        debug_assert!(to_bitw >= self.bitw && to_bitw <= 64);
+        debug_assert!(to_bitw >= self.bitw && to_bitw <= 128);
         Self {
             bitw: to_bitw,
             val: self.val.truncate(self.bitw),
@@ -105,7 +127,7 @@ impl ArbBitInt {
     ///
     /// If `to_bitw` is larger than `self.bitw()`.
     pub(crate) fn truncate(&self, to_bitw: u32) -> Self {
-        debug_assert!(to_bitw <= self.bitw && to_bitw <= 64);
+        debug_assert!(to_bitw <= self.bitw && to_bitw <= 128);
         Self {
             bitw: to_bitw,
             val: self.val,
@@ -115,49 +137,59 @@ impl ArbBitInt {
     /// Sign extend the underlying value and, if it is representable as an `i8`, return it.
     #[allow(unused)]
     pub(crate) fn to_sign_ext_i8(&self) -> Option<i8> {
-        i8::try_from(self.val.sign_extend(self.bitw, 64) as i64).ok()
+        i8::try_from(self.sext()).ok()
     }
 
     /// Sign extend the underlying value and, if it is representable as an `i16`, return it.
     #[allow(unused)]
     pub(crate) fn to_sign_ext_i16(&self) -> Option<i16> {
-        i16::try_from(self.val.sign_extend(self.bitw, 64) as i64).ok()
+        i16::try_from(self.sext()).ok()
     }
 
     /// Sign extend the underlying value and, if it is representable as an `i32`, return it.
     pub(crate) fn to_sign_ext_i32(&self) -> Option<i32> {
-        i32::try_from(self.val.sign_extend(self.bitw, 64) as i64).ok()
+        i32::try_from(self.sext()).ok()
     }
 
     /// Sign extend the underlying value and, if it is representable as an `i64`, return it.
     pub(crate) fn to_sign_ext_i64(&self) -> Option<i64> {
-        Some(self.val.sign_extend(self.bitw, 64) as i64)
+        i64::try_from(self.sext()).ok()
+    }
+
+    /// Sign extend the underlying value and, if it is representable as an `i128`, return it.
+    pub(crate) fn to_sign_ext_i128(&self) -> Option<i128> {
+        Some(self.sext())
     }
 
     /// zero extend the underlying value and, if it is representable as an `u8`, return it.
     pub(crate) fn to_zero_ext_u8(&self) -> Option<u8> {
-        u8::try_from(self.val.truncate(self.bitw)).ok()
+        u8::try_from(self.zext()).ok()
     }
 
     /// zero extend the underlying value and, if it is representable as an `u16`, return it.
     pub(crate) fn to_zero_ext_u16(&self) -> Option<u16> {
-        u16::try_from(self.val.truncate(self.bitw)).ok()
+        u16::try_from(self.zext()).ok()
     }
 
     /// zero extend the underlying value and, if it is representable as an `u32`, return it.
     pub(crate) fn to_zero_ext_u32(&self) -> Option<u32> {
-        u32::try_from(self.val.truncate(self.bitw)).ok()
+        u32::try_from(self.zext()).ok()
     }
 
     /// zero extend the underlying value and, if it is representable as an `u64`, return it.
     pub(crate) fn to_zero_ext_u64(&self) -> Option<u64> {
-        Some(self.val.truncate(self.bitw))
+        u64::try_from(self.zext()).ok()
+    }
+
+    /// zero extend the underlying value and, if it is representable as an `u128`, return it.
+    pub(crate) fn to_zero_ext_u128(&self) -> Option<u128> {
+        Some(self.zext())
     }
 
     /// zero extend the underlying value and, if it is representable as an `u64`, return it.
     #[cfg(target_arch = "x86_64")]
     pub(crate) fn to_zero_ext_usize(&self) -> Option<usize> {
-        Some(self.val.truncate(self.bitw) as usize)
+        usize::try_from(self.zext()).ok()
     }
 
     /// Return a new [ArbBitInt] that performs two's complement wrapping addition on `self` and
@@ -202,6 +234,109 @@ impl ArbBitInt {
         }
     }
 
+    /// Return a new [ArbBitInt] that performs signed addition on `self` and `other`, or `None`
+    /// if the result overflows.
+    ///
+    /// # Panics
+    ///
+    /// If `self` and `other` are not the same bit width.
+    pub(crate) fn checked_sadd(&self, other: &Self) -> Option<Self> {
+        debug_assert_eq!(self.bitw, other.bitw);
+        self.fit_signed(self.sext().checked_add(other.sext())?)
+    }
+
+    /// Return a new [ArbBitInt] that performs unsigned addition on `self` and `other`, or `None`
+    /// if the result overflows.
+    ///
+    /// # Panics
+    ///
+    /// If `self` and `other` are not the same bit width.
+    pub(crate) fn checked_uadd(&self, other: &Self) -> Option<Self> {
+        debug_assert_eq!(self.bitw, other.bitw);
+        self.fit_unsigned(self.zext().checked_add(other.zext())?)
+    }
+
+    /// Return a new [ArbBitInt] that performs signed subtraction on `self` and `other`, or
+    /// `None` if the result overflows.
+    ///
+    /// # Panics
+    ///
+    /// If `self` and `other` are not the same bit width.
+    pub(crate) fn checked_ssub(&self, other: &Self) -> Option<Self> {
+        debug_assert_eq!(self.bitw, other.bitw);
+        self.fit_signed(self.sext().checked_sub(other.sext())?)
+    }
+
+    /// Return a new [ArbBitInt] that performs unsigned subtraction on `self` and `other`, or
+    /// `None` if the result overflows.
+    ///
+    /// # Panics
+    ///
+    /// If `self` and `other` are not the same bit width.
+    pub(crate) fn checked_usub(&self, other: &Self) -> Option<Self> {
+        debug_assert_eq!(self.bitw, other.bitw);
+        self.fit_unsigned(self.zext().checked_sub(other.zext())?)
+    }
+
+    /// Return a new [ArbBitInt] that performs signed multiplication on `self` and `other`, or
+    /// `None` if the result overflows.
+    ///
+    /// # Panics
+    ///
+    /// If `self` and `other` are not the same bit width.
+    pub(crate) fn checked_smul(&self, other: &Self) -> Option<Self> {
+        debug_assert_eq!(self.bitw, other.bitw);
+        self.fit_signed(self.sext().checked_mul(other.sext())?)
+    }
+
+    /// Return a new [ArbBitInt] that performs unsigned multiplication on `self` and `other`, or
+    /// `None` if the result overflows.
+    ///
+    /// # Panics
+    ///
+    /// If `self` and `other` are not the same bit width.
+    pub(crate) fn checked_umul(&self, other: &Self) -> Option<Self> {
+        debug_assert_eq!(self.bitw, other.bitw);
+        self.fit_unsigned(self.zext().checked_mul(other.zext())?)
+    }
+
+    /// The underlying value sign extended to an `i128`.
+    fn sext(&self) -> i128 {
+        self.val.sign_extend(self.bitw, 128) as i128
+    }
+
+    /// The underlying value zero extended to a `u128`.
+    fn zext(&self) -> u128 {
+        self.val.truncate(self.bitw)
+    }
+
+    /// Return an [ArbBitInt] of `self.bitw` bits with the value `val`, or `None` if `val` can't
+    /// be represented as a signed integer of that many bits.
+    fn fit_signed(&self, val: i128) -> Option<Self> {
+        if self.bitw < 128 {
+            let max = (1i128 << (self.bitw - 1)) - 1;
+            if val < -max - 1 || val > max {
+                return None;
+            }
+        }
+        Some(Self {
+            bitw: self.bitw,
+            val: val as u128,
+        })
+    }
+
+    /// Return an [ArbBitInt] of `self.bitw` bits with the value `val`, or `None` if `val` can't
+    /// be represented as an unsigned integer of that many bits.
+    fn fit_unsigned(&self, val: u128) -> Option<Self> {
+        if val.checked_shr(self.bitw).unwrap_or(0) != 0 {
+            return None;
+        }
+        Some(Self {
+            bitw: self.bitw,
+            val,
+        })
+    }
+
     /// Return a new [ArbBitInt] that left shifts `self` by `bits`s or `None` if `bits >=
     /// self.bitw()`.
     pub(crate) fn checked_shl(&self, bits: u32) -> Option<Self> {
@@ -895,6 +1030,66 @@ mod tests {
             );
         }
 
+        #[test]
+        fn arbbitint_8bit_checked(x in any::<i8>(), y in any::<i8>()) {
+            let ax = ArbBitInt::from_i64(8, i64::from(x));
+            let ay = ArbBitInt::from_i64(8, i64::from(y));
+            let s = |z: Option<ArbBitInt>| z.map(|z| z.to_sign_ext_i64().unwrap());
+            assert_eq!(s(ax.checked_sadd(&ay)), x.checked_add(y).map(i64::from));
+            assert_eq!(s(ax.checked_ssub(&ay)), x.checked_sub(y).map(i64::from));
+            assert_eq!(s(ax.checked_smul(&ay)), x.checked_mul(y).map(i64::from));
+            let (x, y) = (x as u8, y as u8);
+            let u = |z: Option<ArbBitInt>| z.map(|z| z.to_zero_ext_u64().unwrap());
+            assert_eq!(u(ax.checked_uadd(&ay)), x.checked_add(y).map(u64::from));
+            assert_eq!(u(ax.checked_usub(&ay)), x.checked_sub(y).map(u64::from));
+            assert_eq!(u(ax.checked_umul(&ay)), x.checked_mul(y).map(u64::from));
+        }
+
+        #[test]
+        fn arbbitint_16bit_checked(x in any::<i16>(), y in any::<i16>()) {
+            let ax = ArbBitInt::from_i64(16, i64::from(x));
+            let ay = ArbBitInt::from_i64(16, i64::from(y));
+            let s = |z: Option<ArbBitInt>| z.map(|z| z.to_sign_ext_i64().unwrap());
+            assert_eq!(s(ax.checked_sadd(&ay)), x.checked_add(y).map(i64::from));
+            assert_eq!(s(ax.checked_ssub(&ay)), x.checked_sub(y).map(i64::from));
+            assert_eq!(s(ax.checked_smul(&ay)), x.checked_mul(y).map(i64::from));
+            let (x, y) = (x as u16, y as u16);
+            let u = |z: Option<ArbBitInt>| z.map(|z| z.to_zero_ext_u64().unwrap());
+            assert_eq!(u(ax.checked_uadd(&ay)), x.checked_add(y).map(u64::from));
+            assert_eq!(u(ax.checked_usub(&ay)), x.checked_sub(y).map(u64::from));
+            assert_eq!(u(ax.checked_umul(&ay)), x.checked_mul(y).map(u64::from));
+        }
+
+        #[test]
+        fn arbbitint_32bit_checked(x in any::<i32>(), y in any::<i32>()) {
+            let ax = ArbBitInt::from_i64(32, i64::from(x));
+            let ay = ArbBitInt::from_i64(32, i64::from(y));
+            let s = |z: Option<ArbBitInt>| z.map(|z| z.to_sign_ext_i64().unwrap());
+            assert_eq!(s(ax.checked_sadd(&ay)), x.checked_add(y).map(i64::from));
+            assert_eq!(s(ax.checked_ssub(&ay)), x.checked_sub(y).map(i64::from));
+            assert_eq!(s(ax.checked_smul(&ay)), x.checked_mul(y).map(i64::from));
+            let (x, y) = (x as u32, y as u32);
+            let u = |z: Option<ArbBitInt>| z.map(|z| z.to_zero_ext_u64().unwrap());
+            assert_eq!(u(ax.checked_uadd(&ay)), x.checked_add(y).map(u64::from));
+            assert_eq!(u(ax.checked_usub(&ay)), x.checked_sub(y).map(u64::from));
+            assert_eq!(u(ax.checked_umul(&ay)), x.checked_mul(y).map(u64::from));
+        }
+
+        #[test]
+        fn arbbitint_64bit_checked(x in any::<i64>(), y in any::<i64>()) {
+            let ax = ArbBitInt::from_i64(64, x);
+            let ay = ArbBitInt::from_i64(64, y);
+            let s = |z: Option<ArbBitInt>| z.map(|z| z.to_sign_ext_i64().unwrap());
+            assert_eq!(s(ax.checked_sadd(&ay)), x.checked_add(y));
+            assert_eq!(s(ax.checked_ssub(&ay)), x.checked_sub(y));
+            assert_eq!(s(ax.checked_smul(&ay)), x.checked_mul(y));
+            let (x, y) = (x as u64, y as u64);
+            let u = |z: Option<ArbBitInt>| z.map(|z| z.to_zero_ext_u64().unwrap());
+            assert_eq!(u(ax.checked_uadd(&ay)), x.checked_add(y));
+            assert_eq!(u(ax.checked_usub(&ay)), x.checked_sub(y));
+            assert_eq!(u(ax.checked_umul(&ay)), x.checked_mul(y));
+        }
+
         #[test]
         fn arbbitint_8bit_shl(x in any::<u8>(), y in 0u32..=8) {
             // Notice that we deliberately allow y to extend beyond to the shiftable range, to make
@@ -1026,5 +1221,83 @@ mod tests {
                 x.checked_shr(y).map(Some)
             );
         }
+
+        #[test]
+        fn arbbitint_128bit(x in any::<i128>(), y in any::<i128>()) {
+            match (i64::try_from(x), ArbBitInt::from_i128(128, x).to_sign_ext_i64()) {
+                (Ok(a), Some(b)) if a == b => (),
+                (Err(_), None) => (),
+                a => panic!("{a:?}")
+            }
+            assert_eq!(ArbBitInt::from_i128(128, x).to_sign_ext_i128(), Some(x));
+            assert_eq!(ArbBitInt::from_i128(128, x).to_zero_ext_u128(), Some(x as u128));
+
+            let ax = ArbBitInt::from_i128(128, x);
+            let ay = ArbBitInt::from_i128(128, y);
+            let s = |z: ArbBitInt| z.to_sign_ext_i128().unwrap();
+            assert_eq!(s(ax.wrapping_add(&ay)), x.wrapping_add(y));
+            assert_eq!(s(ax.wrapping_sub(&ay)), x.wrapping_sub(y));
+            assert_eq!(s(ax.wrapping_mul(&ay)), x.wrapping_mul(y));
+            assert_eq!(s(ax.bitand(&ay)), x.bitand(y));
+            assert_eq!(s(ax.bitor(&ay)), x.bitor(y));
+            assert_eq!(s(ax.bitxor(&ay)), x.bitxor(y));
+
+            // Extending and truncating to and from 128 bits.
+            let x64 = x as i64;
+            assert_eq!(s(ArbBitInt::from_i64(64, x64).sign_extend(128)), i128::from(x64));
+            assert_eq!(
+                ArbBitInt::from_i64(64, x64).zero_extend(128).to_zero_ext_u128(),
+                Some(u128::from(x64 as u64))
+            );
+            assert_eq!(ax.truncate(64).to_sign_ext_i64(), Some(x64));
+        }
+
+        #[test]
+        fn arbbitint_128bit_checked(x in any::<i128>(), y in any::<i128>()) {
+            let ax = ArbBitInt::from_i128(128, x);
+            let ay = ArbBitInt::from_i128(128, y);
+            let s = |z: Option<ArbBitInt>| z.map(|z| z.to_sign_ext_i128().unwrap());
+            assert_eq!(s(ax.checked_sadd(&ay)), x.checked_add(y));
+            assert_eq!(s(ax.checked_ssub(&ay)), x.checked_sub(y));
+            assert_eq!(s(ax.checked_smul(&ay)), x.checked_mul(y));
+            let (x, y) = (x as u128, y as u128);
+            let u = |z: Option<ArbBitInt>| z.map(|z| z.to_zero_ext_u128().unwrap());
+            assert_eq!(u(ax.checked_uadd(&ay)), x.checked_add(y));
+            assert_eq!(u(ax.checked_usub(&ay)), x.checked_sub(y));
+            assert_eq!(u(ax.checked_umul(&ay)), x.checked_mul(y));
+        }
+
+        #[test]
+        fn arbbitint_128bit_shifts(x in any::<i128>(), y in 0u32..=128) {
+            assert_eq!(
+                ArbBitInt::from_i128(128, x).checked_shl(y).map(|x| x.to_sign_ext_i128()),
+                x.checked_shl(y).map(Some)
+            );
+            assert_eq!(
+                ArbBitInt::from_i128(128, x).checked_ashr(y).map(|x| x.to_sign_ext_i128()),
+                x.checked_shr(y).map(Some)
+            );
+            assert_eq!(
+                ArbBitInt::from_i128(128, x).checked_lshr(y).map(|x| x.to_zero_ext_u128()),
+                (x as u128).checked_shr(y).map(Some)
+            );
+        }
+
+        #[test]
+        fn arbbitint_24bit_checked(x in -0x80_0000i32..0x80_0000, y in -0x80_0000i32..0x80_0000) {
+            let ax = ArbBitInt::from_i64(24, i64::from(x));
+            let ay = ArbBitInt::from_i64(24, i64::from(y));
+            let fit = |z: i64| (-0x80_0000..0x80_0000).contains(&z).then_some(z);
+            let s = |z: Option<ArbBitInt>| z.map(|z| z.to_sign_ext_i64().unwrap());
+            assert_eq!(s(ax.checked_sadd(&ay)), fit(i64::from(x) + i64::from(y)));
+            assert_eq!(s(ax.checked_ssub(&ay)), fit(i64::from(x) - i64::from(y)));
+            assert_eq!(s(ax.checked_smul(&ay)), fit(i64::from(x) * i64::from(y)));
+            let (x, y) = (x as u32 & 0xff_ffff, y as u32 & 0xff_ffff);
+            let u = |z: Option<ArbBitInt>| z.map(|z| z.to_zero_ext_u64().unwrap());
+            let fit = |z: u64| (z <= 0xff_ffff).then_some(z);
+            assert_eq!(u(ax.checked_uadd(&ay)), fit(u64::from(x) + u64::from(y)));
+            assert_eq!(u(ax.checked_usub(&ay)), u64::from(x).checked_sub(u64::from(y)));
+            assert_eq!(u(ax.checked_umul(&ay)), fit(u64::from(x) * u64::from(y)));
+        }
     }
 }
@@ -37,4 +37,8 @@ pub(crate) enum VarLocation<R> {
     ConstFloat(f64),
     /// A constant pointer.
     ConstPtr(usize),
+    /// A pointer to the virtual allocation at the given index of a guard's
+    /// [jit_ir::GuardInfo::virtuals]. This is only valid for the live variables of a guard: the
+    /// allocation is materialised when the guard fails.
+    Virtual(usize),
 }
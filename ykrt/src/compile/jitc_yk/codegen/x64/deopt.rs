@@ -79,6 +79,31 @@ pub(crate) extern "C" fn __yk_deopt(
         &format!("deoptimise {:?} {gidx:?}", ctr.ctrid()),
     );
 
+    // Materialise any virtual allocations: live variables which point to them (see
+    // [VarLocation::Virtual]) are written to the new stack below.
+    let virtuals = cgd
+        .virtuals
+        .iter()
+        .map(|va| {
+            let allocator = unsafe {
+                std::mem::transmute::<usize, extern "C" fn(usize) -> *mut c_void>(va.allocator)
+            };
+            let p = allocator(va.size);
+            for (off, bytesize, vloc) in &va.fields {
+                let v = read_var(*vloc, frameaddr, gp_regs, fp_regs);
+                let fp = unsafe { p.byte_add(usize::try_from(*off).unwrap()) };
+                match bytesize {
+                    1 => unsafe { ptr::write_unaligned(fp as *mut u8, v as u8) },
+                    2 => unsafe { ptr::write_unaligned(fp as *mut u16, v as u16) },
+                    4 => unsafe { ptr::write_unaligned(fp as *mut u32, v as u32) },
+                    8 => unsafe { ptr::write_unaligned(fp as *mut u64, v) },
+                    _ => todo!("{}", bytesize),
+                }
+            }
+            p
+        })
+        .collect::<Vec<_>>();
+
     // Calculate space required for the new stack.
     // Add space for live register values which we'll be adding at the end.
     let mut memsize = RECOVER_REG.len() * REG64_BYTESIZE;
@@ -190,27 +215,7 @@ pub(crate) extern "C" fn __yk_deopt(
         for aotvar in rec.live_vals.iter() {
             // Read live JIT values from the trace's stack frame.
             let jitval = match cgd.live_vars[varidx].1 {
-                VarLocation::Stack { frame_off, size } => {
-                    // rbp-0 can't contain a variable.
-                    // [rbp-0] points to either the return address or the previous frame's rbp
-                    // (when using --no-omit-framepointer) and thus can't contain live variables.
-                    debug_assert!(frame_off > 0);
-                    let p = unsafe { frameaddr.byte_sub(usize::try_from(frame_off).unwrap()) };
-                    match size {
-                        1 => unsafe { u64::from(std::ptr::read::<u8>(p as *const u8)) },
-                        2 => unsafe { u64::from(std::ptr::read::<u16>(p as *const u16)) },
-                        4 => unsafe { u64::from(std::ptr::read::<u32>(p as *const u32)) },
-                        8 => unsafe { std::ptr::read::<u64>(p as *const u64) },
-                        _ => todo!(),
-                    }
-                }
-                VarLocation::Register(x) => match x {
-                    Register::GP(x) => gp_regs[usize::from(x.code())],
-                    Register::FP(x) => fp_regs[usize::from(x.code())],
-                },
-                VarLocation::ConstInt { bits: _, v } => v,
-                VarLocation::ConstFloat(f) => f.to_bits(),
-                VarLocation::ConstPtr(v) => u64::try_from(v).unwrap(),
+                VarLocation::Virtual(i) => virtuals[i] as u64,
                 VarLocation::Direct { frame_off, size } => {
                     // See comment below: this case never needs to do anything.
                     debug_assert_eq!(
@@ -220,6 +225,7 @@ pub(crate) extern "C" fn __yk_deopt(
                     varidx += 1;
                     continue;
                 }
+                vloc => read_var(vloc, frameaddr, gp_regs, fp_regs),
             };
             varidx += 1;
 
@@ -348,6 +354,39 @@ pub(crate) extern "C" fn __yk_deopt(
     unsafe { replace_stack(newframedst, newstack, memsize) };
 }
 
+/// Read the value of the JIT variable at `vloc` when the guard failed.
+fn read_var(
+    vloc: VarLocation,
+    frameaddr: *mut c_void,
+    gp_regs: &[u64; 16],
+    fp_regs: &[u64; 16],
+) -> u64 {
+    match vloc {
+        VarLocation::Stack { frame_off, size } => {
+            // rbp-0 can't contain a variable.
+            // [rbp-0] points to either the return address or the previous frame's rbp
+            // (when using --no-omit-framepointer) and thus can't contain live variables.
+            debug_assert!(frame_off > 0);
+            let p = unsafe { frameaddr.byte_sub(usize::try_from(frame_off).unwrap()) };
+            match size {
+                1 => unsafe { u64::from(std::ptr::read::<u8>(p as *const u8)) },
+                2 => unsafe { u64::from(std::ptr::read::<u16>(p as *const u16)) },
+                4 => unsafe { u64::from(std::ptr::read::<u32>(p as *const u32)) },
+                8 => unsafe { std::ptr::read::<u64>(p as *const u64) },
+                _ => todo!(),
+            }
+        }
+        VarLocation::Register(x) => match x {
+            Register::GP(x) => gp_regs[usize::from(x.code())],
+            Register::FP(x) => fp_regs[usize::from(x.code())],
+        },
+        VarLocation::ConstInt { bits: _, v } => v,
+        VarLocation::ConstFloat(f) => f.to_bits(),
+        VarLocation::ConstPtr(v) => u64::try_from(v).unwrap(),
+        VarLocation::Direct { .. } | VarLocation::Virtual(_) => unreachable!(),
+    }
+}
+
 /// Writes the stack frames that we recreated in [__yk_deopt] onto the current stack, overwriting
 /// the stack frames of any running traces in the process. This deoptimises trace execution after
 /// which we can safely return to the normal execution of the interpreter.
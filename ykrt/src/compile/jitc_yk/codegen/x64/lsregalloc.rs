@@ -303,12 +303,18 @@ impl<'a> LSRegAlloc<'a> {
 
     /// When generating the code for a guard failure, do the necessary work from the register
     /// allocator's perspective (e.g. ensuring registers have an appropriate [RegExtension]) for
-    /// deopt to occur.
+    /// deopt to occur. Returns the patch register, the locations of the guard's live variables,
+    /// and, for each of the guard's virtual allocations, the locations of its fields.
+    #[allow(clippy::type_complexity)]
     pub(super) fn get_ready_for_deopt(
         &mut self,
         asm: &mut Assembler,
         ginst: GuardInst,
-    ) -> (Rq, Vec<(aot_ir::InstId, VarLocation)>) {
+    ) -> (
+        Rq,
+        Vec<(aot_ir::InstId, VarLocation)>,
+        Vec<Vec<(u32, VarLocation)>>,
+    ) {
         let patch_reg = self.force_tmp_register(asm, RegSet::with_gp_reserved());
 
         let gi = ginst.guard_info(self.m);
@@ -317,55 +323,84 @@ impl<'a> LSRegAlloc<'a> {
         let mut lives = Vec::with_capacity(gi.live_vars().len());
         for (iid, pop) in gi.live_vars() {
             let op = pop.unpack(self.m);
-            match op {
-                Operand::Var(x) => {
-                    if let Some(reg) = self.find_op_in_gp_reg(&op)
-                        && !seen_gp_regs.is_set(reg)
-                    {
-                        assert_ne!(patch_reg, reg);
-                        let RegState::FromInst(ref insts, ext) =
-                            self.gp_reg_states[usize::from(reg.code())]
-                        else {
-                            panic!()
-                        };
-                        let bitw = insts
-                            .iter()
-                            .map(|x| self.m.inst_nocopy(*x).unwrap().def_bitw(self.m))
-                            .max()
-                            .unwrap();
-                        if ext != RegExtension::ZeroExtended {
-                            self.force_zero_extend_to_reg64(asm, reg, bitw);
-                            seen_gp_regs.set(reg);
-                        }
-                    }
-                    lives.push((iid.clone(), self.var_location(x)));
-                }
-                Operand::Const(x) => {
-                    // The live variable is a constant (e.g. this can happen during inlining), so
-                    // it doesn't have an allocation. We can just push the actual value instead
-                    // which will be written as is during deoptimisation.
-                    match self.m.const_(x) {
-                        Const::Int(_, y) => lives.push((
-                            iid.clone(),
-                            VarLocation::ConstInt {
-                                bits: y.bitw(),
-                                v: y.to_zero_ext_u64().unwrap(),
-                            },
-                        )),
-                        Const::Ptr(p) => lives.push((
-                            iid.clone(),
-                            VarLocation::ConstInt {
-                                bits: 64,
-                                v: u64::try_from(*p).unwrap(),
-                            },
-                        )),
-                        e => todo!("{:?}", e),
+            let vloc = if let Operand::Var(x) = op
+                && let Some(i) = gi.virtual_idx(x)
+            {
+                VarLocation::Virtual(i)
+            } else {
+                self.deopt_location(asm, op, patch_reg, &mut seen_gp_regs)
+            };
+            lives.push((iid.clone(), vloc));
+        }
+        let virtuals = gi
+            .virtuals()
+            .iter()
+            .map(|va| {
+                va.fields
+                    .iter()
+                    .map(|(off, pop)| {
+                        let op = pop.unpack(self.m);
+                        (
+                            *off,
+                            self.deopt_location(asm, op, patch_reg, &mut seen_gp_regs),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        (patch_reg, lives, virtuals)
+    }
+
+    /// Return the location of `op` for deopt, zero extending the register it is in (if any, and
+    /// if that register is not in `seen_gp_regs`).
+    fn deopt_location(
+        &mut self,
+        asm: &mut Assembler,
+        op: Operand,
+        patch_reg: Rq,
+        seen_gp_regs: &mut RegSet<Rq>,
+    ) -> VarLocation {
+        match op {
+            Operand::Var(x) => {
+                if let Some(reg) = self.find_op_in_gp_reg(&op)
+                    && !seen_gp_regs.is_set(reg)
+                {
+                    assert_ne!(patch_reg, reg);
+                    let RegState::FromInst(ref insts, ext) =
+                        self.gp_reg_states[usize::from(reg.code())]
+                    else {
+                        panic!()
+                    };
+                    let bitw = insts
+                        .iter()
+                        .map(|x| self.m.inst_nocopy(*x).unwrap().def_bitw(self.m))
+                        .max()
+                        .unwrap();
+                    if ext != RegExtension::ZeroExtended {
+                        self.force_zero_extend_to_reg64(asm, reg, bitw);
+                        seen_gp_regs.set(reg);
                     }
                 }
+                self.var_location(x)
+            }
+            Operand::Const(x) => {
+                // The live variable is a constant (e.g. this can happen during inlining), so
+                // it doesn't have an allocation. We can just push the actual value instead
+                // which will be written as is during deoptimisation.
+                match self.m.const_(x) {
+                    Const::Int(_, y) => VarLocation::ConstInt {
+                        bits: y.bitw(),
+                        v: y.to_zero_ext_u64().unwrap(),
+                    },
+                    Const::Ptr(p) => VarLocation::ConstInt {
+                        bits: 64,
+                        v: u64::try_from(*p).unwrap(),
+                    },
+                    e => todo!("{:?}", e),
+                }
             }
         }
-
-        (patch_reg, lives)
     }
 }
 
@@ -446,6 +481,26 @@ impl LSRegAlloc<'_> {
         self.spills[usize::from(iidx)] = SpillState::Stack(frame_off);
     }
 
+    /// Assign a 16-byte stack slot to the 128-bit integer produced by instruction `iidx`,
+    /// returning the slot's frame offset. The low 64 bits of the integer are stored at
+    /// `[rbp - off]` and the high 64 bits at `[rbp - off + 8]`.
+    pub(crate) fn assign_wide_int_stack(&mut self, iidx: InstIdx) -> i32 {
+        debug_assert_eq!(self.spills[usize::from(iidx)], SpillState::Empty);
+        self.stack.align(16);
+        let off = i32::try_from(self.stack.grow(16)).unwrap();
+        self.spills[usize::from(iidx)] = SpillState::Stack(off);
+        off
+    }
+
+    /// Return the frame offset of the stack slot assigned to the 128-bit integer produced by
+    /// instruction `iidx` by [Self::assign_wide_int_stack].
+    pub(crate) fn wide_int_stack_off(&self, iidx: InstIdx) -> i32 {
+        match self.spills[usize::from(iidx)] {
+            SpillState::Stack(off) => off,
+            _ => panic!(),
+        }
+    }
+
     /// Forcibly assign a constant integer to an instruction. This typically only happens when
     /// traces pass live variables that have been optimised to constants into side-traces.
     pub(crate) fn assign_const_int(&mut self, iidx: InstIdx, bits: u32, v: u64) {
@@ -674,7 +729,17 @@ impl LSRegAlloc<'_> {
                     match op {
                         Operand::Const(cidx) => {
                             let ss = match self.m.const_(*cidx) {
-                                Const::Float(_ty_idx, _) => todo!(),
+                                Const::Float(tyidx, v) => match self.m.type_(*tyidx) {
+                                    Ty::Float(FloatTy::Float) => SpillState::ConstInt {
+                                        bits: 32,
+                                        v: u64::from((*v as f32).to_bits()),
+                                    },
+                                    Ty::Float(FloatTy::Double) => SpillState::ConstInt {
+                                        bits: 64,
+                                        v: v.to_bits(),
+                                    },
+                                    _ => panic!(),
+                                },
                                 Const::Int(_ty_idx, arb_bit_int) => SpillState::ConstInt {
                                     bits: arb_bit_int.bitw(),
                                     v: arb_bit_int.to_zero_ext_u64().unwrap(),
@@ -1496,12 +1561,21 @@ impl LSRegAlloc<'_> {
             RegState::FromInst(vec![iidx], RegExtension::ZeroExtended);
     }
 
-    /// Load the constant from `cidx` into `reg`.
+    /// Load the constant from `cidx` into `reg`. Floating point constants are loaded as their bit
+    /// patterns.
     ///
     /// If the register is larger than the constant, the unused high-order bits are undefined.
     fn load_const_into_gp_reg(&mut self, asm: &mut Assembler, cidx: ConstIdx, reg: Rq) {
         match self.m.const_(cidx) {
-            Const::Float(_tyidx, _x) => todo!(),
+            Const::Float(tyidx, x) => match self.m.type_(*tyidx) {
+                Ty::Float(FloatTy::Float) => {
+                    dynasm!(asm; mov Rd(reg.code()), (*x as f32).to_bits() as i32);
+                }
+                Ty::Float(FloatTy::Double) => {
+                    dynasm!(asm; mov Rq(reg.code()), QWORD x.to_bits() as i64);
+                }
+                _ => panic!(),
+            },
             Const::Int(_, x) => match x.bitw() {
                 1..=32 => {
                     dynasm!(asm; mov Rd(reg.code()), x.to_zero_ext_u32().unwrap() as i32);
@@ -1999,6 +2073,10 @@ impl LSRegAlloc<'_> {
     /// Load the constant from `cidx` into `reg`.
     fn load_const_into_fp_reg(&mut self, asm: &mut Assembler, cidx: ConstIdx, reg: Rx) {
         match self.m.const_(cidx) {
+            Const::Float(_, val) if val.to_bits() == 0 => {
+                // Positive zero (of either width) has an all-zeros bit pattern.
+                dynasm!(asm; xorps Rx(reg.code()), Rx(reg.code()));
+            }
             Const::Float(tyidx, val) => {
                 // FIXME: We need to use a temporary GP register to move immediate values into but
                 // we don't have a reliable way of expressing this to the register allocator at
@@ -24,7 +24,7 @@ use crate::{
             arbbitint::ArbBitInt,
             jit_ir::{
                 self, BinOp, Const, FloatTy, GuardInst, IndirectCallIdx, InlinedFrame, Inst,
-                InstIdx, Module, Operand, TraceKind, Ty,
+                InstIdx, Module, Operand, OverflowOp, TraceKind, Ty,
             },
             CodeGen, YkSideTraceInfo,
         },
@@ -49,7 +49,10 @@ use std::{
     cell::Cell,
     error::Error,
     slice,
-    sync::{atomic::fence, Arc, Weak},
+    sync::{
+        atomic::{fence, AtomicBool, Ordering},
+        Arc, Weak,
+    },
 };
 use ykaddr::addr::symbol_to_ptr;
 
@@ -297,6 +300,8 @@ impl From<&VarLocation> for yksmp::Location {
                 }
             }
             VarLocation::ConstPtr(v) => yksmp::Location::LargeConstant(u64::try_from(*v).unwrap()),
+            // Guards with virtual allocations are never side-traced.
+            VarLocation::Virtual(_) => unreachable!(),
             e => todo!("{:?}", e),
         }
     }
@@ -454,6 +459,7 @@ impl<'a> Assemble<'a> {
             prologue_offset: self.prologue_offset.0,
             entry_vars: self.header_start_locs.clone(),
             hl: Arc::downgrade(&hl),
+            invalidated: AtomicBool::new(false),
             comments: self.comments.take(),
             #[cfg(any(debug_assertions, test))]
             gdb_ctx,
@@ -510,6 +516,12 @@ impl<'a> Assemble<'a> {
             self.comment_inst(iidx, inst);
             self.ra.expire_regs(iidx);
 
+            if is_wide_int_inst(self.m, &inst) {
+                self.cg_wide_int(iidx, inst);
+                next = iter.next();
+                continue;
+            }
+
             match &inst {
                 #[cfg(test)]
                 jit_ir::Inst::BlackBox(_) => (),
@@ -517,7 +529,29 @@ impl<'a> Assemble<'a> {
                     unreachable!();
                 }
 
-                jit_ir::Inst::BinOp(i) => self.cg_binop(iidx, i),
+                jit_ir::Inst::BinOp(b_inst) => {
+                    next = iter.next();
+                    // We can generate better code for an arithmetic operation whose overflow is
+                    // immediately checked by a guard:
+                    //   %2 = add %0, %1
+                    //   %3 = sadd_overflow %0, %1
+                    //   guard false, %3, [...]
+                    if let Some((o_iidx, Inst::Overflow(o_inst))) = next
+                        && let Some((g_iidx, Inst::Guard(g_inst))) = iter.peek()
+                        && g_inst.cond(self.m) == Operand::Var(o_iidx)
+                        && !self.ra.rev_an.is_inst_var_still_used_after(*g_iidx, o_iidx)
+                        && self.can_fuse_binop_overflow(b_inst, &o_inst)
+                    {
+                        self.cg_binop_overflow_guard(
+                            iidx, b_inst, o_iidx, &o_inst, *g_iidx, *g_inst,
+                        );
+                        let _ = iter.next();
+                        next = iter.next();
+                        continue;
+                    }
+                    self.cg_binop(iidx, b_inst);
+                    continue;
+                }
                 jit_ir::Inst::Param(i) => {
                     // Right now, `Param`s in the body contain dummy values, and shouldn't be
                     // processed.
@@ -591,6 +625,23 @@ impl<'a> Assemble<'a> {
                     self.cg_icmp(iidx, ic_inst);
                     continue;
                 }
+                jit_ir::Inst::Overflow(o_inst) => {
+                    next = iter.next();
+                    // As with `ICmp`s, if the overflow check is immediately followed by a `Guard`,
+                    // we can jump straight on the flags set by the arithmetic operation.
+                    if let Some((g_iidx, Inst::Guard(g_inst))) = next
+                        && g_inst.cond(self.m) == Operand::Var(iidx)
+                        && !self.ra.rev_an.is_inst_var_still_used_after(g_iidx, iidx)
+                        && matches!(o_inst.lhs(self.m).bitw(self.m), 32 | 64)
+                        && o_inst.op() != OverflowOp::UMul
+                    {
+                        self.cg_overflow_guard(iidx, o_inst, g_iidx, g_inst);
+                        next = iter.next();
+                        continue;
+                    }
+                    self.cg_overflow(iidx, o_inst);
+                    continue;
+                }
                 jit_ir::Inst::Guard(i) => self.cg_guard(iidx, i),
                 jit_ir::Inst::TraceHeaderStart => self.cg_header_start(),
                 jit_ir::Inst::TraceHeaderEnd(is_connector) => {
@@ -609,11 +660,14 @@ impl<'a> Assemble<'a> {
                 jit_ir::Inst::FPExt(i) => self.cg_fpext(iidx, i),
                 jit_ir::Inst::FCmp(i) => self.cg_fcmp(iidx, i),
                 jit_ir::Inst::FPToSI(i) => self.cg_fptosi(iidx, i),
+                jit_ir::Inst::FPToUI(i) => self.cg_fptoui(iidx, i),
+                jit_ir::Inst::FPTrunc(i) => self.cg_fptrunc(iidx, i),
                 jit_ir::Inst::FNeg(i) => self.cg_fneg(iidx, i),
                 jit_ir::Inst::DebugStr(..) => (),
                 jit_ir::Inst::PtrToInt(i) => self.cg_ptrtoint(iidx, i),
                 jit_ir::Inst::IntToPtr(i) => self.cg_inttoptr(iidx, i),
                 jit_ir::Inst::UIToFP(i) => self.cg_uitofp(iidx, i),
+                jit_ir::Inst::SecondRetVal(i) => self.cg_second_ret_val(iidx, i),
             }
 
             next = iter.next();
@@ -652,8 +706,20 @@ impl<'a> Assemble<'a> {
                     })
                     .collect::<Vec<_>>()
                     .join(", ");
+                let virtuals = if gi.virtuals().is_empty() {
+                    String::new()
+                } else {
+                    format!(
+                        " virtuals [{}]",
+                        gi.virtuals()
+                            .iter()
+                            .map(|x| x.display(self.m).to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                };
                 self.comment(format!(
-                    "guard {}, {}, [{live_vars}] ; trace_gidx {} safepoint_id {}",
+                    "guard {}, {}, [{live_vars}]{virtuals} ; trace_gidx {} safepoint_id {}",
                     if x.expect() { "true" } else { "false" },
                     x.cond(self.m).display(self.m),
                     self.guards.len(),
@@ -771,9 +837,18 @@ impl<'a> Assemble<'a> {
             let mut todos = ginfo
                 .live_vars()
                 .iter()
-                .map(|(_, pop)| pop.unpack(self.m))
+                .map(|(_, pop)| pop)
+                .chain(
+                    ginfo
+                        .virtuals()
+                        .iter()
+                        .flat_map(|x| x.fields.iter().map(|(_, pop)| pop)),
+                )
+                .map(|pop| pop.unpack(self.m))
                 .filter_map(|x| {
-                    if let Operand::Var(y) = x {
+                    if let Operand::Var(y) = x
+                        && ginfo.virtual_idx(y).is_none()
+                    {
                         Some(y)
                     } else {
                         None
@@ -808,6 +883,7 @@ impl<'a> Assemble<'a> {
                 match inst {
                     Inst::BinOp(x) => self.cg_binop(body_iidx, &x),
                     Inst::ICmp(x) => self.cg_icmp(body_iidx, &x),
+                    Inst::Overflow(x) => self.cg_overflow(body_iidx, &x),
                     Inst::LookupGlobal(x) => self.cg_lookupglobal(body_iidx, &x),
                     Inst::PtrAdd(x) => self.cg_ptradd(body_iidx, &x),
                     Inst::PtrToInt(x) => self.cg_ptrtoint(body_iidx, &x),
@@ -819,7 +895,32 @@ impl<'a> Assemble<'a> {
                 }
             }
 
-            let (jumpreg, live_vars) = self.ra.get_ready_for_deopt(&mut self.asm, gd.ginst);
+            let (jumpreg, live_vars, virtual_fields) =
+                self.ra.get_ready_for_deopt(&mut self.asm, gd.ginst);
+            let virtuals = ginfo
+                .virtuals()
+                .iter()
+                .zip(virtual_fields)
+                .map(|(va, vlocs)| {
+                    let name = self.m.func_decl(va.target).name();
+                    let allocator = symbol_to_ptr(name)
+                        .map_err(|e| CompilationError::General(e.to_string()))?
+                        .addr();
+                    let fields = va
+                        .fields
+                        .iter()
+                        .zip(vlocs)
+                        .map(|((_, pop), (off, vloc))| {
+                            (off, pop.unpack(self.m).byte_size(self.m), vloc)
+                        })
+                        .collect::<Vec<_>>();
+                    Ok(CompiledVirtualAlloc {
+                        allocator,
+                        size: usize::try_from(va.size).unwrap(),
+                        fields,
+                    })
+                })
+                .collect::<Result<Vec<_>, CompilationError>>()?;
             // FIXME: Why are `deoptid`s 64 bit? We're not going to have that many guards!
 
             // Align this location in such a way that the operand of the below `mov`
@@ -867,9 +968,19 @@ impl<'a> Assemble<'a> {
             compiled_guards.push(CompiledGuard {
                 bid: gd.bid,
                 fail_offset: gd.fail_offset,
+                deopt_offset: deopt_off,
                 live_vars,
                 inlined_frames: gd.inlined_frames,
-                guard: Guard::new(),
+                // FIXME: Side-traces are jumped to directly from the guard failure code, without
+                // going through deopt, so virtual allocations would never be materialised. Until
+                // side-traces can materialise virtual allocations themselves, we never side-trace
+                // a guard which has them.
+                guard: if virtuals.is_empty() {
+                    Guard::new()
+                } else {
+                    Guard::dont_trace()
+                },
+                virtuals,
             });
 
             max_stack_size = max_stack_size.max(self.ra.stack_size());
@@ -1756,6 +1867,57 @@ impl<'a> Assemble<'a> {
                     }
                 }
             }
+            Ty::Float(fty) if matches!(val, Operand::Const(_)) => {
+                // Storing a constant float doesn't need an FP register: we store its bit pattern.
+                let Operand::Const(cidx) = val else { panic!() };
+                let Const::Float(_, v) = self.m.const_(cidx) else {
+                    panic!()
+                };
+                // A `double` can only be stored as an immediate if its bit pattern fits in a
+                // sign-extended imm32.
+                let imm = match fty {
+                    FloatTy::Float => Some((*v as f32).to_bits().cast_signed()),
+                    FloatTy::Double => i32::try_from(v.to_bits().cast_signed()).ok(),
+                };
+                if let Some(imm) = imm {
+                    let [tgt_reg] = self.ra.assign_gp_regs(
+                        &mut self.asm,
+                        iidx,
+                        [GPConstraint::Input {
+                            op: tgt_op,
+                            in_ext: RegExtension::Undefined,
+                            force_reg: None,
+                            clobber_reg: false,
+                        }],
+                    );
+                    match fty {
+                        FloatTy::Float => {
+                            dynasm!(self.asm ; mov DWORD [Rq(tgt_reg.code()) + off], imm)
+                        }
+                        FloatTy::Double => {
+                            dynasm!(self.asm ; mov QWORD [Rq(tgt_reg.code()) + off], imm)
+                        }
+                    }
+                } else {
+                    let [tgt_reg, tmp_reg] = self.ra.assign_gp_regs(
+                        &mut self.asm,
+                        iidx,
+                        [
+                            GPConstraint::Input {
+                                op: tgt_op,
+                                in_ext: RegExtension::Undefined,
+                                force_reg: None,
+                                clobber_reg: false,
+                            },
+                            GPConstraint::Temporary,
+                        ],
+                    );
+                    dynasm!(self.asm
+                        ; mov Rq(tmp_reg.code()), QWORD v.to_bits().cast_signed()
+                        ; mov QWORD [Rq(tgt_reg.code()) + off], Rq(tmp_reg.code())
+                    );
+                }
+            }
             Ty::Float(fty) => {
                 let ([tgt_reg], [val_reg]) = self.ra.assign_regs(
                     &mut self.asm,
@@ -1832,26 +1994,61 @@ impl<'a> Assemble<'a> {
                 self.cg_abs(iidx, op, is_int_min);
                 Ok(())
             }
+            x if x.starts_with("llvm.bswap.") => {
+                let [op] = args.try_into().unwrap();
+                self.cg_bswap(iidx, op)
+            }
+            x if x.starts_with("llvm.ceil.") => {
+                let [op] = args.try_into().unwrap();
+                self.cg_ceil(iidx, op);
+                Ok(())
+            }
+            x if x.starts_with("llvm.ctlz.") => {
+                let [op, is_zero_poison] = args.try_into().unwrap();
+                self.cg_ctlz(iidx, op, is_zero_poison)
+            }
             x if x.starts_with("llvm.ctpop.") => {
                 let [op] = args.try_into().unwrap();
                 self.cg_ctpop(iidx, op);
                 Ok(())
             }
+            x if x.starts_with("llvm.cttz.") => {
+                let [op, is_zero_poison] = args.try_into().unwrap();
+                self.cg_cttz(iidx, op, is_zero_poison)
+            }
+            x if x.starts_with("llvm.fabs.") => {
+                let [op] = args.try_into().unwrap();
+                self.cg_fabs(iidx, op);
+                Ok(())
+            }
             x if x.starts_with("llvm.floor.") => {
                 let [op] = args.try_into().unwrap();
                 self.cg_floor(iidx, op);
                 Ok(())
             }
+            x if x.starts_with("llvm.fma.") => {
+                let [op_a, op_b, op_c] = args.try_into().unwrap();
+                self.cg_fma(iidx, fty, op_a, op_b, op_c)
+            }
             x if x.starts_with("llvm.fshl.i") => {
                 let [op_a, op_b, op_c] = args.try_into().unwrap();
                 self.cg_fshl(iidx, op_a, op_b, op_c);
                 Ok(())
             }
+            x if x.starts_with("llvm.fshr.i") => {
+                let [op_a, op_b, op_c] = args.try_into().unwrap();
+                self.cg_fshr(iidx, op_a, op_b, op_c)
+            }
             x if x.starts_with("llvm.memcpy.") => {
                 let [dst, src, len, is_volatile] = args.try_into().unwrap();
                 self.cg_memcpy(iidx, dst, src, len, is_volatile);
                 Ok(())
             }
+            x if x.starts_with("llvm.memmove.") => {
+                let [dst, src, len, is_volatile] = args.try_into().unwrap();
+                self.cg_memmove(iidx, dst, src, len, is_volatile);
+                Ok(())
+            }
             "llvm.memset.p0.i64" => {
                 let [dst, val, len, is_volatile] = args.try_into().unwrap();
                 self.cg_memset(iidx, dst, val, len, is_volatile);
@@ -1867,6 +2064,21 @@ impl<'a> Assemble<'a> {
                 self.cg_smin(iidx, lhs_op, rhs_op);
                 Ok(())
             }
+            x if x.starts_with("llvm.sqrt.") => {
+                let [op] = args.try_into().unwrap();
+                self.cg_sqrt(iidx, op);
+                Ok(())
+            }
+            x if x.starts_with("llvm.umax.") => {
+                let [lhs_op, rhs_op] = args.try_into().unwrap();
+                self.cg_umax_umin(iidx, lhs_op, rhs_op, true);
+                Ok(())
+            }
+            x if x.starts_with("llvm.umin.") => {
+                let [lhs_op, rhs_op] = args.try_into().unwrap();
+                self.cg_umax_umin(iidx, lhs_op, rhs_op, false);
+                Ok(())
+            }
             x => {
                 let va = symbol_to_ptr(x).map_err(|e| CompilationError::General(e.to_string()))?;
                 self.emit_call(iidx, fty, Some(va), None, &args)
@@ -2077,6 +2289,48 @@ impl<'a> Assemble<'a> {
         Ok(())
     }
 
+    /// Codegen a [jit_ir::SecondRetValInst]. The SysV ABI returns the second field of a two-field
+    /// aggregate in the next unused return register of the field's class: `rdx` / `xmm1` if the
+    /// first field (returned by the call itself) is of the same class, or `rax` / `xmm0` otherwise.
+    fn cg_second_ret_val(&mut self, iidx: InstIdx, inst: &jit_ir::SecondRetValInst) {
+        // The call can only be separated from this instruction by instructions which generate no
+        // code.
+        let mut call_iidx = iidx;
+        let first_is_float = loop {
+            call_iidx = call_iidx.checked_sub(1).unwrap();
+            match self.m.inst_nocopy(call_iidx) {
+                Some(inst @ (Inst::Call(_) | Inst::IndirectCall(_))) => {
+                    break matches!(inst.def_type(self.m), Some(Ty::Float(_)));
+                }
+                None | Some(Inst::Const(_) | Inst::Tombstone) => (),
+                Some(x) => panic!("{x:?}"),
+            }
+        };
+        match self.m.type_(inst.tyidx()) {
+            Ty::Float(_) => {
+                let reg = if first_is_float { Rx::XMM1 } else { Rx::XMM0 };
+                let _ = self.ra.assign_fp_regs(
+                    &mut self.asm,
+                    iidx,
+                    [RegConstraint::OutputFromReg(reg)],
+                );
+            }
+            Ty::Integer(_) | Ty::Ptr => {
+                let reg = if first_is_float { Rq::RAX } else { Rq::RDX };
+                let _: [_; 1] = self.ra.assign_gp_regs(
+                    &mut self.asm,
+                    iidx,
+                    [GPConstraint::Output {
+                        out_ext: RegExtension::Undefined,
+                        force_reg: Some(reg),
+                        can_be_same_as_input: false,
+                    }],
+                );
+            }
+            x => panic!("{x:?}"),
+        }
+    }
+
     fn cg_abs(&mut self, iidx: InstIdx, op: Operand, _is_int_min: Operand) {
         let bitw = op.bitw(self.m);
         let [io_reg, tmp_reg] = self.ra.assign_gp_regs(
@@ -2106,6 +2360,37 @@ impl<'a> Assemble<'a> {
         }
     }
 
+    fn cg_bswap(&mut self, iidx: InstIdx, op: Operand) -> Result<(), CompilationError> {
+        let bitw = op.bitw(self.m);
+        if !matches!(bitw, 16 | 32 | 64) {
+            return Err(CompilationError::General(format!(
+                "bswap on i{bitw} values is not supported"
+            )));
+        }
+        let [io_reg] = self.ra.assign_gp_regs(
+            &mut self.asm,
+            iidx,
+            [GPConstraint::InputOutput {
+                op,
+                in_ext: RegExtension::Undefined,
+                // Rotating a 16-bit register leaves the bits above it untouched.
+                out_ext: if bitw == 16 {
+                    RegExtension::Undefined
+                } else {
+                    RegExtension::ZeroExtended
+                },
+                force_reg: None,
+            }],
+        );
+        match bitw {
+            16 => dynasm!(self.asm; rol Rw(io_reg.code()), 8),
+            32 => dynasm!(self.asm; bswap Rd(io_reg.code())),
+            64 => dynasm!(self.asm; bswap Rq(io_reg.code())),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
     fn cg_ctpop(&mut self, iidx: InstIdx, op: Operand) {
         let bitw = op.bitw(self.m);
         let [in_reg, out_reg] = self.ra.assign_gp_regs(
@@ -2139,6 +2424,163 @@ impl<'a> Assemble<'a> {
         }
     }
 
+    /// Count the leading zeros of `op`. We use `bsr` rather than `lzcnt`, since the latter silently
+    /// executes as `bsr` on CPUs which don't support it. `bsr` leaves its output undefined when its
+    /// input is zero, so we always produce the bit width of `op` in that case (which is correct
+    /// whether or not `is_zero_poison` is 0 or 1).
+    fn cg_ctlz(
+        &mut self,
+        iidx: InstIdx,
+        op: Operand,
+        _is_zero_poison: Operand,
+    ) -> Result<(), CompilationError> {
+        let bitw = op.bitw(self.m);
+        if bitw > 32 && bitw != 64 {
+            return Err(CompilationError::General(format!(
+                "ctlz on i{bitw} values is not supported"
+            )));
+        }
+        let [in_reg, out_reg, tmp_reg] = self.ra.assign_gp_regs(
+            &mut self.asm,
+            iidx,
+            [
+                GPConstraint::Input {
+                    op,
+                    in_ext: RegExtension::ZeroExtended,
+                    force_reg: None,
+                    clobber_reg: false,
+                },
+                GPConstraint::Output {
+                    out_ext: RegExtension::ZeroExtended,
+                    force_reg: None,
+                    can_be_same_as_input: true,
+                },
+                GPConstraint::Temporary,
+            ],
+        );
+        // If the input is non-zero, `bsr` gives us the index `i` of its most significant set bit,
+        // and `i ^ (N - 1)` (i.e. `N - 1 - i`) is then the number of leading zeros in an `N`-bit
+        // register. If the input is zero, we substitute `2N - 1`, which `^ (N - 1)` turns into
+        // `N`.
+        match bitw {
+            64 => {
+                dynasm!(self.asm
+                    ; bsr Rq(out_reg.code()), Rq(in_reg.code())
+                    ; mov Rd(tmp_reg.code()), 127
+                    ; cmovz Rq(out_reg.code()), Rq(tmp_reg.code())
+                    ; xor Rd(out_reg.code()), 63
+                );
+            }
+            x if x <= 32 => {
+                dynasm!(self.asm
+                    ; bsr Rd(out_reg.code()), Rd(in_reg.code())
+                    ; mov Rd(tmp_reg.code()), 63
+                    ; cmovz Rd(out_reg.code()), Rd(tmp_reg.code())
+                    ; xor Rd(out_reg.code()), 31
+                );
+                if bitw < 32 {
+                    // The input is zero extended, so we've counted too many leading zeros.
+                    dynasm!(self.asm; sub Rd(out_reg.code()), i32::try_from(32 - bitw).unwrap());
+                }
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Count the trailing zeros of `op`. As with [Self::cg_ctlz], we use `bsf` rather than `tzcnt`
+    /// and produce the bit width of `op` if it is zero.
+    fn cg_cttz(
+        &mut self,
+        iidx: InstIdx,
+        op: Operand,
+        _is_zero_poison: Operand,
+    ) -> Result<(), CompilationError> {
+        let bitw = op.bitw(self.m);
+        if bitw > 32 && bitw != 64 {
+            return Err(CompilationError::General(format!(
+                "cttz on i{bitw} values is not supported"
+            )));
+        }
+        let [in_reg, out_reg, tmp_reg] = self.ra.assign_gp_regs(
+            &mut self.asm,
+            iidx,
+            [
+                GPConstraint::Input {
+                    op,
+                    in_ext: RegExtension::ZeroExtended,
+                    force_reg: None,
+                    clobber_reg: false,
+                },
+                GPConstraint::Output {
+                    out_ext: RegExtension::ZeroExtended,
+                    force_reg: None,
+                    can_be_same_as_input: true,
+                },
+                GPConstraint::Temporary,
+            ],
+        );
+        match bitw {
+            64 => {
+                dynasm!(self.asm
+                    ; bsf Rq(out_reg.code()), Rq(in_reg.code())
+                    ; mov Rd(tmp_reg.code()), 64
+                    ; cmovz Rd(out_reg.code()), Rd(tmp_reg.code())
+                );
+            }
+            x if x <= 32 => {
+                dynasm!(self.asm
+                    ; bsf Rd(out_reg.code()), Rd(in_reg.code())
+                    ; mov Rd(tmp_reg.code()), i32::try_from(bitw).unwrap()
+                    ; cmovz Rd(out_reg.code()), Rd(tmp_reg.code())
+                );
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn cg_ceil(&mut self, iidx: InstIdx, op: Operand) {
+        let Ty::Float(fty) = self.m.type_(op.tyidx(self.m)) else {
+            panic!()
+        };
+        let [in_reg, out_reg] = self.ra.assign_fp_regs(
+            &mut self.asm,
+            iidx,
+            [RegConstraint::Input(op.clone()), RegConstraint::Output],
+        );
+        // Rounding mode 2 is "round towards +infinity".
+        match fty {
+            FloatTy::Float => dynasm!(self.asm; roundss Rx(out_reg.code()), Rx(in_reg.code()), 2),
+            FloatTy::Double => dynasm!(self.asm; roundsd Rx(out_reg.code()), Rx(in_reg.code()), 2),
+        }
+    }
+
+    fn cg_fabs(&mut self, iidx: InstIdx, op: Operand) {
+        let Ty::Float(fty) = self.m.type_(op.tyidx(self.m)) else {
+            panic!()
+        };
+        let [io_reg, mask_reg] = self.ra.assign_fp_regs(
+            &mut self.asm,
+            iidx,
+            [RegConstraint::InputOutput(op), RegConstraint::Temporary],
+        );
+        // Build a mask with all bits other than the sign bit set, and use it to clear the sign
+        // bit.
+        match fty {
+            FloatTy::Float => dynasm!(self.asm
+                ; pcmpeqd Rx(mask_reg.code()), Rx(mask_reg.code())
+                ; psrld Rx(mask_reg.code()), 1
+                ; andps Rx(io_reg.code()), Rx(mask_reg.code())
+            ),
+            FloatTy::Double => dynasm!(self.asm
+                ; pcmpeqd Rx(mask_reg.code()), Rx(mask_reg.code())
+                ; psrlq Rx(mask_reg.code()), 1
+                ; andpd Rx(io_reg.code()), Rx(mask_reg.code())
+            ),
+        }
+    }
+
     fn cg_floor(&mut self, iidx: InstIdx, op: Operand) {
         match self.m.type_(op.tyidx(self.m)) {
             Ty::Void => todo!(),
@@ -2162,6 +2604,63 @@ impl<'a> Assemble<'a> {
         }
     }
 
+    /// Codegen a fused multiply-add `op_a * op_b + op_c`. This must be computed with a single
+    /// rounding: if the CPU doesn't support FMA instructions, we call libm's `fma` / `fmaf`.
+    fn cg_fma(
+        &mut self,
+        iidx: InstIdx,
+        fty: &jit_ir::FuncTy,
+        op_a: Operand,
+        op_b: Operand,
+        op_c: Operand,
+    ) -> Result<(), CompilationError> {
+        let Ty::Float(float_ty) = self.m.type_(op_a.tyidx(self.m)) else {
+            panic!()
+        };
+        if !std::arch::is_x86_feature_detected!("fma") {
+            let sym = match float_ty {
+                FloatTy::Float => "fmaf",
+                FloatTy::Double => "fma",
+            };
+            let va = symbol_to_ptr(sym).map_err(|e| CompilationError::General(e.to_string()))?;
+            return self.emit_call(iidx, fty, Some(va), None, &[op_a, op_b, op_c]);
+        }
+        let [a_reg, b_reg, c_reg] = self.ra.assign_fp_regs(
+            &mut self.asm,
+            iidx,
+            [
+                RegConstraint::InputOutput(op_a),
+                RegConstraint::Input(op_b),
+                RegConstraint::Input(op_c),
+            ],
+        );
+        // `vfmadd213` computes `op2 * op1 + op3`, storing the result in `op1`.
+        match float_ty {
+            FloatTy::Float => dynasm!(self.asm
+                ; vfmadd213ss Rx(a_reg.code()), Rx(b_reg.code()), Rx(c_reg.code())
+            ),
+            FloatTy::Double => dynasm!(self.asm
+                ; vfmadd213sd Rx(a_reg.code()), Rx(b_reg.code()), Rx(c_reg.code())
+            ),
+        }
+        Ok(())
+    }
+
+    fn cg_sqrt(&mut self, iidx: InstIdx, op: Operand) {
+        let Ty::Float(fty) = self.m.type_(op.tyidx(self.m)) else {
+            panic!()
+        };
+        let [in_reg, out_reg] = self.ra.assign_fp_regs(
+            &mut self.asm,
+            iidx,
+            [RegConstraint::Input(op.clone()), RegConstraint::Output],
+        );
+        match fty {
+            FloatTy::Float => dynasm!(self.asm; sqrtss Rx(out_reg.code()), Rx(in_reg.code())),
+            FloatTy::Double => dynasm!(self.asm; sqrtsd Rx(out_reg.code()), Rx(in_reg.code())),
+        }
+    }
+
     fn cg_fshl(&mut self, iidx: InstIdx, op_a: Operand, op_b: Operand, op_c: Operand) {
         let bitw = op_a.bitw(self.m);
         match bitw {
@@ -2229,29 +2728,124 @@ impl<'a> Assemble<'a> {
         dynasm!(self.asm; rep movsb);
     }
 
-    fn cg_memset(
+    fn cg_fshr(
         &mut self,
         iidx: InstIdx,
-        dst_op: Operand,
-        val_op: Operand,
-        len_op: Operand,
-        _is_volatile_op: Operand,
-    ) {
-        let [_, _, _] = self.ra.assign_gp_regs(
+        op_a: Operand,
+        op_b: Operand,
+        op_c: Operand,
+    ) -> Result<(), CompilationError> {
+        let bitw = op_a.bitw(self.m);
+        if !matches!(bitw, 32 | 64) {
+            return Err(CompilationError::General(format!(
+                "fshr on i{bitw} values is not supported"
+            )));
+        }
+        let Some(c) = self.op_to_zero_ext_i8(&op_c) else {
+            return Err(CompilationError::General(
+                "fshr with a non-constant shift amount is not supported".to_owned(),
+            ));
+        };
+        // `fshr(a, b, c)` shifts `b` right, filling the vacated bits from `a`: `shrd b, a, c`
+        // does exactly that, leaving the result in `b`'s register.
+        let [b_reg, a_reg] = self.ra.assign_gp_regs(
             &mut self.asm,
             iidx,
             [
-                GPConstraint::Input {
-                    op: dst_op.clone(),
-                    in_ext: RegExtension::ZeroExtended,
-                    force_reg: Some(Rq::RDI),
-                    clobber_reg: true,
+                GPConstraint::InputOutput {
+                    op: op_b,
+                    in_ext: RegExtension::Undefined,
+                    out_ext: RegExtension::ZeroExtended,
+                    force_reg: None,
                 },
                 GPConstraint::Input {
-                    op: val_op.clone(),
-                    in_ext: RegExtension::ZeroExtended,
-                    force_reg: Some(Rq::RAX),
-                    clobber_reg: true,
+                    op: op_a,
+                    in_ext: RegExtension::Undefined,
+                    force_reg: None,
+                    clobber_reg: false,
+                },
+            ],
+        );
+        match bitw {
+            64 => dynasm!(self.asm; shrd Rq(b_reg.code()), Rq(a_reg.code()), c),
+            32 => dynasm!(self.asm; shrd Rd(b_reg.code()), Rd(a_reg.code()), c),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Codegen `memmove`. Unlike `memcpy`, the source and destination may overlap, so if the
+    /// destination is above the source, we copy backwards.
+    fn cg_memmove(
+        &mut self,
+        iidx: InstIdx,
+        dst_op: Operand,
+        src_op: Operand,
+        len_op: Operand,
+        _is_volatile_op: Operand,
+    ) {
+        let [dst_reg, src_reg, len_reg] = self.ra.assign_gp_regs(
+            &mut self.asm,
+            iidx,
+            [
+                GPConstraint::Input {
+                    op: dst_op.clone(),
+                    in_ext: RegExtension::ZeroExtended,
+                    force_reg: Some(Rq::RDI),
+                    clobber_reg: true,
+                },
+                GPConstraint::Input {
+                    op: src_op.clone(),
+                    in_ext: RegExtension::ZeroExtended,
+                    force_reg: Some(Rq::RSI),
+                    clobber_reg: true,
+                },
+                GPConstraint::Input {
+                    op: len_op.clone(),
+                    in_ext: RegExtension::ZeroExtended,
+                    force_reg: Some(Rq::RCX),
+                    clobber_reg: true,
+                },
+            ],
+        );
+        dynasm!(self.asm
+            ;   cmp Rq(dst_reg.code()), Rq(src_reg.code())
+            ;   jbe >forwards
+            ;   lea Rq(dst_reg.code()), [Rq(dst_reg.code()) + Rq(len_reg.code()) - 1]
+            ;   lea Rq(src_reg.code()), [Rq(src_reg.code()) + Rq(len_reg.code()) - 1]
+            ;   std
+            ;   rep movsb
+            ;   cld
+            ;   jmp >done
+            ; forwards:
+            ;   rep movsb
+            ; done:
+        );
+    }
+
+    fn cg_memset(
+        &mut self,
+        iidx: InstIdx,
+        dst_op: Operand,
+        val_op: Operand,
+        len_op: Operand,
+        _is_volatile_op: Operand,
+    ) {
+        let [_, _, _] = self.ra.assign_gp_regs(
+            &mut self.asm,
+            iidx,
+            [
+                GPConstraint::Input {
+                    op: dst_op.clone(),
+                    in_ext: RegExtension::ZeroExtended,
+                    force_reg: Some(Rq::RDI),
+                    clobber_reg: true,
+                },
+                GPConstraint::Input {
+                    op: val_op.clone(),
+                    in_ext: RegExtension::ZeroExtended,
+                    force_reg: Some(Rq::RAX),
+                    clobber_reg: true,
                 },
                 GPConstraint::Input {
                     op: len_op.clone(),
@@ -2334,6 +2928,37 @@ impl<'a> Assemble<'a> {
         }
     }
 
+    /// Codegen `umax` (if `is_max` is true) or `umin` (otherwise).
+    fn cg_umax_umin(&mut self, iidx: InstIdx, lhs: Operand, rhs: Operand, is_max: bool) {
+        assert_eq!(lhs.bitw(self.m), rhs.bitw(self.m));
+        assert!(lhs.bitw(self.m) <= 64);
+        let [lhs_reg, rhs_reg] = self.ra.assign_gp_regs(
+            &mut self.asm,
+            iidx,
+            [
+                GPConstraint::InputOutput {
+                    op: lhs,
+                    in_ext: RegExtension::ZeroExtended,
+                    out_ext: RegExtension::ZeroExtended,
+                    force_reg: None,
+                },
+                GPConstraint::Input {
+                    op: rhs,
+                    in_ext: RegExtension::ZeroExtended,
+                    force_reg: None,
+                    clobber_reg: false,
+                },
+            ],
+        );
+        // Since both values are zero extended, we can compare them as 64-bit values.
+        dynasm!(self.asm; cmp Rq(lhs_reg.code()), Rq(rhs_reg.code()));
+        if is_max {
+            dynasm!(self.asm; cmovb Rq(lhs_reg.code()), Rq(rhs_reg.code()));
+        } else {
+            dynasm!(self.asm; cmova Rq(lhs_reg.code()), Rq(rhs_reg.code()));
+        }
+    }
+
     /// Return the [VarLocation] an [Operand] relates to.
     fn op_to_var_location(&self, op: Operand) -> VarLocation {
         match op {
@@ -2365,7 +2990,7 @@ impl<'a> Assemble<'a> {
     fn op_to_imm64(&self, op: &Operand) -> Option<i32> {
         if let Operand::Const(cidx) = op {
             match self.m.const_(*cidx) {
-                Const::Float(_, _) => todo!(),
+                Const::Float(_, _) => None,
                 Const::Int(_, v) => v
                     .to_zero_ext_u32()
                     .filter(|x| *x <= i32::MAX.cast_unsigned())
@@ -2407,7 +3032,7 @@ impl<'a> Assemble<'a> {
     fn op_to_zero_ext_i32(&self, op: &Operand) -> Option<i32> {
         if let Operand::Const(cidx) = op {
             match self.m.const_(*cidx) {
-                Const::Float(_, _) => todo!(),
+                Const::Float(_, _) => None,
                 Const::Int(_, v) => v.to_zero_ext_u32().map(|x| x.cast_signed()),
                 Const::Ptr(v) => ArbBitInt::from_u64(64, u64::try_from(*v).unwrap())
                     .to_zero_ext_u32()
@@ -2622,6 +3247,288 @@ impl<'a> Assemble<'a> {
         }
     }
 
+    /// Can the [jit_ir::BinOpInst] `b_inst` and the [jit_ir::OverflowInst] `o_inst` be
+    /// generated as a single machine instruction?
+    fn can_fuse_binop_overflow(
+        &self,
+        b_inst: &jit_ir::BinOpInst,
+        o_inst: &jit_ir::OverflowInst,
+    ) -> bool {
+        let lhs = b_inst.lhs(self.m);
+        o_inst.op() != OverflowOp::UMul
+            && b_inst.binop() == o_inst.op().binop()
+            && lhs == o_inst.lhs(self.m)
+            && b_inst.rhs(self.m) == o_inst.rhs(self.m)
+            && matches!(lhs.bitw(self.m), 32 | 64)
+    }
+
+    /// Codegen a [jit_ir::BinOpInst] followed by a [jit_ir::OverflowInst] on the same operands and
+    /// a [jit_ir::GuardInst] on the overflow check. The `add`/`sub`/`imul` which computes the
+    /// result also sets the overflow and carry flags, so the guard is a single conditional jump.
+    fn cg_binop_overflow_guard(
+        &mut self,
+        b_iidx: InstIdx,
+        b_inst: &jit_ir::BinOpInst,
+        o_iidx: InstIdx,
+        o_inst: &jit_ir::OverflowInst,
+        g_iidx: InstIdx,
+        g_inst: jit_ir::GuardInst,
+    ) {
+        assert!(!self.ra.rev_an.is_inst_var_still_used_after(g_iidx, o_iidx));
+        let (lhs, rhs) = (b_inst.lhs(self.m), b_inst.rhs(self.m));
+        let bitw = lhs.bitw(self.m);
+        let op = o_inst.op();
+        if op.binop() != BinOp::Mul
+            && let Some(v) = self.op_to_sign_ext_i32(&rhs)
+        {
+            let [lhs_reg] = self.ra.assign_gp_regs(
+                &mut self.asm,
+                b_iidx,
+                [GPConstraint::InputOutput {
+                    op: lhs,
+                    in_ext: RegExtension::Undefined,
+                    out_ext: RegExtension::ZeroExtended,
+                    force_reg: None,
+                }],
+            );
+            match (op.binop(), bitw) {
+                (BinOp::Add, 32) => dynasm!(self.asm; add Rd(lhs_reg.code()), v),
+                (BinOp::Add, 64) => dynasm!(self.asm; add Rq(lhs_reg.code()), v),
+                (BinOp::Sub, 32) => dynasm!(self.asm; sub Rd(lhs_reg.code()), v),
+                (BinOp::Sub, 64) => dynasm!(self.asm; sub Rq(lhs_reg.code()), v),
+                _ => unreachable!(),
+            }
+        } else {
+            let [lhs_reg, rhs_reg] = self.ra.assign_gp_regs(
+                &mut self.asm,
+                b_iidx,
+                [
+                    GPConstraint::InputOutput {
+                        op: lhs,
+                        in_ext: RegExtension::Undefined,
+                        out_ext: RegExtension::ZeroExtended,
+                        force_reg: None,
+                    },
+                    GPConstraint::Input {
+                        op: rhs,
+                        in_ext: RegExtension::Undefined,
+                        force_reg: None,
+                        clobber_reg: false,
+                    },
+                ],
+            );
+            self.cg_overflow_arith(op, bitw, lhs_reg, rhs_reg);
+        }
+
+        self.ra.expire_regs(o_iidx);
+        self.comment_inst(o_iidx, (*o_inst).into());
+        self.cg_overflow_jcc(op, g_iidx, g_inst);
+    }
+
+    /// Codegen a [jit_ir::OverflowInst] followed by a [jit_ir::GuardInst] on its result. The
+    /// arithmetic operation is computed in a temporary register purely to set the flags.
+    fn cg_overflow_guard(
+        &mut self,
+        o_iidx: InstIdx,
+        o_inst: &jit_ir::OverflowInst,
+        g_iidx: InstIdx,
+        g_inst: jit_ir::GuardInst,
+    ) {
+        assert!(!self.ra.rev_an.is_inst_var_still_used_after(g_iidx, o_iidx));
+        let (lhs, rhs) = (o_inst.lhs(self.m), o_inst.rhs(self.m));
+        let bitw = lhs.bitw(self.m);
+        let [lhs_reg, rhs_reg, tmp_reg] = self.ra.assign_gp_regs(
+            &mut self.asm,
+            o_iidx,
+            [
+                GPConstraint::Input {
+                    op: lhs,
+                    in_ext: RegExtension::Undefined,
+                    force_reg: None,
+                    clobber_reg: false,
+                },
+                GPConstraint::Input {
+                    op: rhs,
+                    in_ext: RegExtension::Undefined,
+                    force_reg: None,
+                    clobber_reg: false,
+                },
+                GPConstraint::Temporary,
+            ],
+        );
+        dynasm!(self.asm; mov Rq(tmp_reg.code()), Rq(lhs_reg.code()));
+        self.cg_overflow_arith(o_inst.op(), bitw, tmp_reg, rhs_reg);
+        self.cg_overflow_jcc(o_inst.op(), g_iidx, g_inst);
+    }
+
+    /// Emit the 32 or 64 bit `add`/`sub`/`imul` for the (signed or unsigned) operation `op`,
+    /// storing the result in `lhs_reg` and setting the overflow and carry flags.
+    fn cg_overflow_arith(&mut self, op: OverflowOp, bitw: u32, lhs_reg: Rq, rhs_reg: Rq) {
+        match (op.binop(), bitw) {
+            (BinOp::Add, 32) => dynasm!(self.asm; add Rd(lhs_reg.code()), Rd(rhs_reg.code())),
+            (BinOp::Add, 64) => dynasm!(self.asm; add Rq(lhs_reg.code()), Rq(rhs_reg.code())),
+            (BinOp::Sub, 32) => dynasm!(self.asm; sub Rd(lhs_reg.code()), Rd(rhs_reg.code())),
+            (BinOp::Sub, 64) => dynasm!(self.asm; sub Rq(lhs_reg.code()), Rq(rhs_reg.code())),
+            (BinOp::Mul, 32) => {
+                assert_eq!(op, OverflowOp::SMul);
+                dynasm!(self.asm; imul Rd(lhs_reg.code()), Rd(rhs_reg.code()))
+            }
+            (BinOp::Mul, 64) => {
+                assert_eq!(op, OverflowOp::SMul);
+                dynasm!(self.asm; imul Rq(lhs_reg.code()), Rq(rhs_reg.code()))
+            }
+            x => todo!("{x:?}"),
+        }
+    }
+
+    /// Codegen the guard `g_inst` on the result of an overflow check whose arithmetic operation
+    /// has just set the flags.
+    fn cg_overflow_jcc(&mut self, op: OverflowOp, g_iidx: InstIdx, g_inst: jit_ir::GuardInst) {
+        self.ra.expire_regs(g_iidx);
+        self.comment_inst(g_iidx, g_inst.into());
+        let fail_label = self.guard_to_deopt(g_inst);
+        // Signed overflow is signalled by the overflow flag, unsigned overflow by the carry flag.
+        match (op.signed(), g_inst.expect()) {
+            (true, true) => dynasm!(self.asm; jno => fail_label),
+            (true, false) => dynasm!(self.asm; jo => fail_label),
+            (false, true) => dynasm!(self.asm; jnc => fail_label),
+            (false, false) => dynasm!(self.asm; jc => fail_label),
+        }
+    }
+
+    fn cg_overflow(&mut self, iidx: InstIdx, inst: &jit_ir::OverflowInst) {
+        let (lhs, op, rhs) = (inst.lhs(self.m), inst.op(), inst.rhs(self.m));
+        let bitw = lhs.bitw(self.m);
+        match bitw {
+            32 | 64 if op == OverflowOp::UMul => {
+                // `mul` operates on RDX:RAX, setting the carry flag if the upper half of the
+                // product (in RDX) is non-zero.
+                let [lhs_reg, rhs_reg, _, _, out_reg] = self.ra.assign_gp_regs(
+                    &mut self.asm,
+                    iidx,
+                    [
+                        GPConstraint::Input {
+                            op: lhs,
+                            in_ext: RegExtension::Undefined,
+                            force_reg: None,
+                            clobber_reg: false,
+                        },
+                        GPConstraint::Input {
+                            op: rhs,
+                            in_ext: RegExtension::Undefined,
+                            force_reg: None,
+                            clobber_reg: false,
+                        },
+                        GPConstraint::Clobber { force_reg: Rq::RAX },
+                        GPConstraint::Clobber { force_reg: Rq::RDX },
+                        GPConstraint::Output {
+                            out_ext: RegExtension::Undefined,
+                            force_reg: None,
+                            can_be_same_as_input: true,
+                        },
+                    ],
+                );
+                dynasm!(self.asm; mov rax, Rq(lhs_reg.code()));
+                match bitw {
+                    32 => dynasm!(self.asm; mul Rd(rhs_reg.code())),
+                    64 => dynasm!(self.asm; mul Rq(rhs_reg.code())),
+                    _ => unreachable!(),
+                }
+                dynasm!(self.asm; setc Rb(out_reg.code()));
+            }
+            32 | 64 => {
+                let [lhs_reg, rhs_reg, tmp_reg, out_reg] = self.ra.assign_gp_regs(
+                    &mut self.asm,
+                    iidx,
+                    [
+                        GPConstraint::Input {
+                            op: lhs,
+                            in_ext: RegExtension::Undefined,
+                            force_reg: None,
+                            clobber_reg: false,
+                        },
+                        GPConstraint::Input {
+                            op: rhs,
+                            in_ext: RegExtension::Undefined,
+                            force_reg: None,
+                            clobber_reg: false,
+                        },
+                        GPConstraint::Temporary,
+                        GPConstraint::Output {
+                            out_ext: RegExtension::Undefined,
+                            force_reg: None,
+                            can_be_same_as_input: true,
+                        },
+                    ],
+                );
+                dynasm!(self.asm; mov Rq(tmp_reg.code()), Rq(lhs_reg.code()));
+                self.cg_overflow_arith(op, bitw, tmp_reg, rhs_reg);
+                if op.signed() {
+                    dynasm!(self.asm; seto Rb(out_reg.code()));
+                } else {
+                    dynasm!(self.asm; setc Rb(out_reg.code()));
+                }
+            }
+            8 | 16 => {
+                // We extend the operands to 32 bits, where the operation can't overflow, and then
+                // check whether the result fits in `bitw` bits.
+                let in_ext = if op.signed() {
+                    RegExtension::SignExtended
+                } else {
+                    RegExtension::ZeroExtended
+                };
+                let [lhs_reg, rhs_reg, tmp_reg, out_reg] = self.ra.assign_gp_regs(
+                    &mut self.asm,
+                    iidx,
+                    [
+                        GPConstraint::Input {
+                            op: lhs,
+                            in_ext,
+                            force_reg: None,
+                            clobber_reg: false,
+                        },
+                        GPConstraint::Input {
+                            op: rhs,
+                            in_ext,
+                            force_reg: None,
+                            clobber_reg: false,
+                        },
+                        GPConstraint::Temporary,
+                        GPConstraint::Output {
+                            out_ext: RegExtension::Undefined,
+                            force_reg: None,
+                            can_be_same_as_input: true,
+                        },
+                    ],
+                );
+                dynasm!(self.asm; mov Rq(tmp_reg.code()), Rq(lhs_reg.code()));
+                match op.binop() {
+                    BinOp::Add => dynasm!(self.asm; add Rd(tmp_reg.code()), Rd(rhs_reg.code())),
+                    BinOp::Sub => dynasm!(self.asm; sub Rd(tmp_reg.code()), Rd(rhs_reg.code())),
+                    BinOp::Mul => dynasm!(self.asm; imul Rd(tmp_reg.code()), Rd(rhs_reg.code())),
+                    _ => unreachable!(),
+                }
+                if op.signed() {
+                    match bitw {
+                        8 => dynasm!(self.asm; movsx Rd(out_reg.code()), Rb(tmp_reg.code())),
+                        16 => dynasm!(self.asm; movsx Rd(out_reg.code()), Rw(tmp_reg.code())),
+                        _ => unreachable!(),
+                    }
+                    dynasm!(self.asm
+                        ; cmp Rd(out_reg.code()), Rd(tmp_reg.code())
+                        ; setne Rb(out_reg.code())
+                    );
+                } else {
+                    dynasm!(self.asm
+                        ; shr Rd(tmp_reg.code()), i8::try_from(bitw).unwrap()
+                        ; setne Rb(out_reg.code())
+                    );
+                }
+            }
+            x => todo!("{x}"),
+        }
+    }
+
     fn cg_fcmp(&mut self, iidx: InstIdx, inst: &jit_ir::FCmpInst) {
         // For some predicates we do as LLVM does and rewrite the operation into an equivalent one
         // that can be codegenned more efficiently.
@@ -3363,6 +4270,99 @@ impl<'a> Assemble<'a> {
         }
     }
 
+    fn cg_fptoui(&mut self, iidx: InstIdx, inst: &jit_ir::FPToUIInst) {
+        let from_val = inst.val(self.m);
+        // Unwrap cannot fail: floats and integers are sized.
+        let from_bitw = from_val.bitw(self.m);
+        let to_bitw = self.m.type_(inst.dest_tyidx()).bitw().unwrap();
+        assert!(to_bitw <= 64);
+
+        if to_bitw < 64 {
+            // Any value which fits in the destination type also fits in an `i64`, so a signed
+            // 64-bit conversion gives us the right answer.
+            let ([tgt_reg], [src_reg]) = self.ra.assign_regs(
+                &mut self.asm,
+                iidx,
+                [GPConstraint::Output {
+                    out_ext: RegExtension::ZeroExtended,
+                    force_reg: None,
+                    can_be_same_as_input: false,
+                }],
+                [RegConstraint::Input(from_val)],
+            );
+            match from_bitw {
+                32 => dynasm!(self.asm; cvttss2si Rq(tgt_reg.code()), Rx(src_reg.code())),
+                64 => dynasm!(self.asm; cvttsd2si Rq(tgt_reg.code()), Rx(src_reg.code())),
+                _ => panic!(),
+            }
+            return;
+        }
+
+        // x64 can only convert floats to signed 64-bit integers. If a signed conversion overflows
+        // (i.e. produces a negative number), the value must be in the range [2^63, 2^64): we then
+        // convert `val - 2^63` and set the top bit of the result.
+        let ([tgt_reg, tmp_reg], [src_reg, tmp_freg]) = self.ra.assign_regs(
+            &mut self.asm,
+            iidx,
+            [
+                GPConstraint::Output {
+                    out_ext: RegExtension::ZeroExtended,
+                    force_reg: None,
+                    can_be_same_as_input: false,
+                },
+                GPConstraint::Temporary,
+            ],
+            [RegConstraint::Input(from_val), RegConstraint::Temporary],
+        );
+        match from_bitw {
+            32 => dynasm!(self.asm
+                ;   cvttss2si Rq(tgt_reg.code()), Rx(src_reg.code())
+                ;   test Rq(tgt_reg.code()), Rq(tgt_reg.code())
+                ;   jns >done
+                ;   mov Rd(tmp_reg.code()), (-2f32).powi(63).to_bits() as i32
+                ;   movd Rx(tmp_freg.code()), Rd(tmp_reg.code())
+                ;   addss Rx(tmp_freg.code()), Rx(src_reg.code())
+                ;   cvttss2si Rq(tgt_reg.code()), Rx(tmp_freg.code())
+                ;   btc Rq(tgt_reg.code()), 63
+                ; done:
+            ),
+            64 => dynasm!(self.asm
+                ;   cvttsd2si Rq(tgt_reg.code()), Rx(src_reg.code())
+                ;   test Rq(tgt_reg.code()), Rq(tgt_reg.code())
+                ;   jns >done
+                ;   mov Rq(tmp_reg.code()), QWORD (-2f64).powi(63).to_bits() as i64
+                ;   movq Rx(tmp_freg.code()), Rq(tmp_reg.code())
+                ;   addsd Rx(tmp_freg.code()), Rx(src_reg.code())
+                ;   cvttsd2si Rq(tgt_reg.code()), Rx(tmp_freg.code())
+                ;   btc Rq(tgt_reg.code()), 63
+                ; done:
+            ),
+            _ => panic!(),
+        }
+    }
+
+    fn cg_fptrunc(&mut self, iidx: InstIdx, i: &jit_ir::FPTruncInst) {
+        let from_val = i.val(self.m);
+        let from_type = self.m.type_(from_val.tyidx(self.m));
+        let to_type = self.m.type_(i.dest_tyidx());
+
+        let [tgt_reg] =
+            self.ra
+                .assign_fp_regs(&mut self.asm, iidx, [RegConstraint::InputOutput(from_val)]);
+
+        match (from_type, to_type) {
+            (
+                jit_ir::Ty::Float(jit_ir::FloatTy::Double),
+                jit_ir::Ty::Float(jit_ir::FloatTy::Float),
+            ) => dynasm!(self.asm; cvtsd2ss Rx(tgt_reg.code()), Rx(tgt_reg.code())),
+            _ => {
+                // since we only support 32- and 64-bit floats, any other conversion is
+                // nonsensical.
+                panic!();
+            }
+        }
+    }
+
     fn cg_fpext(&mut self, iidx: InstIdx, i: &jit_ir::FPExtInst) {
         let from_val = i.val(self.m);
         let from_type = self.m.type_(from_val.tyidx(self.m));
@@ -3566,6 +4566,389 @@ impl<'a> Assemble<'a> {
         }
     }
 
+    /// Generate code for an instruction which defines, or uses, an integer wider than 64 bits
+    /// (i.e. an `i128`). Such integers never live in registers: each is stored in its own 16-byte
+    /// stack slot (see [LSRegAlloc::assign_wide_int_stack]) which we operate on 64 bits at a time.
+    fn cg_wide_int(&mut self, iidx: InstIdx, inst: Inst) {
+        match inst {
+            #[cfg(test)]
+            Inst::BlackBox(_) => (),
+            Inst::BinOp(x) => self.cg_wide_binop(iidx, &x),
+            Inst::ICmp(x) => self.cg_wide_icmp(iidx, &x),
+            Inst::ZExt(x) => self.cg_wide_ext(iidx, x.val(self.m), RegExtension::ZeroExtended),
+            Inst::SExt(x) => self.cg_wide_ext(iidx, x.val(self.m), RegExtension::SignExtended),
+            Inst::Trunc(x) => self.cg_wide_trunc(iidx, &x),
+            Inst::Load(x) => self.cg_wide_load(iidx, &x),
+            Inst::Store(x) => self.cg_wide_store(iidx, &x),
+            Inst::Select(x) => self.cg_wide_select(iidx, &x),
+            x => todo!("{}", x.display(self.m, iidx)),
+        }
+    }
+
+    /// Where is the 128-bit integer `op`?
+    fn wide_int_loc(&self, op: &Operand) -> WideIntLoc {
+        match op {
+            Operand::Const(cidx) => {
+                let Const::Int(_, x) = self.m.const_(*cidx) else {
+                    panic!()
+                };
+                WideIntLoc::Const(x.to_zero_ext_u128().unwrap())
+            }
+            Operand::Var(iidx) => WideIntLoc::Stack(self.ra.wide_int_stack_off(*iidx)),
+        }
+    }
+
+    /// Move the low (if `hi` is false) or high (if `hi` is true) 64 bits of the 128-bit integer at
+    /// `loc` into `reg`.
+    fn cg_wide_int_half(&mut self, reg: Rq, loc: &WideIntLoc, hi: bool) {
+        match loc {
+            WideIntLoc::Const(v) => {
+                let v = if hi { (v >> 64) as u64 } else { *v as u64 };
+                dynasm!(self.asm; mov Rq(reg.code()), QWORD v.cast_signed());
+            }
+            WideIntLoc::Stack(off) => {
+                let off = if hi { off - 8 } else { *off };
+                dynasm!(self.asm; mov Rq(reg.code()), [rbp - off]);
+            }
+        }
+    }
+
+    /// Move both halves of the 128-bit integer at `loc` into `lo` and `hi`.
+    fn cg_wide_int_halves(&mut self, lo: Rq, hi: Rq, loc: &WideIntLoc) {
+        self.cg_wide_int_half(lo, loc, false);
+        self.cg_wide_int_half(hi, loc, true);
+    }
+
+    /// Store `lo` and `hi` as the low and high halves of the 128-bit integer defined by `iidx`.
+    fn cg_wide_int_def(&mut self, iidx: InstIdx, lo: Rq, hi: Rq) {
+        let off = self.ra.assign_wide_int_stack(iidx);
+        let hi_off = off - 8;
+        dynasm!(self.asm
+            ; mov [rbp - off], Rq(lo.code())
+            ; mov [rbp - hi_off], Rq(hi.code())
+        );
+    }
+
+    fn cg_wide_binop(&mut self, iidx: InstIdx, inst: &jit_ir::BinOpInst) {
+        let lhs = self.wide_int_loc(&inst.lhs(self.m));
+        let rhs = self.wide_int_loc(&inst.rhs(self.m));
+        match inst.binop() {
+            BinOp::Add | BinOp::Sub | BinOp::And | BinOp::Or | BinOp::Xor => {
+                let [lo, hi, rhs_lo, rhs_hi] = self.ra.assign_gp_regs(
+                    &mut self.asm,
+                    iidx,
+                    [
+                        GPConstraint::Temporary,
+                        GPConstraint::Temporary,
+                        GPConstraint::Temporary,
+                        GPConstraint::Temporary,
+                    ],
+                );
+                self.cg_wide_int_halves(lo, hi, &lhs);
+                self.cg_wide_int_halves(rhs_lo, rhs_hi, &rhs);
+                match inst.binop() {
+                    BinOp::Add => dynasm!(self.asm
+                        ; add Rq(lo.code()), Rq(rhs_lo.code())
+                        ; adc Rq(hi.code()), Rq(rhs_hi.code())
+                    ),
+                    BinOp::Sub => dynasm!(self.asm
+                        ; sub Rq(lo.code()), Rq(rhs_lo.code())
+                        ; sbb Rq(hi.code()), Rq(rhs_hi.code())
+                    ),
+                    BinOp::And => dynasm!(self.asm
+                        ; and Rq(lo.code()), Rq(rhs_lo.code())
+                        ; and Rq(hi.code()), Rq(rhs_hi.code())
+                    ),
+                    BinOp::Or => dynasm!(self.asm
+                        ; or Rq(lo.code()), Rq(rhs_lo.code())
+                        ; or Rq(hi.code()), Rq(rhs_hi.code())
+                    ),
+                    BinOp::Xor => dynasm!(self.asm
+                        ; xor Rq(lo.code()), Rq(rhs_lo.code())
+                        ; xor Rq(hi.code()), Rq(rhs_hi.code())
+                    ),
+                    _ => unreachable!(),
+                }
+                self.cg_wide_int_def(iidx, lo, hi);
+            }
+            BinOp::Mul => {
+                // The low 128 bits of `(a_hi * 2^64 + a_lo) * (b_hi * 2^64 + b_lo)` are
+                // `a_lo * b_lo + ((a_lo * b_hi + a_hi * b_lo) << 64)`.
+                let [lo, hi, tmp1, tmp2] = self.ra.assign_gp_regs(
+                    &mut self.asm,
+                    iidx,
+                    [
+                        GPConstraint::Clobber { force_reg: Rq::RAX },
+                        GPConstraint::Clobber { force_reg: Rq::RDX },
+                        GPConstraint::Temporary,
+                        GPConstraint::Temporary,
+                    ],
+                );
+                self.cg_wide_int_half(lo, &lhs, false);
+                self.cg_wide_int_half(tmp1, &rhs, false);
+                dynasm!(self.asm; mul Rq(tmp1.code()));
+                self.cg_wide_int_half(tmp1, &lhs, false);
+                self.cg_wide_int_half(tmp2, &rhs, true);
+                dynasm!(self.asm
+                    ; imul Rq(tmp1.code()), Rq(tmp2.code())
+                    ; add Rq(hi.code()), Rq(tmp1.code())
+                );
+                self.cg_wide_int_half(tmp1, &lhs, true);
+                self.cg_wide_int_half(tmp2, &rhs, false);
+                dynasm!(self.asm
+                    ; imul Rq(tmp1.code()), Rq(tmp2.code())
+                    ; add Rq(hi.code()), Rq(tmp1.code())
+                );
+                self.cg_wide_int_def(iidx, lo, hi);
+            }
+            BinOp::Shl | BinOp::LShr | BinOp::AShr => {
+                // `shld`/`shrd` only shift by the bottom 6 bits of `cl`: if bit 6 is set we then
+                // have to move one half into the other.
+                let [_, lo, hi] = self.ra.assign_gp_regs(
+                    &mut self.asm,
+                    iidx,
+                    [
+                        GPConstraint::Clobber { force_reg: Rq::RCX },
+                        GPConstraint::Temporary,
+                        GPConstraint::Temporary,
+                    ],
+                );
+                self.cg_wide_int_half(Rq::RCX, &rhs, false);
+                self.cg_wide_int_halves(lo, hi, &lhs);
+                match inst.binop() {
+                    BinOp::Shl => dynasm!(self.asm
+                        ;   shld Rq(hi.code()), Rq(lo.code()), cl
+                        ;   shl Rq(lo.code()), cl
+                        ;   test cl, 64
+                        ;   jz >done
+                        ;   mov Rq(hi.code()), Rq(lo.code())
+                        ;   xor Rd(lo.code()), Rd(lo.code())
+                        ; done:
+                    ),
+                    BinOp::LShr => dynasm!(self.asm
+                        ;   shrd Rq(lo.code()), Rq(hi.code()), cl
+                        ;   shr Rq(hi.code()), cl
+                        ;   test cl, 64
+                        ;   jz >done
+                        ;   mov Rq(lo.code()), Rq(hi.code())
+                        ;   xor Rd(hi.code()), Rd(hi.code())
+                        ; done:
+                    ),
+                    BinOp::AShr => dynasm!(self.asm
+                        ;   shrd Rq(lo.code()), Rq(hi.code()), cl
+                        ;   sar Rq(hi.code()), cl
+                        ;   test cl, 64
+                        ;   jz >done
+                        ;   mov Rq(lo.code()), Rq(hi.code())
+                        ;   sar Rq(hi.code()), 63
+                        ; done:
+                    ),
+                    _ => unreachable!(),
+                }
+                self.cg_wide_int_def(iidx, lo, hi);
+            }
+            x => todo!("{x}"),
+        }
+    }
+
+    fn cg_wide_icmp(&mut self, iidx: InstIdx, inst: &jit_ir::ICmpInst) {
+        let pred = inst.predicate();
+        let mut lhs = self.wide_int_loc(&inst.lhs(self.m));
+        let mut rhs = self.wide_int_loc(&inst.rhs(self.m));
+        let [out_reg, lo, hi, rhs_lo, rhs_hi] = self.ra.assign_gp_regs(
+            &mut self.asm,
+            iidx,
+            [
+                GPConstraint::Output {
+                    out_ext: RegExtension::Undefined,
+                    force_reg: None,
+                    can_be_same_as_input: false,
+                },
+                GPConstraint::Temporary,
+                GPConstraint::Temporary,
+                GPConstraint::Temporary,
+                GPConstraint::Temporary,
+            ],
+        );
+        if matches!(pred, jit_ir::Predicate::Equal | jit_ir::Predicate::NotEqual) {
+            self.cg_wide_int_halves(lo, hi, &lhs);
+            self.cg_wide_int_halves(rhs_lo, rhs_hi, &rhs);
+            dynasm!(self.asm
+                ; xor Rq(lo.code()), Rq(rhs_lo.code())
+                ; xor Rq(hi.code()), Rq(rhs_hi.code())
+                ; or Rq(lo.code()), Rq(hi.code())
+            );
+            if pred == jit_ir::Predicate::Equal {
+                dynasm!(self.asm; sete Rb(out_reg.code()));
+            } else {
+                dynasm!(self.asm; setne Rb(out_reg.code()));
+            }
+            return;
+        }
+        // There is no 128-bit `cmp`, but subtracting with borrow leaves the flags that a `cmp` of
+        // the high halves would, taking into account the low halves. However, `ZF` only reflects
+        // the high halves, so we can only directly test for "less than" and "greater or equal":
+        // the other predicates swap their operands.
+        if matches!(
+            pred,
+            jit_ir::Predicate::UnsignedGreater
+                | jit_ir::Predicate::UnsignedLessEqual
+                | jit_ir::Predicate::SignedGreater
+                | jit_ir::Predicate::SignedLessEqual
+        ) {
+            std::mem::swap(&mut lhs, &mut rhs);
+        }
+        self.cg_wide_int_halves(lo, hi, &lhs);
+        self.cg_wide_int_halves(rhs_lo, rhs_hi, &rhs);
+        dynasm!(self.asm
+            ; cmp Rq(lo.code()), Rq(rhs_lo.code())
+            ; sbb Rq(hi.code()), Rq(rhs_hi.code())
+        );
+        match pred {
+            jit_ir::Predicate::UnsignedLess | jit_ir::Predicate::UnsignedGreater => {
+                dynasm!(self.asm; setb Rb(out_reg.code()))
+            }
+            jit_ir::Predicate::UnsignedGreaterEqual | jit_ir::Predicate::UnsignedLessEqual => {
+                dynasm!(self.asm; setae Rb(out_reg.code()))
+            }
+            jit_ir::Predicate::SignedLess | jit_ir::Predicate::SignedGreater => {
+                dynasm!(self.asm; setl Rb(out_reg.code()))
+            }
+            jit_ir::Predicate::SignedGreaterEqual | jit_ir::Predicate::SignedLessEqual => {
+                dynasm!(self.asm; setge Rb(out_reg.code()))
+            }
+            jit_ir::Predicate::Equal | jit_ir::Predicate::NotEqual => unreachable!(),
+        }
+    }
+
+    /// Zero or sign extend (as specified by `ext`) the integer `val`, of at most 64 bits, to the
+    /// 128-bit integer defined by `iidx`.
+    fn cg_wide_ext(&mut self, iidx: InstIdx, val: Operand, ext: RegExtension) {
+        let [in_reg, hi] = self.ra.assign_gp_regs(
+            &mut self.asm,
+            iidx,
+            [
+                GPConstraint::Input {
+                    op: val,
+                    in_ext: ext,
+                    force_reg: None,
+                    clobber_reg: false,
+                },
+                GPConstraint::Temporary,
+            ],
+        );
+        if ext == RegExtension::SignExtended {
+            dynasm!(self.asm
+                ; mov Rq(hi.code()), Rq(in_reg.code())
+                ; sar Rq(hi.code()), 63
+            );
+        } else {
+            dynasm!(self.asm; xor Rd(hi.code()), Rd(hi.code()));
+        }
+        self.cg_wide_int_def(iidx, in_reg, hi);
+    }
+
+    fn cg_wide_trunc(&mut self, iidx: InstIdx, inst: &jit_ir::TruncInst) {
+        let val = self.wide_int_loc(&inst.val(self.m));
+        let [out_reg] = self.ra.assign_gp_regs(
+            &mut self.asm,
+            iidx,
+            [GPConstraint::Output {
+                out_ext: RegExtension::ZeroExtended,
+                force_reg: None,
+                can_be_same_as_input: false,
+            }],
+        );
+        self.cg_wide_int_half(out_reg, &val, false);
+        self.ra.force_zero_extend_to_reg64(
+            &mut self.asm,
+            out_reg,
+            self.m.type_(inst.dest_tyidx()).bitw().unwrap(),
+        );
+    }
+
+    fn cg_wide_load(&mut self, iidx: InstIdx, inst: &jit_ir::LoadInst) {
+        let (ptr_op, off) = match self.ra.ptradd(iidx) {
+            Some(x) => (x.ptr(self.m), x.off()),
+            None => (inst.ptr(self.m), 0),
+        };
+        let [ptr_reg, lo, hi] = self.ra.assign_gp_regs(
+            &mut self.asm,
+            iidx,
+            [
+                GPConstraint::Input {
+                    op: ptr_op,
+                    in_ext: RegExtension::Undefined,
+                    force_reg: None,
+                    clobber_reg: false,
+                },
+                GPConstraint::Temporary,
+                GPConstraint::Temporary,
+            ],
+        );
+        dynasm!(self.asm
+            ; mov Rq(lo.code()), [Rq(ptr_reg.code()) + off]
+            ; mov Rq(hi.code()), [Rq(ptr_reg.code()) + off + 8]
+        );
+        self.cg_wide_int_def(iidx, lo, hi);
+    }
+
+    fn cg_wide_store(&mut self, iidx: InstIdx, inst: &jit_ir::StoreInst) {
+        let (ptr_op, off) = match self.ra.ptradd(iidx) {
+            Some(x) => (x.ptr(self.m), x.off()),
+            None => (inst.ptr(self.m), 0),
+        };
+        let val = self.wide_int_loc(&inst.val(self.m));
+        let [ptr_reg, lo, hi] = self.ra.assign_gp_regs(
+            &mut self.asm,
+            iidx,
+            [
+                GPConstraint::Input {
+                    op: ptr_op,
+                    in_ext: RegExtension::Undefined,
+                    force_reg: None,
+                    clobber_reg: false,
+                },
+                GPConstraint::Temporary,
+                GPConstraint::Temporary,
+            ],
+        );
+        self.cg_wide_int_halves(lo, hi, &val);
+        dynasm!(self.asm
+            ; mov [Rq(ptr_reg.code()) + off], Rq(lo.code())
+            ; mov [Rq(ptr_reg.code()) + off + 8], Rq(hi.code())
+        );
+    }
+
+    fn cg_wide_select(&mut self, iidx: InstIdx, inst: &jit_ir::SelectInst) {
+        let trueval = self.wide_int_loc(&inst.trueval(self.m));
+        let falseval = self.wide_int_loc(&inst.falseval(self.m));
+        let [cond_reg, lo, hi, false_lo, false_hi] = self.ra.assign_gp_regs(
+            &mut self.asm,
+            iidx,
+            [
+                GPConstraint::Input {
+                    op: inst.cond(self.m),
+                    in_ext: RegExtension::Undefined,
+                    force_reg: None,
+                    clobber_reg: false,
+                },
+                GPConstraint::Temporary,
+                GPConstraint::Temporary,
+                GPConstraint::Temporary,
+                GPConstraint::Temporary,
+            ],
+        );
+        self.cg_wide_int_halves(lo, hi, &trueval);
+        self.cg_wide_int_halves(false_lo, false_hi, &falseval);
+        dynasm!(self.asm
+            ; bt Rd(cond_reg.code()), 0
+            ; cmovnc Rq(lo.code()), Rq(false_lo.code())
+            ; cmovnc Rq(hi.code()), Rq(false_hi.code())
+        );
+        self.cg_wide_int_def(iidx, lo, hi);
+    }
+
     fn guard_to_deopt(&mut self, ginst: jit_ir::GuardInst) -> DynamicLabel {
         let fail_label = self.asm.new_dynamic_label();
         let ginfo = ginst.guard_info(self.m);
@@ -3630,6 +5013,31 @@ impl<'a> Assemble<'a> {
     }
 }
 
+/// Does `inst` define, or use, an integer wider than 64 bits? The code generator handles such
+/// instructions with [Assemble::cg_wide_int].
+pub(super) fn is_wide_int_inst(m: &Module, inst: &Inst) -> bool {
+    let is_wide = |op: Operand| op.bitw(m) > 64;
+    match inst {
+        #[cfg(test)]
+        Inst::BlackBox(x) => is_wide(x.operand(m)),
+        Inst::BinOp(x) => is_wide(x.lhs(m)),
+        Inst::ICmp(x) => is_wide(x.lhs(m)),
+        Inst::Trunc(x) => is_wide(x.val(m)),
+        Inst::Store(x) => is_wide(x.val(m)),
+        Inst::Load(_) | Inst::Select(_) | Inst::SExt(_) | Inst::ZExt(_) => inst.def_bitw(m) > 64,
+        _ => false,
+    }
+}
+
+/// Where is a 128-bit integer? Such integers never live in registers.
+enum WideIntLoc {
+    /// A constant.
+    Const(u128),
+    /// On the stack, with the low 64 bits at `[rbp - off]` and the high 64 bits at
+    /// `[rbp - off + 8]`.
+    Stack(i32),
+}
+
 /// Information required by guards while we're compiling them.
 #[derive(Debug)]
 struct CompilingGuard {
@@ -3648,11 +5056,28 @@ struct CompiledGuard {
     /// The AOT block that the failing guard originated from.
     bid: aot_ir::BBlockId,
     fail_offset: AssemblyOffset,
+    /// The offset of the code which calls `__yk_deopt`. Until a side-trace is patched in (see
+    /// [X64CompiledTrace::patch_guard]), the `mov` at `fail_offset` loads this address.
+    deopt_offset: AssemblyOffset,
     /// Live variables, mapping AOT vars to JIT vars.
     live_vars: Vec<(aot_ir::InstId, VarLocation)>,
     inlined_frames: Vec<InlinedFrame>,
     /// Keeps track of deopt amount and compiled side-trace.
     guard: Guard,
+    /// The virtual allocations that must be materialised before live variables are written back.
+    /// [VarLocation::Virtual] live variables index into this.
+    virtuals: Vec<CompiledVirtualAlloc>,
+}
+
+/// A virtual allocation (see [jit_ir::VirtualAlloc]) that deopt must materialise.
+#[derive(Debug)]
+struct CompiledVirtualAlloc {
+    /// The address of the allocator.
+    allocator: usize,
+    /// The size in bytes of the allocation.
+    size: usize,
+    /// The allocation's fields as `(offset, bytesize, location)` triples.
+    fields: Vec<(u32, usize, VarLocation)>,
 }
 
 #[derive(Debug)]
@@ -3678,6 +5103,8 @@ pub(crate) struct X64CompiledTrace {
     entry_vars: Vec<VarLocation>,
     /// Reference to the HotLocation, required for side tracing.
     hl: Weak<Mutex<HotLocation>>,
+    /// Has this trace been invalidated? See [CompiledTrace::invalidate].
+    invalidated: AtomicBool,
     /// Comments to be shown when printing the compiled trace using `AsmPrinter`.
     ///
     /// Maps a byte offset in the native JITted code to a collection of line comments to show when
@@ -3732,6 +5159,10 @@ impl CompiledTrace for X64CompiledTrace {
         self.ctrid
     }
 
+    fn mt(&self) -> &Arc<MT> {
+        &self.mt
+    }
+
     fn safepoint(&self) -> &Option<DeoptSafepoint> {
         &self.safepoint
     }
@@ -3765,6 +5196,27 @@ impl CompiledTrace for X64CompiledTrace {
         patch_addresses(&[(patch_addr, staddr as u64)]);
     }
 
+    /// Patch the address of the deopt routine back into the parent trace, undoing a previous call
+    /// to [Self::patch_guard].
+    /// * `gidx`: The guard to be unpatched.
+    fn unpatch_guard(&self, gidx: GuardIdx) {
+        let _lock = LK_PATCH.lock();
+
+        let cgd = &self.compiled_guards[usize::from(gidx)];
+        // Add 2 bytes to get to the address operand of the mov instruction.
+        let patch_addr = unsafe { self.buf.ptr(cgd.fail_offset).offset(2) };
+        let deopt_addr = self.buf.ptr(cgd.deopt_offset);
+        patch_addresses(&[(patch_addr, deopt_addr as u64)]);
+    }
+
+    fn invalidate(&self) {
+        self.invalidated.store(true, Ordering::SeqCst);
+    }
+
+    fn is_invalidated(&self) -> bool {
+        self.invalidated.load(Ordering::SeqCst)
+    }
+
     fn hl(&self) -> &std::sync::Weak<parking_lot::Mutex<crate::location::HotLocation>> {
         &self.hl
     }
@@ -3916,7 +5368,7 @@ mod tests {
     use crate::{
         compile::{
             jitc_yk::jit_ir::{self, Inst, Module, ParamIdx, TraceKind},
-            CompiledTrace,
+            CompilationError, CompiledTrace,
         },
         location::{HotLocation, HotLocationKind},
         mt::{TraceId, MT},
@@ -4116,6 +5568,7 @@ mod tests {
             tracecompilation_errors: 0,
             #[cfg(feature = "ykd")]
             debug_str: None,
+            invalidated: None,
         };
         match_asm(
             Assemble::new(&m)
@@ -4339,6 +5792,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn cg_store_const_float() {
+        codegen_and_test(
+            "
+              entry:
+                %0: ptr = param reg
+                *%0 = 1.5float
+                *%0 = 0double
+                *%0 = 2.5double
+            ",
+            "
+                ...
+                ; *%0 = 1.5float
+                mov dword ptr [rax], 0x3FC00000
+                ; *%0 = 0double
+                mov qword ptr [rax], 0x00
+                ; *%0 = 2.5double
+                mov r.64.x, 0x4004000000000000
+                mov [rax], r.64.x
+                ",
+            false,
+        );
+    }
+
     #[test]
     fn cg_const_ptr() {
         codegen_and_test(
@@ -5350,6 +6827,70 @@ mod tests {
         );
     }
 
+    #[test]
+    fn cg_second_ret_val() {
+        codegen_and_test(
+            "
+             func_decl ldiv(i64, i64) -> i64
+             entry:
+               %0: i64 = param reg
+               %1: i64 = call @ldiv(%0, 3i64)
+               %2: i64 = second_ret_val
+               %3: i64 = add %1, %2
+               black_box %3
+            ",
+            "
+                ...
+                ; %1: i64 = call @ldiv(%0, 3i64)
+                ...
+                call r.64.x
+                ; %2: i64 = second_ret_val
+                ; %3: i64 = add %1, %2
+                add rax, rdx
+                ...
+            ",
+            false,
+        );
+        codegen_and_test(
+            "
+             func_decl puts() -> i32
+             entry:
+               %0: i32 = call @puts()
+               %1: double = second_ret_val
+               %2: double = fadd %1, %1
+               black_box %0
+               black_box %2
+            ",
+            "
+                ...
+                ; %1: double = second_ret_val
+                ; %2: double = fadd %1, %1
+                movsd fp.128.x, xmm0
+                ...
+            ",
+            false,
+        );
+        codegen_and_test(
+            "
+             func_decl puts() -> double
+             entry:
+               %0: double = call @puts()
+               %1: ptr = second_ret_val
+               %2: i64 = load %1
+               black_box %0
+               black_box %2
+            ",
+            "
+                ...
+                ; %1: ptr = second_ret_val
+                ; %2: i64 = load %1
+                mov r.64._, [rax]
+                ...
+            ",
+            false,
+        );
+    }
+
     #[test]
     fn cg_call_hints() {
         codegen_and_test(
@@ -5481,109 +7022,373 @@ mod tests {
     }
 
     #[test]
-    fn cg_call_memcpy() {
+    fn cg_call_memcpy() {
+        codegen_and_test(
+            "
+             func_decl llvm.memcpy.p0.p0.i64 (ptr, ptr, i64, i1)
+             entry:
+               %0: ptr = param reg
+               %1: ptr = param reg
+               %2: i64 = param reg
+               call @llvm.memcpy.p0.p0.i64(%0, %1, %2, 0i1)
+            ",
+            "
+               ...
+               ; call @llvm.memcpy.p0.p0.i64(%0, %1, %2, 0i1)
+               mov rsi, rcx
+               mov rcx, rdx
+               mov rdi, rax
+               rep movsb
+            ",
+            false,
+        );
+    }
+
+    #[test]
+    fn cg_call_memset() {
+        codegen_and_test(
+            "
+             func_decl llvm.memset.p0.i64 (ptr, i8, i64, i1)
+             entry:
+               %0: ptr = param reg
+               %1: i8 = param reg
+               %2: i64 = param reg
+               call @llvm.memset.p0.i64(%0, %1, %2, 0i1)
+            ",
+            "
+               ...
+               ; call @llvm.memset.p0.i64(%0, %1, %2, 0i1)
+               mov rdi, rax
+               mov rax, rcx
+               mov rcx, rdx
+               and eax, 0xff
+               rep stosb
+            ",
+            false,
+        );
+    }
+
+    #[test]
+    fn cg_call_smax() {
+        codegen_and_test(
+            "
+             func_decl llvm.smax.i64 (i64, i64) -> i64
+             entry:
+               %0: i64 = param reg
+               %1: i64 = param reg
+               %2: i64 = call @llvm.smax.i64(%0, %1)
+               black_box %2
+            ",
+            "
+               ...
+               ; %2: i64 = call @llvm.smax.i64(%0, %1)
+               cmp r.64.a, r.64.b
+               cmovl r.64.a, r.64.b
+            ",
+            false,
+        );
+    }
+
+    #[test]
+    fn cg_call_smin() {
+        codegen_and_test(
+            "
+             func_decl llvm.smin.i64 (i64, i64) -> i64
+             entry:
+               %0: i64 = param reg
+               %1: i64 = param reg
+               %2: i64 = call @llvm.smin.i64(%0, %1)
+               black_box %2
+            ",
+            "
+               ...
+               ; %2: i64 = call @llvm.smin.i64(%0, %1)
+               ...
+               cmp r.64.a, r.64.b
+               cmovnle r.64.a, r.64.b
+            ",
+            false,
+        );
+
+        codegen_and_test(
+            "
+             func_decl llvm.smin.i32 (i32, i32) -> i32
+             entry:
+               %0: i32 = param reg
+               %1: i32 = param reg
+               %2: i32 = call @llvm.smin.i32(%0, %1)
+               black_box %2
+            ",
+            "
+               ...
+               ; %2: i32 = call @llvm.smin.i32(%0, %1)
+               ...
+               cmp r.64.a, r.64.b
+               cmovnle r.64.a, r.64.b
+            ",
+            false,
+        );
+    }
+
+    #[test]
+    fn cg_call_umax_umin() {
+        codegen_and_test(
+            "
+             func_decl llvm.umax.i32 (i32, i32) -> i32
+             func_decl llvm.umin.i32 (i32, i32) -> i32
+             entry:
+               %0: i32 = param reg
+               %1: i32 = param reg
+               %2: i32 = call @llvm.umax.i32(%0, %1)
+               %3: i32 = call @llvm.umin.i32(%0, %1)
+               black_box %2
+               black_box %3
+            ",
+            "
+               ...
+               ; %2: i32 = call @llvm.umax.i32(%0, %1)
+               ...
+               cmp r.64.a, r.64.b
+               cmovb r.64.a, r.64.b
+               ; %3: i32 = call @llvm.umin.i32(%0, %1)
+               ...
+               cmp r.64.c, r.64.b
+               cmovnbe r.64.c, r.64.b
+               ...
+            ",
+            false,
+        );
+    }
+
+    #[test]
+    fn cg_call_ctlz() {
+        codegen_and_test(
+            "
+             func_decl llvm.ctlz.i16 (i16, i1) -> i16
+             entry:
+               %0: i16 = param reg
+               %1: i16 = call @llvm.ctlz.i16(%0, 0i1)
+               black_box %1
+            ",
+            "
+               ...
+               ; %1: i16 = call @llvm.ctlz.i16(%0, 0i1)
+               ...
+               bsr r.32.x, r.32._
+               mov r.32.z, 0x3f
+               cmovz r.32.x, r.32.z
+               xor r.32.x, 0x1f
+               sub r.32.x, 0x10
+            ",
+            false,
+        );
+    }
+
+    #[test]
+    fn cg_call_cttz() {
+        codegen_and_test(
+            "
+             func_decl llvm.cttz.i64 (i64, i1) -> i64
+             entry:
+               %0: i64 = param reg
+               %1: i64 = call @llvm.cttz.i64(%0, 1i1)
+               black_box %1
+            ",
+            "
+               ...
+               ; %1: i64 = call @llvm.cttz.i64(%0, 1i1)
+               ...
+               bsf r.64.x, r.64._
+               mov r.32.z, 0x40
+               cmovz r.32.x, r.32.z
+            ",
+            false,
+        );
+    }
+
+    #[test]
+    fn cg_call_bswap() {
+        codegen_and_test(
+            "
+             func_decl llvm.bswap.i32 (i32) -> i32
+             func_decl llvm.bswap.i16 (i16) -> i16
+             entry:
+               %0: i32 = param reg
+               %1: i16 = param reg
+               %2: i32 = call @llvm.bswap.i32(%0)
+               %3: i16 = call @llvm.bswap.i16(%1)
+               black_box %2
+               black_box %3
+            ",
+            "
+               ...
+               ; %2: i32 = call @llvm.bswap.i32(%0)
+               ...
+               bswap r.32.x
+               ; %3: i16 = call @llvm.bswap.i16(%1)
+               ...
+               rol r.16.y, 0x08
+               ...
+            ",
+            false,
+        );
+    }
+
+    #[test]
+    fn cg_call_fshr() {
         codegen_and_test(
             "
-             func_decl llvm.memcpy.p0.p0.i64 (ptr, ptr, i64, i1)
+             func_decl llvm.fshr.i64 (i64, i64, i64) -> i64
              entry:
-               %0: ptr = param reg
-               %1: ptr = param reg
-               %2: i64 = param reg
-               call @llvm.memcpy.p0.p0.i64(%0, %1, %2, 0i1)
+               %0: i64 = param reg
+               %1: i64 = param reg
+               %2: i64 = call @llvm.fshr.i64(%0, %1, 17i64)
+               black_box %2
             ",
             "
                ...
-               ; call @llvm.memcpy.p0.p0.i64(%0, %1, %2, 0i1)
-               mov rsi, rcx
-               mov rcx, rdx
-               mov rdi, rax
-               rep movsb
+               ; %2: i64 = call @llvm.fshr.i64(%0, %1, 17i64)
+               ...
+               shrd r.64.b, r.64.a, 0x11
             ",
             false,
         );
     }
 
     #[test]
-    fn cg_call_memset() {
-        codegen_and_test(
+    fn cg_call_unsupported_intrinsics() {
+        for mod_str in [
             "
-             func_decl llvm.memset.p0.i64 (ptr, i8, i64, i1)
+             func_decl llvm.bswap.i8 (i8) -> i8
              entry:
-               %0: ptr = param reg
-               %1: i8 = param reg
-               %2: i64 = param reg
-               call @llvm.memset.p0.i64(%0, %1, %2, 0i1)
+               %0: i8 = param reg
+               %1: i8 = call @llvm.bswap.i8(%0)
+               black_box %1
             ",
             "
-               ...
-               ; call @llvm.memset.p0.i64(%0, %1, %2, 0i1)
-               mov rdi, rax
-               mov rax, rcx
-               mov rcx, rdx
-               and eax, 0xff
-               rep stosb
+             func_decl llvm.ctlz.i48 (i48, i1) -> i48
+             entry:
+               %0: i48 = param reg
+               %1: i48 = call @llvm.ctlz.i48(%0, 0i1)
+               black_box %1
             ",
-            false,
-        );
+            "
+             func_decl llvm.cttz.i48 (i48, i1) -> i48
+             entry:
+               %0: i48 = param reg
+               %1: i48 = call @llvm.cttz.i48(%0, 0i1)
+               black_box %1
+            ",
+            "
+             func_decl llvm.fshr.i16 (i16, i16, i16) -> i16
+             entry:
+               %0: i16 = param reg
+               %1: i16 = param reg
+               %2: i16 = call @llvm.fshr.i16(%0, %1, 3i16)
+               black_box %2
+            ",
+            "
+             func_decl llvm.fshr.i64 (i64, i64, i64) -> i64
+             entry:
+               %0: i64 = param reg
+               %1: i64 = param reg
+               %2: i64 = param reg
+               %3: i64 = call @llvm.fshr.i64(%0, %1, %2)
+               black_box %3
+            ",
+        ] {
+            let m = Module::from_str(mod_str);
+            let mt = MT::new().unwrap();
+            let hl = HotLocation {
+                kind: HotLocationKind::Tracing(mt.next_trace_id()),
+                tracecompilation_errors: 0,
+                #[cfg(feature = "ykd")]
+                debug_str: None,
+                invalidated: None,
+            };
+            assert!(matches!(
+                Assemble::new(&m)
+                    .unwrap()
+                    .codegen(mt, Arc::new(Mutex::new(hl))),
+                Err(CompilationError::General(_))
+            ));
+        }
     }
 
     #[test]
-    fn cg_call_smax() {
+    fn cg_call_ceil_sqrt() {
         codegen_and_test(
             "
-             func_decl llvm.smax.i64 (i64, i64) -> i64
+             func_decl llvm.ceil.f32 (float) -> float
+             func_decl llvm.sqrt.f64 (double) -> double
              entry:
-               %0: i64 = param reg
-               %1: i64 = param reg
-               %2: i64 = call @llvm.smax.i64(%0, %1)
+               %0: float = param reg
+               %1: double = param reg
+               %2: float = call @llvm.ceil.f32(%0)
+               %3: double = call @llvm.sqrt.f64(%1)
                black_box %2
+               black_box %3
             ",
             "
                ...
-               ; %2: i64 = call @llvm.smax.i64(%0, %1)
-               cmp r.64.a, r.64.b
-               cmovl r.64.a, r.64.b
+               ; %2: float = call @llvm.ceil.f32(%0)
+               roundss fp.128._, fp.128._, 0x02
+               ; %3: double = call @llvm.sqrt.f64(%1)
+               sqrtsd fp.128._, fp.128._
+               ...
             ",
             false,
         );
     }
 
     #[test]
-    fn cg_call_smin() {
+    fn cg_call_fabs() {
         codegen_and_test(
             "
-             func_decl llvm.smin.i64 (i64, i64) -> i64
+             func_decl llvm.fabs.f64 (double) -> double
              entry:
-               %0: i64 = param reg
-               %1: i64 = param reg
-               %2: i64 = call @llvm.smin.i64(%0, %1)
-               black_box %2
+               %0: double = param reg
+               %1: double = call @llvm.fabs.f64(%0)
+               black_box %1
             ",
             "
                ...
-               ; %2: i64 = call @llvm.smin.i64(%0, %1)
+               ; %1: double = call @llvm.fabs.f64(%0)
                ...
-               cmp r.64.a, r.64.b
-               cmovnle r.64.a, r.64.b
+               pcmpeqd fp.128.x, fp.128.x
+               psrlq fp.128.x, 0x01
+               andpd fp.128.y, fp.128.x
             ",
             false,
         );
+    }
 
+    #[test]
+    fn cg_call_memmove() {
         codegen_and_test(
             "
-             func_decl llvm.smin.i32 (i32, i32) -> i32
+             func_decl llvm.memmove.p0.p0.i64 (ptr, ptr, i64, i1)
              entry:
-               %0: i32 = param reg
-               %1: i32 = param reg
-               %2: i32 = call @llvm.smin.i32(%0, %1)
-               black_box %2
+               %0: ptr = param reg
+               %1: ptr = param reg
+               %2: i64 = param reg
+               call @llvm.memmove.p0.p0.i64(%0, %1, %2, 0i1)
             ",
             "
                ...
-               ; %2: i32 = call @llvm.smin.i32(%0, %1)
-               ...
-               cmp r.64.a, r.64.b
-               cmovnle r.64.a, r.64.b
+               ; call @llvm.memmove.p0.p0.i64(%0, %1, %2, 0i1)
+               mov rsi, rcx
+               mov rcx, rdx
+               mov rdi, rax
+               cmp rdi, rsi
+               jbe 0x{{_}}
+               lea rdi, [rdi+rcx*1-0x01]
+               lea rsi, [rsi+rcx*1-0x01]
+               std
+               rep movsb
+               cld
+               jmp 0x{{_}}
+               rep movsb
             ",
             false,
         );
@@ -6113,6 +7918,116 @@ mod tests {
         );
     }
 
+    #[test]
+    fn cg_binop_overflow_guard() {
+        codegen_and_test(
+            "
+              entry:
+                %0: i64 = param reg
+                %1: i64 = param reg
+                %2: i64 = add %0, 1i64
+                %3: i1 = sadd_overflow %0, 1i64
+                guard false, %3, [%2]
+                %5: i32 = trunc %1
+                %6: i32 = mul %5, %5
+                %7: i1 = umul_overflow %5, %5
+                guard false, %7, [%6]
+                %9: i32 = mul %5, %5
+                %10: i1 = smul_overflow %5, %5
+                guard false, %10, [%9]
+                black_box %2
+                black_box %6
+                black_box %9
+            ",
+            "
+                ...
+                ; %2: i64 = add %0, 1i64
+                ...
+                add r.64.a, 0x01
+                ; %3: i1 = sadd_overflow %0, 1i64
+                ; guard false, %3, ...
+                jo 0x...
+                ...
+                ; %7: i1 = umul_overflow %5, %5
+                ...
+                mul r.32._
+                setb r.8._
+                ; guard false, %7, ...
+                ...
+                ; %9: i32 = mul %5, %5
+                ...
+                imul r.32.b, r.32.b
+                ; %10: i1 = smul_overflow %5, %5
+                ; guard false, %10, ...
+                jo 0x...
+                ...
+            ",
+            false,
+        );
+    }
+
+    #[test]
+    fn cg_overflow_guard() {
+        codegen_and_test(
+            "
+              entry:
+                %0: i32 = param reg
+                %1: i32 = param reg
+                %2: i1 = usub_overflow %0, %1
+                guard true, %2, []
+                black_box %0
+            ",
+            "
+                ...
+                ; %2: i1 = usub_overflow %0, %1
+                ...
+                sub r.32.a, r.32.b
+                ; guard true, %2, ...
+                jnb 0x...
+                ...
+            ",
+            false,
+        );
+    }
+
+    #[test]
+    fn cg_overflow() {
+        codegen_and_test(
+            "
+              entry:
+                %0: i64 = param reg
+                %1: i8 = param reg
+                %2: i1 = sadd_overflow %0, %0
+                %3: i1 = smul_overflow %1, %1
+                %4: i1 = uadd_overflow %1, 3i8
+                black_box %2
+                black_box %3
+                black_box %4
+            ",
+            "
+                ...
+                ; %2: i1 = sadd_overflow %0, %0
+                ...
+                add r.64.a, r.64.b
+                seto r.8._
+                ; %3: i1 = smul_overflow %1, %1
+                movsx r.64.c, r.8.c
+                mov r.64.a, r.64.c
+                imul r.32.a, r.32.c
+                movsx r.32.e, r.8.a
+                cmp r.32.e, r.32.a
+                setnz r.8.e
+                ; %4: i1 = uadd_overflow %1, 3i8
+                ...
+                add r.32.f, r.32._
+                shr r.32.f, 0x08
+                setnz r.8._
+                ...
+            ",
+            false,
+        );
+    }
+
     #[should_panic]
     #[test]
     fn unterminated_trace() {
@@ -6200,6 +8115,185 @@ mod tests {
         );
     }
 
+    #[test]
+    fn cg_i128_zext_add_trunc() {
+        codegen_and_test(
+            "
+              entry:
+                %0: i64 = param reg
+                %1: i128 = zext %0
+                %2: i128 = add %1, 18446744073709551617i128
+                %3: i64 = trunc %2
+                black_box %3
+            ",
+            "
+                ...
+                ; %1: i128 = zext %0
+                xor r.32._, r.32._
+                mov [rbp-0x10], rax
+                mov [rbp-0x08], r.64._
+                ; %2: i128 = add %1, 18446744073709551617i128
+                mov r.64._, [rbp-0x10]
+                mov r.64._, [rbp-0x08]
+                mov r.64._, 0x01
+                mov r.64._, 0x01
+                add r.64._, r.64._
+                adc r.64._, r.64._
+                mov [rbp-0x20], r.64._
+                mov [rbp-0x18], r.64._
+                ; %3: i64 = trunc %2
+                mov r.64._, [rbp-0x20]
+            ",
+            false,
+        );
+    }
+
+    #[test]
+    fn cg_i128_sext_mul_shl() {
+        codegen_and_test(
+            "
+              entry:
+                %0: i64 = param reg
+                %1: i64 = param reg
+                %2: i128 = sext %0
+                %3: i128 = sext %1
+                %4: i128 = mul %2, %3
+                %5: i128 = ashr %4, 64i128
+                %6: i64 = trunc %5
+                black_box %6
+            ",
+            "
+                ...
+                ; %2: i128 = sext %0
+                mov r.64._, rax
+                sar r.64._, 0x3f
+                mov [rbp-0x10], rax
+                mov [rbp-0x08], r.64._
+                ; %3: i128 = sext %1
+                ...
+                ; %4: i128 = mul %2, %3
+                mov rax, [rbp-0x10]
+                mov r.64._, [rbp-0x20]
+                mul r.64._
+                mov r.64._, [rbp-0x10]
+                mov r.64._, [rbp-0x18]
+                imul r.64._, r.64._
+                add rdx, r.64._
+                mov r.64._, [rbp-0x08]
+                mov r.64._, [rbp-0x20]
+                imul r.64._, r.64._
+                add rdx, r.64._
+                mov [rbp-0x30], rax
+                mov [rbp-0x28], rdx
+                ; %5: i128 = ashr %4, 64i128
+                mov rcx, 0x40
+                mov r.64._, [rbp-0x30]
+                mov r.64._, [rbp-0x28]
+                shrd r.64._, r.64._, cl
+                sar r.64._, cl
+                test cl, 0x40
+                jz ...
+                mov r.64._, r.64._
+                sar r.64._, 0x3f
+                mov [rbp-0x40], r.64._
+                mov [rbp-0x38], r.64._
+                ; %6: i64 = trunc %5
+                mov r.64._, [rbp-0x40]
+            ",
+            false,
+        );
+    }
+
+    #[test]
+    fn cg_i128_icmp() {
+        codegen_and_test(
+            "
+              entry:
+                %0: ptr = param reg
+                %1: i128 = load %0
+                %2: i1 = eq %1, 1i128
+                %3: i1 = slt %1, 1i128
+                %4: i1 = ugt %1, 1i128
+                black_box %2
+                black_box %3
+                black_box %4
+            ",
+            "
+                ...
+                ; %1: i128 = load %0
+                mov r.64._, [rax]
+                mov r.64._, [rax+0x08]
+                mov [rbp-0x10], r.64._
+                mov [rbp-0x08], r.64._
+                ; %2: i1 = eq %1, 1i128
+                mov r.64._, [rbp-0x10]
+                mov r.64._, [rbp-0x08]
+                mov r.64._, 0x01
+                mov r.64._, 0x00
+                xor r.64._, r.64._
+                xor r.64._, r.64._
+                or r.64._, r.64._
+                setz r.8._
+                ; %3: i1 = slt %1, 1i128
+                mov r.64._, [rbp-0x10]
+                mov r.64._, [rbp-0x08]
+                mov r.64._, 0x01
+                mov r.64._, 0x00
+                cmp r.64._, r.64._
+                sbb r.64._, r.64._
+                setl r.8._
+                ; %4: i1 = ugt %1, 1i128
+                mov r.64._, 0x01
+                mov r.64._, 0x00
+                mov r.64._, [rbp-0x10]
+                mov r.64._, [rbp-0x08]
+                cmp r.64._, r.64._
+                sbb r.64._, r.64._
+                setb r.8._
+            ",
+            false,
+        );
+    }
+
+    #[test]
+    fn cg_i128_load_store_select() {
+        codegen_and_test(
+            "
+              entry:
+                %0: ptr = param reg
+                %1: i1 = param reg
+                %2: i128 = load %0
+                %3: ptr = ptr_add %0, 16
+                %4: i128 = %1 ? %2 : 5i128
+                *%3 = %4
+            ",
+            "
+                ...
+                ; %2: i128 = load %0
+                mov r.64._, [rax]
+                mov r.64._, [rax+0x08]
+                mov [rbp-0x10], r.64._
+                mov [rbp-0x08], r.64._
+                ; %4: i128 = %1 ? %2 : 5i128
+                mov r.64._, [rbp-0x10]
+                mov r.64._, [rbp-0x08]
+                mov r.64._, 0x05
+                mov r.64._, 0x00
+                bt ecx, 0x00
+                cmovnb r.64._, r.64._
+                cmovnb r.64._, r.64._
+                mov [rbp-0x20], r.64._
+                mov [rbp-0x18], r.64._
+                ; *(%0 + 16) = %4
+                mov r.64._, [rbp-0x20]
+                mov r.64._, [rbp-0x18]
+                mov [rax+0x10], r.64._
+                mov [rax+0x18], r.64._
+            ",
+            false,
+        );
+    }
+
     #[test]
     fn cg_trunc() {
         codegen_and_test(
@@ -6488,6 +8582,67 @@ mod tests {
         );
     }
 
+    #[test]
+    fn cg_fptoui() {
+        codegen_and_test(
+            "
+              entry:
+                %0: float = param reg
+                %1: i32 = fp_to_ui %0
+                black_box %1
+            ",
+            "
+                ...
+                ; %1: i32 = fp_to_ui %0
+                cvttss2si r.64.x, fp.128.x
+                ...
+                ",
+            false,
+        );
+
+        codegen_and_test(
+            "
+              entry:
+                %0: double = param reg
+                %1: i64 = fp_to_ui %0
+                black_box %1
+            ",
+            "
+                ...
+                ; %1: i64 = fp_to_ui %0
+                cvttsd2si r.64.x, fp.128.y
+                test r.64.x, r.64.x
+                jns 0x{{_}}
+                mov r.64.z, 0xC3E0000000000000
+                movq fp.128.w, r.64.z
+                addsd fp.128.w, fp.128.y
+                cvttsd2si r.64.x, fp.128.w
+                btc r.64.x, 0x3f
+                ...
+                ",
+            false,
+        );
+    }
+
+    #[test]
+    fn cg_fptrunc() {
+        codegen_and_test(
+            "
+              entry:
+                %0: double = param reg
+                %1: float = fp_trunc %0
+                black_box %1
+            ",
+            "
+                ...
+                ; %1: float = fp_trunc %0
+                cvtsd2ss fp.128.x, fp.128.x
+                ...
+                ",
+            false,
+        );
+    }
+
     #[test]
     fn cg_fdiv_float() {
         codegen_and_test(
@@ -7053,6 +9208,7 @@ mod tests {
             tracecompilation_errors: 0,
             #[cfg(feature = "ykd")]
             debug_str: None,
+            invalidated: None,
         };
 
         Assemble::new(&m)
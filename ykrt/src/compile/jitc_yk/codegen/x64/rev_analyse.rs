@@ -12,7 +12,7 @@
 //!      subsumes the functionality of `dead_code.rs`, so if you use this module for you don't need
 //!      to use `dead_code.rs` as well.
 
-use super::{Register, VarLocation, X64CompiledTrace};
+use super::{is_wide_int_inst, Register, VarLocation, X64CompiledTrace};
 use crate::compile::jitc_yk::{
     codegen::x64::{ARG_FP_REGS, ARG_GP_REGS},
     jit_ir::{
@@ -234,6 +234,12 @@ impl<'a> RevAnalyse<'a> {
                 _ => (),
             }
 
+            // The code generator can't sink instructions involving integers wider than 64 bits (or
+            // the instructions they use) into guard bodies.
+            if is_wide_int_inst(self.m, &inst) {
+                self.used_only_by_guards.set(usize::from(iidx), false);
+            }
+
             // Calculate inst_vals_alive_until
             if let Inst::Guard(ginst) = inst
                 && let Operand::Var(def_iidx) = ginst.cond(self.m)
@@ -10,36 +10,43 @@ pub(crate) trait SignExtend {
     fn sign_extend(&self, from_bits: u32, to_bits: u32) -> Self;
 }
 
-impl SignExtend for u64 {
-    fn sign_extend(&self, from_bits: u32, to_bits: u32) -> Self {
-        debug_assert!(
-            from_bits > 0 && from_bits <= Self::BITS,
-            "to_bits {to_bits} outside range 1..={}",
-            Self::BITS
-        );
-        debug_assert!(
-            to_bits > 0 && to_bits <= Self::BITS,
-            "to_bits {to_bits} outside range 1..={}",
-            Self::BITS
-        );
-        debug_assert!(from_bits <= to_bits);
-        // There are probably more clever ways to do this.
-        if from_bits == to_bits {
-            *self
-        } else if self & (1 << (from_bits - 1)) == 0 {
-            // Extend with zeros.
-            let shift = Self::BITS - from_bits;
-            (*self << shift) >> shift
-        } else {
-            // Extend with ones.
-            // How many high-order zero bits do we need?
-            let num_zeros = Self::BITS - to_bits;
-            let mask = ((Self::MAX << from_bits) << num_zeros) >> num_zeros;
-            *self | mask
+macro_rules! sign_extend_impl {
+    ($ty:ty) => {
+        impl SignExtend for $ty {
+            fn sign_extend(&self, from_bits: u32, to_bits: u32) -> Self {
+                debug_assert!(
+                    from_bits > 0 && from_bits <= Self::BITS,
+                    "to_bits {to_bits} outside range 1..={}",
+                    Self::BITS
+                );
+                debug_assert!(
+                    to_bits > 0 && to_bits <= Self::BITS,
+                    "to_bits {to_bits} outside range 1..={}",
+                    Self::BITS
+                );
+                debug_assert!(from_bits <= to_bits);
+                // There are probably more clever ways to do this.
+                if from_bits == to_bits {
+                    *self
+                } else if self & (1 << (from_bits - 1)) == 0 {
+                    // Extend with zeros.
+                    let shift = Self::BITS - from_bits;
+                    (*self << shift) >> shift
+                } else {
+                    // Extend with ones.
+                    // How many high-order zero bits do we need?
+                    let num_zeros = Self::BITS - to_bits;
+                    let mask = ((Self::MAX << from_bits) << num_zeros) >> num_zeros;
+                    *self | mask
+                }
+            }
         }
-    }
+    };
 }
 
+sign_extend_impl!(u64);
+sign_extend_impl!(u128);
+
 pub(crate) trait Truncate {
     /// Truncate the value to a `bits`-bit value by unsetting higher order bits.
     ///
@@ -47,21 +54,28 @@ pub(crate) trait Truncate {
     fn truncate(&self, bits: u32) -> Self;
 }
 
-impl Truncate for u64 {
-    fn truncate(&self, bits: u32) -> Self {
-        debug_assert!(
-            bits > 0 && bits <= Self::BITS,
-            "{bits} outside range 1..{}",
-            Self::BITS
-        );
-        if bits == Self::BITS {
-            *self
-        } else {
-            *self & ((1 as Self).wrapping_shl(bits) - 1)
+macro_rules! truncate_impl {
+    ($ty:ty) => {
+        impl Truncate for $ty {
+            fn truncate(&self, bits: u32) -> Self {
+                debug_assert!(
+                    bits > 0 && bits <= Self::BITS,
+                    "{bits} outside range 1..{}",
+                    Self::BITS
+                );
+                if bits == Self::BITS {
+                    *self
+                } else {
+                    *self & ((1 as Self).wrapping_shl(bits) - 1)
+                }
+            }
         }
-    }
+    };
 }
 
+truncate_impl!(u64);
+truncate_impl!(u128);
+
 #[cfg(test)]
 mod tests {
     use super::{SignExtend, Truncate};
@@ -81,6 +95,13 @@ mod tests {
             // sign extending up to 16 bits should always give the same numeric value.
             assert_eq!(iu.sign_extend(8, 16) as i16, i as i16);
         }
+        assert_eq!(0xffffffffu128.sign_extend(32, 128), u128::MAX);
+        assert_eq!(0xffffffu128.sign_extend(24, 64), 0xffffffffffffffff);
+        assert_eq!(u128::from(u64::MAX).sign_extend(64, 96), (1 << 96) - 1);
+        assert_eq!(
+            0x7fffffffffffffffu128.sign_extend(64, 128),
+            0x7fffffffffffffff
+        );
     }
 
     #[test]
@@ -91,6 +112,10 @@ mod tests {
         assert_eq!(u64::MAX.truncate(32), 0xffffffff);
         assert_eq!(u64::MAX.truncate(63), 0x7fffffffffffffff);
         assert_eq!(u64::MAX.truncate(64), u64::MAX);
+        assert_eq!(u128::MAX.truncate(24), 0xffffff);
+        assert_eq!(u128::MAX.truncate(64), u128::from(u64::MAX));
+        assert_eq!(u128::MAX.truncate(127), u128::MAX >> 1);
+        assert_eq!(u128::MAX.truncate(128), u128::MAX);
         for i in 0u64..255 {
             // These should all be no-ops.
             assert_eq!(i.truncate(8), i);
@@ -29,7 +29,11 @@ FuncDecls -> Result<Vec<ASTFuncDecl>, Box<dyn Error>>:
 FuncDecl -> Result<ASTFuncDecl, Box<dyn Error>>:
     "FUNC_DECL" "ID" "(" FuncArgs ")" FuncRtnType {
       let (arg_tys, is_varargs) = $4?;
-      Ok(ASTFuncDecl{name: $2?.span(), arg_tys, is_varargs, rtn_ty: $6?})
+      Ok(ASTFuncDecl{name: $2?.span(), arg_tys, is_varargs, rtn_ty: $6?, is_allocator: false})
+    }
+  | "FUNC_DECL" "YK_ALLOCATOR" "ID" "(" FuncArgs ")" FuncRtnType {
+      let (arg_tys, is_varargs) = $5?;
+      Ok(ASTFuncDecl{name: $3?.span(), arg_tys, is_varargs, rtn_ty: $7?, is_allocator: true})
     }
   ;
 
@@ -86,6 +90,9 @@ Inst -> Result<ASTInst, Box<dyn Error>>:
   | "LOCAL_OPERAND" ":" Type "=" BinOp Operand "," Operand  {
       Ok(ASTInst::BinOp{assign: $1?.span(), type_: $3?, bin_op: $5?, lhs: $6?, rhs: $8?})
     }
+  | "LOCAL_OPERAND" ":" Type "=" OverflowOp Operand "," Operand  {
+      Ok(ASTInst::Overflow{assign: $1?.span(), type_: $3?, op: $5?, lhs: $6?, rhs: $8?})
+    }
   | "LOCAL_OPERAND" ":" Type "=" "CALL" "GLOBAL" "(" OperandsList ")" IdemConstOpt {
       Ok(ASTInst::Call{assign: Some($1?.span()), name: $6?.span(), args: $8?, idem_const: $10?})
     }
@@ -143,6 +150,15 @@ Inst -> Result<ASTInst, Box<dyn Error>>:
   | "LOCAL_OPERAND" ":" Type "=" "FP_TO_SI" Operand {
       Ok(ASTInst::FPToSI{assign: $1?.span(), type_: $3?, val: $6? })
     }
+  | "LOCAL_OPERAND" ":" Type "=" "FP_TO_UI" Operand {
+      Ok(ASTInst::FPToUI{assign: $1?.span(), type_: $3?, val: $6? })
+    }
+  | "LOCAL_OPERAND" ":" Type "=" "FP_TRUNC" Operand {
+      Ok(ASTInst::FPTrunc{assign: $1?.span(), type_: $3?, val: $6? })
+    }
+  | "LOCAL_OPERAND" ":" Type "=" "SECOND_RET_VAL" {
+      Ok(ASTInst::SecondRetVal{assign: $1?.span(), type_: $3? })
+    }
   | "LOCAL_OPERAND" ":" Type "=" "FNEG" Operand {
       Ok(ASTInst::FNeg{assign: $1?.span(), val: $6? })
     }
@@ -187,6 +203,15 @@ Type -> Result<ASTType, Box<dyn Error>>:
   | "PTR" { Ok(ASTType::Ptr) }
   ;
 
+OverflowOp -> Result<OverflowOp, Box<dyn Error>>:
+    "SADD_OVERFLOW" { Ok(OverflowOp::SAdd) }
+  | "UADD_OVERFLOW" { Ok(OverflowOp::UAdd) }
+  | "SSUB_OVERFLOW" { Ok(OverflowOp::SSub) }
+  | "USUB_OVERFLOW" { Ok(OverflowOp::USub) }
+  | "SMUL_OVERFLOW" { Ok(OverflowOp::SMul) }
+  | "UMUL_OVERFLOW" { Ok(OverflowOp::UMul) }
+  ;
+
 BinOp -> Result<BinOp, Box<dyn Error>>:
     "ADD" { Ok(BinOp::Add) }
   | "SUB" { Ok(BinOp::Sub) }
@@ -94,6 +94,7 @@
 mod dead_code;
 #[cfg(test)]
 mod parser;
+mod serialise;
 #[cfg(any(debug_assertions, test))]
 mod well_formed;
 
@@ -695,7 +696,12 @@ impl fmt::Display for Module {
         for x in &self.func_decls {
             writeln!(
                 f,
-                "func_decl {} {}",
+                "func_decl {}{} {}",
+                if x.is_allocator() {
+                    "yk_allocator "
+                } else {
+                    ""
+                },
                 x.name(),
                 self.type_(x.tyidx()).display(self)
             )?;
@@ -1169,11 +1175,18 @@ impl fmt::Display for DisplayableTy<'_> {
 pub(crate) struct FuncDecl {
     name: String,
     tyidx: TyIdx,
+    /// Is this function an allocator? Allocators take a single integer argument (a size in bytes)
+    /// and return a pointer to a fresh, never `NULL`, block of memory of that size.
+    is_allocator: bool,
 }
 
 impl FuncDecl {
-    pub(crate) fn new(name: String, tyidx: TyIdx) -> Self {
-        Self { name, tyidx }
+    pub(crate) fn new(name: String, tyidx: TyIdx, is_allocator: bool) -> Self {
+        Self {
+            name,
+            tyidx,
+            is_allocator,
+        }
     }
 
     /// Return the name of this function declaration.
@@ -1181,6 +1194,11 @@ impl FuncDecl {
         &self.name
     }
 
+    /// Is this function an allocator?
+    pub(crate) fn is_allocator(&self) -> bool {
+        self.is_allocator
+    }
+
     pub(crate) fn tyidx(&self) -> TyIdx {
         self.tyidx
     }
@@ -1381,7 +1399,7 @@ impl fmt::Display for DisplayableConst<'_> {
                 _ => unreachable!(),
             },
             Const::Int(_, x) => {
-                write!(f, "{}i{}", x.to_zero_ext_u64().unwrap(), x.bitw())
+                write!(f, "{}i{}", x.to_zero_ext_u128().unwrap(), x.bitw())
             }
             Const::Ptr(x) => write!(f, "{:#x}", *x),
         }
@@ -1445,6 +1463,8 @@ pub(crate) struct GuardInfo {
     /// What AOT safepoint does this guard correspond to? This is used solely for debugging
     /// purposes.
     safepoint_id: u64,
+    /// The virtual allocations referenced, directly, by `live_vars`.
+    virtuals: Vec<VirtualAlloc>,
 }
 
 impl GuardInfo {
@@ -1459,6 +1479,7 @@ impl GuardInfo {
             live_vars,
             inlined_frames,
             safepoint_id,
+            virtuals: Vec::new(),
         }
     }
 
@@ -1480,6 +1501,66 @@ impl GuardInfo {
     pub(crate) fn safepoint_id(&self) -> u64 {
         self.safepoint_id
     }
+
+    /// Return the virtual allocations that must be materialised if this guard fails.
+    pub(crate) fn virtuals(&self) -> &[VirtualAlloc] {
+        &self.virtuals
+    }
+
+    /// If the live variable `iidx` is a virtual allocation, return its index in [Self::virtuals].
+    pub(crate) fn virtual_idx(&self, iidx: InstIdx) -> Option<usize> {
+        self.virtuals.iter().position(|x| x.iidx == iidx)
+    }
+
+    pub(crate) fn set_virtuals(&mut self, virtuals: Vec<VirtualAlloc>) {
+        self.virtuals = virtuals;
+    }
+}
+
+/// An allocation which the optimiser has removed from the trace because it never escapes. If a
+/// guard which references the allocation in its live variables fails, the allocation has to be
+/// made for real (i.e. "materialised"), with the contents it would have had at that point.
+#[derive(Clone, Debug)]
+pub(crate) struct VirtualAlloc {
+    /// The (removed) instruction which made the allocation. Live variables which reference this
+    /// instruction refer to the materialised allocation.
+    pub(crate) iidx: InstIdx,
+    /// The allocator which must be called to materialise the allocation.
+    pub(crate) target: FuncDeclIdx,
+    /// The size in bytes of the allocation.
+    pub(crate) size: u64,
+    /// The values stored in the allocation as `(offset, value)` pairs, sorted by offset. Bytes
+    /// which are not covered by a value are uninitialised.
+    pub(crate) fields: Vec<(u32, PackedOperand)>,
+}
+
+impl VirtualAlloc {
+    pub(crate) fn display<'a>(&'a self, m: &'a Module) -> DisplayableVirtualAlloc<'a> {
+        DisplayableVirtualAlloc { va: self, m }
+    }
+}
+
+pub(crate) struct DisplayableVirtualAlloc<'a> {
+    va: &'a VirtualAlloc,
+    m: &'a Module,
+}
+
+impl fmt::Display for DisplayableVirtualAlloc<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "%{} = @{}({}) {{{}}}",
+            self.va.iidx,
+            self.m.func_decl(self.va.target).name(),
+            self.va.size,
+            self.va
+                .fields
+                .iter()
+                .map(|(off, x)| format!("{off}: {}", x.unpack(self.m).display(self.m)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
 }
 
 /// An abstract call frame for functions inlined in a trace. This contains enough information for a
@@ -1570,6 +1651,10 @@ pub(crate) enum Inst {
     IntToPtr(IntToPtrInst),
     PtrToInt(PtrToIntInst),
     UIToFP(UIToFPInst),
+    Overflow(OverflowInst),
+    FPTrunc(FPTruncInst),
+    FPToUI(FPToUIInst),
+    SecondRetVal(SecondRetValInst),
 }
 
 impl Inst {
@@ -1635,6 +1720,10 @@ impl Inst {
             Self::PtrToInt(i) => i.dest_tyidx(),
             Self::IntToPtr(_) => m.ptr_tyidx(),
             Self::UIToFP(i) => i.dest_tyidx(),
+            Self::Overflow(_) => m.int1_tyidx(),
+            Self::FPTrunc(i) => i.dest_tyidx(),
+            Self::FPToUI(i) => i.dest_tyidx(),
+            Self::SecondRetVal(i) => i.tyidx(),
         }
     }
 
@@ -1644,6 +1733,9 @@ impl Inst {
             Inst::Copy(x) => m.inst_raw(*x).has_load_effect(m),
             Inst::Load(_) => true,
             Inst::Call(_) | Inst::IndirectCall(_) => true,
+            // This reads a register set by the preceding call, so it must not be moved away from
+            // that call.
+            Inst::SecondRetVal(_) => true,
             _ => false,
         }
     }
@@ -1738,8 +1830,17 @@ impl Inst {
             }
             Inst::Guard(x @ GuardInst { cond, .. }) => {
                 cond.unpack(m).map_iidx(f);
-                for (_, pop) in x.guard_info(m).live_vars() {
-                    pop.unpack(m).map_iidx(f);
+                let gi = x.guard_info(m);
+                for (_, pop) in gi.live_vars() {
+                    match pop.unpack(m) {
+                        Operand::Var(iidx) if gi.virtual_idx(iidx).is_some() => (),
+                        op => op.map_iidx(f),
+                    }
+                }
+                for va in gi.virtuals() {
+                    for (_, pop) in &va.fields {
+                        pop.unpack(m).map_iidx(f);
+                    }
                 }
             }
             Inst::TraceHeaderStart => {
@@ -1787,11 +1888,18 @@ impl Inst {
                 rhs.unpack(m).map_iidx(f);
             }
             Inst::FPToSI(FPToSIInst { val, .. }) => val.unpack(m).map_iidx(f),
+            Inst::FPToUI(FPToUIInst { val, .. }) => val.unpack(m).map_iidx(f),
+            Inst::FPTrunc(FPTruncInst { val, .. }) => val.unpack(m).map_iidx(f),
+            Inst::SecondRetVal(_) => (),
             Inst::FNeg(FNegInst { val }) => val.unpack(m).map_iidx(f),
             Inst::DebugStr(..) => (),
             Inst::PtrToInt(PtrToIntInst { val, .. }) => val.unpack(m).map_iidx(f),
             Inst::IntToPtr(IntToPtrInst { val }) => val.unpack(m).map_iidx(f),
             Inst::UIToFP(UIToFPInst { val, .. }) => val.unpack(m).map_iidx(f),
+            Inst::Overflow(OverflowInst { lhs, op: _, rhs }) => {
+                lhs.unpack(m).map_iidx(f);
+                rhs.unpack(m).map_iidx(f);
+            }
         }
     }
 
@@ -1864,10 +1972,18 @@ impl Inst {
                 val: mapper(m, val),
                 dest_tyidx: *dest_tyidx,
             }),
+            Inst::FPToUI(FPToUIInst { val, dest_tyidx }) => Inst::FPToUI(FPToUIInst {
+                val: mapper(m, val),
+                dest_tyidx: *dest_tyidx,
+            }),
             Inst::FPExt(FPExtInst { val, dest_tyidx }) => Inst::FPExt(FPExtInst {
                 val: mapper(m, val),
                 dest_tyidx: *dest_tyidx,
             }),
+            Inst::FPTrunc(FPTruncInst { val, dest_tyidx }) => Inst::FPTrunc(FPTruncInst {
+                val: mapper(m, val),
+                dest_tyidx: *dest_tyidx,
+            }),
             Inst::FCmp(FCmpInst { lhs, pred, rhs }) => Inst::FCmp(FCmpInst {
                 lhs: mapper(m, lhs),
                 pred: *pred,
@@ -1895,12 +2011,32 @@ impl Inst {
                         )
                     })
                     .collect::<Vec<_>>();
-                let newginfo = GuardInfo::new(
+                let virtuals = ginfo
+                    .virtuals()
+                    .iter()
+                    .map(|x| {
+                        let Operand::Var(iidx) = f(m, x.iidx) else {
+                            panic!()
+                        };
+                        VirtualAlloc {
+                            iidx,
+                            target: x.target,
+                            size: x.size,
+                            fields: x
+                                .fields
+                                .iter()
+                                .map(|(off, op)| (*off, mapper(m, op)))
+                                .collect(),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                let mut newginfo = GuardInfo::new(
                     ginfo.bid().clone(),
                     newlives,
                     inlined_frames,
                     ginfo.safepoint_id,
                 );
+                newginfo.set_virtuals(virtuals);
                 let newgidx = m.push_guardinfo(newginfo).unwrap();
                 Inst::Guard(GuardInst {
                     cond: mapper(m, cond),
@@ -2000,6 +2136,12 @@ impl Inst {
                 val: mapper(m, val),
                 dest_tyidx: *dest_tyidx,
             }),
+            Inst::Overflow(OverflowInst { lhs, op, rhs }) => Inst::Overflow(OverflowInst {
+                lhs: mapper(m, lhs),
+                op: *op,
+                rhs: mapper(m, rhs),
+            }),
+            Inst::SecondRetVal(x) => Inst::SecondRetVal(*x),
         };
         Ok(inst)
     }
@@ -2086,10 +2228,15 @@ impl Inst {
             (Self::FPExt(x), Self::FPExt(y)) => x.decopy_eq(m, y),
             (Self::FCmp(x), Self::FCmp(y)) => x.decopy_eq(m, y),
             (Self::FPToSI(x), Self::FPToSI(y)) => x.decopy_eq(m, y),
+            (Self::FPToUI(x), Self::FPToUI(y)) => x.decopy_eq(m, y),
+            (Self::FPTrunc(x), Self::FPTrunc(y)) => x.decopy_eq(m, y),
             (Self::FNeg(x), Self::FNeg(y)) => x.decopy_eq(m, y),
             (Self::PtrToInt(x), Self::PtrToInt(y)) => x.decopy_eq(m, y),
             (Self::IntToPtr(x), Self::IntToPtr(y)) => x.decopy_eq(m, y),
             (Self::UIToFP(x), Self::UIToFP(y)) => x.decopy_eq(m, y),
+            (Self::Overflow(x), Self::Overflow(y)) => x.decopy_eq(m, y),
+            // Each instance reads the return registers of a different call.
+            (Self::SecondRetVal(_), Self::SecondRetVal(_)) => false,
             (x, y) => todo!("{x:?} {y:?}"),
         }
     }
@@ -2206,9 +2353,21 @@ impl fmt::Display for DisplayableInst<'_> {
                     })
                     .collect::<Vec<_>>()
                     .join(", ");
+                let virtuals = if gi.virtuals().is_empty() {
+                    String::new()
+                } else {
+                    format!(
+                        " virtuals [{}]",
+                        gi.virtuals()
+                            .iter()
+                            .map(|x| x.display(self.m).to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                };
                 write!(
                     f,
-                    "guard {}, {}, [{live_vars}] ; trace_gidx {gidx} safepoint_id {}",
+                    "guard {}, {}, [{live_vars}]{virtuals} ; trace_gidx {gidx} safepoint_id {}",
                     if *expect { "true" } else { "false" },
                     cond.unpack(self.m).display(self.m),
                     gi.safepoint_id
@@ -2304,6 +2463,9 @@ impl fmt::Display for DisplayableInst<'_> {
                 x.rhs(self.m).display(self.m)
             ),
             Inst::FPToSI(i) => write!(f, "fp_to_si {}", i.val(self.m).display(self.m)),
+            Inst::FPToUI(i) => write!(f, "fp_to_ui {}", i.val(self.m).display(self.m)),
+            Inst::FPTrunc(i) => write!(f, "fp_trunc {}", i.val(self.m).display(self.m)),
+            Inst::SecondRetVal(_) => write!(f, "second_ret_val"),
             Inst::FNeg(i) => write!(f, "fneg {}", i.val(self.m).display(self.m)),
             Inst::DebugStr(i) => write!(f, "; debug_str: {}", i.msg(self.m)),
             Inst::PtrToInt(i) => {
@@ -2313,6 +2475,13 @@ impl fmt::Display for DisplayableInst<'_> {
                 write!(f, "int_to_ptr {}", i.val(self.m).display(self.m),)
             }
             Inst::UIToFP(i) => write!(f, "ui_to_fp {}", i.val(self.m).display(self.m)),
+            Inst::Overflow(x) => write!(
+                f,
+                "{} {}, {}",
+                x.op,
+                x.lhs(self.m).display(self.m),
+                x.rhs(self.m).display(self.m)
+            ),
         }
     }
 }
@@ -2347,12 +2516,16 @@ inst!(SIToFP, SIToFPInst);
 inst!(FPExt, FPExtInst);
 inst!(FCmp, FCmpInst);
 inst!(FPToSI, FPToSIInst);
+inst!(FPToUI, FPToUIInst);
+inst!(FPTrunc, FPTruncInst);
 inst!(BitCast, BitCastInst);
 inst!(FNeg, FNegInst);
 inst!(DebugStr, DebugStrInst);
 inst!(PtrToInt, PtrToIntInst);
 inst!(IntToPtr, IntToPtrInst);
 inst!(UIToFP, UIToFPInst);
+inst!(Overflow, OverflowInst);
+inst!(SecondRetVal, SecondRetValInst);
 
 /// The operands for a [Instruction::BinOp]
 ///
@@ -3093,6 +3266,118 @@ impl ICmpInst {
     }
 }
 
+/// An arithmetic operation whose overflow can be checked for with an [Inst::Overflow].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum OverflowOp {
+    /// Signed addition (as performed by `llvm.sadd.with.overflow`).
+    SAdd,
+    /// Unsigned addition (as performed by `llvm.uadd.with.overflow`).
+    UAdd,
+    /// Signed subtraction (as performed by `llvm.ssub.with.overflow`).
+    SSub,
+    /// Unsigned subtraction (as performed by `llvm.usub.with.overflow`).
+    USub,
+    /// Signed multiplication (as performed by `llvm.smul.with.overflow`).
+    SMul,
+    /// Unsigned multiplication (as performed by `llvm.umul.with.overflow`).
+    UMul,
+}
+
+impl OverflowOp {
+    /// The [BinOp] whose (wrapping) result this operation computes.
+    pub(crate) fn binop(&self) -> BinOp {
+        match self {
+            Self::SAdd | Self::UAdd => BinOp::Add,
+            Self::SSub | Self::USub => BinOp::Sub,
+            Self::SMul | Self::UMul => BinOp::Mul,
+        }
+    }
+
+    /// Does this operation interpret its operands as signed integers?
+    pub(crate) fn signed(&self) -> bool {
+        matches!(self, Self::SAdd | Self::SSub | Self::SMul)
+    }
+}
+
+impl fmt::Display for OverflowOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::SAdd => "sadd_overflow",
+            Self::UAdd => "uadd_overflow",
+            Self::SSub => "ssub_overflow",
+            Self::USub => "usub_overflow",
+            Self::SMul => "smul_overflow",
+            Self::UMul => "umul_overflow",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// The operands for a [Inst::Overflow]
+///
+/// # Semantics
+///
+/// Defines an `i1` which is true if performing the arithmetic operation `op` on two integer
+/// operands of the same type would overflow. The (wrapped) result of the operation itself is
+/// computed by a separate [Inst::BinOp]: the trace builder emits the two instructions next to
+/// each other so that the code generator can compute both with a single machine instruction.
+#[derive(Clone, Copy, Debug)]
+pub struct OverflowInst {
+    pub(crate) lhs: PackedOperand,
+    pub(crate) op: OverflowOp,
+    pub(crate) rhs: PackedOperand,
+}
+
+impl OverflowInst {
+    pub(crate) fn new(lhs: Operand, op: OverflowOp, rhs: Operand) -> Self {
+        Self {
+            lhs: PackedOperand::new(&lhs),
+            op,
+            rhs: PackedOperand::new(&rhs),
+        }
+    }
+
+    fn decopy_eq(&self, m: &Module, other: Self) -> bool {
+        self.lhs(m) == other.lhs(m) && self.op == other.op && self.rhs(m) == other.rhs(m)
+    }
+
+    pub(crate) fn lhs(&self, m: &Module) -> Operand {
+        self.lhs.unpack(m)
+    }
+
+    pub(crate) fn op(&self) -> OverflowOp {
+        self.op
+    }
+
+    pub(crate) fn rhs(&self, m: &Module) -> Operand {
+        self.rhs.unpack(m)
+    }
+}
+
+/// The operands for a [Inst::SecondRetVal]
+///
+/// # Semantics
+///
+/// Defines the second register-sized value returned by the call immediately preceding this
+/// instruction. This is used to represent calls returning a two-field aggregate (e.g. `{i64, i64}`
+/// or `{i32, double}`), which the x64 SysV ABI returns in two registers: the call instruction
+/// defines the first field and this instruction the second.
+#[derive(Clone, Copy, Debug)]
+pub struct SecondRetValInst {
+    /// The type of the value. Must be an integer, pointer, or float type.
+    tyidx: TyIdx,
+}
+
+impl SecondRetValInst {
+    pub(crate) fn new(tyidx: TyIdx) -> Self {
+        Self { tyidx }
+    }
+
+    pub(crate) fn tyidx(&self) -> TyIdx {
+        self.tyidx
+    }
+}
+
 /// The operands for a [Inst::FCmp]
 ///
 /// # Semantics
@@ -3185,6 +3470,10 @@ impl GuardInst {
     pub(crate) fn guard_info<'a>(&self, m: &'a Module) -> &'a GuardInfo {
         &m.guard_info[usize::from(self.gidx)]
     }
+
+    pub(crate) fn guard_info_mut<'a>(&self, m: &'a mut Module) -> &'a mut GuardInfo {
+        &mut m.guard_info[usize::from(self.gidx)]
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -3369,6 +3658,45 @@ impl FPToSIInst {
     }
 }
 
+/// The operands for a [Inst::FPToUI]
+///
+/// # Semantics
+///
+/// This instruction converts a floating point value to an unsigned integer value, rounding towards
+/// zero.
+///
+/// The source float and destination integer need not be the same width, but if the resulting
+/// numeric value does not fit, the result is undefined.
+#[derive(Clone, Copy, Debug)]
+pub struct FPToUIInst {
+    /// The value to convert. Must be of floating point type.
+    val: PackedOperand,
+    /// The type to convert to. Must be an integer type.
+    dest_tyidx: TyIdx,
+}
+
+impl FPToUIInst {
+    #[cfg(test)]
+    pub(crate) fn new(val: &Operand, dest_tyidx: TyIdx) -> Self {
+        Self {
+            val: PackedOperand::new(val),
+            dest_tyidx,
+        }
+    }
+
+    fn decopy_eq(&self, m: &Module, other: Self) -> bool {
+        self.val(m) == other.val(m) && self.dest_tyidx == other.dest_tyidx
+    }
+
+    pub(crate) fn val(&self, m: &Module) -> Operand {
+        self.val.unpack(m)
+    }
+
+    pub(crate) fn dest_tyidx(&self) -> TyIdx {
+        self.dest_tyidx
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct FPExtInst {
     /// The value to convert.
@@ -3398,6 +3726,42 @@ impl FPExtInst {
     }
 }
 
+/// The operands for a [Inst::FPTrunc]
+///
+/// # Semantics
+///
+/// This instruction converts a floating point value to a smaller floating point type, rounding to
+/// the nearest representable value.
+#[derive(Clone, Copy, Debug)]
+pub struct FPTruncInst {
+    /// The value to convert.
+    val: PackedOperand,
+    /// The type to convert to. Must be a smaller floating point type.
+    dest_tyidx: TyIdx,
+}
+
+impl FPTruncInst {
+    #[cfg(test)]
+    pub(crate) fn new(val: &Operand, dest_tyidx: TyIdx) -> Self {
+        Self {
+            val: PackedOperand::new(val),
+            dest_tyidx,
+        }
+    }
+
+    fn decopy_eq(&self, m: &Module, other: Self) -> bool {
+        self.val(m) == other.val(m) && self.dest_tyidx == other.dest_tyidx
+    }
+
+    pub(crate) fn val(&self, m: &Module) -> Operand {
+        self.val.unpack(m)
+    }
+
+    pub(crate) fn dest_tyidx(&self) -> TyIdx {
+        self.dest_tyidx
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -3437,7 +3801,7 @@ mod tests {
         let func_ty = Ty::Func(FuncTy::new(vec![i32_tyidx; 3], i32_tyidx, true));
         let func_tyidx = m.insert_ty(func_ty).unwrap();
         let func_decl_idx = m
-            .insert_func_decl(FuncDecl::new("foo".to_owned(), func_tyidx))
+            .insert_func_decl(FuncDecl::new("foo".to_owned(), func_tyidx, false))
             .unwrap();
 
         // Build a call to the function.
@@ -3479,7 +3843,7 @@ mod tests {
         let func_ty = FuncTy::new(arg_tyidxs, ret_tyidx, false);
         let func_tyidx = m.insert_ty(Ty::Func(func_ty)).unwrap();
         let func_decl_idx = m
-            .insert_func_decl(FuncDecl::new("blah".into(), func_tyidx))
+            .insert_func_decl(FuncDecl::new("blah".into(), func_tyidx, false))
             .unwrap();
 
         // Now build a call to the function.
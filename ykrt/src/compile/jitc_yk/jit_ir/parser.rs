@@ -18,10 +18,11 @@ use super::super::{
     arbbitint::ArbBitInt,
     jit_ir::{
         BinOpInst, BitCastInst, BlackBoxInst, Const, DirectCallInst, DynPtrAddInst, FCmpInst,
-        FNegInst, FPExtInst, FPToSIInst, FloatTy, FuncDecl, FuncTy, GuardInfo, GuardInst, ICmpInst,
-        IndirectCallInst, Inst, InstIdx, IntToPtrInst, LoadInst, Module, Operand, PackedOperand,
-        ParamIdx, ParamInst, PtrAddInst, PtrToIntInst, SExtInst, SIToFPInst, SelectInst, StoreInst,
-        TruncInst, Ty, TyIdx, UIToFPInst, ZExtInst,
+        FNegInst, FPExtInst, FPToSIInst, FPToUIInst, FPTruncInst, FloatTy, FuncDecl, FuncTy,
+        GuardInfo, GuardInst, ICmpInst, IndirectCallInst, Inst, InstIdx, IntToPtrInst, LoadInst,
+        Module, Operand, OverflowInst, OverflowOp, PackedOperand, ParamIdx, ParamInst, PtrAddInst,
+        PtrToIntInst, SExtInst, SIToFPInst, SecondRetValInst, SelectInst, StoreInst, TruncInst, Ty,
+        TyIdx, UIToFPInst, ZExtInst,
     },
 };
 use fm::FMBuilder;
@@ -281,6 +282,33 @@ impl<'lexer, 'input: 'lexer> JITIRParser<'lexer, 'input, '_> {
                         );
                         self.push_assign(inst.into(), assign)?;
                     }
+                    ASTInst::Overflow {
+                        assign,
+                        type_,
+                        op,
+                        lhs,
+                        rhs,
+                    } => {
+                        let ty = self.process_type(type_)?;
+                        match self.m.type_(ty) {
+                            Ty::Integer(1) => (),
+                            x => {
+                                return Err(self.error_at_span(
+                                    assign,
+                                    &format!(
+                                        "Overflow instructions must assign to an i1, not '{}'",
+                                        x.display(self.m)
+                                    ),
+                                ))
+                            }
+                        }
+                        let inst = OverflowInst::new(
+                            self.process_operand(lhs)?,
+                            op,
+                            self.process_operand(rhs)?,
+                        );
+                        self.push_assign(inst.into(), assign)?;
+                    }
                     ASTInst::FCmp {
                         assign,
                         type_,
@@ -430,6 +458,22 @@ impl<'lexer, 'input: 'lexer> JITIRParser<'lexer, 'input, '_> {
                             FPToSIInst::new(&self.process_operand(val)?, self.process_type(type_)?);
                         self.push_assign(inst.into(), assign)?;
                     }
+                    ASTInst::FPToUI { assign, type_, val } => {
+                        let inst =
+                            FPToUIInst::new(&self.process_operand(val)?, self.process_type(type_)?);
+                        self.push_assign(inst.into(), assign)?;
+                    }
+                    ASTInst::FPTrunc { assign, type_, val } => {
+                        let inst = FPTruncInst::new(
+                            &self.process_operand(val)?,
+                            self.process_type(type_)?,
+                        );
+                        self.push_assign(inst.into(), assign)?;
+                    }
+                    ASTInst::SecondRetVal { assign, type_ } => {
+                        let inst = SecondRetValInst::new(self.process_type(type_)?);
+                        self.push_assign(inst.into(), assign)?;
+                    }
                     ASTInst::FNeg { assign, val } => {
                         let inst = FNegInst::new(self.process_operand(val)?);
                         self.push_assign(inst.into(), assign)?;
@@ -539,6 +583,7 @@ impl<'lexer, 'input: 'lexer> JITIRParser<'lexer, 'input, '_> {
             arg_tys,
             is_varargs,
             rtn_ty,
+            is_allocator,
         } in func_decls
         {
             let name = self.lexer.span_str(name_span).to_owned();
@@ -558,7 +603,7 @@ impl<'lexer, 'input: 'lexer> JITIRParser<'lexer, 'input, '_> {
                 .insert_ty(Ty::Func(FuncTy::new(arg_tys, rtn_ty, is_varargs)))
                 .map_err(|e| self.error_at_span(name_span, &e.to_string()))?;
             self.m
-                .insert_func_decl(FuncDecl::new(name, func_ty))
+                .insert_func_decl(FuncDecl::new(name, func_ty, is_allocator))
                 .map_err(|e| self.error_at_span(name_span, &e.to_string()))?;
         }
         Ok(())
@@ -580,31 +625,37 @@ impl<'lexer, 'input: 'lexer> JITIRParser<'lexer, 'input, '_> {
                 let bitw = width
                     .parse::<u32>()
                     .map_err(|e| self.error_at_span(span, &e.to_string()))?;
+                if bitw == 0 || bitw > 128 {
+                    return Err(self.error_at_span(
+                        span,
+                        &format!("Integer bit width {bitw} outside the range 1..=128"),
+                    ));
+                }
                 let val = if val.starts_with("-") {
                     let val = val
-                        .parse::<i64>()
+                        .parse::<i128>()
                         .map_err(|e| self.error_at_span(span, &e.to_string()))?;
-                    if bitw < 64
+                    if bitw < 128
                         && (val < -((1 << bitw) - 1) / 2 - 1 || val >= ((1 << bitw) - 1) / 2)
                     {
                         return Err(self.error_at_span(span,
                           &format!("Signed constant {val} exceeds the bit width {bitw} of the integer type")));
                     }
-                    val as u64
+                    ArbBitInt::from_i128(bitw, val)
                 } else {
                     let val = val
-                        .parse::<u64>()
+                        .parse::<u128>()
                         .map_err(|e| self.error_at_span(span, &e.to_string()))?;
-                    if bitw < 64 && val > (1 << bitw) - 1 {
+                    if bitw < 128 && val > (1 << bitw) - 1 {
                         return Err(self.error_at_span(span,
                           &format!("Unsigned constant {val} exceeds the bit width {bitw} of the integer type")));
                     }
-                    val
+                    ArbBitInt::from_u128(bitw, val)
                 };
                 let tyidx = self.m.insert_ty(Ty::Integer(bitw)).unwrap();
                 Ok(Operand::Const(
                     self.m
-                        .insert_const(Const::Int(tyidx, ArbBitInt::from_u64(bitw, val)))
+                        .insert_const(Const::Int(tyidx, val))
                         .map_err(|e| self.error_at_span(span, &e.to_string()))?,
                 ))
             }
@@ -738,6 +789,7 @@ struct ASTFuncDecl {
     arg_tys: Vec<ASTType>,
     is_varargs: bool,
     rtn_ty: ASTType,
+    is_allocator: bool,
 }
 
 #[derive(Debug)]
@@ -782,6 +834,13 @@ enum ASTInst {
         lhs: ASTOperand,
         rhs: ASTOperand,
     },
+    Overflow {
+        assign: Span,
+        type_: ASTType,
+        op: OverflowOp,
+        lhs: ASTOperand,
+        rhs: ASTOperand,
+    },
     FCmp {
         assign: Span,
         type_: ASTType,
@@ -864,6 +923,20 @@ enum ASTInst {
         type_: ASTType,
         val: ASTOperand,
     },
+    FPToUI {
+        assign: Span,
+        type_: ASTType,
+        val: ASTOperand,
+    },
+    FPTrunc {
+        assign: Span,
+        type_: ASTType,
+        val: ASTOperand,
+    },
+    SecondRetVal {
+        assign: Span,
+        type_: ASTType,
+    },
     FNeg {
         assign: Span,
         val: ASTOperand,
@@ -1061,6 +1134,16 @@ mod tests {
               %62: float = bitcast %7
               %63: float = fneg %54
               %64: double = ui_to_fp %43
+              %65: i1 = sadd_overflow %7, %9
+              %66: i1 = uadd_overflow %7, %9
+              %67: i1 = ssub_overflow %7, %9
+              %68: i1 = usub_overflow %7, %9
+              %69: i1 = smul_overflow %7, %9
+              %70: i1 = umul_overflow %7, 3i32
+              %71: float = fp_trunc %57
+              %72: i64 = fp_to_ui %57
+              %73: i64 = call @f3(%5, %7, %0)
+              %74: double = second_ret_val
               body_end [%43, %58]
         ",
         );
@@ -1084,7 +1167,7 @@ mod tests {
         let f1_tyidx = m
             .insert_ty(Ty::Func(FuncTy::new(Vec::new(), m.void_tyidx(), false)))
             .unwrap();
-        m.insert_func_decl(FuncDecl::new("f1".to_owned(), f1_tyidx))
+        m.insert_func_decl(FuncDecl::new("f1".to_owned(), f1_tyidx, false))
             .unwrap();
         assert_eq!(m.func_decls_len(), 4);
 
@@ -1096,7 +1179,7 @@ mod tests {
                 false,
             )))
             .unwrap();
-        m.insert_func_decl(FuncDecl::new("f2".to_owned(), f2_tyidx))
+        m.insert_func_decl(FuncDecl::new("f2".to_owned(), f2_tyidx, false))
             .unwrap();
         assert_eq!(m.func_decls_len(), 4);
 
@@ -1108,14 +1191,14 @@ mod tests {
                 true,
             )))
             .unwrap();
-        m.insert_func_decl(FuncDecl::new("f3".to_owned(), f3_tyidx))
+        m.insert_func_decl(FuncDecl::new("f3".to_owned(), f3_tyidx, false))
             .unwrap();
         assert_eq!(m.func_decls_len(), 4);
 
         let f4_tyidx = m
             .insert_ty(Ty::Func(FuncTy::new(Vec::new(), m.void_tyidx(), true)))
             .unwrap();
-        m.insert_func_decl(FuncDecl::new("f4".to_owned(), f4_tyidx))
+        m.insert_func_decl(FuncDecl::new("f4".to_owned(), f4_tyidx, false))
             .unwrap();
         assert_eq!(m.func_decls_len(), 4);
     }
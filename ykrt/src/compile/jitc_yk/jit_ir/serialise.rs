@@ -0,0 +1,947 @@
+//! Serialisation and deserialisation of [Module]s.
+//!
+//! This is used by the on-disk trace cache to store optimised modules between process runs. The
+//! format is private to yk and has no stability guarantees: any change to the IR must be reflected
+//! here (and [FORMAT_VERSION] incremented, so that modules serialised in the old format are
+//! rejected).
+//!
+//! Only self-contained root traces (i.e. [TraceKind::HeaderOnly] and [TraceKind::HeaderAndBody])
+//! can be serialised, since other kinds of trace reference other, in-memory, compiled traces.
+//! References into the AOT module (i.e. [aot_ir::DeoptSafepoint]s) are serialised as stackmap IDs,
+//! and resolved back into references when deserialising.
+
+use super::{
+    aot_ir, ArbBitInt, CompilationError, Const, ConstIndexSetWrapper, FloatTy, FuncDecl, FuncTy,
+    GlobalDecl, GuardInfo, IndirectCallInst, InlinedFrame, Inst, InstDiscriminants, Module,
+    PackedOperand, TraceKind, Ty, VirtualAlloc,
+};
+use crate::mt::TraceId;
+use deku::DekuContainerRead;
+use std::ffi::CString;
+
+/// The version of the serialisation format. This must be incremented whenever the format changes.
+const FORMAT_VERSION: u32 = 5;
+
+impl Module {
+    /// Serialise this module.
+    ///
+    /// # Panics
+    ///
+    /// If this module is not a [TraceKind::HeaderOnly] or [TraceKind::HeaderAndBody] trace.
+    pub(crate) fn serialise(&self) -> Vec<u8> {
+        let mut w = Writer(Vec::new());
+        w.u32(FORMAT_VERSION);
+        match self.tracekind {
+            TraceKind::HeaderOnly => w.u8(0),
+            TraceKind::HeaderAndBody => w.u8(1),
+            TraceKind::Connector(_) | TraceKind::Sidetrace(_) => {
+                panic!("Can't serialise a {:?} trace", self.tracekind)
+            }
+        }
+        w.option(self.safepoint.as_ref(), |w, x| w.u64(x.id));
+        w.seq(&self.insts, |w, x| w.inst(*x));
+        w.seq(&self.args, |w, x| w.operand(*x));
+        w.seq(&self.consts, |w, x| w.const_(&x.0));
+        w.seq(&self.types, |w, x| w.ty(x));
+        w.seq(&self.params, |w, x| w.location(x));
+        w.seq(&self.func_decls, |w, x| {
+            w.str(&x.name);
+            w.idx(x.tyidx);
+            w.bool(x.is_allocator);
+        });
+        w.seq(&self.global_decls, |w, x| {
+            w.bytes(x.name.as_bytes());
+            w.bool(x.is_threadlocal);
+            w.idx(x.global_ptr_idx);
+        });
+        w.seq(&self.guard_info, |w, x| w.guard_info(x));
+        w.seq(&self.indirect_calls, |w, x| {
+            w.operand(x.target);
+            w.idx(x.ftyidx);
+            w.u16(x.num_args);
+            w.idx(x.args_idx);
+        });
+        for ops in [
+            &self.trace_body_start,
+            &self.trace_body_end,
+            &self.trace_header_start,
+            &self.trace_header_end,
+        ] {
+            w.seq(ops, |w, x| w.operand(*x));
+        }
+        w.seq(&self.debug_strs, |w, x| w.str(x));
+        w.0
+    }
+
+    /// Deserialise a module previously serialised by [Module::serialise], giving it the ID
+    /// `ctr_id`. `safepoint` is used to resolve stackmap IDs to the AOT safepoints they were
+    /// serialised from.
+    pub(crate) fn deserialise(
+        bytes: &[u8],
+        ctr_id: TraceId,
+        global_decls_len: usize,
+        safepoint: &dyn Fn(u64) -> Option<&'static aot_ir::DeoptSafepoint>,
+    ) -> Result<Self, CompilationError> {
+        let mut r = Reader {
+            bytes,
+            off: 0,
+            safepoint,
+        };
+        let version = r.u32()?;
+        if version != FORMAT_VERSION {
+            return Err(r.error(&format!("unsupported format version {version}")));
+        }
+        let tracekind = match r.u8()? {
+            0 => TraceKind::HeaderOnly,
+            1 => TraceKind::HeaderAndBody,
+            x => return Err(r.error(&format!("unknown trace kind {x}"))),
+        };
+        let mut m = Module::new(tracekind, ctr_id, global_decls_len)?;
+        m.safepoint = r.option(|r| r.safepoint())?.cloned();
+        m.insts = r.seq(|r| r.inst())?;
+        m.args = r.seq(|r| r.operand())?;
+        // The constant, type, and declaration pools must end up with exactly the same indices as
+        // in the serialised module. Note that constants and types are added to the pools which
+        // [Module::new] has already created: the predefined entries in those pools (e.g. the void
+        // type) must also match those in the serialised module.
+        for (i, c) in r.seq(|r| r.const_())?.into_iter().enumerate() {
+            if m.consts.insert_full(ConstIndexSetWrapper(c)).0 != i {
+                return Err(r.error("inconsistent constant pool"));
+            }
+        }
+        for (i, ty) in r.seq(|r| r.ty())?.into_iter().enumerate() {
+            if m.types.insert_full(ty).0 != i {
+                return Err(r.error("inconsistent type pool"));
+            }
+        }
+        m.params = r.seq(|r| r.location())?;
+        for (i, fd) in r
+            .seq(|r| Ok(FuncDecl::new(r.str()?, r.idx()?, r.bool()?)))?
+            .into_iter()
+            .enumerate()
+        {
+            if m.func_decls.insert_full(fd).0 != i {
+                return Err(r.error("inconsistent function declaration pool"));
+            }
+        }
+        for (i, gd) in r
+            .seq(|r| {
+                let name = CString::new(r.bytes()?).map_err(|e| r.error(&e.to_string()))?;
+                Ok(GlobalDecl::new(name, r.bool()?, r.idx()?))
+            })?
+            .into_iter()
+            .enumerate()
+        {
+            if m.global_decls.insert_full(gd).0 != i {
+                return Err(r.error("inconsistent global declaration pool"));
+            }
+        }
+        m.guard_info = r.seq(|r| r.guard_info())?;
+        m.indirect_calls = r.seq(|r| {
+            Ok(IndirectCallInst {
+                target: r.operand()?,
+                ftyidx: r.idx()?,
+                num_args: r.u16()?,
+                args_idx: r.idx()?,
+            })
+        })?;
+        m.trace_body_start = r.seq(|r| r.operand())?;
+        m.trace_body_end = r.seq(|r| r.operand())?;
+        m.trace_header_start = r.seq(|r| r.operand())?;
+        m.trace_header_end = r.seq(|r| r.operand())?;
+        m.debug_strs = r.seq(|r| r.str())?;
+        if r.off != bytes.len() {
+            return Err(r.error("trailing data"));
+        }
+        Ok(m)
+    }
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, x: u8) {
+        self.0.push(x);
+    }
+
+    fn bool(&mut self, x: bool) {
+        self.u8(u8::from(x));
+    }
+
+    fn u16(&mut self, x: u16) {
+        self.0.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn u32(&mut self, x: u32) {
+        self.0.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn i32(&mut self, x: i32) {
+        self.0.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn u64(&mut self, x: u64) {
+        self.0.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn u128(&mut self, x: u128) {
+        self.0.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn usize(&mut self, x: usize) {
+        self.u64(u64::try_from(x).unwrap());
+    }
+
+    /// Write an index of any of the JIT or AOT IR index types.
+    fn idx<T: Into<usize>>(&mut self, x: T) {
+        self.usize(x.into());
+    }
+
+    fn operand(&mut self, x: PackedOperand) {
+        self.u32(x.0);
+    }
+
+    fn bytes(&mut self, x: &[u8]) {
+        self.usize(x.len());
+        self.0.extend_from_slice(x);
+    }
+
+    fn str(&mut self, x: &str) {
+        self.bytes(x.as_bytes());
+    }
+
+    fn option<T>(&mut self, x: Option<T>, f: impl FnOnce(&mut Self, T)) {
+        match x {
+            Some(x) => {
+                self.bool(true);
+                f(self, x);
+            }
+            None => self.bool(false),
+        }
+    }
+
+    fn seq<'a, T: 'a>(
+        &mut self,
+        xs: impl IntoIterator<Item = &'a T, IntoIter: ExactSizeIterator>,
+        mut f: impl FnMut(&mut Self, &'a T),
+    ) {
+        let xs = xs.into_iter();
+        self.usize(xs.len());
+        for x in xs {
+            f(self, x);
+        }
+    }
+
+    fn const_(&mut self, x: &Const) {
+        match x {
+            Const::Float(tyidx, v) => {
+                self.u8(0);
+                self.idx(*tyidx);
+                self.u64(v.to_bits());
+            }
+            Const::Int(tyidx, v) => {
+                self.u8(1);
+                self.idx(*tyidx);
+                self.u32(v.bitw());
+                self.u128(v.to_zero_ext_u128().unwrap());
+            }
+            Const::Ptr(v) => {
+                self.u8(2);
+                self.usize(*v);
+            }
+        }
+    }
+
+    fn ty(&mut self, x: &Ty) {
+        match x {
+            Ty::Void => self.u8(0),
+            Ty::Integer(bitw) => {
+                self.u8(1);
+                self.u32(*bitw);
+            }
+            Ty::Ptr => self.u8(2),
+            Ty::Func(fty) => {
+                self.u8(3);
+                self.seq(&fty.param_tyidxs, |w, x| w.idx(*x));
+                self.idx(fty.ret_tyidx);
+                self.bool(fty.is_vararg);
+            }
+            Ty::Float(fty) => {
+                self.u8(4);
+                self.u8(match fty {
+                    FloatTy::Float => 0,
+                    FloatTy::Double => 1,
+                });
+            }
+            Ty::Unimplemented(s) => {
+                self.u8(5);
+                self.str(s);
+            }
+        }
+    }
+
+    fn location(&mut self, x: &yksmp::Location) {
+        match x {
+            yksmp::Location::Register(reg, size, extras) => {
+                self.u8(0);
+                self.u16(*reg);
+                self.u16(*size);
+                self.seq(extras, |w, x| w.u16(*x as u16));
+            }
+            yksmp::Location::Direct(reg, off, size) => {
+                self.u8(1);
+                self.u16(*reg);
+                self.i32(*off);
+                self.u16(*size);
+            }
+            yksmp::Location::Indirect(reg, off, size) => {
+                self.u8(2);
+                self.u16(*reg);
+                self.i32(*off);
+                self.u16(*size);
+            }
+            yksmp::Location::Constant(v) => {
+                self.u8(3);
+                self.u32(*v);
+            }
+            yksmp::Location::LargeConstant(v) => {
+                self.u8(4);
+                self.u64(*v);
+            }
+        }
+    }
+
+    fn inst_id(&mut self, x: &aot_ir::InstId) {
+        self.idx(x.funcidx());
+        self.idx(x.bbidx());
+        self.idx(x.iidx());
+    }
+
+    fn guard_info(&mut self, x: &GuardInfo) {
+        self.idx(x.bid.funcidx());
+        self.idx(x.bid.bbidx());
+        self.seq(&x.live_vars, |w, (iid, op)| {
+            w.inst_id(iid);
+            w.operand(*op);
+        });
+        self.seq(&x.inlined_frames, |w, x| {
+            w.option(x.callinst.as_ref(), |w, x| w.inst_id(x));
+            w.idx(x.funcidx);
+            w.u64(x.safepoint.id);
+            w.seq(&x.args, |w, x| w.operand(*x));
+        });
+        self.u64(x.safepoint_id);
+        self.seq(&x.virtuals, |w, x| {
+            w.idx(x.iidx);
+            w.idx(x.target);
+            w.u64(x.size);
+            w.seq(&x.fields, |w, (off, op)| {
+                w.u32(*off);
+                w.operand(*op);
+            });
+        });
+    }
+
+    fn inst(&mut self, inst: Inst) {
+        self.u8(u8::try_from(inst.discriminant()).unwrap());
+        match inst {
+            #[cfg(test)]
+            Inst::BlackBox(x) => self.operand(x.op),
+            Inst::Const(x) => self.idx(x),
+            Inst::Copy(x) => self.idx(x),
+            Inst::Tombstone
+            | Inst::TraceHeaderStart
+            | Inst::TraceBodyStart
+            | Inst::TraceBodyEnd
+            | Inst::SidetraceEnd => (),
+            Inst::BinOp(x) => {
+                self.operand(x.lhs);
+                self.u8(x.binop as u8);
+                self.operand(x.rhs);
+            }
+            Inst::Load(x) => {
+                self.operand(x.ptr);
+                self.idx(x.tyidx);
+                self.bool(x.volatile);
+            }
+            Inst::LookupGlobal(x) => self.idx(x.global_decl_idx),
+            Inst::Param(x) => {
+                self.idx(x.pidx);
+                self.idx(x.tyidx);
+            }
+            Inst::Call(x) => {
+                self.idx(x.target);
+                self.idx(x.args_idx);
+                self.u16(x.num_args);
+                self.option(x.idem_const, |w, x| w.idx(x));
+            }
+            Inst::IndirectCall(x) => self.idx(x),
+            Inst::PtrAdd(x) => {
+                self.operand(x.ptr);
+                self.i32(x.off);
+            }
+            Inst::DynPtrAdd(x) => {
+                self.operand(x.ptr);
+                self.operand(x.num_elems);
+                self.u16(x.elem_size);
+            }
+            Inst::Store(x) => {
+                self.operand(x.ptr);
+                self.operand(x.val);
+                self.bool(x.volatile);
+            }
+            Inst::ICmp(x) => {
+                self.operand(x.lhs);
+                self.u8(x.pred as u8);
+                self.operand(x.rhs);
+            }
+            Inst::Guard(x) => {
+                self.operand(x.cond);
+                self.bool(x.expect);
+                self.idx(x.gidx.0);
+            }
+            Inst::TraceHeaderEnd(x) => self.bool(x),
+            Inst::SExt(x) => {
+                self.operand(x.val);
+                self.idx(x.dest_tyidx);
+            }
+            Inst::ZExt(x) => {
+                self.operand(x.val);
+                self.idx(x.dest_tyidx);
+            }
+            Inst::Trunc(x) => {
+                self.operand(x.val);
+                self.idx(x.dest_tyidx);
+            }
+            Inst::Select(x) => {
+                self.operand(x.cond);
+                self.operand(x.trueval);
+                self.operand(x.falseval);
+            }
+            Inst::SIToFP(x) => {
+                self.operand(x.val);
+                self.idx(x.dest_tyidx);
+            }
+            Inst::FPExt(x) => {
+                self.operand(x.val);
+                self.idx(x.dest_tyidx);
+            }
+            Inst::FCmp(x) => {
+                self.operand(x.lhs);
+                self.u8(x.pred as u8);
+                self.operand(x.rhs);
+            }
+            Inst::FPToSI(x) => {
+                self.operand(x.val);
+                self.idx(x.dest_tyidx);
+            }
+            Inst::BitCast(x) => {
+                self.operand(x.val);
+                self.idx(x.dest_tyidx);
+            }
+            Inst::FNeg(x) => self.operand(x.val),
+            Inst::DebugStr(x) => self.idx(x.idx),
+            Inst::IntToPtr(x) => self.operand(x.val),
+            Inst::PtrToInt(x) => {
+                self.operand(x.val);
+                self.idx(x.dest_tyidx);
+            }
+            Inst::UIToFP(x) => {
+                self.operand(x.val);
+                self.idx(x.dest_tyidx);
+            }
+            Inst::Overflow(x) => {
+                self.operand(x.lhs);
+                self.u8(x.op as u8);
+                self.operand(x.rhs);
+            }
+            Inst::FPTrunc(x) => {
+                self.operand(x.val);
+                self.idx(x.dest_tyidx);
+            }
+            Inst::FPToUI(x) => {
+                self.operand(x.val);
+                self.idx(x.dest_tyidx);
+            }
+            Inst::SecondRetVal(x) => self.idx(x.tyidx),
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    off: usize,
+    safepoint: &'a dyn Fn(u64) -> Option<&'static aot_ir::DeoptSafepoint>,
+}
+
+impl Reader<'_> {
+    fn error(&self, msg: &str) -> CompilationError {
+        CompilationError::General(format!(
+            "Can't deserialise module at offset {}: {msg}",
+            self.off
+        ))
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], CompilationError> {
+        match self.bytes.get(self.off..self.off + N) {
+            Some(x) => {
+                self.off += N;
+                // The `unwrap` can't fail as `x` is exactly `N` bytes long.
+                Ok(x.try_into().unwrap())
+            }
+            None => Err(self.error("unexpected end of data")),
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8, CompilationError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, CompilationError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            x => Err(self.error(&format!("invalid bool {x}"))),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, CompilationError> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, CompilationError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn i32(&mut self) -> Result<i32, CompilationError> {
+        Ok(i32::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, CompilationError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    fn u128(&mut self) -> Result<u128, CompilationError> {
+        Ok(u128::from_le_bytes(self.take()?))
+    }
+
+    fn usize(&mut self) -> Result<usize, CompilationError> {
+        let x = self.u64()?;
+        usize::try_from(x).map_err(|_| self.error(&format!("{x} doesn't fit in a usize")))
+    }
+
+    /// Read an index of any of the JIT or AOT IR index types.
+    fn idx<T: TryFrom<usize>>(&mut self) -> Result<T, CompilationError> {
+        let x = self.usize()?;
+        T::try_from(x).map_err(|_| self.error(&format!("index {x} out of range")))
+    }
+
+    fn operand(&mut self) -> Result<PackedOperand, CompilationError> {
+        Ok(PackedOperand(self.u32()?))
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, CompilationError> {
+        let len = self.usize()?;
+        match self.bytes.get(self.off..self.off.saturating_add(len)) {
+            Some(x) => {
+                self.off += len;
+                Ok(x.to_vec())
+            }
+            None => Err(self.error("unexpected end of data")),
+        }
+    }
+
+    fn str(&mut self) -> Result<String, CompilationError> {
+        String::from_utf8(self.bytes()?).map_err(|e| self.error(&e.to_string()))
+    }
+
+    fn option<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, CompilationError>,
+    ) -> Result<Option<T>, CompilationError> {
+        if self.bool()? {
+            Ok(Some(f(self)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn seq<T>(
+        &mut self,
+        mut f: impl FnMut(&mut Self) -> Result<T, CompilationError>,
+    ) -> Result<Vec<T>, CompilationError> {
+        let len = self.usize()?;
+        // Don't trust `len` for preallocation: a corrupt length could be enormous.
+        let mut xs = Vec::new();
+        for _ in 0..len {
+            xs.push(f(self)?);
+        }
+        Ok(xs)
+    }
+
+    /// Read a stackmap ID and resolve it to the AOT safepoint it identifies.
+    fn safepoint(&mut self) -> Result<&'static aot_ir::DeoptSafepoint, CompilationError> {
+        let id = self.u64()?;
+        (self.safepoint)(id).ok_or_else(|| self.error(&format!("unknown safepoint {id}")))
+    }
+
+    /// Read a `u8` and convert it to one of the (`u8` discriminated) AOT IR enums.
+    fn enum_<T: for<'b> DekuContainerRead<'b>>(&mut self) -> Result<T, CompilationError> {
+        let x = self.u8()?;
+        T::from_bytes((&[x], 0))
+            .map(|(_, x)| x)
+            .map_err(|e| self.error(&e.to_string()))
+    }
+
+    fn const_(&mut self) -> Result<Const, CompilationError> {
+        match self.u8()? {
+            0 => Ok(Const::Float(self.idx()?, f64::from_bits(self.u64()?))),
+            1 => {
+                let tyidx = self.idx()?;
+                let bitw = self.u32()?;
+                Ok(Const::Int(tyidx, ArbBitInt::from_u128(bitw, self.u128()?)))
+            }
+            2 => Ok(Const::Ptr(self.usize()?)),
+            x => Err(self.error(&format!("unknown constant kind {x}"))),
+        }
+    }
+
+    fn ty(&mut self) -> Result<Ty, CompilationError> {
+        match self.u8()? {
+            0 => Ok(Ty::Void),
+            1 => Ok(Ty::Integer(self.u32()?)),
+            2 => Ok(Ty::Ptr),
+            3 => Ok(Ty::Func(FuncTy::new(
+                self.seq(|r| r.idx())?,
+                self.idx()?,
+                self.bool()?,
+            ))),
+            4 => match self.u8()? {
+                0 => Ok(Ty::Float(FloatTy::Float)),
+                1 => Ok(Ty::Float(FloatTy::Double)),
+                x => Err(self.error(&format!("unknown float type {x}"))),
+            },
+            5 => Ok(Ty::Unimplemented(self.str()?)),
+            x => Err(self.error(&format!("unknown type kind {x}"))),
+        }
+    }
+
+    fn location(&mut self) -> Result<yksmp::Location, CompilationError> {
+        match self.u8()? {
+            0 => Ok(yksmp::Location::Register(
+                self.u16()?,
+                self.u16()?,
+                self.seq(|r| Ok(r.u16()? as i16))?.into_iter().collect(),
+            )),
+            1 => Ok(yksmp::Location::Direct(
+                self.u16()?,
+                self.i32()?,
+                self.u16()?,
+            )),
+            2 => Ok(yksmp::Location::Indirect(
+                self.u16()?,
+                self.i32()?,
+                self.u16()?,
+            )),
+            3 => Ok(yksmp::Location::Constant(self.u32()?)),
+            4 => Ok(yksmp::Location::LargeConstant(self.u64()?)),
+            x => Err(self.error(&format!("unknown location kind {x}"))),
+        }
+    }
+
+    fn inst_id(&mut self) -> Result<aot_ir::InstId, CompilationError> {
+        Ok(aot_ir::InstId::new(self.idx()?, self.idx()?, self.idx()?))
+    }
+
+    fn guard_info(&mut self) -> Result<GuardInfo, CompilationError> {
+        let bid = aot_ir::BBlockId::new(self.idx()?, self.idx()?);
+        let live_vars = self.seq(|r| Ok((r.inst_id()?, r.operand()?)))?;
+        let inlined_frames = self.seq(|r| {
+            Ok(InlinedFrame {
+                callinst: r.option(|r| r.inst_id())?,
+                funcidx: r.idx()?,
+                safepoint: r.safepoint()?,
+                args: r.seq(|r| r.operand())?,
+            })
+        })?;
+        let mut gi = GuardInfo::new(bid, live_vars, inlined_frames, self.u64()?);
+        gi.set_virtuals(self.seq(|r| {
+            Ok(VirtualAlloc {
+                iidx: r.idx()?,
+                target: r.idx()?,
+                size: r.u64()?,
+                fields: r.seq(|r| Ok((r.u32()?, r.operand()?)))?,
+            })
+        })?);
+        Ok(gi)
+    }
+
+    fn inst(&mut self) -> Result<Inst, CompilationError> {
+        use super::{
+            BinOpInst, BitCastInst, DirectCallInst, DynPtrAddInst, FCmpInst, FNegInst, FPExtInst,
+            FPToSIInst, FPToUIInst, FPTruncInst, GuardInst, ICmpInst, IntToPtrInst, LoadInst,
+            LookupGlobalInst, OverflowInst, OverflowOp, ParamInst, PtrAddInst, PtrToIntInst,
+            SExtInst, SIToFPInst, SecondRetValInst, SelectInst, StoreInst, TruncInst, UIToFPInst,
+            ZExtInst,
+        };
+        use InstDiscriminants as ID;
+
+        let d = self.u8()?;
+        let inst = match d {
+            #[cfg(test)]
+            _ if d == ID::BlackBox as u8 => Inst::BlackBox(super::BlackBoxInst {
+                op: self.operand()?,
+            }),
+            _ if d == ID::Const as u8 => Inst::Const(self.idx()?),
+            _ if d == ID::Copy as u8 => Inst::Copy(self.idx()?),
+            _ if d == ID::Tombstone as u8 => Inst::Tombstone,
+            _ if d == ID::BinOp as u8 => Inst::BinOp(BinOpInst {
+                lhs: self.operand()?,
+                binop: self.enum_()?,
+                rhs: self.operand()?,
+            }),
+            _ if d == ID::Load as u8 => Inst::Load(LoadInst {
+                ptr: self.operand()?,
+                tyidx: self.idx()?,
+                volatile: self.bool()?,
+            }),
+            _ if d == ID::LookupGlobal as u8 => Inst::LookupGlobal(LookupGlobalInst {
+                global_decl_idx: self.idx()?,
+            }),
+            _ if d == ID::Param as u8 => Inst::Param(ParamInst {
+                pidx: self.idx()?,
+                tyidx: self.idx()?,
+            }),
+            _ if d == ID::Call as u8 => Inst::Call(DirectCallInst {
+                target: self.idx()?,
+                args_idx: self.idx()?,
+                num_args: self.u16()?,
+                idem_const: self.option(|r| r.idx())?,
+            }),
+            _ if d == ID::IndirectCall as u8 => Inst::IndirectCall(self.idx()?),
+            _ if d == ID::PtrAdd as u8 => Inst::PtrAdd(PtrAddInst {
+                ptr: self.operand()?,
+                off: self.i32()?,
+            }),
+            _ if d == ID::DynPtrAdd as u8 => Inst::DynPtrAdd(DynPtrAddInst {
+                ptr: self.operand()?,
+                num_elems: self.operand()?,
+                elem_size: self.u16()?,
+            }),
+            _ if d == ID::Store as u8 => Inst::Store(StoreInst {
+                ptr: self.operand()?,
+                val: self.operand()?,
+                volatile: self.bool()?,
+            }),
+            _ if d == ID::ICmp as u8 => Inst::ICmp(ICmpInst {
+                lhs: self.operand()?,
+                pred: self.enum_()?,
+                rhs: self.operand()?,
+            }),
+            _ if d == ID::Guard as u8 => Inst::Guard(GuardInst {
+                cond: self.operand()?,
+                expect: self.bool()?,
+                gidx: self.idx()?,
+            }),
+            _ if d == ID::TraceHeaderStart as u8 => Inst::TraceHeaderStart,
+            _ if d == ID::TraceHeaderEnd as u8 => Inst::TraceHeaderEnd(self.bool()?),
+            _ if d == ID::TraceBodyStart as u8 => Inst::TraceBodyStart,
+            _ if d == ID::TraceBodyEnd as u8 => Inst::TraceBodyEnd,
+            _ if d == ID::SidetraceEnd as u8 => Inst::SidetraceEnd,
+            _ if d == ID::SExt as u8 => Inst::SExt(SExtInst {
+                val: self.operand()?,
+                dest_tyidx: self.idx()?,
+            }),
+            _ if d == ID::ZExt as u8 => Inst::ZExt(ZExtInst {
+                val: self.operand()?,
+                dest_tyidx: self.idx()?,
+            }),
+            _ if d == ID::Trunc as u8 => Inst::Trunc(TruncInst {
+                val: self.operand()?,
+                dest_tyidx: self.idx()?,
+            }),
+            _ if d == ID::Select as u8 => Inst::Select(SelectInst {
+                cond: self.operand()?,
+                trueval: self.operand()?,
+                falseval: self.operand()?,
+            }),
+            _ if d == ID::SIToFP as u8 => Inst::SIToFP(SIToFPInst {
+                val: self.operand()?,
+                dest_tyidx: self.idx()?,
+            }),
+            _ if d == ID::FPExt as u8 => Inst::FPExt(FPExtInst {
+                val: self.operand()?,
+                dest_tyidx: self.idx()?,
+            }),
+            _ if d == ID::FCmp as u8 => Inst::FCmp(FCmpInst {
+                lhs: self.operand()?,
+                pred: self.enum_()?,
+                rhs: self.operand()?,
+            }),
+            _ if d == ID::FPToSI as u8 => Inst::FPToSI(FPToSIInst {
+                val: self.operand()?,
+                dest_tyidx: self.idx()?,
+            }),
+            _ if d == ID::BitCast as u8 => Inst::BitCast(BitCastInst {
+                val: self.operand()?,
+                dest_tyidx: self.idx()?,
+            }),
+            _ if d == ID::FNeg as u8 => Inst::FNeg(FNegInst {
+                val: self.operand()?,
+            }),
+            _ if d == ID::DebugStr as u8 => {
+                Inst::DebugStr(super::DebugStrInst { idx: self.idx()? })
+            }
+            _ if d == ID::IntToPtr as u8 => Inst::IntToPtr(IntToPtrInst {
+                val: self.operand()?,
+            }),
+            _ if d == ID::PtrToInt as u8 => Inst::PtrToInt(PtrToIntInst {
+                val: self.operand()?,
+                dest_tyidx: self.idx()?,
+            }),
+            _ if d == ID::UIToFP as u8 => Inst::UIToFP(UIToFPInst {
+                val: self.operand()?,
+                dest_tyidx: self.idx()?,
+            }),
+            _ if d == ID::Overflow as u8 => Inst::Overflow(OverflowInst {
+                lhs: self.operand()?,
+                op: match self.u8()? {
+                    x if x == OverflowOp::SAdd as u8 => OverflowOp::SAdd,
+                    x if x == OverflowOp::UAdd as u8 => OverflowOp::UAdd,
+                    x if x == OverflowOp::SSub as u8 => OverflowOp::SSub,
+                    x if x == OverflowOp::USub as u8 => OverflowOp::USub,
+                    x if x == OverflowOp::SMul as u8 => OverflowOp::SMul,
+                    x if x == OverflowOp::UMul as u8 => OverflowOp::UMul,
+                    x => return Err(self.error(&format!("unknown overflow operation {x}"))),
+                },
+                rhs: self.operand()?,
+            }),
+            _ if d == ID::FPTrunc as u8 => Inst::FPTrunc(FPTruncInst {
+                val: self.operand()?,
+                dest_tyidx: self.idx()?,
+            }),
+            _ if d == ID::FPToUI as u8 => Inst::FPToUI(FPToUIInst {
+                val: self.operand()?,
+                dest_tyidx: self.idx()?,
+            }),
+            _ if d == ID::SecondRetVal as u8 => {
+                Inst::SecondRetVal(SecondRetValInst::new(self.idx()?))
+            }
+            _ => return Err(self.error(&format!("unknown instruction {d}"))),
+        };
+        Ok(inst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(s: &str) {
+        let m = Module::from_str(s);
+        let bytes = m.serialise();
+        let m2 = Module::deserialise(&bytes, m.ctrid(), 0, &|_| None).unwrap();
+        assert_eq!(m.to_string(), m2.to_string());
+        assert_eq!(bytes, m2.serialise());
+    }
+
+    #[test]
+    fn roundtrip_simple() {
+        roundtrip(
+            "
+          entry:
+            %0: i8 = param reg
+            %1: i8 = add %0, 1i8
+            %2: i1 = eq %1, 3i8
+            guard true, %2, [%0, %1]
+            black_box %1
+        ",
+        );
+    }
+
+    #[test]
+    fn roundtrip_many_insts() {
+        roundtrip(
+            "
+          func_type ft(i64) -> ptr
+          func_decl f(i32, ...) -> i32
+          entry:
+            %0: ptr = param reg
+            %1: i32 = param reg
+            %2: double = param reg
+            body_start [%0, %1]
+            %4: i64 = sext %1
+            %5: i32 = call @f(%1, %4) <idem_const 5i32>
+            %6: ptr = icall<ft> %0(%4)
+            %7: ptr = ptr_add %6, -8
+            %8: ptr = dyn_ptr_add %7, %4, 4
+            %9: i8 = load %8
+            *%7 = %9
+            %11: i8 = trunc %1
+            %12: i64 = zext %11
+            %13: i1 = ult %12, 1000i64
+            %14: i32 = %13 ? %1 : 7i32
+            %15: double = si_to_fp %14
+            %16: double = fadd %2, 1.5double
+            %17: i1 = f_ugt %15, %16
+            %18: double = fneg %16
+            %19: i32 = fp_to_si %18
+            %20: i64 = ptr_to_int %0
+            %21: ptr = int_to_ptr %20
+            %22: double = ui_to_fp %19
+            %23: float = bitcast %19
+            %24: double = fp_ext %23
+            %25: i1 = smul_overflow %19, 3i32
+            %26: i128 = zext %20
+            %27: i128 = add %26, 18446744073709551616i128
+            guard false, %17, [%21, %22, %24, 0x1234, %25]
+            black_box %5
+            black_box %27
+            body_end [%21, %19]
+        ",
+        );
+    }
+
+    #[test]
+    fn roundtrip_virtuals() {
+        let m = crate::compile::jitc_yk::opt::opt(Module::from_str(
+            "
+          func_decl yk_allocator malloc(i64) -> ptr
+          entry:
+            %0: i64 = param reg
+            %1: i1 = param reg
+            %2: ptr = call @malloc(16i64)
+            *%2 = %0
+            guard true, %1, [%2]
+        ",
+        ))
+        .unwrap();
+        let bytes = m.serialise();
+        let m2 = Module::deserialise(&bytes, m.ctrid(), 0, &|_| None).unwrap();
+        assert!(m2
+            .to_string()
+            .contains("virtuals [%2 = @malloc(16) {0: %0}]"));
+        assert_eq!(m.to_string(), m2.to_string());
+        assert_eq!(bytes, m2.serialise());
+    }
+
+    #[test]
+    fn corrupt() {
+        let m = Module::from_str(
+            "
+          entry:
+            %0: i8 = param reg
+            %1: i8 = add %0, 1i8
+            black_box %1
+        ",
+        );
+        let bytes = m.serialise();
+        // Every strict prefix of a valid serialisation must be rejected.
+        for i in 0..bytes.len() {
+            assert!(Module::deserialise(&bytes[..i], m.ctrid(), 0, &|_| None).is_err());
+        }
+        let mut bytes = bytes;
+        bytes.push(0);
+        assert!(Module::deserialise(&bytes, m.ctrid(), 0, &|_| None).is_err());
+    }
+}
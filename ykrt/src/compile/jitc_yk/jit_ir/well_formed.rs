@@ -13,10 +13,17 @@
 //!       * Have an float-typed source operand.
 //!       * Have a float-type as the destination type operand.
 //!       * Have a destination type operand strictly larger than the type of the source operand.
+//!   * [super::FPTruncInst]s:
+//!       * Have an float-typed source operand.
+//!       * Have a float-type as the destination type operand.
+//!       * Have a destination type operand strictly smaller than the type of the source operand.
 //!   * [super::GuardInst]s:
 //!       * Have a `cond` whose type is [super::Ty::Integer(1)] (i.e. an `i1`).
 //!       * If `cond` references a constant, that constant matches the guard's `expect` attribute.
 //!   * [super::ICmpInst]s left and right hand side operands have the same [Ty]s.
+//!   * [super::OverflowInst]s left and right hand side operands have the same integer [Ty]s.
+//!   * [super::SecondRetValInst]s immediately follow a [super::DirectCallInst] or
+//!     [super::IndirectCallInst].
 //!   * [super::SIToFPInst]s:
 //!       * Have an integer-typed source operand.
 //!       * Have a float-type as the destination type operand.
@@ -154,6 +161,21 @@ impl Module {
                             );
                     }
                 }
+                Inst::Overflow(x) => {
+                    let lhs_tyidx = x.lhs(self).tyidx(self);
+                    if lhs_tyidx != x.rhs(self).tyidx(self) {
+                        panic!(
+                            "Instruction at position {iidx} has different types on lhs and rhs\n  {}",
+                            self.inst(iidx).display(self, iidx)
+                        );
+                    }
+                    if !matches!(self.type_(lhs_tyidx), Ty::Integer(_)) {
+                        panic!(
+                            "Overflow instruction at position {iidx} operates on non-integer operands\n  {}",
+                            self.inst(iidx).display(self, iidx)
+                        );
+                    }
+                }
                 Inst::Select(x) => {
                     let Ty::Integer(bitsize) = self.type_(x.cond(self).tyidx(self)) else {
                         panic!();
@@ -286,6 +308,22 @@ impl Module {
                             self.inst(iidx).display(self, iidx));
                     }
                 }
+                Inst::FPTrunc(x) => {
+                    let from_type = self.type_(x.val(self).tyidx(self));
+                    let to_type = self.type_(x.dest_tyidx());
+                    if !matches!(from_type, Ty::Float(_)) {
+                        panic!("Instruction at position {iidx} trying to truncate from a non-float type\n  {}",
+                            self.inst(iidx).display(self, iidx));
+                    }
+                    if !matches!(to_type, Ty::Float(_)) {
+                        panic!("Instruction at position {iidx} trying to truncate to a non-float type\n  {}",
+                            self.inst(iidx).display(self, iidx));
+                    }
+                    if to_type.byte_size() >= from_type.byte_size() {
+                        panic!("Instruction at position {iidx} trying to truncate to a larger-sized float\n  {}",
+                            self.inst(iidx).display(self, iidx));
+                    }
+                }
                 Inst::FPToSI(x) => {
                     let from_type = self.type_(x.val(self).tyidx(self));
                     let to_type = self.type_(x.dest_tyidx());
@@ -299,6 +337,25 @@ impl Module {
                             self.inst(iidx).display(self, iidx));
                     }
                 }
+                Inst::FPToUI(x) => {
+                    let from_type = self.type_(x.val(self).tyidx(self));
+                    let to_type = self.type_(x.dest_tyidx());
+
+                    if !matches!(from_type, Ty::Float(_)) {
+                        panic!("Instruction at position {iidx} trying to convert a non-float type\n  {}",
+                            self.inst(iidx).display(self, iidx));
+                    }
+                    if !matches!(to_type, Ty::Integer(_)) {
+                        panic!("Instruction at position {iidx} trying to convert to a non-integer type\n  {}",
+                            self.inst(iidx).display(self, iidx));
+                    }
+                }
+                Inst::SecondRetVal(_) => {
+                    if !matches!(last_inst, Some(Inst::Call(_) | Inst::IndirectCall(_))) {
+                        panic!("Instruction at position {iidx} does not immediately follow a call\n  {}",
+                            self.inst(iidx).display(self, iidx));
+                    }
+                }
                 Inst::Param(_) => {
                     if let Some(i) = last_inst
                         && !matches!(i, Inst::Param(_) | Inst::TraceHeaderEnd(_))
@@ -436,6 +493,34 @@ mod tests {
         );
     }
 
+    #[test]
+    #[should_panic(expected = "Instruction at position 2 has different types on lhs and rhs")]
+    fn overflow_diff_types() {
+        Module::from_str(
+            "
+              entry:
+                %0: i8 = param reg
+                %1: i64 = param reg
+                %2: i1 = sadd_overflow %0, %1
+            ",
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Overflow instruction at position 2 operates on non-integer operands"
+    )]
+    fn overflow_ptr() {
+        Module::from_str(
+            "
+              entry:
+                %0: ptr = param reg
+                %1: ptr = param reg
+                %2: i1 = uadd_overflow %0, %1
+            ",
+        );
+    }
+
     #[test]
     #[should_panic(expected = "Instruction at position 1 trying to select on a non-i1")]
     fn select_bad_width() {
@@ -679,6 +764,46 @@ mod tests {
         );
     }
 
+    #[test]
+    #[should_panic(expected = "Instruction at position 1 trying to convert a non-float type")]
+    fn fp_to_ui_from_non_float() {
+        Module::from_str(
+            "
+              entry:
+                %0: i32 = param reg
+                %1: i32 = fp_to_ui %0
+            ",
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Instruction at position 1 trying to truncate to a larger-sized float"
+    )]
+    fn fp_trunc_to_larger() {
+        Module::from_str(
+            "
+              entry:
+                %0: float = param reg
+                %1: double = fp_trunc %0
+            ",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Instruction at position 2 does not immediately follow a call")]
+    fn second_ret_val_not_after_call() {
+        Module::from_str(
+            "
+              func_decl f() -> i64
+              entry:
+                %0: i64 = call @f()
+                %1: i64 = add %0, %0
+                %2: i64 = second_ret_val
+            ",
+        );
+    }
+
     #[test]
     #[should_panic(expected = "Integer binop at position 1 operates on float operands")]
     fn int_binop_with_float_opnds() {
@@ -2,15 +2,15 @@
 
 use super::CompilationError;
 use crate::{
-    compile::{jitc_yk::codegen::CodeGen, CompiledTrace, Compiler, GuardIdx},
+    compile::{jitc_yk::codegen::CodeGen, CancellationToken, CompiledTrace, Compiler, GuardIdx},
     location::HotLocation,
-    log::{log_ir, should_log_ir, IRPhase},
+    log::IRPhase,
+    log::Verbosity,
     mt::{TraceId, MT},
     trace::AOTTraceIterator,
 };
 use parking_lot::Mutex;
 use std::{
-    env,
     error::Error,
     fmt,
     marker::PhantomData,
@@ -29,15 +29,8 @@ mod int_signs;
 pub mod jit_ir;
 mod opt;
 mod trace_builder;
-
-/// Should we turn trace optimisations on or off? Defaults to "on".
-static YKD_OPT: LazyLock<bool> = LazyLock::new(|| {
-    let x = env::var("YKD_OPT");
-    match x.as_ref().map(|x| x.as_str()) {
-        Ok("0") => false,
-        Ok(_) | Err(_) => true,
-    }
-});
+mod trace_cache;
+pub(crate) mod trace_dump;
 
 pub(crate) static AOT_MOD: LazyLock<aot_ir::Module> = LazyLock::new(|| {
     let ir_slice = yk_ir_section().unwrap();
@@ -110,16 +103,154 @@ impl<Register: Send + Sync + 'static> JITCYk<Register> {
         promotions: Box<[u8]>,
         debug_strs: Vec<String>,
         connector_ctr: Option<Arc<dyn CompiledTrace>>,
+        cancel: &CancellationToken,
     ) -> Result<Arc<dyn CompiledTrace>, CompilationError> {
+        cancel.check()?;
         // If either `unwrap` fails, there is no chance of the system working correctly.
         let aot_mod = &*AOT_MOD;
 
-        if should_log_ir(IRPhase::AOT) {
-            log_ir(&format!("--- Begin aot ---\n{aot_mod}\n--- End aot ---\n"));
+        if mt.log.should_log_ir(IRPhase::AOT) {
+            mt.log
+                .log_ir(&format!("--- Begin aot ---\n{aot_mod}\n--- End aot ---\n"));
         }
 
+        let ds = if let Some(x) = &hl.lock().debug_str {
+            format!(": {}", x.as_str())
+        } else {
+            "".to_owned()
+        };
+
+        // Only self-contained root traces can be cached or dumped. Both need the entire trace in
+        // memory.
+        let (aottrace_iter, cache) = if (mt.trace_cache.is_some() || mt.trace_dump.is_some())
+            && sti.is_none()
+            && connector_ctr.is_none()
+        {
+            let trace = aottrace_iter.collect::<Vec<_>>();
+            let mut cache = None;
+            // If the trace contains an error, `trace_builder` will report it.
+            if let Some(actions) = trace
+                .iter()
+                .map(|x| x.as_ref().ok())
+                .collect::<Option<Vec<_>>>()
+            {
+                if let Some(dir) = &mt.trace_dump {
+                    let hl_ds = hl.lock().debug_str.clone();
+                    match trace_dump::TraceDump::write(
+                        dir,
+                        ctrid,
+                        hl_ds.as_deref(),
+                        &actions,
+                        &promotions,
+                        &debug_strs,
+                    ) {
+                        Ok(path) => mt.log.log(
+                            Verbosity::Tracing,
+                            &format!("trace-dumped: {}", path.display()),
+                        ),
+                        Err(e) => mt
+                            .log
+                            .log(Verbosity::Warning, &format!("trace-dump-failed: {e}")),
+                    }
+                }
+                cache = mt.trace_cache.as_ref().map(|dir| {
+                    (
+                        dir,
+                        trace_cache::CacheKey::new(
+                            &actions,
+                            &promotions,
+                            &debug_strs,
+                            mt.opt,
+                            &mt.allocators,
+                        ),
+                    )
+                });
+            }
+            (
+                Box::new(trace_cache::CollectedTrace::new(trace)) as Box<dyn AOTTraceIterator>,
+                cache,
+            )
+        } else {
+            (aottrace_iter, None)
+        };
+        let cached = match &cache {
+            Some((dir, key)) => match key.load(dir, ctrid) {
+                Ok(x) => x,
+                Err(e) => {
+                    mt.log
+                        .log(Verbosity::Warning, &format!("trace-cache-read-failed: {e}"));
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let jit_mod = match cached {
+            Some(jit_mod) => {
+                mt.log.log(Verbosity::Tracing, "trace-cache-hit");
+                jit_mod
+            }
+            None => {
+                let jit_mod = self.build(
+                    &mt,
+                    aot_mod,
+                    aottrace_iter,
+                    ctrid,
+                    sti,
+                    promotions,
+                    debug_strs,
+                    connector_ctr,
+                    &ds,
+                    cancel,
+                )?;
+                if let Some((dir, key)) = &cache {
+                    if let Err(e) = key.store(dir, &jit_mod) {
+                        mt.log.log(
+                            Verbosity::Warning,
+                            &format!("trace-cache-write-failed: {e}"),
+                        );
+                    }
+                }
+                jit_mod
+            }
+        };
+
+        // FIXME: This needs to be the combined stacksize of all parent traces.
+        let ct = self.codegen.codegen(jit_mod, Arc::clone(&mt), hl)?;
+        cancel.check()?;
+
+        if mt.log.should_log_ir(IRPhase::Asm) {
+            mt.log.log_ir(&format!(
+                "--- Begin jit-asm{ds} ---\n{}\n--- End jit-asm ---\n",
+                ct.disassemble(false).unwrap()
+            ));
+        }
+        if mt.log.should_log_ir(IRPhase::AsmFull) {
+            mt.log.log_ir(&format!(
+                "--- Begin jit-asm-full{ds} ---\n{}\n--- End jit-asm-full ---\n",
+                ct.disassemble(true).unwrap()
+            ));
+        }
+
+        Ok(ct)
+    }
+
+    /// Build, and (if enabled) optimise, a JIT IR module from a trace.
+    fn build(
+        &self,
+        mt: &Arc<MT>,
+        aot_mod: &'static aot_ir::Module,
+        aottrace_iter: Box<dyn AOTTraceIterator>,
+        ctrid: TraceId,
+        sti: Option<Arc<YkSideTraceInfo<codegen::x64::Register>>>,
+        promotions: Box<[u8]>,
+        debug_strs: Vec<String>,
+        connector_ctr: Option<Arc<dyn CompiledTrace>>,
+        ds: &str,
+        cancel: &CancellationToken,
+    ) -> Result<jit_ir::Module, CompilationError> {
         let mut jit_mod = trace_builder::build(
-            &mt,
+            mt,
             aot_mod,
             ctrid,
             aottrace_iter,
@@ -128,14 +259,9 @@ impl<Register: Send + Sync + 'static> JITCYk<Register> {
             debug_strs,
             connector_ctr,
         )?;
+        cancel.check()?;
 
-        let ds = if let Some(x) = &hl.lock().debug_str {
-            format!(": {}", x.as_str())
-        } else {
-            "".to_owned()
-        };
-
-        if should_log_ir(IRPhase::DebugStrs) {
+        if mt.log.should_log_ir(IRPhase::DebugStrs) {
             let kind = match jit_mod.tracekind() {
                 jit_ir::TraceKind::HeaderOnly => "header",
                 jit_ir::TraceKind::HeaderAndBody => unreachable!(),
@@ -150,42 +276,27 @@ impl<Register: Send + Sync + 'static> JITCYk<Register> {
                 }
             }
             out.push_str("--- End debugstrs ---\n");
-            log_ir(&out);
+            mt.log.log_ir(&out);
         }
 
-        if should_log_ir(IRPhase::PreOpt) {
-            log_ir(&format!(
+        if mt.log.should_log_ir(IRPhase::PreOpt) {
+            mt.log.log_ir(&format!(
                 "--- Begin jit-pre-opt{ds} ---\n{jit_mod}\n--- End jit-pre-opt ---\n",
             ));
         }
 
-        if *YKD_OPT {
+        if mt.opt {
             jit_mod = opt::opt(jit_mod)?;
-            if should_log_ir(IRPhase::PostOpt) {
+            if mt.log.should_log_ir(IRPhase::PostOpt) {
                 jit_mod.dead_code_elimination();
-                log_ir(&format!(
+                mt.log.log_ir(&format!(
                     "--- Begin jit-post-opt{ds} ---\n{jit_mod}\n--- End jit-post-opt ---\n",
                 ));
             }
+            cancel.check()?;
         }
 
-        // FIXME: This needs to be the combined stacksize of all parent traces.
-        let ct = self.codegen.codegen(jit_mod, mt, hl)?;
-
-        if should_log_ir(IRPhase::Asm) {
-            log_ir(&format!(
-                "--- Begin jit-asm{ds} ---\n{}\n--- End jit-asm ---\n",
-                ct.disassemble(false).unwrap()
-            ));
-        }
-        if should_log_ir(IRPhase::AsmFull) {
-            log_ir(&format!(
-                "--- Begin jit-asm-full{ds} ---\n{}\n--- End jit-asm-full ---\n",
-                ct.disassemble(true).unwrap()
-            ));
-        }
-
-        Ok(ct)
+        Ok(jit_mod)
     }
 }
 
@@ -199,6 +310,7 @@ impl<Register: Send + Sync + 'static> Compiler for JITCYk<Register> {
         promotions: Box<[u8]>,
         debug_strs: Vec<String>,
         connector_ctr: Option<Arc<dyn CompiledTrace>>,
+        cancel: &CancellationToken,
     ) -> Result<Arc<dyn CompiledTrace>, CompilationError> {
         self.compile(
             mt,
@@ -209,6 +321,7 @@ impl<Register: Send + Sync + 'static> Compiler for JITCYk<Register> {
             promotions,
             debug_strs,
             connector_ctr,
+            cancel,
         )
     }
 
@@ -223,6 +336,7 @@ impl<Register: Send + Sync + 'static> Compiler for JITCYk<Register> {
         hl: Arc<Mutex<HotLocation>>,
         promotions: Box<[u8]>,
         debug_strs: Vec<String>,
+        cancel: &CancellationToken,
     ) -> Result<Arc<dyn CompiledTrace>, CompilationError> {
         let parent_ctr = parent_ctr
             .as_any()
@@ -238,6 +352,7 @@ impl<Register: Send + Sync + 'static> Compiler for JITCYk<Register> {
             promotions,
             debug_strs,
             None,
+            cancel,
         )
     }
 }
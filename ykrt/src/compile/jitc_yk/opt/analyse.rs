@@ -1,21 +1,28 @@
 //! Analyse a trace and gradually refine what values we know a previous instruction can produce.
 
 use super::{
-    super::jit_ir::{ConstIdx, GuardInst, Inst, InstIdx, Module, Operand, Predicate},
+    super::jit_ir::{
+        BinOp, Const, ConstIdx, FloatPredicate, GuardInst, Inst, InstIdx, Module, Operand,
+        Predicate, Ty,
+    },
+    escape,
     heapvalues::{Address, HeapValues},
+    intfacts::IntFacts,
 };
-use std::cell::RefCell;
+use std::{cell::RefCell, collections::HashMap};
 
 #[derive(Clone, Debug)]
 pub(super) enum Value {
     Unknown,
     Const(ConstIdx),
+    /// An integer whose exact value we don't know, but about which we know some facts.
+    Int(IntFacts),
 }
 
 impl Value {
     fn to_operand(&self) -> Operand {
         match self {
-            Value::Unknown => todo!(),
+            Value::Unknown | Value::Int(_) => todo!(),
             Value::Const(cidx) => Operand::Const(*cidx),
         }
     }
@@ -32,6 +39,9 @@ pub(super) struct Analyse {
     /// other parts of the system w.r.t. the borrow checker.
     values: RefCell<Vec<Value>>,
     heapvalues: RefCell<HeapValues>,
+    /// Comparisons between pairs of variables that we know to be true. Each key is ordered so that
+    /// its first [InstIdx] is smaller than its second: `(x, y) -> [p]` means that `x p y` is true.
+    relations: RefCell<HashMap<(InstIdx, InstIdx), Vec<Predicate>>>,
 }
 
 impl Analyse {
@@ -42,7 +52,8 @@ impl Analyse {
             // don't copy over [Tombstone]s and [Copy]s it will be slightly less than that.
             // FIXME: Can we calculate this more accurately?
             values: RefCell::new(vec![Value::Unknown; m.insts_len() * 2]),
-            heapvalues: RefCell::new(HeapValues::new()),
+            heapvalues: RefCell::new(HeapValues::new(escape::non_escaping(m))),
+            relations: RefCell::new(HashMap::new()),
         }
     }
 
@@ -53,6 +64,41 @@ impl Analyse {
         self.heapvalues
             .borrow_mut()
             .propagate_header_to_body(m, map);
+
+        // A variable which is passed unchanged from the end of the header back to its start is
+        // loop invariant: its body counterpart always has the same value. Anything we learnt about
+        // it in the header thus also holds in the body.
+        let invariant = |iidx: InstIdx| match (
+            m.trace_header_start_position(Operand::Var(iidx)),
+            m.trace_header_end_position(Operand::Var(iidx)),
+        ) {
+            (Some(i), Some(j)) if i == j => Some(map[usize::from(iidx)]),
+            _ => None,
+        };
+        {
+            let mut values = self.values.borrow_mut();
+            for op in m.trace_header_start() {
+                if let Operand::Var(iidx) = op.unpack(m)
+                    && let Some(body_iidx) = invariant(iidx)
+                    && let Value::Unknown = values[usize::from(body_iidx)]
+                {
+                    values[usize::from(body_iidx)] = values[usize::from(iidx)].clone();
+                }
+            }
+        }
+        let body_relations = self
+            .relations
+            .borrow()
+            .iter()
+            .filter_map(|(&(lhs, rhs), preds)| {
+                Some(((invariant(lhs)?, invariant(rhs)?), preds.clone()))
+            })
+            .collect::<Vec<_>>();
+        for ((lhs, rhs), preds) in body_relations {
+            for pred in preds {
+                self.relate(lhs, pred, rhs);
+            }
+        }
     }
 
     /// Map `op` based on our analysis so far. In some cases this will return `op` unchanged, but
@@ -60,17 +106,16 @@ impl Analyse {
     pub(super) fn op_map(&self, m: &Module, op: Operand) -> Operand {
         match op {
             Operand::Var(iidx) => match self.values.borrow()[usize::from(iidx)] {
-                Value::Unknown => {
+                Value::Unknown | Value::Int(_) => {
                     // Since we last saw an `ICmp` instruction, we may have gathered new knowledge
                     // that allows us to turn it into a constant.
                     if let Inst::ICmp(inst) = m.inst(iidx) {
                         let lhs = self.op_map(m, inst.lhs(m));
                         let rhs = self.op_map(m, inst.rhs(m));
-                        if let (&Operand::Const(_lhs_cidx), &Operand::Const(_rhs_cidx)) =
-                            (&lhs, &rhs)
-                        {
-                            // Can we still hit this case?
-                            todo!();
+                        match self.icmp(m, lhs, inst.predicate(), rhs) {
+                            Some(true) => return Operand::Const(m.true_constidx()),
+                            Some(false) => return Operand::Const(m.false_constidx()),
+                            None => (),
                         }
                     }
                     op
@@ -81,15 +126,121 @@ impl Analyse {
         }
     }
 
+    /// What do we know about the integer `op`? Returns `None` if `op` is not an integer, or is an
+    /// integer whose bit width we can't reason about.
+    pub(super) fn int_facts(&self, m: &Module, op: Operand) -> Option<IntFacts> {
+        match op {
+            Operand::Var(iidx) => match &self.values.borrow()[usize::from(iidx)] {
+                Value::Unknown => match m.type_(op.tyidx(m)) {
+                    Ty::Integer(bitw) => IntFacts::unknown(*bitw),
+                    _ => None,
+                },
+                Value::Const(cidx) => self.int_facts(m, Operand::Const(*cidx)),
+                Value::Int(x) => Some(*x),
+            },
+            Operand::Const(cidx) => match m.const_(cidx) {
+                Const::Int(_, x) => IntFacts::exact(x),
+                _ => None,
+            },
+        }
+    }
+
+    /// Is `lhs pred rhs` always true (`Some(true)`), always false (`Some(false)`), or can it be
+    /// either (`None`)?
+    pub(super) fn icmp(
+        &self,
+        m: &Module,
+        lhs: Operand,
+        pred: Predicate,
+        rhs: Operand,
+    ) -> Option<bool> {
+        if let (&Operand::Var(lhs_iidx), &Operand::Var(rhs_iidx)) = (&lhs, &rhs) {
+            if lhs_iidx == rhs_iidx {
+                return Some(Predicate::Equal.implies(pred));
+            }
+            let (k, pred) = Self::relation_key(lhs_iidx, pred, rhs_iidx);
+            if let Some(preds) = self.relations.borrow().get(&k) {
+                for p in preds {
+                    if p.implies(pred) {
+                        return Some(true);
+                    } else if p.implies(pred.inverted()) {
+                        return Some(false);
+                    }
+                }
+            }
+        }
+        let lhs = self.int_facts(m, lhs)?;
+        let rhs = self.int_facts(m, rhs)?;
+        lhs.icmp(pred, &rhs)
+    }
+
+    /// Work out what we can about the integer produced by the (already optimised) instruction at
+    /// `iidx` from what we know about its operands.
+    pub(super) fn derive_int(&self, m: &Module, iidx: InstIdx) {
+        if !matches!(self.values.borrow()[usize::from(iidx)], Value::Unknown) {
+            return;
+        }
+        let facts = |op| self.int_facts(m, self.op_map(m, op));
+        let dest_bitw = |tyidx| match m.type_(tyidx) {
+            Ty::Integer(bitw) => Some(*bitw),
+            _ => None,
+        };
+        let derived = match m.inst_nocopy(iidx) {
+            Some(Inst::BinOp(x)) => {
+                facts(x.lhs(m))
+                    .zip(facts(x.rhs(m)))
+                    .and_then(|(lhs, rhs)| match x.binop() {
+                        BinOp::Add => Some(lhs.add(&rhs)),
+                        BinOp::Sub => Some(lhs.sub(&rhs)),
+                        BinOp::Mul => Some(lhs.mul(&rhs)),
+                        BinOp::And => Some(lhs.and(&rhs)),
+                        BinOp::Or => Some(lhs.or(&rhs)),
+                        BinOp::Xor => Some(lhs.xor(&rhs)),
+                        BinOp::Shl => rhs.as_u64().map(|sh| lhs.shl(sh)),
+                        BinOp::LShr => rhs.as_u64().map(|sh| lhs.lshr(sh)),
+                        BinOp::AShr => rhs.as_u64().map(|sh| lhs.ashr(sh)),
+                        BinOp::UDiv => Some(lhs.udiv(&rhs)),
+                        BinOp::URem => Some(lhs.urem(&rhs)),
+                        _ => None,
+                    })
+            }
+            Some(Inst::Select(x)) => facts(x.trueval(m))
+                .zip(facts(x.falseval(m)))
+                .map(|(t, f)| t.union(&f)),
+            Some(Inst::SExt(x)) => facts(x.val(m)).and_then(|v| v.sext(dest_bitw(x.dest_tyidx())?)),
+            Some(Inst::ZExt(x)) => facts(x.val(m)).and_then(|v| v.zext(dest_bitw(x.dest_tyidx())?)),
+            Some(Inst::Trunc(x)) => facts(x.val(m))
+                .zip(dest_bitw(x.dest_tyidx()))
+                .map(|(v, bitw)| v.trunc(bitw)),
+            _ => None,
+        };
+        if let Some(x) = derived
+            && !x.is_unknown()
+        {
+            self.values.borrow_mut()[usize::from(iidx)] = Value::Int(x);
+        }
+    }
+
     /// Update our idea of what value the instruction at `iidx` can produce.
     pub(super) fn set_value(&self, m: &Module, iidx: InstIdx, v: Value) {
         self.values.borrow_mut()[usize::from(iidx)] = v.clone();
-        if let Some(Inst::Load(linst)) = m.inst_nocopy(iidx) {
+        if let Value::Const(_) = v
+            && let Some(Inst::Load(linst)) = m.inst_nocopy(iidx)
+        {
             let addr = Address::from_operand(m, linst.ptr(m));
             self.heapvalues.borrow_mut().load(m, addr, v.to_operand());
         }
     }
 
+    /// Record that we now know the facts `x` about the integer produced by the instruction at
+    /// `iidx`. Note that `x` must be at least as precise as what we previously knew.
+    fn set_int(&self, iidx: InstIdx, x: IntFacts) {
+        let mut values = self.values.borrow_mut();
+        if !matches!(values[usize::from(iidx)], Value::Const(_)) {
+            values[usize::from(iidx)] = Value::Int(x);
+        }
+    }
+
     /// What, if any, is the currently known value of `bytesize` bytes stored at `addr`?
     pub(super) fn heapvalue(&self, m: &Module, addr: Address, bytesize: usize) -> Option<Operand> {
         self.heapvalues.borrow_mut().get(m, addr, bytesize)
@@ -110,29 +261,158 @@ impl Analyse {
         self.heapvalues.borrow_mut().barrier();
     }
 
+    /// Record that the store `iidx` writes `bytesize` bytes to `addr`, returning the [InstIdx]s of
+    /// earlier stores which are now dead.
+    pub(super) fn heap_pending_store(
+        &self,
+        iidx: InstIdx,
+        addr: Address,
+        bytesize: usize,
+    ) -> Vec<InstIdx> {
+        self.heapvalues
+            .borrow_mut()
+            .pending_store(iidx, addr, bytesize)
+    }
+
+    /// Record that `bytesize` bytes at `addr` may have been read.
+    pub(super) fn heap_observe(&self, addr: &Address, bytesize: usize) {
+        self.heapvalues.borrow_mut().observe(addr, bytesize);
+    }
+
+    /// Record that all memory may have been read.
+    pub(super) fn heap_observe_all(&self) {
+        self.heapvalues.borrow_mut().observe_all();
+    }
+
+    /// Might the `bytesize_a` bytes at `a` and the `bytesize_b` bytes at `b` overlap?
+    pub(super) fn may_alias(
+        &self,
+        a: &Address,
+        bytesize_a: usize,
+        b: &Address,
+        bytesize_b: usize,
+    ) -> bool {
+        self.heapvalues
+            .borrow()
+            .may_alias(a, bytesize_a, b, bytesize_b)
+    }
+
     /// Use the guard `inst` to update our knowledge about the variable used as its condition.
     pub(super) fn guard(&self, m: &Module, g_inst: GuardInst) {
-        if let Operand::Var(iidx) = g_inst.cond(m)
-            && let Inst::ICmp(ic_inst) = m.inst(iidx)
-        {
-            let lhs = self.op_map(m, ic_inst.lhs(m));
-            let pred = ic_inst.predicate();
-            let rhs = self.op_map(m, ic_inst.rhs(m));
-            match (&lhs, &rhs) {
-                (&Operand::Const(_), &Operand::Const(_)) => {
-                    // This will have been handled by icmp/guard optimisations.
-                    unreachable!();
-                }
-                (&Operand::Var(iidx), &Operand::Const(cidx))
-                | (&Operand::Const(cidx), &Operand::Var(iidx)) => {
-                    if (g_inst.expect && pred == Predicate::Equal)
-                        || (!g_inst.expect && pred == Predicate::NotEqual)
-                    {
-                        self.set_value(m, iidx, Value::Const(cidx));
+        if let Operand::Var(iidx) = g_inst.cond(m) {
+            self.learn_cond(m, iidx, g_inst.expect);
+        }
+    }
+
+    /// Record that the `i1` produced by the instruction at `iidx` is known to be `v`, and learn
+    /// what we can from that about the values that it was derived from.
+    fn learn_cond(&self, m: &Module, iidx: InstIdx, v: bool) {
+        let cidx = if v {
+            m.true_constidx()
+        } else {
+            m.false_constidx()
+        };
+        match m.inst(iidx) {
+            Inst::BinOp(x) if (v && x.binop() == BinOp::And) || (!v && x.binop() == BinOp::Or) => {
+                // `x & y` being true means that both `x` and `y` are true; `x | y` being false
+                // means that both `x` and `y` are false.
+                self.set_value(m, iidx, Value::Const(cidx));
+                for op in [x.lhs(m), x.rhs(m)] {
+                    if let Operand::Var(op_iidx) = self.op_map(m, op) {
+                        self.learn_cond(m, op_iidx, v);
                     }
                 }
-                (&Operand::Var(_), &Operand::Var(_)) => (),
             }
+            Inst::ICmp(ic_inst) => {
+                self.set_value(m, iidx, Value::Const(cidx));
+                self.learn_icmp(m, ic_inst.lhs(m), ic_inst.predicate(), ic_inst.rhs(m), v);
+            }
+            Inst::FCmp(fc_inst) => {
+                self.set_value(m, iidx, Value::Const(cidx));
+                let pred = fc_inst.predicate();
+                // `x f_oeq c` being true, or `x f_une c` being false, means that `x` is `c`.
+                // However, since `-0.0 == 0.0`, that doesn't tell us which zero `x` is.
+                if ((v && pred == FloatPredicate::OrderedEqual)
+                    || (!v && pred == FloatPredicate::UnorderedNotEqual))
+                    && let (Operand::Var(x), Operand::Const(c))
+                    | (Operand::Const(c), Operand::Var(x)) = (
+                        self.op_map(m, fc_inst.lhs(m)),
+                        self.op_map(m, fc_inst.rhs(m)),
+                    )
+                    && let Const::Float(_, fv) = m.const_(c)
+                    && *fv != 0.0
+                {
+                    self.set_value(m, x, Value::Const(c));
+                }
+            }
+            _ => self.set_value(m, iidx, Value::Const(cidx)),
+        }
+    }
+
+    /// Record that `lhs pred rhs` is known to be `v`.
+    fn learn_icmp(&self, m: &Module, lhs: Operand, pred: Predicate, rhs: Operand, v: bool) {
+        let lhs = self.op_map(m, lhs);
+        let rhs = self.op_map(m, rhs);
+        match (&lhs, &rhs) {
+            (&Operand::Const(_), &Operand::Const(_)) => {
+                // There's nothing to learn about constants.
+                return;
+            }
+            (&Operand::Var(iidx), &Operand::Const(cidx))
+            | (&Operand::Const(cidx), &Operand::Var(iidx)) => {
+                if (v && pred == Predicate::Equal) || (!v && pred == Predicate::NotEqual) {
+                    self.set_value(m, iidx, Value::Const(cidx));
+                    return;
+                }
+            }
+            (&Operand::Var(lhs_iidx), &Operand::Var(rhs_iidx)) => {
+                if lhs_iidx != rhs_iidx {
+                    self.relate(lhs_iidx, if v { pred } else { pred.inverted() }, rhs_iidx);
+                }
+            }
+        }
+
+        // Whatever the comparison is, we know that `lhs pred rhs` is true (or false, if `v` is
+        // false).
+        let pred = if v { pred } else { pred.inverted() };
+        if let (Some(lhs_facts), Some(rhs_facts)) = (
+            self.int_facts(m, lhs.clone()),
+            self.int_facts(m, rhs.clone()),
+        ) {
+            if let Operand::Var(iidx) = lhs
+                && let Some(x) = lhs_facts.refine(pred, &rhs_facts)
+            {
+                self.set_int(iidx, x);
+            }
+            if let Operand::Var(iidx) = rhs
+                && let Some(x) = rhs_facts.refine(pred.swapped(), &lhs_facts)
+            {
+                self.set_int(iidx, x);
+            }
+        }
+    }
+
+    /// Record that `lhs pred rhs` is true.
+    fn relate(&self, lhs: InstIdx, pred: Predicate, rhs: InstIdx) {
+        let (k, pred) = Self::relation_key(lhs, pred, rhs);
+        let mut relations = self.relations.borrow_mut();
+        let preds = relations.entry(k).or_default();
+        if !preds.contains(&pred) {
+            preds.push(pred);
+        }
+    }
+
+    /// Return the key into [Self::relations] for `lhs pred rhs`, and the predicate that `pred`
+    /// becomes relative to that key.
+    fn relation_key(
+        lhs: InstIdx,
+        pred: Predicate,
+        rhs: InstIdx,
+    ) -> ((InstIdx, InstIdx), Predicate) {
+        if lhs < rhs {
+            ((lhs, rhs), pred)
+        } else {
+            ((rhs, lhs), pred.swapped())
         }
     }
 }
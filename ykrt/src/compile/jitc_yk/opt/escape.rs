@@ -0,0 +1,279 @@
+//! Escape analysis.
+//!
+//! An "allocation" is a call to a function marked as an allocator (see
+//! [super::super::jit_ir::FuncDecl::is_allocator]). If the pointer returned by an allocation (or a
+//! pointer derived from it with `ptr_add`) is only used to load from, and store to, the
+//! allocation, then code outside the trace can never observe the allocation: we say that the
+//! allocation does not "escape".
+//!
+//! This module is used in two ways. First, before optimisation, the heap value analysis uses
+//! [non_escaping] to learn which allocations can't be aliased by other pointers or touched by
+//! calls. Second, after optimisation, [virtualise] removes non-escaping allocations whose loads
+//! have all been optimised away from the trace: such allocations are only made (with the values
+//! they would have contained at that point) if a guard which references them fails.
+
+use super::super::jit_ir::{
+    Const, FuncDeclIdx, Inst, InstIdx, Module, Operand, PackedOperand, Predicate, VirtualAlloc,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Is the instruction `iidx` an allocation?
+pub(super) fn is_alloc(m: &Module, iidx: InstIdx) -> bool {
+    match m.inst_nocopy(iidx) {
+        Some(inst) => alloc_size(m, inst).is_some(),
+        None => false,
+    }
+}
+
+/// Is `op` the null pointer?
+pub(super) fn is_null(m: &Module, op: &Operand) -> bool {
+    match op {
+        Operand::Const(cidx) => matches!(m.const_(*cidx), Const::Ptr(0)),
+        Operand::Var(_) => false,
+    }
+}
+
+/// If `inst` is an allocation, return the operand specifying its size in bytes.
+fn alloc_size(m: &Module, inst: Inst) -> Option<Operand> {
+    match inst {
+        Inst::Call(x)
+            if x.idem_const().is_none()
+                && x.num_args() == 1
+                && m.func_decl(x.target()).is_allocator() =>
+        {
+            Some(x.operand(m, 0))
+        }
+        _ => None,
+    }
+}
+
+/// Which allocations in a trace escape?
+struct Escapes {
+    /// The allocations in the trace, in the order they are made.
+    allocs: Vec<InstIdx>,
+    /// Maps each pointer derived from an allocation (including the allocation itself) to the
+    /// allocation and the offset in bytes of the pointer from the start of the allocation.
+    derived: HashMap<InstIdx, (InstIdx, i32)>,
+    /// The allocations that escape.
+    escaped: HashSet<InstIdx>,
+}
+
+impl Escapes {
+    /// Analyse the trace `m`. If `null_cmps` is true, comparing an allocation for (in)equality
+    /// with the null pointer does not cause the allocation to escape.
+    fn new(m: &Module, null_cmps: bool) -> Self {
+        let mut esc = Escapes {
+            allocs: Vec::new(),
+            derived: HashMap::new(),
+            escaped: HashSet::new(),
+        };
+        for (iidx, inst) in m.iter_skipping_insts() {
+            if alloc_size(m, inst).is_some() {
+                esc.allocs.push(iidx);
+                esc.derived.insert(iidx, (iidx, 0));
+                continue;
+            }
+            match inst {
+                Inst::PtrAdd(x) => {
+                    if let Some((alloc, off)) = esc.root(&x.ptr(m)) {
+                        match off.checked_add(x.off()) {
+                            Some(off) => {
+                                esc.derived.insert(iidx, (alloc, off));
+                            }
+                            None => {
+                                esc.escaped.insert(alloc);
+                            }
+                        }
+                    }
+                }
+                Inst::Load(_) => (),
+                Inst::Store(x) => esc.escape(&x.val(m)),
+                Inst::ICmp(x) => {
+                    let (lhs, rhs) = (x.lhs(m), x.rhs(m));
+                    let is_alloc =
+                        |op: &Operand| matches!(op, Operand::Var(y) if esc.allocs.contains(y));
+                    if !(null_cmps
+                        && matches!(x.predicate(), Predicate::Equal | Predicate::NotEqual)
+                        && ((is_alloc(&lhs) && is_null(m, &rhs))
+                            || (is_null(m, &lhs) && is_alloc(&rhs))))
+                    {
+                        esc.escape(&lhs);
+                        esc.escape(&rhs);
+                    }
+                }
+                Inst::Guard(x) => {
+                    let gi = x.guard_info(m);
+                    for (_, pop) in gi.live_vars() {
+                        // Only the allocation itself can be materialised by deopt: a live
+                        // variable which is derived from it escapes.
+                        match pop.unpack(m) {
+                            Operand::Var(y) if esc.allocs.contains(&y) => (),
+                            op => esc.escape(&op),
+                        }
+                    }
+                    for frame in gi.inlined_frames() {
+                        for pop in &frame.args {
+                            esc.escape(&pop.unpack(m));
+                        }
+                    }
+                }
+                _ => {
+                    let mut ops = Vec::new();
+                    inst.map_operand_vars(m, &mut |x| ops.push(x));
+                    for x in ops {
+                        esc.escape(&Operand::Var(x));
+                    }
+                }
+            }
+        }
+        esc
+    }
+
+    /// If `op` is derived from an allocation, return that allocation and the offset of `op` from
+    /// its start.
+    fn root(&self, op: &Operand) -> Option<(InstIdx, i32)> {
+        match op {
+            Operand::Var(iidx) => self.derived.get(iidx).cloned(),
+            Operand::Const(_) => None,
+        }
+    }
+
+    /// Record that `op` is used in a way which causes the allocation it is derived from, if any,
+    /// to escape.
+    fn escape(&mut self, op: &Operand) {
+        if let Some((alloc, _)) = self.root(op) {
+            self.escaped.insert(alloc);
+        }
+    }
+
+    /// Iterate over the allocations which do not escape.
+    fn non_escaping(&self) -> impl Iterator<Item = InstIdx> + '_ {
+        self.allocs
+            .iter()
+            .filter(|x| !self.escaped.contains(x))
+            .cloned()
+    }
+}
+
+/// Return the allocations in `m` which do not escape. Note that allocations are also considered
+/// not to escape if they are compared to the null pointer: since allocators never return null
+/// pointers, the optimiser is expected to remove such comparisons.
+pub(super) fn non_escaping(m: &Module) -> HashSet<InstIdx> {
+    Escapes::new(m, true).non_escaping().collect()
+}
+
+/// Remove from `m` the allocations which do not escape, are of a constant size, are never
+/// loaded from, and whose stores are in bounds and never partially overlap. Each guard which
+/// references such an allocation in its live variables records the values stored in the
+/// allocation at that point, so that deopt can materialise the allocation.
+pub(super) fn virtualise(m: &mut Module) {
+    let esc = Escapes::new(m, false);
+    let mut cands = HashMap::new();
+    for iidx in esc.non_escaping() {
+        let Operand::Const(cidx) = alloc_size(m, m.inst(iidx)).unwrap() else {
+            continue;
+        };
+        let Const::Int(_, size) = m.const_(cidx) else {
+            panic!()
+        };
+        if let Some(size) = size.to_zero_ext_u64() {
+            cands.insert(iidx, (size, Vec::new()));
+        }
+    }
+    if cands.is_empty() {
+        return;
+    }
+
+    for (_, inst) in m.iter_skipping_insts() {
+        match inst {
+            Inst::Load(x) => {
+                if let Some((alloc, _)) = esc.root(&x.ptr(m)) {
+                    cands.remove(&alloc);
+                }
+            }
+            Inst::Store(x) => {
+                if let Some((alloc, off)) = esc.root(&x.ptr(m))
+                    && let Some((_, stores)) = cands.get_mut(&alloc)
+                {
+                    // Deopt can only materialise fields of 1, 2, 4, or 8 bytes.
+                    if x.is_volatile() || !matches!(x.val(m).byte_size(m), 1 | 2 | 4 | 8) {
+                        cands.remove(&alloc);
+                    } else {
+                        stores.push((off, x.val(m).byte_size(m)));
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    // Every store must be within the allocation's bounds; and any two stores must either be to
+    // exactly the same bytes or not overlap at all.
+    cands.retain(|_, (size, stores)| {
+        stores.iter().all(|(off, bytesize)| {
+            *off >= 0 && u64::try_from(*off).unwrap() + u64::try_from(*bytesize).unwrap() <= *size
+        }) && stores.iter().all(|(off, bytesize)| {
+            stores.iter().all(|(o_off, o_bytesize)| {
+                (off == o_off && bytesize == o_bytesize)
+                    || i64::from(*off) + i64::try_from(*bytesize).unwrap() <= i64::from(*o_off)
+                    || i64::from(*o_off) + i64::try_from(*o_bytesize).unwrap() <= i64::from(*off)
+            })
+        })
+    });
+    if cands.is_empty() {
+        return;
+    }
+
+    // For each allocation, its allocator and the values currently stored in it (as a map from
+    // offsets to values).
+    let mut fields: HashMap<InstIdx, (FuncDeclIdx, BTreeMap<u32, PackedOperand>)> = HashMap::new();
+    for (iidx, inst) in m.iter_skipping_insts().collect::<Vec<_>>() {
+        if cands.contains_key(&iidx) {
+            let Inst::Call(x) = inst else { panic!() };
+            fields.insert(iidx, (x.target(), BTreeMap::new()));
+            m.replace(iidx, Inst::Tombstone);
+            continue;
+        }
+        match inst {
+            Inst::PtrAdd(x) => {
+                if let Some((alloc, _)) = esc.root(&x.ptr(m))
+                    && cands.contains_key(&alloc)
+                {
+                    m.replace(iidx, Inst::Tombstone);
+                }
+            }
+            Inst::Store(x) => {
+                if let Some((alloc, off)) = esc.root(&x.ptr(m))
+                    && cands.contains_key(&alloc)
+                {
+                    fields
+                        .get_mut(&alloc)
+                        .unwrap()
+                        .1
+                        .insert(u32::try_from(off).unwrap(), PackedOperand::new(&x.val(m)));
+                    m.replace(iidx, Inst::Tombstone);
+                }
+            }
+            Inst::Guard(x) => {
+                let mut virtuals = Vec::<VirtualAlloc>::new();
+                for (_, pop) in x.guard_info(m).live_vars() {
+                    if let Operand::Var(alloc) = pop.unpack(m)
+                        && let Some((size, _)) = cands.get(&alloc)
+                        && !virtuals.iter().any(|x| x.iidx == alloc)
+                    {
+                        let (target, vals) = &fields[&alloc];
+                        virtuals.push(VirtualAlloc {
+                            iidx: alloc,
+                            target: *target,
+                            size: *size,
+                            fields: vals.iter().map(|(k, v)| (*k, *v)).collect(),
+                        });
+                    }
+                }
+                if !virtuals.is_empty() {
+                    x.guard_info_mut(m).set_virtuals(virtuals);
+                }
+            }
+            _ => (),
+        }
+    }
+}
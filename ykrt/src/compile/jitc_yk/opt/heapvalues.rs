@@ -3,9 +3,16 @@
 //! provides a way of keeping track of what values we know about at a given point in the trace.
 //!
 //! Broadly speaking, loads add new information; stores tend to remove most old information and add
-//! new information; and barriers remove all information.
+//! new information; and barriers remove all information. The exception to this are allocations
+//! which do not escape the trace (see [super::escape]): since no other pointer can alias them,
+//! knowledge about their contents is only ever invalidated by stores to the allocations
+//! themselves.
+//!
+//! This module also tracks which stores have not yet been "observed" (e.g. by a load or a guard
+//! which might read the bytes they wrote): a store which is completely overwritten before being
+//! observed is dead.
 use super::super::jit_ir::{Const, Inst, InstIdx, Module, Operand};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 /// An abstract "address" representing a location in RAM.
 ///
@@ -57,11 +64,20 @@ impl Address {
 pub(super) struct HeapValues {
     /// The heap values we currently know about.
     hv: HashMap<Address, Operand>,
+    /// The allocations which do not escape the trace.
+    non_escaping: HashSet<InstIdx>,
+    /// The stores which have not yet been observed, as a map from addresses to the store
+    /// instruction and the number of bytes it stores.
+    pending: HashMap<Address, (InstIdx, usize)>,
 }
 
 impl HeapValues {
-    pub(super) fn new() -> Self {
-        HeapValues { hv: HashMap::new() }
+    pub(super) fn new(non_escaping: HashSet<InstIdx>) -> Self {
+        HeapValues {
+            hv: HashMap::new(),
+            non_escaping,
+            pending: HashMap::new(),
+        }
     }
 
     /// Propagate relevant analysis from the trace header to body. This must only be called at the
@@ -93,6 +109,16 @@ impl HeapValues {
             new.insert(k, v);
         }
         self.hv = new;
+        // The body's copy of an allocation which doesn't escape doesn't escape either.
+        let body = self
+            .non_escaping
+            .iter()
+            .map(|x| map[usize::from(*x)])
+            .filter(|x| *x != InstIdx::max())
+            .collect::<Vec<_>>();
+        self.non_escaping.extend(body);
+        // The end of the header observes all stores in the header.
+        self.pending.clear();
     }
 
     /// What is the currently known value at `addr` of `bytesize` bytes? Returns `None` if no value
@@ -117,22 +143,11 @@ impl HeapValues {
         // We now need to perform alias analysis to see if this new value invalidates some or all
         // of our previous knowledge.
         match addr {
-            Address::PtrPlusOff(iidx, off) => {
-                let off = isize::try_from(off).unwrap();
-                let op_bytesize = isize::try_from(v.byte_size(m)).unwrap();
-                // We are ultra conservative here: we only say "these don't overlap" if two stores
-                // ultimately reference the same SSA variable with pointer adds. In other words, if
-                // we're writing 8 bytes and we're storing to `%3 + 8` and `%3 + 24` we can be
-                // entirely sure the stores don't overlap: in any other situation, we assume
-                // overlap is possible. This can be relaxed in the future.
-                self.hv.retain(|hv_addr, hv_v| match hv_addr {
-                    Address::PtrPlusOff(hv_iidx, hv_off) => {
-                        let hv_off = isize::try_from(*hv_off).unwrap();
-                        let hv_bytesize = isize::try_from(hv_v.byte_size(m)).unwrap();
-                        iidx == *hv_iidx
-                            && (off + op_bytesize <= hv_off || hv_off + hv_bytesize <= off)
-                    }
-                    Address::Const(_) => false,
+            Address::PtrPlusOff(_, _) => {
+                let bytesize = v.byte_size(m);
+                let non_escaping = &self.non_escaping;
+                self.hv.retain(|hv_addr, hv_v| {
+                    !may_alias(non_escaping, &addr, bytesize, hv_addr, hv_v.byte_size(m))
                 });
                 self.hv.insert(addr, v);
             }
@@ -140,10 +155,115 @@ impl HeapValues {
         }
     }
 
+    /// Record that the store instruction `iidx`, which writes `bytesize` bytes to `addr`, has
+    /// been encountered. Returns the [InstIdx]s of the previous, unobserved, stores whose bytes
+    /// this store completely overwrites: such stores are dead.
+    pub(super) fn pending_store(
+        &mut self,
+        iidx: InstIdx,
+        addr: Address,
+        bytesize: usize,
+    ) -> Vec<InstIdx> {
+        let mut dead = Vec::new();
+        let bytesize = i64::try_from(bytesize).unwrap();
+        self.pending.retain(|p_addr, (p_iidx, p_bytesize)| {
+            let p_bytesize = i64::try_from(*p_bytesize).unwrap();
+            let covered = match (&addr, p_addr) {
+                (Address::PtrPlusOff(x, off), Address::PtrPlusOff(p_x, p_off)) => {
+                    let (off, p_off) = (i64::from(*off), i64::from(*p_off));
+                    x == p_x && off <= p_off && p_off + p_bytesize <= off + bytesize
+                }
+                (Address::Const(x), Address::Const(p_x)) => {
+                    let (x, p_x) = (i64::try_from(*x).unwrap(), i64::try_from(*p_x).unwrap());
+                    x <= p_x && p_x + p_bytesize <= x + bytesize
+                }
+                _ => false,
+            };
+            if covered {
+                dead.push(*p_iidx);
+            }
+            !covered
+        });
+        self.pending
+            .insert(addr, (iidx, usize::try_from(bytesize).unwrap()));
+        dead
+    }
+
+    /// Record that `bytesize` bytes at `addr` may have been read: stores to those bytes are no
+    /// longer candidates for removal.
+    pub(super) fn observe(&mut self, addr: &Address, bytesize: usize) {
+        let non_escaping = &self.non_escaping;
+        self.pending.retain(|p_addr, (_, p_bytesize)| {
+            !may_alias(non_escaping, addr, bytesize, p_addr, *p_bytesize)
+        });
+    }
+
+    /// Record that all memory may have been read (e.g. because a guard may deoptimise, or the
+    /// trace may end).
+    pub(super) fn observe_all(&mut self) {
+        self.pending.clear();
+    }
+
     /// Record a barrier instruction as having been encountered. This will invalidate all of our
-    /// existing heap knowledge.
+    /// existing heap knowledge other than that about allocations which don't escape.
     pub(super) fn barrier(&mut self) {
-        self.hv.clear();
+        let non_escaping = &self.non_escaping;
+        self.hv.retain(|hv_addr, _| match hv_addr {
+            Address::PtrPlusOff(hv_iidx, _) => non_escaping.contains(hv_iidx),
+            Address::Const(_) => false,
+        });
+        self.pending.retain(|p_addr, _| match p_addr {
+            Address::PtrPlusOff(p_iidx, _) => non_escaping.contains(p_iidx),
+            Address::Const(_) => false,
+        });
+    }
+
+    /// Might the `bytesize_a` bytes at `a` and the `bytesize_b` bytes at `b` overlap?
+    pub(super) fn may_alias(
+        &self,
+        a: &Address,
+        bytesize_a: usize,
+        b: &Address,
+        bytesize_b: usize,
+    ) -> bool {
+        may_alias(&self.non_escaping, a, bytesize_a, b, bytesize_b)
+    }
+}
+
+/// Might the `bytesize_a` bytes at `a` and the `bytesize_b` bytes at `b` overlap? `non_escaping`
+/// is the set of allocations which don't escape the trace.
+///
+/// We are ultra conservative here: we only say "these don't overlap" if two addresses ultimately
+/// reference the same SSA variable with pointer adds. In other words, if we're writing 8 bytes
+/// and we're storing to `%3 + 8` and `%3 + 24` we can be entirely sure the stores don't overlap:
+/// in any other situation, we assume overlap is possible (unless one of the pointers is an
+/// allocation which doesn't escape, in which case the other pointer can't alias it). This can be
+/// relaxed in the future.
+fn may_alias(
+    non_escaping: &HashSet<InstIdx>,
+    a: &Address,
+    bytesize_a: usize,
+    b: &Address,
+    bytesize_b: usize,
+) -> bool {
+    let (bytesize_a, bytesize_b) = (
+        i64::try_from(bytesize_a).unwrap(),
+        i64::try_from(bytesize_b).unwrap(),
+    );
+    match (a, b) {
+        (Address::PtrPlusOff(a_iidx, a_off), Address::PtrPlusOff(b_iidx, b_off)) => {
+            if a_iidx != b_iidx {
+                return !(non_escaping.contains(a_iidx) || non_escaping.contains(b_iidx));
+            }
+            let (a_off, b_off) = (i64::from(*a_off), i64::from(*b_off));
+            !(a_off + bytesize_a <= b_off || b_off + bytesize_b <= a_off)
+        }
+        (Address::PtrPlusOff(iidx, _), Address::Const(_))
+        | (Address::Const(_), Address::PtrPlusOff(iidx, _)) => !non_escaping.contains(iidx),
+        (Address::Const(a), Address::Const(b)) => {
+            let (a, b) = (i64::try_from(*a).unwrap(), i64::try_from(*b).unwrap());
+            !(a + bytesize_a <= b || b + bytesize_b <= a)
+        }
     }
 }
 
@@ -168,7 +288,7 @@ mod test {
             %4: ptr = load %3
         ",
         );
-        let mut hv = HeapValues::new();
+        let mut hv = HeapValues::new(HashSet::new());
 
         // Add a single load
         let addr0 = Address::from_operand(&m, Operand::Var(InstIdx::unchecked_from(0)));
@@ -252,7 +372,7 @@ mod test {
             *%1 = 2i8
         ",
         );
-        let mut hv = HeapValues::new();
+        let mut hv = HeapValues::new(HashSet::new());
         let addr0 = Address::from_operand(&m, Operand::Var(InstIdx::unchecked_from(0)));
         let addr1 = Address::from_operand(&m, Operand::Var(InstIdx::unchecked_from(1)));
         let cidx1 = m
@@ -0,0 +1,743 @@
+//! What do we know about an integer whose exact value we don't know?
+//!
+//! [IntFacts] is the product of three lattices: an unsigned interval; a signed interval; and the
+//! bits known to be zero or one. Each can capture facts the others can't (e.g. `x & 0xF0` has a
+//! useful interval and useful known bits, whereas `x u< 100` only has a useful interval), and each
+//! is used to tighten the others.
+//!
+//! Only integers of at most 64 bits are supported.
+
+use super::super::{
+    arbbitint::ArbBitInt,
+    jit_ir::{OverflowOp, Predicate},
+};
+use std::cmp::{max, min};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) struct IntFacts {
+    bitw: u32,
+    /// The smallest possible value when interpreted as an unsigned integer.
+    umin: u64,
+    /// The largest possible value when interpreted as an unsigned integer.
+    umax: u64,
+    /// The smallest possible value when interpreted as a signed integer.
+    smin: i64,
+    /// The largest possible value when interpreted as a signed integer.
+    smax: i64,
+    /// The bits known to be zero.
+    zeros: u64,
+    /// The bits known to be one.
+    ones: u64,
+}
+
+impl IntFacts {
+    /// Nothing is known about a `bitw`-bit integer. Returns `None` if `bitw` is not supported.
+    pub(super) fn unknown(bitw: u32) -> Option<Self> {
+        if bitw == 0 || bitw > 64 {
+            return None;
+        }
+        Some(Self {
+            bitw,
+            umin: 0,
+            umax: mask(bitw),
+            smin: smin(bitw),
+            smax: smax(bitw),
+            zeros: 0,
+            ones: 0,
+        })
+    }
+
+    /// Everything is known about `x`. Returns `None` if `x`'s bit width is not supported.
+    pub(super) fn exact(x: &ArbBitInt) -> Option<Self> {
+        let bitw = x.bitw();
+        let v = x.to_zero_ext_u64()?;
+        Self::unknown(bitw)?;
+        Some(Self {
+            bitw,
+            umin: v,
+            umax: v,
+            smin: sext(v, bitw),
+            smax: sext(v, bitw),
+            zeros: !v & mask(bitw),
+            ones: v,
+        })
+    }
+
+    /// Create an [IntFacts] from (possibly inconsistent) components, tightening each component
+    /// using the others. If the components contradict each other, return `None`.
+    fn new(
+        bitw: u32,
+        (mut umin, mut umax): (u64, u64),
+        (mut smin, mut smax): (i64, i64),
+        (mut zeros, mut ones): (u64, u64),
+    ) -> Option<Self> {
+        let m = mask(bitw);
+        let signbit = 1 << (bitw - 1);
+        // Each iteration can only make components tighter, so this converges quickly: in practise
+        // two iterations almost always suffice.
+        loop {
+            let old = (umin, umax, smin, smax, zeros, ones);
+            if zeros & ones != 0 {
+                return None;
+            }
+            umin = max(umin, ones);
+            umax = min(umax, m & !zeros);
+            // If the unsigned interval lies entirely on one side of the sign bit, it is also a
+            // signed interval (and vice versa).
+            if umax < signbit || umin >= signbit {
+                smin = max(smin, sext(umin, bitw));
+                smax = min(smax, sext(umax, bitw));
+            }
+            if smin >= 0 || smax < 0 {
+                umin = max(umin, smin.cast_unsigned() & m);
+                umax = min(umax, smax.cast_unsigned() & m);
+            }
+            if umin > umax || smin > smax {
+                return None;
+            }
+            // The bits above the highest bit in which `umin` and `umax` differ are known.
+            let prefix = m & !u64::MAX
+                .checked_shr((umin ^ umax).leading_zeros())
+                .unwrap_or(0);
+            ones |= umin & prefix;
+            zeros |= !umin & prefix;
+            if old == (umin, umax, smin, smax, zeros, ones) {
+                return Some(Self {
+                    bitw,
+                    umin,
+                    umax,
+                    smin,
+                    smax,
+                    zeros,
+                    ones,
+                });
+            }
+        }
+    }
+
+    /// As [Self::new], but if the components contradict each other, return [Self::unknown]. This
+    /// is used by transfer functions: if their inputs are consistent then so are their outputs, so
+    /// a contradiction can only occur on code that can never execute.
+    fn new_or_unknown(bitw: u32, urange: (u64, u64), srange: (i64, i64), bits: (u64, u64)) -> Self {
+        Self::new(bitw, urange, srange, bits).unwrap_or_else(|| Self::unknown(bitw).unwrap())
+    }
+
+    /// An [IntFacts] of `bitw` bits whose only known facts are its known bits.
+    fn from_bits(bitw: u32, zeros: u64, ones: u64) -> Self {
+        Self::new_or_unknown(
+            bitw,
+            (0, mask(bitw)),
+            (smin(bitw), smax(bitw)),
+            (zeros, ones),
+        )
+    }
+
+    /// Is nothing known about this integer?
+    pub(super) fn is_unknown(&self) -> bool {
+        *self == Self::unknown(self.bitw).unwrap()
+    }
+
+    /// If this integer can only have a single value, return it (zero extended).
+    pub(super) fn as_u64(&self) -> Option<u64> {
+        if self.umin == self.umax {
+            Some(self.umin)
+        } else {
+            None
+        }
+    }
+
+    /// Return the facts which are true of both `self` and `other`: the result describes an
+    /// integer which may be either.
+    pub(super) fn union(&self, other: &Self) -> Self {
+        debug_assert_eq!(self.bitw, other.bitw);
+        Self::new_or_unknown(
+            self.bitw,
+            (min(self.umin, other.umin), max(self.umax, other.umax)),
+            (min(self.smin, other.smin), max(self.smax, other.smax)),
+            (self.zeros & other.zeros, self.ones & other.ones),
+        )
+    }
+
+    /// Return the facts of an integer about which both `self` and `other` are true, or `None` if
+    /// no such integer exists.
+    pub(super) fn intersect(&self, other: &Self) -> Option<Self> {
+        debug_assert_eq!(self.bitw, other.bitw);
+        Self::new(
+            self.bitw,
+            (max(self.umin, other.umin), min(self.umax, other.umax)),
+            (max(self.smin, other.smin), min(self.smax, other.smax)),
+            (self.zeros | other.zeros, self.ones | other.ones),
+        )
+    }
+
+    /// Is `self pred other` always true (`Some(true)`), always false (`Some(false)`), or can it be
+    /// either (`None`)?
+    pub(super) fn icmp(&self, pred: Predicate, other: &Self) -> Option<bool> {
+        debug_assert_eq!(self.bitw, other.bitw);
+        match pred {
+            Predicate::Equal => {
+                if let (Some(x), Some(y)) = (self.as_u64(), other.as_u64()) {
+                    Some(x == y)
+                } else if self.intersect(other).is_none() {
+                    Some(false)
+                } else {
+                    None
+                }
+            }
+            Predicate::NotEqual => self.icmp(Predicate::Equal, other).map(|x| !x),
+            Predicate::UnsignedLess => {
+                if self.umax < other.umin {
+                    Some(true)
+                } else if self.umin >= other.umax {
+                    Some(false)
+                } else {
+                    None
+                }
+            }
+            Predicate::SignedLess => {
+                if self.smax < other.smin {
+                    Some(true)
+                } else if self.smin >= other.smax {
+                    Some(false)
+                } else {
+                    None
+                }
+            }
+            Predicate::UnsignedGreater | Predicate::SignedGreater => {
+                other.icmp(pred.swapped(), self)
+            }
+            Predicate::UnsignedLessEqual
+            | Predicate::SignedLessEqual
+            | Predicate::UnsignedGreaterEqual
+            | Predicate::SignedGreaterEqual => self.icmp(pred.inverted(), other).map(|x| !x),
+        }
+    }
+
+    /// Return the facts of `self` given that `self pred other` is true, or `None` if it cannot be
+    /// true.
+    pub(super) fn refine(&self, pred: Predicate, other: &Self) -> Option<Self> {
+        debug_assert_eq!(self.bitw, other.bitw);
+        let (mut ulo, mut uhi, mut slo, mut shi) = (self.umin, self.umax, self.smin, self.smax);
+        match pred {
+            Predicate::Equal => return self.intersect(other),
+            Predicate::NotEqual => {
+                if let Some(v) = other.as_u64() {
+                    if v == ulo {
+                        ulo = ulo.checked_add(1)?;
+                    }
+                    if v == uhi {
+                        uhi = uhi.checked_sub(1)?;
+                    }
+                    let v = sext(v, self.bitw);
+                    if v == slo {
+                        slo = slo.checked_add(1)?;
+                    }
+                    if v == shi {
+                        shi = shi.checked_sub(1)?;
+                    }
+                }
+            }
+            Predicate::UnsignedLess => uhi = min(uhi, other.umax.checked_sub(1)?),
+            Predicate::UnsignedLessEqual => uhi = min(uhi, other.umax),
+            Predicate::UnsignedGreater => {
+                if other.umin == mask(self.bitw) {
+                    return None;
+                }
+                ulo = max(ulo, other.umin + 1);
+            }
+            Predicate::UnsignedGreaterEqual => ulo = max(ulo, other.umin),
+            Predicate::SignedLess => {
+                if other.smax == smin(self.bitw) {
+                    return None;
+                }
+                shi = min(shi, other.smax - 1);
+            }
+            Predicate::SignedLessEqual => shi = min(shi, other.smax),
+            Predicate::SignedGreater => {
+                if other.smin == smax(self.bitw) {
+                    return None;
+                }
+                slo = max(slo, other.smin + 1);
+            }
+            Predicate::SignedGreaterEqual => slo = max(slo, other.smin),
+        }
+        Self::new(self.bitw, (ulo, uhi), (slo, shi), (self.zeros, self.ones))
+    }
+
+    pub(super) fn add(&self, other: &Self) -> Self {
+        let bitw = self.bitw;
+        let urange = match self.umax.checked_add(other.umax) {
+            Some(x) if x <= mask(bitw) => (self.umin + other.umin, x),
+            _ => (0, mask(bitw)),
+        };
+        let srange = signed_range(
+            bitw,
+            i128::from(self.smin) + i128::from(other.smin),
+            i128::from(self.smax) + i128::from(other.smax),
+        );
+        // The result has at least as many trailing zeros as the operand with the fewest.
+        let tz = min(self.trailing_zeros(), other.trailing_zeros());
+        Self::new_or_unknown(bitw, urange, srange, (low_bits(tz), 0))
+    }
+
+    pub(super) fn sub(&self, other: &Self) -> Self {
+        let bitw = self.bitw;
+        let urange = if self.umin >= other.umax {
+            (self.umin - other.umax, self.umax - other.umin)
+        } else {
+            (0, mask(bitw))
+        };
+        let srange = signed_range(
+            bitw,
+            i128::from(self.smin) - i128::from(other.smax),
+            i128::from(self.smax) - i128::from(other.smin),
+        );
+        let tz = min(self.trailing_zeros(), other.trailing_zeros());
+        Self::new_or_unknown(bitw, urange, srange, (low_bits(tz), 0))
+    }
+
+    pub(super) fn mul(&self, other: &Self) -> Self {
+        let bitw = self.bitw;
+        let urange = match self.umax.checked_mul(other.umax) {
+            Some(x) if x <= mask(bitw) => (self.umin * other.umin, x),
+            _ => (0, mask(bitw)),
+        };
+        let tz = min(bitw, self.trailing_zeros() + other.trailing_zeros());
+        Self::new_or_unknown(bitw, urange, (smin(bitw), smax(bitw)), (low_bits(tz), 0))
+    }
+
+    pub(super) fn and(&self, other: &Self) -> Self {
+        let mut r = Self::from_bits(self.bitw, self.zeros | other.zeros, self.ones & other.ones);
+        // `x & y` can't be bigger than either `x` or `y`.
+        r.umax = min(r.umax, min(self.umax, other.umax));
+        Self::new_or_unknown(
+            r.bitw,
+            (r.umin, r.umax),
+            (r.smin, r.smax),
+            (r.zeros, r.ones),
+        )
+    }
+
+    pub(super) fn or(&self, other: &Self) -> Self {
+        let mut r = Self::from_bits(self.bitw, self.zeros & other.zeros, self.ones | other.ones);
+        // `x | y` can't be smaller than either `x` or `y`.
+        r.umin = max(r.umin, max(self.umin, other.umin));
+        Self::new_or_unknown(
+            r.bitw,
+            (r.umin, r.umax),
+            (r.smin, r.smax),
+            (r.zeros, r.ones),
+        )
+    }
+
+    pub(super) fn xor(&self, other: &Self) -> Self {
+        Self::from_bits(
+            self.bitw,
+            (self.zeros & other.zeros) | (self.ones & other.ones),
+            (self.zeros & other.ones) | (self.ones & other.zeros),
+        )
+    }
+
+    /// `self << sh`. Note that LLVM defines a shift by `>= bitw` bits to be poison: we return
+    /// [Self::unknown] in such cases.
+    pub(super) fn shl(&self, sh: u64) -> Self {
+        let bitw = self.bitw;
+        let Ok(sh) = u32::try_from(sh) else {
+            return Self::unknown(bitw).unwrap();
+        };
+        if sh >= bitw {
+            return Self::unknown(bitw).unwrap();
+        }
+        let m = mask(bitw);
+        let urange = if self.umax <= m >> sh {
+            (self.umin << sh, self.umax << sh)
+        } else {
+            (0, m)
+        };
+        Self::new_or_unknown(
+            bitw,
+            urange,
+            (smin(bitw), smax(bitw)),
+            (
+                ((self.zeros << sh) | low_bits(sh)) & m,
+                (self.ones << sh) & m,
+            ),
+        )
+    }
+
+    /// `self >> sh` (logical shift right). See [Self::shl] for shifts by `>= bitw` bits.
+    pub(super) fn lshr(&self, sh: u64) -> Self {
+        let bitw = self.bitw;
+        let Ok(sh) = u32::try_from(sh) else {
+            return Self::unknown(bitw).unwrap();
+        };
+        if sh >= bitw {
+            return Self::unknown(bitw).unwrap();
+        }
+        let m = mask(bitw);
+        Self::new_or_unknown(
+            bitw,
+            (self.umin >> sh, self.umax >> sh),
+            (smin(bitw), smax(bitw)),
+            ((self.zeros >> sh) | (m & !(m >> sh)), self.ones >> sh),
+        )
+    }
+
+    /// `self >> sh` (arithmetic shift right). See [Self::shl] for shifts by `>= bitw` bits.
+    pub(super) fn ashr(&self, sh: u64) -> Self {
+        let bitw = self.bitw;
+        let Ok(sh) = u32::try_from(sh) else {
+            return Self::unknown(bitw).unwrap();
+        };
+        if sh >= bitw {
+            return Self::unknown(bitw).unwrap();
+        }
+        let m = mask(bitw);
+        // Sign extending the known bits means that if the sign bit is known (to be zero or one)
+        // then so are the bits shifted in.
+        Self::new_or_unknown(
+            bitw,
+            (0, m),
+            (self.smin >> sh, self.smax >> sh),
+            (
+                (sext(self.zeros, bitw) >> sh).cast_unsigned() & m,
+                (sext(self.ones, bitw) >> sh).cast_unsigned() & m,
+            ),
+        )
+    }
+
+    /// `self / other` (unsigned division).
+    pub(super) fn udiv(&self, other: &Self) -> Self {
+        let bitw = self.bitw;
+        if other.umin == 0 {
+            // Division by zero is undefined behaviour.
+            return Self::unknown(bitw).unwrap();
+        }
+        Self::new_or_unknown(
+            bitw,
+            (self.umin / other.umax, self.umax / other.umin),
+            (smin(bitw), smax(bitw)),
+            (0, 0),
+        )
+    }
+
+    /// `self % other` (unsigned remainder).
+    pub(super) fn urem(&self, other: &Self) -> Self {
+        let bitw = self.bitw;
+        if other.umin == 0 {
+            // Division by zero is undefined behaviour.
+            return Self::unknown(bitw).unwrap();
+        }
+        Self::new_or_unknown(
+            bitw,
+            (0, min(self.umax, other.umax - 1)),
+            (smin(bitw), smax(bitw)),
+            (0, 0),
+        )
+    }
+
+    /// Zero extend `self` to `bitw` bits.
+    pub(super) fn zext(&self, bitw: u32) -> Option<Self> {
+        Self::unknown(bitw)?;
+        debug_assert!(bitw >= self.bitw);
+        Some(Self::new_or_unknown(
+            bitw,
+            (self.umin, self.umax),
+            (smin(bitw), smax(bitw)),
+            (self.zeros | (mask(bitw) & !mask(self.bitw)), self.ones),
+        ))
+    }
+
+    /// Sign extend `self` to `bitw` bits.
+    pub(super) fn sext(&self, bitw: u32) -> Option<Self> {
+        Self::unknown(bitw)?;
+        debug_assert!(bitw >= self.bitw);
+        let m = mask(bitw);
+        Some(Self::new_or_unknown(
+            bitw,
+            (0, m),
+            (self.smin, self.smax),
+            (
+                sext(self.zeros, self.bitw).cast_unsigned() & m,
+                sext(self.ones, self.bitw).cast_unsigned() & m,
+            ),
+        ))
+    }
+
+    /// Truncate `self` to `bitw` bits.
+    pub(super) fn trunc(&self, bitw: u32) -> Self {
+        debug_assert!(bitw <= self.bitw);
+        let m = mask(bitw);
+        let urange = if self.umax <= m {
+            (self.umin, self.umax)
+        } else {
+            (0, m)
+        };
+        Self::new_or_unknown(
+            bitw,
+            urange,
+            (smin(bitw), smax(bitw)),
+            (self.zeros & m, self.ones & m),
+        )
+    }
+
+    /// Does `self op other` always overflow (`Some(true)`), never overflow (`Some(false)`), or
+    /// can it do either (`None`)?
+    pub(super) fn overflows(&self, op: OverflowOp, other: &Self) -> Option<bool> {
+        debug_assert_eq!(self.bitw, other.bitw);
+        let (l_umin, l_umax) = (i128::from(self.umin), i128::from(self.umax));
+        let (r_umin, r_umax) = (i128::from(other.umin), i128::from(other.umax));
+        let (l_smin, l_smax) = (i128::from(self.smin), i128::from(self.smax));
+        let (r_smin, r_smax) = (i128::from(other.smin), i128::from(other.smax));
+        // The range of possible results, computed without overflow.
+        let (lo, hi) = match op {
+            OverflowOp::UAdd => (l_umin + r_umin, l_umax + r_umax),
+            OverflowOp::SAdd => (l_smin + r_smin, l_smax + r_smax),
+            OverflowOp::USub => (l_umin - r_umax, l_umax - r_umin),
+            OverflowOp::SSub => (l_smin - r_smax, l_smax - r_smin),
+            // Saturation is harmless here: a saturated bound is still out of range.
+            OverflowOp::UMul => (l_umin.saturating_mul(r_umin), l_umax.saturating_mul(r_umax)),
+            OverflowOp::SMul => {
+                let corners = [
+                    l_smin * r_smin,
+                    l_smin * r_smax,
+                    l_smax * r_smin,
+                    l_smax * r_smax,
+                ];
+                (
+                    *corners.iter().min().unwrap(),
+                    *corners.iter().max().unwrap(),
+                )
+            }
+        };
+        let (min_ok, max_ok) = if op.signed() {
+            (i128::from(smin(self.bitw)), i128::from(smax(self.bitw)))
+        } else {
+            (0, i128::from(mask(self.bitw)))
+        };
+        if lo >= min_ok && hi <= max_ok {
+            Some(false)
+        } else if hi < min_ok || lo > max_ok {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    /// How many of the least significant bits are known to be zero?
+    fn trailing_zeros(&self) -> u32 {
+        min(self.bitw, (!self.zeros).trailing_zeros())
+    }
+}
+
+/// A mask with the `bitw` least significant bits set.
+fn mask(bitw: u32) -> u64 {
+    low_bits(bitw)
+}
+
+/// A mask with the `n` least significant bits set.
+fn low_bits(n: u32) -> u64 {
+    u64::MAX.checked_shr(64 - n).unwrap_or(0)
+}
+
+/// Sign extend the `bitw`-bit integer `x`.
+fn sext(x: u64, bitw: u32) -> i64 {
+    (x << (64 - bitw)).cast_signed() >> (64 - bitw)
+}
+
+/// The smallest signed `bitw`-bit integer.
+fn smin(bitw: u32) -> i64 {
+    i64::MIN >> (64 - bitw)
+}
+
+/// The largest signed `bitw`-bit integer.
+fn smax(bitw: u32) -> i64 {
+    i64::MAX >> (64 - bitw)
+}
+
+/// The signed range `[min, max]` if it is representable in `bitw` bits (i.e. computing it did not
+/// overflow), or the full range of `bitw`-bit signed integers otherwise.
+fn signed_range(bitw: u32, min: i128, max: i128) -> (i64, i64) {
+    if min >= i128::from(smin(bitw)) && max <= i128::from(smax(bitw)) {
+        (min as i64, max as i64)
+    } else {
+        (smin(bitw), smax(bitw))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn exact(bitw: u32, v: u64) -> IntFacts {
+        IntFacts::exact(&ArbBitInt::from_u64(bitw, v)).unwrap()
+    }
+
+    fn iexact(bitw: u32, v: i64) -> IntFacts {
+        IntFacts::exact(&ArbBitInt::from_i64(bitw, v)).unwrap()
+    }
+
+    #[test]
+    fn helpers() {
+        assert_eq!(mask(1), 1);
+        assert_eq!(mask(8), 0xFF);
+        assert_eq!(mask(64), u64::MAX);
+        assert_eq!(low_bits(0), 0);
+        assert_eq!(sext(0xFF, 8), -1);
+        assert_eq!(sext(0x7F, 8), 127);
+        assert_eq!(sext(1, 1), -1);
+        assert_eq!(smin(8), -128);
+        assert_eq!(smax(8), 127);
+        assert_eq!(smin(64), i64::MIN);
+        assert_eq!(smax(1), 0);
+    }
+
+    #[test]
+    fn exact_and_unknown() {
+        assert!(IntFacts::unknown(0).is_none());
+        assert!(IntFacts::unknown(65).is_none());
+        assert!(IntFacts::unknown(8).unwrap().is_unknown());
+        assert_eq!(exact(8, 5).as_u64(), Some(5));
+        assert_eq!(iexact(8, -1).as_u64(), Some(0xFF));
+        assert_eq!(IntFacts::unknown(8).unwrap().as_u64(), None);
+    }
+
+    #[test]
+    fn normalise() {
+        // Known bits bound the unsigned interval...
+        let x = IntFacts::from_bits(8, 0xF0, 0x01);
+        assert_eq!((x.umin, x.umax), (1, 0x0F));
+        // ...which bounds the signed interval...
+        assert_eq!((x.smin, x.smax), (1, 15));
+        // ...and vice versa.
+        let x = IntFacts::new(8, (0x40, 0x4F), (-128, 127), (0, 0)).unwrap();
+        assert_eq!((x.zeros, x.ones), (0xB0, 0x40));
+        // Contradictions are detected.
+        assert!(IntFacts::new(8, (10, 5), (-128, 127), (0, 0)).is_none());
+        assert!(IntFacts::new(8, (0, 255), (-128, 127), (1, 1)).is_none());
+        assert!(IntFacts::new(8, (0, 10), (-128, -1), (0, 0)).is_none());
+    }
+
+    #[test]
+    fn icmp() {
+        let u = IntFacts::unknown(8).unwrap();
+        let lt100 = u.refine(Predicate::UnsignedLess, &exact(8, 100)).unwrap();
+        assert_eq!(
+            lt100.icmp(Predicate::UnsignedLess, &exact(8, 200)),
+            Some(true)
+        );
+        assert_eq!(
+            lt100.icmp(Predicate::UnsignedGreaterEqual, &exact(8, 100)),
+            Some(false)
+        );
+        assert_eq!(lt100.icmp(Predicate::UnsignedLess, &exact(8, 50)), None);
+        assert_eq!(lt100.icmp(Predicate::Equal, &exact(8, 100)), Some(false));
+        assert_eq!(lt100.icmp(Predicate::NotEqual, &exact(8, 100)), Some(true));
+        assert_eq!(
+            lt100.icmp(Predicate::SignedLess, &exact(8, 100)),
+            Some(true)
+        );
+        assert_eq!(exact(8, 3).icmp(Predicate::Equal, &exact(8, 3)), Some(true));
+        assert_eq!(
+            iexact(8, -1).icmp(Predicate::SignedLess, &exact(8, 0)),
+            Some(true)
+        );
+        assert_eq!(
+            iexact(8, -1).icmp(Predicate::UnsignedLess, &exact(8, 0)),
+            Some(false)
+        );
+        // Known bits can prove inequality.
+        let even = IntFacts::from_bits(8, 1, 0);
+        assert_eq!(even.icmp(Predicate::Equal, &exact(8, 7)), Some(false));
+        assert_eq!(even.icmp(Predicate::Equal, &exact(8, 8)), None);
+    }
+
+    #[test]
+    fn refine() {
+        let u = IntFacts::unknown(8).unwrap();
+        let x = u
+            .refine(Predicate::SignedGreaterEqual, &exact(8, 0))
+            .unwrap();
+        assert_eq!((x.umin, x.umax, x.smin, x.smax), (0, 127, 0, 127));
+        let x = x.refine(Predicate::NotEqual, &exact(8, 0)).unwrap();
+        assert_eq!((x.umin, x.smin), (1, 1));
+        let x = x
+            .refine(Predicate::UnsignedGreater, &exact(8, 126))
+            .unwrap();
+        assert_eq!(x.as_u64(), Some(127));
+        assert!(x.refine(Predicate::SignedGreater, &exact(8, 127)).is_none());
+        assert!(u.refine(Predicate::UnsignedLess, &exact(8, 0)).is_none());
+        assert!(u.refine(Predicate::SignedLess, &iexact(8, -128)).is_none());
+        assert_eq!(
+            u.refine(Predicate::Equal, &exact(8, 42)).unwrap(),
+            exact(8, 42)
+        );
+    }
+
+    #[test]
+    fn transfer() {
+        let u = IntFacts::unknown(8).unwrap();
+        let x = u.and(&exact(8, 0xF0));
+        assert_eq!((x.umax, x.zeros), (0xF0, 0x0F));
+        let x = u.or(&exact(8, 0x81));
+        assert_eq!((x.umin, x.ones, x.smax), (0x81, 0x81, -1));
+        let x = exact(8, 0x0F).xor(&IntFacts::from_bits(8, 0xF0, 0));
+        assert_eq!((x.zeros, x.ones), (0xF0, 0));
+        let lt16 = u.refine(Predicate::UnsignedLess, &exact(8, 16)).unwrap();
+        assert_eq!((lt16.shl(2).umin, lt16.shl(2).umax), (0, 60));
+        assert_eq!(lt16.shl(2).zeros, 0xC3);
+        assert!(lt16.shl(8).is_unknown());
+        assert_eq!(u.lshr(4).umax, 0x0F);
+        assert_eq!(iexact(8, -16).ashr(2).as_u64(), Some(0xFC));
+        assert_eq!(lt16.add(&lt16).umax, 30);
+        assert!(u.add(&exact(8, 1)).is_unknown());
+        assert_eq!(lt16.add(&exact(8, 1)).umin, 1);
+        let x = exact(8, 20).sub(&lt16);
+        assert_eq!((x.umin, x.umax), (5, 20));
+        assert_eq!(lt16.mul(&exact(8, 4)).umax, 60);
+        assert_eq!(lt16.mul(&exact(8, 4)).zeros & 3, 3);
+        assert_eq!(u.udiv(&exact(8, 16)).umax, 15);
+        assert_eq!(u.urem(&exact(8, 10)).umax, 9);
+        assert!(u.urem(&exact(8, 0)).is_unknown());
+        let x = lt16.zext(32).unwrap();
+        assert_eq!((x.bitw, x.umax, x.smin), (32, 15, 0));
+        let x = u.zext(16).unwrap();
+        assert_eq!((x.umax, x.zeros), (0xFF, 0xFF00));
+        let x = iexact(8, -2).sext(16).unwrap();
+        assert_eq!(x.as_u64(), Some(0xFFFE));
+        let x = u.sext(16).unwrap();
+        assert_eq!((x.smin, x.smax), (-128, 127));
+        assert_eq!(lt16.trunc(4).umax, 15);
+        assert!(u.trunc(4).is_unknown());
+        assert!(u.zext(128).is_none());
+        let x = exact(8, 3).union(&exact(8, 5));
+        assert_eq!((x.umin, x.umax, x.ones, x.zeros), (3, 5, 1, 0xF8));
+    }
+
+    #[test]
+    fn overflows() {
+        let u = IntFacts::unknown(8).unwrap();
+        let lt16 = u.refine(Predicate::UnsignedLess, &exact(8, 16)).unwrap();
+        assert_eq!(lt16.overflows(OverflowOp::UAdd, &lt16), Some(false));
+        assert_eq!(lt16.overflows(OverflowOp::SAdd, &lt16), Some(false));
+        assert_eq!(lt16.overflows(OverflowOp::UMul, &lt16), Some(false));
+        assert_eq!(lt16.overflows(OverflowOp::UMul, &exact(8, 32)), None);
+        assert_eq!(lt16.overflows(OverflowOp::SMul, &exact(8, 7)), Some(false));
+        assert_eq!(lt16.overflows(OverflowOp::USub, &exact(8, 16)), Some(true));
+        assert_eq!(lt16.overflows(OverflowOp::SSub, &exact(8, 16)), Some(false));
+        assert_eq!(u.overflows(OverflowOp::UAdd, &exact(8, 0)), Some(false));
+        assert_eq!(u.overflows(OverflowOp::SAdd, &exact(8, 1)), None);
+        assert_eq!(
+            exact(8, 200).overflows(OverflowOp::UAdd, &exact(8, 100)),
+            Some(true)
+        );
+        assert_eq!(
+            iexact(8, -128).overflows(OverflowOp::SMul, &iexact(8, -1)),
+            Some(true)
+        );
+        assert_eq!(
+            iexact(8, -64).overflows(OverflowOp::SMul, &exact(8, 2)),
+            Some(false)
+        );
+    }
+}
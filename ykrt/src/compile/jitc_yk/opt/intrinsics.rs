@@ -0,0 +1,108 @@
+//! Constant folding of LLVM intrinsics.
+//!
+//! Many LLVM intrinsics (e.g. `llvm.umax.i64`) are "pure": they neither read nor write memory,
+//! and their result depends only on their arguments. Calls to such intrinsics don't need to act as
+//! heap barriers and, if all their arguments are constants, can be replaced with a constant.
+
+use super::super::{
+    arbbitint::ArbBitInt,
+    jit_ir::{Const, FloatTy, Module, Ty, TyIdx},
+};
+
+/// If `name` is a pure LLVM intrinsic, return its base name (e.g. `umax` for `llvm.umax.i64`),
+/// otherwise return `None`.
+pub(super) fn pure_intrinsic(name: &str) -> Option<&str> {
+    let base = name.strip_prefix("llvm.")?.split('.').next()?;
+    match base {
+        "abs" | "bswap" | "ceil" | "ctlz" | "ctpop" | "cttz" | "fabs" | "floor" | "fma"
+        | "fshl" | "fshr" | "smax" | "smin" | "sqrt" | "umax" | "umin" => Some(base),
+        _ => None,
+    }
+}
+
+/// Constant fold a call to the pure intrinsic `intrinsic` (as returned by [pure_intrinsic]) whose
+/// arguments are `args` and whose return type is `ret_tyidx`. Returns `None` if the call can't be
+/// folded.
+pub(super) fn fold(m: &Module, intrinsic: &str, args: &[Const], ret_tyidx: TyIdx) -> Option<Const> {
+    match args.first()? {
+        Const::Int(_, x) => {
+            let bitw = x.bitw();
+            if bitw > 64 {
+                return None;
+            }
+            let ints = args
+                .iter()
+                .map(|c| match c {
+                    Const::Int(_, y) => Some(y),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            let u = |i: usize| ints[i].to_zero_ext_u64().unwrap();
+            let s = |i: usize| ints[i].to_sign_ext_i64().unwrap();
+            let v = match intrinsic {
+                // `abs(INT_MIN)` is either `INT_MIN` or poison, so wrapping is always correct.
+                "abs" => s(0).wrapping_abs().cast_unsigned(),
+                "bswap" => u(0).swap_bytes() >> (64 - bitw),
+                // If the input is zero, the result is either the bit width or poison.
+                "ctlz" => u64::from(u(0).leading_zeros() - (64 - bitw)),
+                "ctpop" => u64::from(u(0).count_ones()),
+                "cttz" => u64::from(u(0).trailing_zeros().min(bitw)),
+                "fshl" | "fshr" => {
+                    // Concatenate the first two arguments, shift the result by the third argument
+                    // (modulo the bit width), and extract the high (`fshl`) or low (`fshr`) half.
+                    let concat = (u128::from(u(0)) << bitw) | u128::from(u(1));
+                    let sh = u(2) % u64::from(bitw);
+                    if intrinsic == "fshl" {
+                        ((concat << sh) >> bitw) as u64
+                    } else {
+                        (concat >> sh) as u64
+                    }
+                }
+                "smax" => s(0).max(s(1)).cast_unsigned(),
+                "smin" => s(0).min(s(1)).cast_unsigned(),
+                "umax" => u(0).max(u(1)),
+                "umin" => u(0).min(u(1)),
+                _ => return None,
+            };
+            Some(Const::Int(ret_tyidx, ArbBitInt::from_u64(bitw, v)))
+        }
+        Const::Float(tyidx, _) => {
+            let fs = args
+                .iter()
+                .map(|c| match c {
+                    Const::Float(_, v) => Some(*v),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            // `float` constants are stored as `f64`s, but we must compute with `f32`s to get
+            // correctly rounded results.
+            let v = match m.type_(*tyidx) {
+                Ty::Float(FloatTy::Float) => {
+                    let f = |i: usize| fs[i] as f32;
+                    f64::from(match intrinsic {
+                        "ceil" => f(0).ceil(),
+                        "fabs" => f(0).abs(),
+                        "floor" => f(0).floor(),
+                        "fma" => f(0).mul_add(f(1), f(2)),
+                        "sqrt" => f(0).sqrt(),
+                        _ => return None,
+                    })
+                }
+                Ty::Float(FloatTy::Double) => {
+                    let f = |i: usize| fs[i];
+                    match intrinsic {
+                        "ceil" => f(0).ceil(),
+                        "fabs" => f(0).abs(),
+                        "floor" => f(0).floor(),
+                        "fma" => f(0).mul_add(f(1), f(2)),
+                        "sqrt" => f(0).sqrt(),
+                        _ => return None,
+                    }
+                }
+                _ => panic!(),
+            };
+            Some(Const::Float(ret_tyidx, v))
+        }
+        Const::Ptr(_) => None,
+    }
+}
@@ -9,31 +9,44 @@
 use super::{
     arbbitint::ArbBitInt,
     jit_ir::{
-        BinOp, BinOpInst, Const, ConstIdx, DirectCallInst, DynPtrAddInst, GuardInst, ICmpInst,
-        Inst, InstIdx, IntToPtrInst, LoadInst, Module, Operand, Predicate, PtrAddInst,
-        PtrToIntInst, SExtInst, SelectInst, StoreInst, TraceKind, TruncInst, Ty, ZExtInst,
+        BinOp, BinOpInst, Const, ConstIdx, DirectCallInst, DynPtrAddInst, FCmpInst, FNegInst,
+        FPExtInst, FPToSIInst, FPToUIInst, FPTruncInst, FloatPredicate, FloatTy, GuardInst,
+        ICmpInst, Inst, InstIdx, IntToPtrInst, LoadInst, Module, Operand, OverflowInst, OverflowOp,
+        Predicate, PtrAddInst, PtrToIntInst, SExtInst, SIToFPInst, SelectInst, StoreInst,
+        TraceKind, TruncInst, Ty, UIToFPInst, ZExtInst,
     },
 };
 use crate::compile::CompilationError;
-use std::assert_matches::debug_assert_matches;
+use std::{assert_matches::debug_assert_matches, collections::HashMap};
 
 mod analyse;
+mod escape;
 mod heapvalues;
 mod instll;
+mod intfacts;
+mod intrinsics;
 
 use analyse::{Analyse, Value};
 use heapvalues::Address;
 use instll::InstLinkedList;
+use intfacts::IntFacts;
 
 struct Opt {
     m: Module,
     an: Analyse,
+    /// For each variable `x`, the most recent guard in the current part of the trace (header or
+    /// body) whose condition is of the form `x pred c` for some constant `c`.
+    cmp_guards: HashMap<InstIdx, InstIdx>,
 }
 
 impl Opt {
     fn new(m: Module) -> Self {
         let an = Analyse::new(&m);
-        Self { m, an }
+        Self {
+            m,
+            an,
+            cmp_guards: HashMap::new(),
+        }
     }
 
     fn opt(mut self) -> Result<Module, CompilationError> {
@@ -81,12 +94,17 @@ impl Opt {
             self.peel()?;
         }
 
+        // Step 3: remove allocations which don't escape the trace.
+        escape::virtualise(&mut self.m);
+
         Ok(self.m)
     }
 
     fn peel(&mut self) -> Result<(), CompilationError> {
         debug_assert_matches!(self.m.tracekind(), TraceKind::HeaderOnly);
         self.m.set_tracekind(TraceKind::HeaderAndBody);
+        // A guard in the body must never strengthen one in the header.
+        self.cmp_guards.clear();
 
         // Now that we've processed the trace header, duplicate it to create the loop body.
         let mut iidx_map = vec![InstIdx::max(); self.m.insts_len()];
@@ -198,6 +216,71 @@ impl Opt {
             }
         }
 
+        self.sink_body_stores()
+    }
+
+    /// Move stores in the loop body to just before [Inst::TraceBodyEnd], if no instruction between
+    /// a store and the end of the body could observe (or overwrite) the bytes it stores.
+    /// Interpreters tend to repeatedly write to the same locations (e.g. frame slots) and sinking
+    /// stores groups those writes together at the end of each iteration.
+    fn sink_body_stores(&mut self) -> Result<(), CompilationError> {
+        let end_iidx = self.m.last_inst_idx();
+        if !matches!(self.m.inst_nocopy(end_iidx), Some(Inst::TraceBodyEnd)) {
+            return Ok(());
+        }
+        // The memory accessed by the instructions which we have not sunk.
+        let mut accesses = Vec::new();
+        // The stores to sink, in reverse order.
+        let mut sink = Vec::new();
+        // Have we seen an instruction which we won't sink?
+        let mut seen_other = false;
+        // Is sinking the stores in `sink` going to move any of them past another instruction?
+        let mut moves = false;
+        for (iidx, inst) in self.m.iter_skipping_insts().rev().skip(1) {
+            match inst {
+                Inst::TraceBodyStart => break,
+                Inst::Store(x) if !x.is_volatile() => {
+                    let addr = Address::from_operand(&self.m, x.ptr(&self.m));
+                    let bytesize = x.val(&self.m).byte_size(&self.m);
+                    if accesses.iter().any(|(a_addr, a_bytesize)| {
+                        self.an.may_alias(&addr, bytesize, a_addr, *a_bytesize)
+                    }) {
+                        accesses.push((addr, bytesize));
+                    } else {
+                        moves |= seen_other;
+                        sink.push((iidx, inst));
+                        continue;
+                    }
+                }
+                Inst::Load(x) if !x.is_volatile() => {
+                    accesses.push((
+                        Address::from_operand(&self.m, x.ptr(&self.m)),
+                        Inst::Load(x).def_byte_size(&self.m),
+                    ));
+                }
+                _ if inst.is_guard()
+                    || inst.has_load_effect(&self.m)
+                    || inst.has_store_effect(&self.m) =>
+                {
+                    break;
+                }
+                _ => (),
+            }
+            seen_other = true;
+        }
+
+        if moves {
+            sink.reverse();
+            for (iidx, _) in &sink {
+                self.m.replace(*iidx, Inst::Tombstone);
+            }
+            let mut sink = sink.into_iter().map(|(_, inst)| inst);
+            self.m.replace(end_iidx, sink.next().unwrap());
+            for inst in sink {
+                self.m.push(inst)?;
+            }
+            self.m.push(Inst::TraceBodyEnd)?;
+        }
         Ok(())
     }
 
@@ -222,10 +305,17 @@ impl Opt {
                 self.an.heap_barrier();
             }
             Inst::DynPtrAdd(x) => self.opt_dynptradd(iidx, x)?,
+            Inst::FCmp(x) => self.opt_fcmp(iidx, x)?,
+            Inst::FNeg(x) => self.opt_fneg(iidx, x)?,
+            Inst::FPExt(x) => self.opt_fpext(iidx, x)?,
+            Inst::FPToSI(x) => self.opt_fptosi(iidx, x)?,
+            Inst::FPToUI(x) => self.opt_fptoui(iidx, x)?,
+            Inst::FPTrunc(x) => self.opt_fptrunc(iidx, x)?,
             Inst::Guard(x) => self.opt_guard(iidx, x)?,
             Inst::ICmp(x) => self.opt_icmp(iidx, x)?,
             Inst::IntToPtr(x) => self.opt_inttoptr(iidx, x)?,
             Inst::Load(x) => self.opt_load(iidx, x)?,
+            Inst::Overflow(x) => self.opt_overflow(iidx, x)?,
             Inst::Param(x) => {
                 // FIXME: This feels like it should be handled by trace_builder, but we can't
                 // do so yet because of https://github.com/ykjit/yk/issues/1435.
@@ -246,12 +336,19 @@ impl Opt {
             Inst::PtrToInt(x) => self.opt_ptrtoint(iidx, x)?,
             Inst::Select(x) => self.opt_select(iidx, x)?,
             Inst::SExt(x) => self.opt_sext(iidx, x)?,
+            Inst::SIToFP(x) => self.opt_sitofp(iidx, x)?,
             Inst::Store(x) => self.opt_store(iidx, x)?,
             Inst::Trunc(x) => self.opt_trunc(iidx, x)?,
+            Inst::UIToFP(x) => self.opt_uitofp(iidx, x)?,
             Inst::ZExt(x) => self.opt_zext(iidx, x)?,
+            Inst::TraceHeaderEnd(_) | Inst::TraceBodyEnd | Inst::SidetraceEnd => {
+                self.an.heap_observe_all();
+            }
             _ => (),
         };
 
+        self.an.derive_int(&self.m, iidx);
+
         Ok(())
     }
 
@@ -265,7 +362,7 @@ impl Opt {
                 (Operand::Const(op_cidx), Operand::Var(op_iidx))
                 | (Operand::Var(op_iidx), Operand::Const(op_cidx)) => {
                     match self.m.const_(op_cidx) {
-                        Const::Int(_, x) if x.to_zero_ext_u64().unwrap() == 0 => {
+                        Const::Int(_, x) if x.to_zero_ext_u128().unwrap() == 0 => {
                             // Replace `x + 0` with `x`.
                             self.m.replace(iidx, Inst::Copy(op_iidx));
                         }
@@ -306,13 +403,13 @@ impl Opt {
                 | (Operand::Var(op_iidx), Operand::Const(op_cidx)) => {
                     match self.m.const_(op_cidx) {
                         Const::Int(_, v) => {
-                            if let Some(0) = v.to_zero_ext_u64() {
+                            if let Some(0) = v.to_zero_ext_u128() {
                                 // Replace `x & 0` with `0`.
                                 self.m.replace(iidx, Inst::Const(op_cidx));
                                 return Ok(());
                             } else {
                                 let all_bits = ArbBitInt::all_bits_set(v.bitw());
-                                if v.to_zero_ext_u64() == all_bits.to_zero_ext_u64() {
+                                if v.to_zero_ext_u128() == all_bits.to_zero_ext_u128() {
                                     // Replace `x & y` with `x` if `y` is a constant that has all
                                     // the necessary bits set for this integer type. For an i1, for
                                     // example, `x & 1` can be replaced with `x`.
@@ -354,7 +451,7 @@ impl Opt {
             ) {
                 (Operand::Var(op_iidx), Operand::Const(op_cidx)) => {
                     if let Const::Int(_, y) = self.m.const_(op_cidx)
-                        && y.to_zero_ext_u64().unwrap() == 0
+                        && y.to_zero_ext_u128().unwrap() == 0
                     {
                         // Replace `x >> 0` with `x`.
                         self.m.replace(iidx, Inst::Copy(op_iidx));
@@ -362,7 +459,7 @@ impl Opt {
                 }
                 (Operand::Const(op_cidx), Operand::Var(_)) => {
                     if let Const::Int(tyidx, y) = self.m.const_(op_cidx)
-                        && y.to_zero_ext_u64().unwrap() == 0
+                        && y.to_zero_ext_u128().unwrap() == 0
                     {
                         // Replace `0 >> x` with `0`.
                         let new_cidx = self
@@ -402,17 +499,17 @@ impl Opt {
                 (Operand::Const(op_cidx), Operand::Var(op_iidx))
                 | (Operand::Var(op_iidx), Operand::Const(op_cidx)) => {
                     match self.m.const_(op_cidx) {
-                        Const::Int(_, y) if y.to_zero_ext_u64().unwrap() == 0 => {
+                        Const::Int(_, y) if y.to_zero_ext_u128().unwrap() == 0 => {
                             // Replace `x * 0` with `0`.
                             self.m.replace(iidx, Inst::Const(op_cidx));
                         }
-                        Const::Int(_, y) if y.to_zero_ext_u64().unwrap() == 1 => {
+                        Const::Int(_, y) if y.to_zero_ext_u128().unwrap() == 1 => {
                             // Replace `x * 1` with `x`.
                             self.m.replace(iidx, Inst::Copy(op_iidx));
                         }
-                        Const::Int(tyidx, y) if y.to_zero_ext_u64().unwrap().is_power_of_two() => {
+                        Const::Int(tyidx, y) if y.to_zero_ext_u128().unwrap().is_power_of_two() => {
                             // Replace `x * y` with `x << ...`.
-                            let shl = u64::from(y.to_zero_ext_u64().unwrap().ilog2());
+                            let shl = u64::from(y.to_zero_ext_u128().unwrap().ilog2());
                             let shl_op = Operand::Const(self.m.insert_const(Const::Int(
                                 *tyidx,
                                 ArbBitInt::from_u64(y.bitw(), shl),
@@ -458,13 +555,13 @@ impl Opt {
                 | (Operand::Var(op_iidx), Operand::Const(op_cidx)) => {
                     match self.m.const_(op_cidx) {
                         Const::Int(_, v) => {
-                            if let Some(0) = v.to_zero_ext_u64() {
+                            if let Some(0) = v.to_zero_ext_u128() {
                                 // Replace `x | 0` with `x`.
                                 self.m.replace(iidx, Inst::Copy(op_iidx));
                                 return Ok(());
                             } else {
                                 let all_bits = ArbBitInt::all_bits_set(v.bitw());
-                                if v.to_zero_ext_u64() == all_bits.to_zero_ext_u64() {
+                                if v.to_zero_ext_u128() == all_bits.to_zero_ext_u128() {
                                     // Replace `x | y` with `y` if `y` is a constant that has all
                                     // the necessary bits set for this integer type. For an i1, for
                                     // example, `x | 1` can be replaced with `1`.
@@ -510,7 +607,7 @@ impl Opt {
             ) {
                 (Operand::Var(op_iidx), Operand::Const(op_cidx)) => {
                     if let Const::Int(_, y) = self.m.const_(op_cidx)
-                        && y.to_zero_ext_u64().unwrap() == 0
+                        && y.to_zero_ext_u128().unwrap() == 0
                     {
                         // Replace `x << 0` with `x`.
                         self.m.replace(iidx, Inst::Copy(op_iidx));
@@ -518,7 +615,7 @@ impl Opt {
                 }
                 (Operand::Const(op_cidx), Operand::Var(_)) => {
                     if let Const::Int(tyidx, y) = self.m.const_(op_cidx)
-                        && y.to_zero_ext_u64().unwrap() == 0
+                        && y.to_zero_ext_u128().unwrap() == 0
                     {
                         // Replace `0 << x` with `0`.
                         let new_cidx = self
@@ -551,7 +648,7 @@ impl Opt {
             ) {
                 (Operand::Var(op_iidx), Operand::Const(op_cidx)) => {
                     if let Const::Int(_, y) = self.m.const_(op_cidx)
-                        && y.to_zero_ext_u64().unwrap() == 0
+                        && y.to_zero_ext_u128().unwrap() == 0
                     {
                         // Replace `x - 0` with `x`.
                         self.m.replace(iidx, Inst::Copy(op_iidx));
@@ -579,7 +676,7 @@ impl Opt {
                 (Operand::Const(op_cidx), Operand::Var(op_iidx))
                 | (Operand::Var(op_iidx), Operand::Const(op_cidx)) => {
                     match self.m.const_(op_cidx) {
-                        Const::Int(_, y) if y.to_zero_ext_u64().unwrap() == 0 => {
+                        Const::Int(_, y) if y.to_zero_ext_u128().unwrap() == 0 => {
                             // Replace `x ^ 0` with `x`.
                             self.m.replace(iidx, Inst::Copy(op_iidx));
                         }
@@ -616,6 +713,9 @@ impl Opt {
                     }
                 }
             },
+            BinOp::FAdd | BinOp::FDiv | BinOp::FMul | BinOp::FRem | BinOp::FSub => {
+                self.opt_fbinop(iidx, inst)?
+            }
             _ => {
                 if let (Operand::Const(_), Operand::Const(_)) = (
                     self.an.op_map(&self.m, inst.lhs(&self.m)),
@@ -629,6 +729,79 @@ impl Opt {
         Ok(())
     }
 
+    /// Optimise a floating point `BinOp`. Constants are folded following IEEE 754 semantics, and
+    /// only those algebraic identities which hold for all inputs (including NaNs, infinities, and
+    /// negative zero) are used: for example `x + 0.0` can't be simplified to `x`, because
+    /// `-0.0 + 0.0` is `0.0`.
+    fn opt_fbinop(&mut self, iidx: InstIdx, inst: BinOpInst) -> Result<(), CompilationError> {
+        let binop = inst.binop();
+        match (
+            self.an.op_map(&self.m, inst.lhs(&self.m)),
+            self.an.op_map(&self.m, inst.rhs(&self.m)),
+        ) {
+            (Operand::Const(lhs_cidx), Operand::Const(rhs_cidx)) => {
+                let (Const::Float(tyidx, x), Const::Float(_, y)) =
+                    (self.m.const_(lhs_cidx), self.m.const_(rhs_cidx))
+                else {
+                    panic!()
+                };
+                let v = match self.m.type_(*tyidx) {
+                    Ty::Float(FloatTy::Float) => {
+                        // `float` constants are stored as `f64`s, but we must compute with `f32`s
+                        // to get correctly rounded results.
+                        let (x, y) = (*x as f32, *y as f32);
+                        f64::from(match binop {
+                            BinOp::FAdd => x + y,
+                            BinOp::FDiv => x / y,
+                            BinOp::FMul => x * y,
+                            BinOp::FRem => x % y,
+                            BinOp::FSub => x - y,
+                            _ => unreachable!(),
+                        })
+                    }
+                    Ty::Float(FloatTy::Double) => match binop {
+                        BinOp::FAdd => x + y,
+                        BinOp::FDiv => x / y,
+                        BinOp::FMul => x * y,
+                        BinOp::FRem => x % y,
+                        BinOp::FSub => x - y,
+                        _ => unreachable!(),
+                    },
+                    _ => panic!(),
+                };
+                let cidx = self.m.insert_const(Const::Float(*tyidx, v))?;
+                self.m.replace(iidx, Inst::Const(cidx));
+            }
+            (Operand::Var(op_iidx), Operand::Const(op_cidx)) => {
+                let Const::Float(_, y) = self.m.const_(op_cidx) else {
+                    panic!()
+                };
+                let is_identity = match binop {
+                    BinOp::FDiv | BinOp::FMul => *y == 1.0,
+                    BinOp::FAdd => *y == 0.0 && y.is_sign_negative(),
+                    BinOp::FSub => *y == 0.0 && y.is_sign_positive(),
+                    _ => false,
+                };
+                if is_identity {
+                    // Replace `x * 1.0`, `x / 1.0`, `x + -0.0`, and `x - 0.0` with `x`.
+                    self.m.replace(iidx, Inst::Copy(op_iidx));
+                }
+            }
+            (Operand::Const(op_cidx), Operand::Var(op_iidx)) => {
+                if let BinOp::FAdd | BinOp::FMul = binop {
+                    // Canonicalise to (Var, Const).
+                    self.m.replace(
+                        iidx,
+                        BinOpInst::new(Operand::Var(op_iidx), binop, Operand::Const(op_cidx))
+                            .into(),
+                    );
+                }
+            }
+            (Operand::Var(_), Operand::Var(_)) => (),
+        }
+        Ok(())
+    }
+
     fn opt_direct_call(
         &mut self,
         iidx: InstIdx,
@@ -645,6 +818,25 @@ impl Opt {
             self.m.replace(iidx, Inst::Const(cidx));
             return Ok(());
         }
+        if let Some(intrinsic) = intrinsics::pure_intrinsic(self.m.func_decl(inst.target()).name())
+        {
+            // Pure intrinsics don't touch memory, so they don't need a memory barrier; and if
+            // their arguments are all constants, we can fold them.
+            let intrinsic = intrinsic.to_owned();
+            let mut args = Vec::with_capacity(inst.num_args());
+            for i in 0..inst.num_args() {
+                match self.an.op_map(&self.m, inst.operand(&self.m, i)) {
+                    Operand::Const(cidx) => args.push(self.m.const_(cidx).clone()),
+                    Operand::Var(_) => return Ok(()),
+                }
+            }
+            let ret_tyidx = self.m.func_type(inst.target()).ret_tyidx();
+            if let Some(c) = intrinsics::fold(&self.m, &intrinsic, &args, ret_tyidx) {
+                let cidx = self.m.insert_const(c)?;
+                self.m.replace(iidx, Inst::Const(cidx));
+            }
+            return Ok(());
+        }
         self.an.heap_barrier();
         Ok(())
     }
@@ -686,52 +878,309 @@ impl Opt {
         Ok(())
     }
 
+    fn opt_fcmp(&mut self, iidx: InstIdx, inst: FCmpInst) -> Result<(), CompilationError> {
+        if let (Operand::Const(lhs_cidx), Operand::Const(rhs_cidx)) = (
+            self.an.op_map(&self.m, inst.lhs(&self.m)),
+            self.an.op_map(&self.m, inst.rhs(&self.m)),
+        ) {
+            let (Const::Float(_, x), Const::Float(_, y)) =
+                (self.m.const_(lhs_cidx), self.m.const_(rhs_cidx))
+            else {
+                panic!()
+            };
+            // Constant fold comparisons of floats. Note that `float` constants are stored as
+            // `f64`s, but since converting an `f32` to an `f64` is exact, comparing them as `f64`s
+            // gives the same result.
+            let unordered = x.is_nan() || y.is_nan();
+            let r = match inst.predicate() {
+                FloatPredicate::False => false,
+                FloatPredicate::OrderedEqual => x == y,
+                FloatPredicate::OrderedGreater => x > y,
+                FloatPredicate::OrderedGreaterEqual => x >= y,
+                FloatPredicate::OrderedLess => x < y,
+                FloatPredicate::OrderedLessEqual => x <= y,
+                FloatPredicate::OrderedNotEqual => !unordered && x != y,
+                FloatPredicate::Ordered => !unordered,
+                FloatPredicate::Unordered => unordered,
+                FloatPredicate::UnorderedEqual => unordered || x == y,
+                FloatPredicate::UnorderedGreater => unordered || x > y,
+                FloatPredicate::UnorderedGreaterEqual => unordered || x >= y,
+                FloatPredicate::UnorderedLess => unordered || x < y,
+                FloatPredicate::UnorderedLessEqual => unordered || x <= y,
+                FloatPredicate::UnorderedNotEqual => x != y,
+                FloatPredicate::True => true,
+            };
+            self.m.replace(
+                iidx,
+                Inst::Const(if r {
+                    self.m.true_constidx()
+                } else {
+                    self.m.false_constidx()
+                }),
+            );
+        }
+        Ok(())
+    }
+
+    fn opt_fneg(&mut self, iidx: InstIdx, inst: FNegInst) -> Result<(), CompilationError> {
+        match self.an.op_map(&self.m, inst.val(&self.m)) {
+            Operand::Const(cidx) => {
+                let Const::Float(tyidx, v) = self.m.const_(cidx) else {
+                    panic!()
+                };
+                let cidx = self.m.insert_const(Const::Float(*tyidx, -v))?;
+                self.m.replace(iidx, Inst::Const(cidx));
+            }
+            Operand::Var(op_iidx) => {
+                if let Some(Inst::FNeg(x)) = self.m.inst_nocopy(op_iidx)
+                    && let Operand::Var(x_iidx) = x.val(&self.m)
+                {
+                    // Replace `-(-x)` with `x`.
+                    self.m.replace(iidx, Inst::Copy(x_iidx));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn opt_fpext(&mut self, iidx: InstIdx, inst: FPExtInst) -> Result<(), CompilationError> {
+        if let Operand::Const(cidx) = self.an.op_map(&self.m, inst.val(&self.m)) {
+            let Const::Float(_, v) = self.m.const_(cidx) else {
+                panic!()
+            };
+            // `float` constants are stored as `f64`s, so extending them doesn't change their value.
+            let cidx = self.m.insert_const(Const::Float(inst.dest_tyidx(), *v))?;
+            self.m.replace(iidx, Inst::Const(cidx));
+        }
+        Ok(())
+    }
+
+    fn opt_fptosi(&mut self, iidx: InstIdx, inst: FPToSIInst) -> Result<(), CompilationError> {
+        if let Operand::Const(cidx) = self.an.op_map(&self.m, inst.val(&self.m)) {
+            let Const::Float(_, v) = self.m.const_(cidx) else {
+                panic!()
+            };
+            let Ty::Integer(bitw) = self.m.type_(inst.dest_tyidx()) else {
+                panic!()
+            };
+            // Converting a NaN, or a value that is out of range of the destination type, produces
+            // a poison value: we leave such instructions alone rather than picking a value.
+            let v = v.trunc();
+            let bound = 2f64.powi(i32::try_from(*bitw).unwrap() - 1);
+            if *bitw <= 64 && v >= -bound && v < bound {
+                let cidx = self.m.insert_const(Const::Int(
+                    inst.dest_tyidx(),
+                    ArbBitInt::from_i64(*bitw, v as i64),
+                ))?;
+                self.m.replace(iidx, Inst::Const(cidx));
+            }
+        }
+        Ok(())
+    }
+
+    fn opt_fptoui(&mut self, iidx: InstIdx, inst: FPToUIInst) -> Result<(), CompilationError> {
+        if let Operand::Const(cidx) = self.an.op_map(&self.m, inst.val(&self.m)) {
+            let Const::Float(_, v) = self.m.const_(cidx) else {
+                panic!()
+            };
+            let Ty::Integer(bitw) = self.m.type_(inst.dest_tyidx()) else {
+                panic!()
+            };
+            // As with `fp_to_si`, NaNs and out of range values produce poison, so we leave them
+            // alone. Note that values in (-1, 0) round to zero, so are in range.
+            let v = v.trunc();
+            if *bitw <= 64 && v >= 0.0 && v < 2f64.powi(i32::try_from(*bitw).unwrap()) {
+                let cidx = self.m.insert_const(Const::Int(
+                    inst.dest_tyidx(),
+                    ArbBitInt::from_u64(*bitw, v as u64),
+                ))?;
+                self.m.replace(iidx, Inst::Const(cidx));
+            }
+        }
+        Ok(())
+    }
+
+    fn opt_fptrunc(&mut self, iidx: InstIdx, inst: FPTruncInst) -> Result<(), CompilationError> {
+        if let Operand::Const(cidx) = self.an.op_map(&self.m, inst.val(&self.m)) {
+            let Const::Float(_, v) = self.m.const_(cidx) else {
+                panic!()
+            };
+            // `float` constants are stored as `f64`s, so we round to the nearest `f32` and then
+            // (exactly) convert back.
+            let cidx = self
+                .m
+                .insert_const(Const::Float(inst.dest_tyidx(), f64::from(*v as f32)))?;
+            self.m.replace(iidx, Inst::Const(cidx));
+        }
+        Ok(())
+    }
+
     fn opt_guard(&mut self, iidx: InstIdx, inst: GuardInst) -> Result<(), CompilationError> {
         if let Operand::Const(cidx) = self.an.op_map(&self.m, inst.cond(&self.m)) {
-            // A guard that references a constant is, by definition, not needed and
-            // doesn't affect future analyses.
             let Const::Int(_, v) = self.m.const_(cidx) else {
                 panic!()
             };
             assert_eq!(v.bitw(), 1);
-            assert!(
-                (inst.expect() && v.to_zero_ext_u8().unwrap() == 1)
-                    || (!inst.expect() && v.to_zero_ext_u8().unwrap() == 0)
-            );
-            self.m.replace(iidx, Inst::Tombstone);
+            if (inst.expect() && v.to_zero_ext_u8().unwrap() == 1)
+                || (!inst.expect() && v.to_zero_ext_u8().unwrap() == 0)
+            {
+                // A guard that references a constant it expects is, by definition, not needed
+                // and doesn't affect future analyses.
+                self.m.replace(iidx, Inst::Tombstone);
+                return Ok(());
+            }
+            // Otherwise earlier guards imply that this guard will always fail. Leaving it in
+            // place is correct (it will fail at run-time), if not optimal.
         } else {
+            self.strengthen_guard(iidx, inst);
             self.an.guard(&self.m, inst);
         }
+        // If the guard fails, the interpreter may read any memory.
+        self.an.heap_observe_all();
 
         Ok(())
     }
 
+    /// If the guard `inst` at `iidx` checks `x pred c` for a constant `c`, and the most recent
+    /// guard on `x` checks a condition `x pred' c'` which is implied by `x pred c`, then strengthen
+    /// the earlier guard so that it checks `x pred c`, and remove the guard at `iidx`. In other
+    /// words we fail earlier, but never in a situation where we would not have failed later.
+    ///
+    /// For example:
+    ///
+    /// ```text
+    /// %1: i1 = ult %0, 200i32
+    /// guard true, %1, [...]
+    /// ...
+    /// %3: i1 = ult %0, 100i32
+    /// guard true, %3, [...]
+    /// ```
+    ///
+    /// becomes:
+    ///
+    /// ```text
+    /// %1: i1 = ult %0, 100i32
+    /// guard true, %1, [...]
+    /// ...
+    /// ```
+    fn strengthen_guard(&mut self, iidx: InstIdx, inst: GuardInst) {
+        let Operand::Var(cond_iidx) = inst.cond(&self.m) else {
+            return;
+        };
+        let Some(Inst::ICmp(ic_inst)) = self.m.inst_nocopy(cond_iidx) else {
+            return;
+        };
+        let (Operand::Var(x), Operand::Const(cidx)) = (
+            self.an.op_map(&self.m, ic_inst.lhs(&self.m)),
+            self.an.op_map(&self.m, ic_inst.rhs(&self.m)),
+        ) else {
+            return;
+        };
+        let pred = if inst.expect() {
+            ic_inst.predicate()
+        } else {
+            ic_inst.predicate().inverted()
+        };
+
+        if let Some(&prev_iidx) = self.cmp_guards.get(&x)
+            && let Some(Inst::Guard(prev_inst)) = self.m.inst_nocopy(prev_iidx)
+            && let Operand::Var(prev_cond_iidx) = prev_inst.cond(&self.m)
+            && let Some(Inst::ICmp(prev_ic_inst)) = self.m.inst_nocopy(prev_cond_iidx)
+            && prev_ic_inst.lhs(&self.m) == Operand::Var(x)
+            && let Operand::Const(prev_cidx) = prev_ic_inst.rhs(&self.m)
+            // We can only change the earlier condition if nothing else depends on it.
+            && self.uses(prev_cond_iidx) == 1
+        {
+            let prev_pred = if prev_inst.expect() {
+                prev_ic_inst.predicate()
+            } else {
+                prev_ic_inst.predicate().inverted()
+            };
+            if let (Const::Int(_, c), Const::Int(_, prev_c)) =
+                (self.m.const_(cidx), self.m.const_(prev_cidx))
+                && let Some(x_facts) = IntFacts::unknown(c.bitw())
+                    .zip(IntFacts::exact(c))
+                    .and_then(|(x, c)| x.refine(pred, &c))
+                && IntFacts::exact(prev_c).and_then(|prev_c| x_facts.icmp(prev_pred, &prev_c))
+                    == Some(true)
+            {
+                let prev_pred = if prev_inst.expect() {
+                    pred
+                } else {
+                    pred.inverted()
+                };
+                self.m.replace(
+                    prev_cond_iidx,
+                    Inst::ICmp(ICmpInst::new(
+                        Operand::Var(x),
+                        prev_pred,
+                        Operand::Const(cidx),
+                    )),
+                );
+                self.m.replace(iidx, Inst::Tombstone);
+                return;
+            }
+        }
+        self.cmp_guards.insert(x, iidx);
+    }
+
+    /// How many times is the instruction at `iidx` used as an operand?
+    fn uses(&self, iidx: InstIdx) -> usize {
+        let mut uses = 0;
+        for (_, inst) in self.m.iter_skipping_insts() {
+            inst.map_operand_vars(&self.m, &mut |x| {
+                if x == iidx {
+                    uses += 1;
+                }
+            });
+        }
+        uses
+    }
+
     fn opt_icmp(&mut self, iidx: InstIdx, inst: ICmpInst) -> Result<(), CompilationError> {
         let lhs = self.an.op_map(&self.m, inst.lhs(&self.m));
         let pred = inst.predicate();
         let rhs = self.an.op_map(&self.m, inst.rhs(&self.m));
+        if let (Operand::Var(_), _) | (_, Operand::Var(_)) = (&lhs, &rhs)
+            && let Some(r) = self.an.icmp(&self.m, lhs.clone(), pred, rhs.clone())
+        {
+            // What we know about the operands tells us the result of the comparison.
+            self.m.replace(
+                iidx,
+                Inst::Const(if r {
+                    self.m.true_constidx()
+                } else {
+                    self.m.false_constidx()
+                }),
+            );
+            return Ok(());
+        }
+        if let Predicate::Equal | Predicate::NotEqual = pred
+            && let (Operand::Var(x), y) | (y, Operand::Var(x)) = (&lhs, &rhs)
+            && escape::is_alloc(&self.m, *x)
+            && escape::is_null(&self.m, y)
+        {
+            // Allocators never return null pointers.
+            self.m.replace(
+                iidx,
+                Inst::Const(if pred == Predicate::NotEqual {
+                    self.m.true_constidx()
+                } else {
+                    self.m.false_constidx()
+                }),
+            );
+            return Ok(());
+        }
         match (&lhs, &rhs) {
             (&Operand::Const(lhs_cidx), &Operand::Const(rhs_cidx)) => {
                 self.opt_icmp_both_const(iidx, lhs_cidx, pred, rhs_cidx)
             }
             (&Operand::Var(_), &Operand::Const(_)) => (),
             (&Operand::Const(_), &Operand::Var(_)) => {
-                // Canonicalise to `rhs inv_pred lhs`.
-                let inv_pred = match pred {
-                    Predicate::Equal => Predicate::Equal,
-                    Predicate::NotEqual => Predicate::NotEqual,
-                    Predicate::UnsignedGreater => Predicate::UnsignedLess,
-                    Predicate::UnsignedGreaterEqual => Predicate::UnsignedLessEqual,
-                    Predicate::UnsignedLess => Predicate::UnsignedGreater,
-                    Predicate::UnsignedLessEqual => Predicate::UnsignedGreaterEqual,
-                    Predicate::SignedGreater => Predicate::SignedLess,
-                    Predicate::SignedGreaterEqual => Predicate::SignedLessEqual,
-                    Predicate::SignedLess => Predicate::SignedGreater,
-                    Predicate::SignedLessEqual => Predicate::SignedGreaterEqual,
-                };
+                // Canonicalise to `rhs swapped_pred lhs`.
                 self.m.replace(
                     iidx,
-                    ICmpInst::new(inst.rhs(&self.m), inv_pred, inst.lhs(&self.m)).into(),
+                    ICmpInst::new(inst.rhs(&self.m), pred.swapped(), inst.lhs(&self.m)).into(),
                 );
             }
             (&Operand::Var(_), &Operand::Var(_)) => (),
@@ -740,6 +1189,60 @@ impl Opt {
         Ok(())
     }
 
+    fn opt_overflow(&mut self, iidx: InstIdx, inst: OverflowInst) -> Result<(), CompilationError> {
+        let lhs = self.an.op_map(&self.m, inst.lhs(&self.m));
+        let op = inst.op();
+        let rhs = self.an.op_map(&self.m, inst.rhs(&self.m));
+        let overflows = match (&lhs, &rhs) {
+            (&Operand::Const(lhs_cidx), &Operand::Const(rhs_cidx)) => {
+                match (self.m.const_(lhs_cidx), self.m.const_(rhs_cidx)) {
+                    (Const::Int(_, x), Const::Int(_, y)) => {
+                        debug_assert_eq!(x.bitw(), y.bitw());
+                        // Constant fold overflow checks of simple integers.
+                        let r = match op {
+                            OverflowOp::SAdd => x.checked_sadd(y),
+                            OverflowOp::UAdd => x.checked_uadd(y),
+                            OverflowOp::SSub => x.checked_ssub(y),
+                            OverflowOp::USub => x.checked_usub(y),
+                            OverflowOp::SMul => x.checked_smul(y),
+                            OverflowOp::UMul => x.checked_umul(y),
+                        };
+                        Some(r.is_none())
+                    }
+                    _ => panic!(),
+                }
+            }
+            // What we know about the operands may tell us whether the operation can overflow.
+            _ => self
+                .an
+                .int_facts(&self.m, lhs.clone())
+                .zip(self.an.int_facts(&self.m, rhs.clone()))
+                .and_then(|(x, y)| x.overflows(op, &y)),
+        };
+        match overflows {
+            Some(r) => {
+                self.m.replace(
+                    iidx,
+                    Inst::Const(if r {
+                        self.m.true_constidx()
+                    } else {
+                        self.m.false_constidx()
+                    }),
+                );
+            }
+            None => {
+                if let (Operand::Const(_), Operand::Var(_)) = (&lhs, &rhs)
+                    && op.binop() != BinOp::Sub
+                {
+                    // Canonicalise to (Var, Const), as we do for the `BinOp` whose overflow this
+                    // instruction checks.
+                    self.m.replace(iidx, OverflowInst::new(rhs, op, lhs).into());
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Optimise an `ICmp` if both sides are constants. It is required that [Opt::op_map] has been
     /// called on both `lhs` and `rhs` to obtain the `ConstIdx`s.
     fn opt_icmp_both_const(
@@ -758,34 +1261,34 @@ impl Opt {
                 // Constant fold comparisons of simple integers.
                 let r = match pred {
                     Predicate::Equal => {
-                        x.to_zero_ext_u64().unwrap() == y.to_zero_ext_u64().unwrap()
+                        x.to_zero_ext_u128().unwrap() == y.to_zero_ext_u128().unwrap()
                     }
                     Predicate::NotEqual => {
-                        x.to_zero_ext_u64().unwrap() != y.to_zero_ext_u64().unwrap()
+                        x.to_zero_ext_u128().unwrap() != y.to_zero_ext_u128().unwrap()
                     }
                     Predicate::UnsignedGreater => {
-                        x.to_zero_ext_u64().unwrap() > y.to_zero_ext_u64().unwrap()
+                        x.to_zero_ext_u128().unwrap() > y.to_zero_ext_u128().unwrap()
                     }
                     Predicate::UnsignedGreaterEqual => {
-                        x.to_zero_ext_u64().unwrap() >= y.to_zero_ext_u64().unwrap()
+                        x.to_zero_ext_u128().unwrap() >= y.to_zero_ext_u128().unwrap()
                     }
                     Predicate::UnsignedLess => {
-                        x.to_zero_ext_u64().unwrap() < y.to_zero_ext_u64().unwrap()
+                        x.to_zero_ext_u128().unwrap() < y.to_zero_ext_u128().unwrap()
                     }
                     Predicate::UnsignedLessEqual => {
-                        x.to_zero_ext_u64().unwrap() <= y.to_zero_ext_u64().unwrap()
+                        x.to_zero_ext_u128().unwrap() <= y.to_zero_ext_u128().unwrap()
                     }
                     Predicate::SignedGreater => {
-                        (x.to_sign_ext_i64().unwrap()) > (y.to_sign_ext_i64().unwrap())
+                        (x.to_sign_ext_i128().unwrap()) > (y.to_sign_ext_i128().unwrap())
                     }
                     Predicate::SignedGreaterEqual => {
-                        (x.to_sign_ext_i64().unwrap()) >= (y.to_sign_ext_i64().unwrap())
+                        (x.to_sign_ext_i128().unwrap()) >= (y.to_sign_ext_i128().unwrap())
                     }
                     Predicate::SignedLess => {
-                        (x.to_sign_ext_i64().unwrap()) < (y.to_sign_ext_i64().unwrap())
+                        (x.to_sign_ext_i128().unwrap()) < (y.to_sign_ext_i128().unwrap())
                     }
                     Predicate::SignedLessEqual => {
-                        (x.to_sign_ext_i64().unwrap()) <= (y.to_sign_ext_i64().unwrap())
+                        (x.to_sign_ext_i128().unwrap()) <= (y.to_sign_ext_i128().unwrap())
                     }
                 };
 
@@ -866,9 +1369,9 @@ impl Opt {
     }
 
     fn opt_load(&mut self, iidx: InstIdx, inst: LoadInst) -> Result<(), CompilationError> {
+        let tgt = self.an.op_map(&self.m, inst.ptr(&self.m));
+        let bytesize = Inst::Load(inst).def_byte_size(&self.m);
         if !inst.is_volatile() {
-            let tgt = self.an.op_map(&self.m, inst.ptr(&self.m));
-            let bytesize = Inst::Load(inst).def_byte_size(&self.m);
             match self.an.heapvalue(
                 &self.m,
                 Address::from_operand(&self.m, tgt.clone()),
@@ -877,7 +1380,7 @@ impl Opt {
                 None => {
                     self.an.push_heap_load(
                         &self.m,
-                        Address::from_operand(&self.m, tgt),
+                        Address::from_operand(&self.m, tgt.clone()),
                         Operand::Var(iidx),
                     );
                 }
@@ -898,10 +1401,14 @@ impl Opt {
                     // now, we simply don't optimise away the load.
                     if self.m.inst_nocopy(iidx).unwrap().tyidx(&self.m) == r_tyidx {
                         self.m.replace(iidx, r);
+                        return Ok(());
                     }
                 }
             };
         }
+        // The load remains in the trace, so stores to the bytes it reads can't be removed.
+        self.an
+            .heap_observe(&Address::from_operand(&self.m, tgt), bytesize);
         Ok(())
     }
 
@@ -993,6 +1500,24 @@ impl Opt {
         Ok(())
     }
 
+    fn opt_sitofp(&mut self, iidx: InstIdx, inst: SIToFPInst) -> Result<(), CompilationError> {
+        if let Operand::Const(cidx) = self.an.op_map(&self.m, inst.val(&self.m)) {
+            let Const::Int(_, x) = self.m.const_(cidx) else {
+                panic!()
+            };
+            let x = x.to_sign_ext_i64().unwrap();
+            // Rust's `as` rounds to the nearest representable value, as the machine instructions do.
+            let v = match self.m.type_(inst.dest_tyidx()) {
+                Ty::Float(FloatTy::Float) => f64::from(x as f32),
+                Ty::Float(FloatTy::Double) => x as f64,
+                _ => panic!(),
+            };
+            let cidx = self.m.insert_const(Const::Float(inst.dest_tyidx(), v))?;
+            self.m.replace(iidx, Inst::Const(cidx));
+        }
+        Ok(())
+    }
+
     fn opt_store(&mut self, iidx: InstIdx, inst: StoreInst) -> Result<(), CompilationError> {
         if !inst.is_volatile() {
             let tgt = self.an.op_map(&self.m, inst.ptr(&self.m));
@@ -1013,6 +1538,11 @@ impl Opt {
                             lhs == rhs
                         }
                         (Const::Ptr(lhs), Const::Ptr(rhs)) => lhs == rhs,
+                        (Const::Float(lhs_tyidx, lhs), Const::Float(rhs_tyidx, rhs)) => {
+                            debug_assert_eq!(lhs_tyidx, rhs_tyidx);
+                            // Storing `0.0` over `-0.0` (or one NaN over another) is not a no-op.
+                            lhs.to_bits() == rhs.to_bits()
+                        }
                         x => todo!("{x:?}"),
                     },
                 },
@@ -1020,9 +1550,23 @@ impl Opt {
             if is_dead {
                 self.m.replace(iidx, Inst::Tombstone);
             } else {
-                self.an
-                    .push_heap_store(&self.m, Address::from_operand(&self.m, tgt), val);
+                let addr = Address::from_operand(&self.m, tgt);
+                // Earlier stores whose bytes this store overwrites, without anything having had
+                // the chance to read them, are dead.
+                for dead_iidx in
+                    self.an
+                        .heap_pending_store(iidx, addr.clone(), val.byte_size(&self.m))
+                {
+                    self.m.replace(dead_iidx, Inst::Tombstone);
+                }
+                self.an.push_heap_store(&self.m, addr, val);
             }
+        } else {
+            // We don't remove volatile stores, nor the stores that precede them.
+            let tgt = self.an.op_map(&self.m, inst.ptr(&self.m));
+            let val = self.an.op_map(&self.m, inst.val(&self.m));
+            self.an
+                .heap_observe(&Address::from_operand(&self.m, tgt), val.byte_size(&self.m));
         }
 
         Ok(())
@@ -1047,6 +1591,23 @@ impl Opt {
         Ok(())
     }
 
+    fn opt_uitofp(&mut self, iidx: InstIdx, inst: UIToFPInst) -> Result<(), CompilationError> {
+        if let Operand::Const(cidx) = self.an.op_map(&self.m, inst.val(&self.m)) {
+            let Const::Int(_, x) = self.m.const_(cidx) else {
+                panic!()
+            };
+            let x = x.to_zero_ext_u64().unwrap();
+            let v = match self.m.type_(inst.dest_tyidx()) {
+                Ty::Float(FloatTy::Float) => f64::from(x as f32),
+                Ty::Float(FloatTy::Double) => x as f64,
+                _ => panic!(),
+            };
+            let cidx = self.m.insert_const(Const::Float(inst.dest_tyidx(), v))?;
+            self.m.replace(iidx, Inst::Const(cidx));
+        }
+        Ok(())
+    }
+
     fn opt_zext(&mut self, iidx: InstIdx, inst: ZExtInst) -> Result<(), CompilationError> {
         if let Operand::Const(cidx) = self.an.op_map(&self.m, inst.val(&self.m)) {
             let Const::Int(_src_ty, src_val) = self.m.const_(cidx) else {
@@ -1213,103 +1774,153 @@ mod test {
     }
 
     #[test]
-    fn opt_add_zero() {
+    fn opt_guard_relation() {
         Module::assert_ir_transform_eq(
             "
           entry:
             %0: i8 = param reg
-            %1: i8 = add %0, 0i8
-            black_box %1
+            %1: i8 = param reg
+            %2: i1 = ult %0, %1
+            guard true, %2, [%0, %1]
+            %4: i1 = ule %0, %1
+            guard true, %4, [%0, %1]
+            %6: i1 = ugt %1, %0
+            %7: i1 = uge %0, %1
+            %8: i1 = eq %0, %0
+            black_box %6
+            black_box %7
+            black_box %8
+            %12: i1 = sgt %0, %1
+            guard false, %12, [%0, %1]
+            %14: i1 = slt %1, %0
+            black_box %14
         ",
             |m| opt(m).unwrap(),
             "
           ...
           entry:
             %0: i8 = param ...
-            black_box %0
+            %1: i8 = param ...
+            %2: i1 = ult %0, %1
+            guard true, %2, ...
+            black_box 1i1
+            black_box 0i1
+            black_box 1i1
+            %12: i1 = sgt %0, %1
+            guard false, %12, ...
+            black_box 0i1
         ",
         );
     }
 
     #[test]
-    fn opt_add_const() {
+    fn opt_guard_and_or() {
         Module::assert_ir_transform_eq(
             "
           entry:
-            %0: i8 = 0i8
-            %1: i8 = add %0, 1i8
-            %2: i64 = 18446744073709551614i64
-            %3: i64 = add %2, 4i64
-            black_box %1
-            black_box %3
+            %0: i8 = param reg
+            %1: i8 = param reg
+            %2: i8 = param reg
+            %3: i1 = eq %0, 3i8
+            %4: i1 = ult %1, 10i8
+            %5: i1 = and %3, %4
+            guard true, %5, [%0, %1, %2]
+            %7: i8 = add %0, 1i8
+            black_box %7
+            guard true, %4, [%0, %1, %2]
+            %10: i1 = eq %2, 0i8
+            %11: i1 = ugt %2, 20i8
+            %12: i1 = or %10, %11
+            guard false, %12, [%0, %1, %2]
+            %14: i1 = ule %2, 20i8
+            black_box %14
         ",
             |m| opt(m).unwrap(),
             "
           ...
           entry:
-            black_box 1i8
-            black_box 2i64
+            %0: i8 = param ...
+            %1: i8 = param ...
+            %2: i8 = param ...
+            %3: i1 = eq %0, 3i8
+            %4: i1 = ult %1, 10i8
+            %5: i1 = and %3, %4
+            guard true, %5, ...
+            black_box 4i8
+            %10: i1 = eq %2, 0i8
+            %11: i1 = ugt %2, 20i8
+            %12: i1 = or %10, %11
+            guard false, %12, ...
+            black_box 1i1
         ",
         );
     }
 
     #[test]
-    fn opt_sub_const() {
+    fn opt_guard_strengthen() {
         Module::assert_ir_transform_eq(
             "
           entry:
             %0: i8 = param reg
-            %1: i8 = 0i8
-            %2: i8 = sub %1, 1i8
-            %3: i64 = 18446744073709551614i64
-            %4: i64 = sub %3, 4i64
-            %5: i8 = sub %0, 0i8
-            %6: i8 = sub 0i8, %0
-            black_box %2
-            black_box %4
-            black_box %5
+            %1: i8 = param reg
+            %2: i1 = ult %0, 20i8
+            guard true, %2, [%0, %1]
+            %4: i1 = sgt %1, 5i8
+            guard false, %4, [%0, %1]
+            %6: i8 = add %0, %1
             black_box %6
+            %8: i1 = uge %0, 10i8
+            guard false, %8, [%0, %1]
+            %10: i1 = slt %1, 3i8
+            guard true, %10, [%0, %1]
         ",
             |m| opt(m).unwrap(),
             "
           ...
           entry:
             %0: i8 = param ...
-            %6: i8 = sub 0i8, %0
-            black_box 255i8
-            black_box 18446744073709551610i64
-            black_box %0
+            %1: i8 = param ...
+            %2: i1 = ult %0, 10i8
+            guard true, %2, ...
+            %4: i1 = sge %1, 3i8
+            guard false, %4, ...
+            %6: i8 = add %0, %1
             black_box %6
         ",
         );
-    }
 
-    #[test]
-    fn opt_and_zero() {
+        // If the earlier guard's condition is used elsewhere, it can't be strengthened.
         Module::assert_ir_transform_eq(
             "
           entry:
             %0: i8 = param reg
-            %1: i8 = and %0, 0i8
+            %1: i1 = ult %0, 20i8
             black_box %1
+            guard true, %1, [%0]
+            %4: i1 = ult %0, 10i8
+            guard true, %4, [%0]
         ",
             |m| opt(m).unwrap(),
             "
           ...
           entry:
             %0: i8 = param ...
-            black_box 0i8
+            %1: i1 = ult %0, 20i8
+            black_box %1
+            guard true, %1, ...
+            %4: i1 = ult %0, 10i8
+            guard true, %4, ...
         ",
         );
     }
 
     #[test]
-    fn opt_and_self() {
+    fn opt_add_zero() {
         Module::assert_ir_transform_eq(
             "
           entry:
             %0: i8 = param reg
-            %1: i8 = and %0, %0
+            %1: i8 = add %0, 0i8
             black_box %1
         ",
             |m| opt(m).unwrap(),
@@ -1317,13 +1928,104 @@ mod test {
           ...
           entry:
             %0: i8 = param ...
-            black_box 1i1
+            black_box %0
         ",
         );
     }
 
     #[test]
-    fn opt_and_const() {
+    fn opt_add_const() {
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: i8 = 0i8
+            %1: i8 = add %0, 1i8
+            %2: i64 = 18446744073709551614i64
+            %3: i64 = add %2, 4i64
+            black_box %1
+            black_box %3
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            black_box 1i8
+            black_box 2i64
+        ",
+        );
+    }
+
+    #[test]
+    fn opt_sub_const() {
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: i8 = param reg
+            %1: i8 = 0i8
+            %2: i8 = sub %1, 1i8
+            %3: i64 = 18446744073709551614i64
+            %4: i64 = sub %3, 4i64
+            %5: i8 = sub %0, 0i8
+            %6: i8 = sub 0i8, %0
+            black_box %2
+            black_box %4
+            black_box %5
+            black_box %6
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: i8 = param ...
+            %6: i8 = sub 0i8, %0
+            black_box 255i8
+            black_box 18446744073709551610i64
+            black_box %0
+            black_box %6
+        ",
+        );
+    }
+
+    #[test]
+    fn opt_and_zero() {
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: i8 = param reg
+            %1: i8 = and %0, 0i8
+            black_box %1
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: i8 = param ...
+            black_box 0i8
+        ",
+        );
+    }
+
+    #[test]
+    fn opt_and_self() {
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: i8 = param reg
+            %1: i8 = and %0, %0
+            black_box %1
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: i8 = param ...
+            black_box 1i1
+        ",
+        );
+    }
+
+    #[test]
+    fn opt_and_const() {
         Module::assert_ir_transform_eq(
             "
           entry:
@@ -1793,6 +2495,41 @@ mod test {
         );
     }
 
+    #[test]
+    fn opt_i128() {
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: i128 = param reg
+            %1: i128 = add 18446744073709551615i128, 1i128
+            %2: i128 = mul %0, 1267650600228229401496703205376i128
+            %3: i1 = slt -1i128, 0i128
+            %4: i1 = ugt -1i128, 0i128
+            %5: i128 = and %0, 0i128
+            %6: i64 = trunc %1
+            black_box %1
+            black_box %2
+            black_box %3
+            black_box %4
+            black_box %5
+            black_box %6
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: i128 = param ...
+            %2: i128 = shl %0, 100i128
+            black_box 18446744073709551616i128
+            black_box %2
+            black_box 1i1
+            black_box 1i1
+            black_box 0i128
+            black_box 0i64
+        ",
+        );
+    }
+
     #[test]
     fn opt_xor_self() {
         Module::assert_ir_transform_eq(
@@ -2346,6 +3083,107 @@ mod test {
         );
     }
 
+    #[test]
+    fn opt_pure_intrinsics() {
+        Module::assert_ir_transform_eq(
+            "
+          func_decl llvm.umax.i8 (i8, i8) -> i8
+          func_decl llvm.umin.i32 (i32, i32) -> i32
+          func_decl llvm.ctlz.i16 (i16, i1) -> i16
+          func_decl llvm.cttz.i32 (i32, i1) -> i32
+          func_decl llvm.bswap.i32 (i32) -> i32
+          func_decl llvm.fshl.i8 (i8, i8, i8) -> i8
+          func_decl llvm.fshr.i8 (i8, i8, i8) -> i8
+          func_decl llvm.sqrt.f64 (double) -> double
+          func_decl llvm.fabs.f32 (float) -> float
+          func_decl llvm.ceil.f64 (double) -> double
+          func_decl llvm.fma.f64 (double, double, double) -> double
+
+          entry:
+            %0: i8 = call @llvm.umax.i8(200i8, 3i8)
+            %1: i32 = call @llvm.umin.i32(-1i32, 7i32)
+            %2: i16 = call @llvm.ctlz.i16(1i16, 0i1)
+            %3: i16 = call @llvm.ctlz.i16(0i16, 0i1)
+            %4: i32 = call @llvm.cttz.i32(8i32, 0i1)
+            %5: i32 = call @llvm.cttz.i32(0i32, 0i1)
+            %6: i32 = call @llvm.bswap.i32(305419896i32)
+            %7: i8 = call @llvm.fshl.i8(18i8, 52i8, 12i8)
+            %8: i8 = call @llvm.fshr.i8(18i8, 52i8, 3i8)
+            %9: double = call @llvm.sqrt.f64(2.25double)
+            %10: float = call @llvm.fabs.f32(-1.5float)
+            %11: double = call @llvm.ceil.f64(-1.5double)
+            %12: double = call @llvm.fma.f64(2double, 3double, 1double)
+            black_box %0
+            black_box %1
+            black_box %2
+            black_box %3
+            black_box %4
+            black_box %5
+            black_box %6
+            black_box %7
+            black_box %8
+            black_box %9
+            black_box %10
+            black_box %11
+            black_box %12
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            black_box 200i8
+            black_box 7i32
+            black_box 15i16
+            black_box 16i16
+            black_box 3i32
+            black_box 32i32
+            black_box 2018915346i32
+            black_box 35i8
+            black_box 70i8
+            black_box 1.5double
+            black_box 1.5float
+            black_box -1double
+            black_box 7double
+        ",
+        );
+
+        // Pure intrinsics don't act as heap barriers.
+        Module::assert_ir_transform_eq(
+            "
+          func_decl llvm.umax.i8 (i8, i8) -> i8
+          func_decl llvm.memmove.p0.p0.i64 (ptr, ptr, i64, i1)
+
+          entry:
+            %0: i8 = param reg
+            %1: ptr = param reg
+            %2: i8 = load %1
+            %3: i8 = call @llvm.umax.i8(%0, 3i8)
+            %4: i8 = load %1
+            call @llvm.memmove.p0.p0.i64(%1, %1, 1i64, 0i1)
+            %6: i8 = load %1
+            black_box %2
+            black_box %3
+            black_box %4
+            black_box %6
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: i8 = param ...
+            %1: ptr = param ...
+            %2: i8 = load %1
+            %3: i8 = call @llvm.umax.i8(%0, 3i8)
+            call @llvm.memmove.p0.p0.i64(%1, %1, 1i64, 0i1)
+            %6: i8 = load %1
+            black_box %2
+            black_box %3
+            black_box %2
+            black_box %6
+        ",
+        );
+    }
+
     #[test]
     fn opt_indirect_call() {
         Module::assert_ir_transform_eq(
@@ -2479,101 +3317,568 @@ mod test {
     }
 
     #[test]
-    fn opt_peeling_simple() {
+    fn opt_guard_implied() {
         Module::assert_ir_transform_eq(
             "
           entry:
-            %0: i8 = param reg
-            header_start [%0]
-            %2: i8 = add %0, 1i8
-            header_end [%2]
+            %0: i32 = param reg
+            %1: i1 = ult %0, 100i32
+            guard true, %1, [%0]
+            %3: i1 = ult %0, 200i32
+            guard true, %3, [%0]
+            %5: i1 = sgt %0, -1i32
+            %6: i1 = eq %0, 150i32
+            black_box %5
+            black_box %6
+            %9: i1 = ult %0, 50i32
+            guard false, %9, [%0]
+            %11: i1 = uge %0, 50i32
+            %12: i32 = %11 ? 1i32 : 2i32
+            black_box %12
+            %14: i1 = ne %0, 99i32
+            guard true, %14, [%0]
+            %16: i1 = ule %0, 98i32
+            guard true, %16, [%0]
         ",
             |m| opt(m).unwrap(),
             "
           ...
           entry:
-            %0: i8 = param ...
-            header_start [%0]
-            %2: i8 = add %0, 1i8
-            header_end [%2]
-            %4: i8 = param ...
-            body_start [%4]
-            %6: i8 = add %4, 1i8
-            body_end [%6]
+            %0: i32 = param ...
+            %1: i1 = ult %0, 100i32
+            guard true, %1, ...
+            black_box 1i1
+            black_box 0i1
+            %9: i1 = ult %0, 50i32
+            guard false, %9, ...
+            black_box 1i32
+            %14: i1 = ne %0, 99i32
+            guard true, %14, ...
         ",
         );
     }
 
     #[test]
-    fn opt_peeling_heap() {
-        // Loads
+    fn opt_icmp_known_bits() {
         Module::assert_ir_transform_eq(
             "
           entry:
-            %0: ptr = param reg
-            header_start [%0]
-            %2: i8 = load %0
-            %3: i1 = eq %2, 1i8
-            guard true, %3, []
-            %5: ptr = ptr_add %0, 1
-            %6: i8 = load %5
-            black_box %2
+            %0: i32 = param reg
+            %1: i1 = param reg
+            %2: i32 = and %0, 240i32
+            %3: i1 = eq %2, 7i32
+            %4: i1 = ugt %2, 240i32
+            %5: i64 = zext %2
+            %6: i1 = ult %5, 256i64
+            %7: i32 = shl %2, 4i32
+            %8: i1 = ule %7, 3840i32
+            %9: i32 = lshr %0, 28i32
+            %10: i1 = slt %9, 16i32
+            %11: i32 = %1 ? 1i32 : 3i32
+            %12: i1 = ne %11, 2i32
+            %13: i8 = trunc %2
+            %14: i1 = sge %13, 0i8
+            black_box %3
+            black_box %4
             black_box %6
-            header_end [%0]
+            black_box %8
+            black_box %10
+            black_box %12
+            black_box %14
         ",
             |m| opt(m).unwrap(),
             "
           ...
-            body_start [%10]
-            %15: ptr = ptr_add %10, 1
-            %16: i8 = load %15
-            black_box 1i8
-            black_box %16
-            body_end [%10]
+          entry:
+            %0: i32 = param ...
+            %1: i1 = param ...
+            %2: i32 = and %0, 240i32
+            %13: i8 = trunc %2
+            %14: i1 = sge %13, 0i8
+            black_box 0i1
+            black_box 0i1
+            black_box 1i1
+            black_box 1i1
+            black_box 1i1
+            black_box 1i1
+            black_box %14
         ",
         );
+    }
 
-        // Stores
+    #[test]
+    fn opt_overflow_const() {
         Module::assert_ir_transform_eq(
             "
           entry:
-            %0: ptr = param reg
-            %1: i8 = param reg
-            header_start [%0, %1]
-            *%0 = 1i8
-            %4: i8 = add %1, 1i8
-            %5: ptr = ptr_add %0, 4
-            *%5 = %4
-            header_end [%0, %4]
+            %0: i1 = sadd_overflow 100i8, 27i8
+            %1: i1 = sadd_overflow 100i8, 28i8
+            %2: i1 = uadd_overflow 200i8, 55i8
+            %3: i1 = uadd_overflow 200i8, 56i8
+            %4: i1 = ssub_overflow -100i8, 28i8
+            %5: i1 = ssub_overflow -100i8, 29i8
+            %6: i1 = usub_overflow 3i8, 3i8
+            %7: i1 = usub_overflow 3i8, 4i8
+            %8: i1 = smul_overflow -64i8, 2i8
+            %9: i1 = smul_overflow -128i8, -1i8
+            %10: i1 = umul_overflow 15i8, 17i8
+            %11: i1 = umul_overflow 16i8, 16i8
+            black_box %0
+            black_box %1
+            black_box %2
+            black_box %3
+            black_box %4
+            black_box %5
+            black_box %6
+            black_box %7
+            black_box %8
+            black_box %9
+            black_box %10
+            black_box %11
         ",
             |m| opt(m).unwrap(),
             "
           ...
-            body_start [%8, %9]
-            %12: i8 = add %9, 1i8
-            %13: ptr = ptr_add %8, 4
-            *%13 = %12
-            body_end [%8, %12]
-        ",
-        );
-
-        // Intermediate updates
-        Module::assert_ir_transform_eq(
-            "
           entry:
-            %0: ptr = param reg
-            header_start [%0]
-            *%0 = 1i8
-            *%0 = 2i8
-            *%0 = 1i8
+            black_box 0i1
+            black_box 1i1
+            black_box 0i1
+            black_box 1i1
+            black_box 0i1
+            black_box 1i1
+            black_box 0i1
+            black_box 1i1
+            black_box 0i1
+            black_box 1i1
+            black_box 0i1
+            black_box 1i1
+        ",
+        );
+    }
+
+    #[test]
+    fn opt_overflow() {
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: i32 = param reg
+            %1: i32 = and %0, 255i32
+            %2: i32 = add %1, 1i32
+            %3: i1 = sadd_overflow %1, 1i32
+            guard false, %3, [%0, %2]
+            %5: i1 = usub_overflow %1, 256i32
+            %6: i1 = smul_overflow 3i32, %0
+            %7: i1 = ssub_overflow 3i32, %0
+            black_box %2
+            black_box %5
+            black_box %6
+            black_box %7
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: i32 = param ...
+            %1: i32 = and %0, 255i32
+            %2: i32 = add %1, 1i32
+            %6: i1 = smul_overflow %0, 3i32
+            %7: i1 = ssub_overflow 3i32, %0
+            black_box %2
+            black_box 1i1
+            black_box %6
+            black_box %7
+        ",
+        );
+    }
+
+    #[test]
+    fn opt_float_const() {
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: double = fadd 1.5double, 2double
+            %1: double = fadd 0.1double, 0.2double
+            %2: float = fadd 0.1float, 0.2float
+            %3: float = fsub 1float, 3float
+            %4: double = fmul 2double, -0.5double
+            %5: double = fdiv 1double, 4double
+            %6: double = frem 7double, 2double
+            %7: double = fdiv 0double, 0double
+            black_box %0
+            black_box %1
+            black_box %2
+            black_box %3
+            black_box %4
+            black_box %5
+            black_box %6
+            black_box %7
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            black_box 3.5double
+            black_box 0.30000000000000004double
+            black_box 0.3float
+            black_box -2float
+            black_box -1double
+            black_box 0.25double
+            black_box 1double
+            black_box NaNdouble
+        ",
+        );
+    }
+
+    #[test]
+    fn opt_float_identities() {
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: double = param reg
+            %1: double = fmul %0, 1double
+            %2: double = fdiv %0, 1double
+            %3: double = fadd %0, -0double
+            %4: double = fsub %0, 0double
+            %5: double = fadd %0, 0double
+            %6: double = fsub %0, -0double
+            %7: double = fmul 2double, %0
+            %8: double = fneg %0
+            %9: double = fneg %8
+            black_box %1
+            black_box %2
+            black_box %3
+            black_box %4
+            black_box %5
+            black_box %6
+            black_box %7
+            black_box %9
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: double = param ...
+            %5: double = fadd %0, 0double
+            %6: double = fsub %0, -0double
+            %7: double = fmul %0, 2double
+            black_box %0
+            black_box %0
+            black_box %0
+            black_box %0
+            black_box %5
+            black_box %6
+            black_box %7
+            black_box %0
+        ",
+        );
+    }
+
+    #[test]
+    fn opt_fcmp_const() {
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: double = fdiv 0double, 0double
+            %1: i1 = f_oeq 1double, 1double
+            %2: i1 = f_olt 2double, 1double
+            %3: i1 = f_oeq -0double, 0double
+            %4: i1 = f_uno %0, 1double
+            %5: i1 = f_one %0, 1double
+            %6: i1 = f_une %0, 1double
+            %7: i1 = f_ord 1float, 2float
+            %8: i1 = f_ule %0, 1double
+            black_box %1
+            black_box %2
+            black_box %3
+            black_box %4
+            black_box %5
+            black_box %6
+            black_box %7
+            black_box %8
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            black_box 1i1
+            black_box 0i1
+            black_box 1i1
+            black_box 1i1
+            black_box 0i1
+            black_box 1i1
+            black_box 1i1
+            black_box 1i1
+        ",
+        );
+    }
+
+    #[test]
+    fn opt_guard_fcmp() {
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: double = param reg
+            %1: double = param reg
+            %2: i1 = f_oeq %0, 2.5double
+            guard true, %2, [%0, %1]
+            %4: double = fadd %0, 1double
+            black_box %4
+            %6: i1 = f_une %1, 0double
+            guard false, %6, [%0, %1]
+            %8: double = fadd %1, 1double
+            black_box %8
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: double = param ...
+            %1: double = param ...
+            %2: i1 = f_oeq %0, 2.5double
+            guard true, %2, ...
+            black_box 3.5double
+            %6: i1 = f_une %1, 0double
+            guard false, %6, ...
+            %8: double = fadd %1, 1double
+            black_box %8
+        ",
+        );
+    }
+
+    #[test]
+    fn opt_float_conversions() {
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: float = si_to_fp -3i32
+            %1: double = ui_to_fp 255i8
+            %2: double = fp_ext 1.5float
+            %3: i32 = fp_to_si -2.7double
+            %4: i8 = fp_to_si 300double
+            %5: double = fneg 1.5double
+            %6: float = si_to_fp 16777217i32
+            %7: float = fp_trunc 0.1double
+            %8: i64 = fp_to_ui 18446744073709549568double
+            %9: i8 = fp_to_ui -0.5double
+            %10: i8 = fp_to_ui 256double
+            %11: i8 = fp_to_ui -1double
+            black_box %0
+            black_box %1
+            black_box %2
+            black_box %3
+            black_box %4
+            black_box %5
+            black_box %6
+            black_box %7
+            black_box %8
+            black_box %9
+            black_box %10
+            black_box %11
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %4: i8 = fp_to_si 300double
+            %10: i8 = fp_to_ui 256double
+            %11: i8 = fp_to_ui -1double
+            black_box -3float
+            black_box 255double
+            black_box 1.5double
+            black_box 4294967294i32
+            black_box %4
+            black_box -1.5double
+            black_box 16777216float
+            black_box 0.1float
+            black_box 18446744073709549568i64
+            black_box 0i8
+            black_box %10
+            black_box %11
+        ",
+        );
+    }
+
+    #[test]
+    fn opt_peeling_guard_implied() {
+        // Guards in the peeled body can't rely on what was learnt in the header, but they can rely
+        // on what was learnt earlier in the body.
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: i32 = param reg
+            header_start [%0]
+            %2: i1 = ult %0, 100i32
+            guard true, %2, [%0]
+            %4: i32 = add %0, 1i32
+            %5: i1 = ult %4, 101i32
+            guard true, %5, [%0]
+            header_end [%4]
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: i32 = param ...
+            header_start [%0]
+            %2: i1 = ult %0, 100i32
+            guard true, %2, ...
+            %4: i32 = add %0, 1i32
+            header_end [%4]
+            %{{8}}: i32 = param ...
+            body_start [%{{8}}]
+            %{{10}}: i1 = ult %{{8}}, 100i32
+            guard true, %{{10}}, ...
+            %{{12}}: i32 = add %{{8}}, 1i32
+            body_end [%{{12}}]
+        ",
+        );
+    }
+
+    #[test]
+    fn opt_peeling_guard_invariant() {
+        // What the header learns about loop invariant variables also holds in the body.
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: i8 = param reg
+            %1: i8 = param reg
+            %2: i8 = param reg
+            header_start [%0, %1, %2]
+            %4: i1 = ult %1, 10i8
+            guard true, %4, [%0, %1, %2]
+            %6: i1 = slt %0, %1
+            guard true, %6, [%0, %1, %2]
+            %8: i1 = ne %1, %2
+            guard true, %8, [%0, %1, %2]
+            %10: i8 = add %0, %1
+            header_end [%10, %1, %2]
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: i8 = param ...
+            %1: i8 = param ...
+            %2: i8 = param ...
+            header_start [%0, %1, %2]
+            %4: i1 = ult %1, 10i8
+            guard true, %4, ...
+            %6: i1 = slt %0, %1
+            guard true, %6, ...
+            %8: i1 = ne %1, %2
+            guard true, %8, ...
+            %10: i8 = add %0, %1
+            header_end [%10, %1, %2]
+            %{{12}}: i8 = param ...
+            %{{13}}: i8 = param ...
+            %{{14}}: i8 = param ...
+            body_start [%{{12}}, %{{13}}, %{{14}}]
+            %{{18}}: i1 = slt %{{12}}, %{{13}}
+            guard true, %{{18}}, ...
+            %{{22}}: i8 = add %{{12}}, %{{13}}
+            body_end [%{{22}}, %{{13}}, %{{14}}]
+        ",
+        );
+    }
+
+    #[test]
+    fn opt_peeling_simple() {
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: i8 = param reg
+            header_start [%0]
+            %2: i8 = add %0, 1i8
+            header_end [%2]
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: i8 = param ...
+            header_start [%0]
+            %2: i8 = add %0, 1i8
+            header_end [%2]
+            %4: i8 = param ...
+            body_start [%4]
+            %6: i8 = add %4, 1i8
+            body_end [%6]
+        ",
+        );
+    }
+
+    #[test]
+    fn opt_peeling_heap() {
+        // Loads
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: ptr = param reg
+            header_start [%0]
+            %2: i8 = load %0
+            %3: i1 = eq %2, 1i8
+            guard true, %3, []
+            %5: ptr = ptr_add %0, 1
+            %6: i8 = load %5
+            black_box %2
+            black_box %6
             header_end [%0]
         ",
             |m| opt(m).unwrap(),
             "
           ...
+            body_start [%10]
+            %15: ptr = ptr_add %10, 1
+            %16: i8 = load %15
+            black_box 1i8
+            black_box %16
+            body_end [%10]
+        ",
+        );
+
+        // Stores
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: ptr = param reg
+            %1: i8 = param reg
+            header_start [%0, %1]
+            *%0 = 1i8
+            %4: i8 = add %1, 1i8
+            %5: ptr = ptr_add %0, 4
+            *%5 = %4
+            header_end [%0, %4]
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+            body_start [%8, %9]
+            %12: i8 = add %9, 1i8
+            %13: ptr = ptr_add %8, 4
+            *%13 = %12
+            body_end [%8, %12]
+        ",
+        );
+
+        // Intermediate updates
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: ptr = param reg
+            header_start [%0]
+            *%0 = 1i8
+            *%0 = 2i8
+            *%0 = 1i8
+            header_end [%0]
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+            header_start [%0]
+            *%0 = 1i8
+            header_end [%0]
+            %{{6}}: ptr = param ...
             body_start [%{{6}}]
-            *%{{6}} = 2i8
-            *%{{6}} = 1i8
             body_end [%{{6}}]
         ",
         );
@@ -2592,8 +3897,8 @@ mod test {
             "
           ...
             body_start [%5]
-            *%5 = 0i8
             %8: ptr = ptr_add %5, 1
+            *%5 = 0i8
             body_end [%8]
         ",
         );
@@ -2770,7 +4075,6 @@ mod test {
           entry:
             %0: ptr = param ...
             %1: i8 = load %0
-            *%0 = 3i8
             *%0 = %1
             black_box %1
         ",
@@ -2817,7 +4121,6 @@ mod test {
             %1: i8 = load %0
             %2: i1 = eq %1, 3i8
             guard true, %2, ...
-            *%0 = 2i8
             *%0 = 3i8
         ",
         );
@@ -2911,13 +4214,339 @@ mod test {
           entry:
             %0: ptr = param ...
             %1: ptr = ptr_add %0, 4
-            *%1 = 1i32
             *%0 = 1i64
             *%1 = 1i32
         ",
         );
     }
 
+    #[test]
+    fn opt_dead_store_observed() {
+        // A load, call, or guard between two stores to the same address may observe the first.
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: ptr = param reg
+            %1: ptr = param reg
+            *%0 = 1i8
+            %3: i8 = load %1
+            *%0 = 2i8
+            black_box %3
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: ptr = param ...
+            %1: ptr = param ...
+            *%0 = 1i8
+            %3: i8 = load %1
+            *%0 = 2i8
+            black_box %3
+        ",
+        );
+
+        Module::assert_ir_transform_eq(
+            "
+          func_decl f()
+
+          entry:
+            %0: ptr = param reg
+            *%0 = 1i8
+            call @f()
+            *%0 = 2i8
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: ptr = param ...
+            *%0 = 1i8
+            call @f()
+            *%0 = 2i8
+        ",
+        );
+
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: ptr = param reg
+            %1: i1 = param reg
+            *%0 = 1i8
+            guard true, %1, []
+            *%0 = 2i8
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: ptr = param ...
+            %1: i1 = param ...
+            *%0 = 1i8
+            guard true, %1, ...
+            *%0 = 2i8
+        ",
+        );
+
+        // A store which only partly overwrites an earlier store doesn't kill it.
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: ptr = param reg
+            *%0 = 1i64
+            *%0 = 2i8
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: ptr = param ...
+            *%0 = 1i64
+            *%0 = 2i8
+        ",
+        );
+    }
+
+    #[test]
+    fn opt_dead_store_non_escaping() {
+        // Calls can't observe stores to allocations which don't escape. Since the allocation is
+        // of a non-constant size, it isn't removed entirely.
+        Module::assert_ir_transform_eq(
+            "
+          func_decl yk_allocator malloc(i64) -> ptr
+          func_decl f()
+
+          entry:
+            %0: i8 = param reg
+            %1: i64 = param reg
+            %2: ptr = call @malloc(%1)
+            *%2 = 1i8
+            call @f()
+            *%2 = %0
+            %6: i8 = load %2
+            black_box %6
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: i8 = param ...
+            %1: i64 = param ...
+            %2: ptr = call @malloc(%1)
+            call @f()
+            *%2 = %0
+            black_box %0
+        ",
+        );
+    }
+
+    #[test]
+    fn opt_sink_body_stores() {
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: ptr = param reg
+            %1: i8 = param reg
+            header_start [%0, %1]
+            *%0 = %1
+            %4: i8 = add %1, 1i8
+            %5: i8 = mul %4, 3i8
+            header_end [%0, %5]
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+            body_start [%{{0}}, %{{1}}]
+            %{{2}}: i8 = add %{{1}}, 1i8
+            %{{3}}: i8 = mul %{{2}}, 3i8
+            *%{{0}} = %{{1}}
+            body_end [%{{0}}, %{{3}}]
+        ",
+        );
+
+        // Stores can't be moved past a guard, nor past a load which may read them.
+        Module::assert_ir_transform_eq(
+            "
+          entry:
+            %0: ptr = param reg
+            %1: ptr = param reg
+            %2: i8 = param reg
+            header_start [%0, %1, %2]
+            *%0 = %2
+            %5: i8 = load %1
+            %6: i1 = eq %5, 0i8
+            guard true, %6, []
+            *%1 = %2
+            %9: i8 = add %2, 1i8
+            header_end [%0, %1, %9]
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+            body_start [%{{0}}, %{{1}}, %{{2}}]
+            *%{{0}} = %{{2}}
+            %{{5}}: i8 = load %{{1}}
+            %{{6}}: i1 = eq %{{5}}, 0i8
+            guard true, %{{6}}, ...
+            %{{9}}: i8 = add %{{2}}, 1i8
+            *%{{1}} = %{{2}}
+            body_end [%{{0}}, %{{1}}, %{{9}}]
+        ",
+        );
+    }
+
+    #[test]
+    fn opt_virtualise_simple() {
+        Module::assert_ir_transform_eq(
+            "
+          func_decl yk_allocator malloc(i64) -> ptr
+
+          entry:
+            %0: i64 = param reg
+            %1: ptr = call @malloc(16i64)
+            %2: i1 = eq %1, 0x0
+            guard false, %2, [%0]
+            %4: ptr = ptr_add %1, 8
+            *%1 = %0
+            *%4 = 3i64
+            %7: i64 = load %1
+            %8: i64 = load %4
+            %9: i64 = add %7, %8
+            black_box %9
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: i64 = param ...
+            %9: i64 = add %0, 3i64
+            black_box %9
+        ",
+        );
+    }
+
+    #[test]
+    fn opt_virtualise_guard() {
+        Module::assert_ir_transform_eq(
+            "
+          func_decl yk_allocator malloc(i64) -> ptr
+
+          entry:
+            %0: i64 = param reg
+            %1: i1 = param reg
+            %2: i1 = param reg
+            %3: ptr = call @malloc(16i64)
+            %4: ptr = ptr_add %3, 8
+            *%3 = %0
+            guard true, %1, [%3]
+            *%4 = 7i64
+            *%3 = 8i64
+            guard true, %2, [%0, %3]
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: i64 = param ...
+            %1: i1 = param ...
+            %2: i1 = param ...
+            guard true, %1, [0:%0_0: %3] virtuals [%3 = @malloc(16) {0: %0}] ...
+            guard true, %2, [0:%0_1: %0, 0:%0_2: %3] virtuals [%3 = @malloc(16) {0: 8i64, 8: 7i64}] ...
+        ",
+        );
+    }
+
+    #[test]
+    fn opt_virtualise_escape() {
+        // Allocations which don't escape are unaffected by stores to other pointers and by calls.
+        Module::assert_ir_transform_eq(
+            "
+          func_decl yk_allocator malloc(i64) -> ptr
+          func_decl f(ptr)
+
+          entry:
+            %0: ptr = param reg
+            %1: ptr = call @malloc(8i64)
+            %2: ptr = call @malloc(8i64)
+            *%1 = 1i64
+            *%0 = %2
+            call @f(%0)
+            %6: i64 = load %1
+            black_box %6
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: ptr = param ...
+            %2: ptr = call @malloc(8i64)
+            *%0 = %2
+            call @f(%0)
+            black_box 1i64
+        ",
+        );
+
+        // Allocations which escape, or are loaded from without a known value, are left alone.
+        Module::assert_ir_transform_eq(
+            "
+          func_decl yk_allocator malloc(i64) -> ptr
+          func_decl f(ptr)
+
+          entry:
+            %0: i1 = param reg
+            %1: ptr = call @malloc(8i64)
+            %2: ptr = call @malloc(16i64)
+            %3: ptr = call @malloc(8i64)
+            call @f(%1)
+            %5: ptr = ptr_add %2, 8
+            *%5 = 1i64
+            guard true, %0, [%5]
+            %8: i64 = load %3
+            black_box %8
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: i1 = param ...
+            %1: ptr = call @malloc(8i64)
+            %2: ptr = call @malloc(16i64)
+            %3: ptr = call @malloc(8i64)
+            call @f(%1)
+            %5: ptr = ptr_add %2, 8
+            *%5 = 1i64
+            guard true, %0, [0:%0_0: %5] ...
+            %8: i64 = load %3
+            black_box %8
+        ",
+        );
+
+        // Deopt can't materialise odd-width fields, so allocations with such stores are left
+        // alone.
+        Module::assert_ir_transform_eq(
+            "
+          func_decl yk_allocator malloc(i64) -> ptr
+
+          entry:
+            %0: i1 = param reg
+            %1: i24 = param reg
+            %2: ptr = call @malloc(8i64)
+            *%2 = %1
+            guard true, %0, [%2]
+        ",
+            |m| opt(m).unwrap(),
+            "
+          ...
+          entry:
+            %0: i1 = param ...
+            %1: i24 = param ...
+            %2: ptr = call @malloc(8i64)
+            *%2 = %1
+            guard true, %0, [0:%0_0: %2] ...
+        ",
+        );
+    }
+
     #[test]
     fn canonicalisation() {
         // Those that can be canonicalised
@@ -1,6 +1,16 @@
 //! The JIT IR trace builder.
 //!
 //! This takes in an (AOT IR, execution trace) pair and constructs a JIT IR trace from it.
+//!
+//! ## Integer widths
+//!
+//! AOT IR integers can be of any bit width, but JIT IR integers are always 1, 8, 16, 32, 64, or
+//! 128 bits wide. An AOT IR `iN` is represented by the narrowest JIT IR integer that can hold it
+//! (e.g. an `i24` by an `i32`, an `i96` by an `i128`): the bits above `N` in such a JIT IR integer
+//! are undefined. Whenever an operation's result depends on those bits (e.g. comparisons, right
+//! shifts, or division) we first zero or sign extend the value within its JIT IR integer. Loads
+//! and stores of such integers are split into pieces so that we access exactly the bytes that the
+//! AOT code would have.
 
 use super::aot_ir::{self, BBlockId, BinOp, Module};
 use super::YkSideTraceInfo;
@@ -14,9 +24,13 @@ use crate::{
     compile::{CompilationError, CompiledTrace},
     log::stats::TimingState,
     mt::{TraceId, MT},
-    trace::{AOTTraceIterator, TraceAction},
+    trace::{AOTTraceIterator, TraceAction, TracerKind},
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    ffi::CString,
+    sync::Arc,
 };
-use std::{collections::HashMap, ffi::CString, sync::Arc};
 use ykaddr::addr::symbol_to_ptr;
 
 /// Given an execution trace and AOT IR, creates a JIT IR trace.
@@ -27,6 +41,9 @@ pub(crate) struct TraceBuilder {
     jit_mod: jit_ir::Module,
     /// Maps an AOT instruction to a jit instruction via their index-based IDs.
     local_map: HashMap<aot_ir::InstId, jit_ir::Operand>,
+    /// Maps an AOT instruction which defines an aggregate (i.e. struct) value to the JIT IR
+    /// operands of each of the aggregate's fields.
+    aggregates: HashMap<aot_ir::InstId, Vec<jit_ir::Operand>>,
     /// BBlock containing the current control point (i.e. the control point that started this trace).
     cp_block: Option<aot_ir::BBlockId>,
     /// Inlined calls.
@@ -46,6 +63,11 @@ pub(crate) struct TraceBuilder {
     promotions: Box<[u8]>,
     /// The trace's current position in the promotions array.
     promote_idx: usize,
+    /// If the tracer recorded promoted values inline (see [TraceAction::Promotion]), the block
+    /// each value in [Self::promotions] was recorded in, and its size in bytes, in the order the
+    /// values were recorded. This allows us to check that we consume each promoted value in the
+    /// right place.
+    inline_promotions: VecDeque<(Option<BBlockId>, usize)>,
     /// The dynamically recorded debug strings, in the order that the corresponding
     /// `yk_debug_str()` calls were encountered in the trace.
     debug_strs: Vec<String>,
@@ -53,6 +75,8 @@ pub(crate) struct TraceBuilder {
     debug_str_idx: usize,
     /// Local variables that we have inferred to be constant.
     inferred_consts: HashMap<jit_ir::InstIdx, jit_ir::ConstIdx>,
+    /// The names of functions which the user has configured to be treated as allocators.
+    allocators: Vec<String>,
 }
 
 impl TraceBuilder {
@@ -64,7 +88,7 @@ impl TraceBuilder {
     ///  - `promotions`: Values promoted to constants during runtime.
     ///  - `debug_archors`: Debug strs recorded during runtime.
     fn new(
-        _mt: &Arc<MT>,
+        mt: &Arc<MT>,
         tracekind: TraceKind,
         aot_mod: &'static Module,
         ctrid: TraceId,
@@ -75,6 +99,7 @@ impl TraceBuilder {
             aot_mod,
             jit_mod: jit_ir::Module::new(tracekind, ctrid, aot_mod.global_decls_len())?,
             local_map: HashMap::new(),
+            aggregates: HashMap::new(),
             cp_block: None,
             // We have to insert a placeholder frame to represent the place we started tracing, as
             // we don't know where that is yet. We'll update it as soon as we do.
@@ -88,9 +113,11 @@ impl TraceBuilder {
             recursion_count: 0,
             promotions,
             promote_idx: 0,
+            inline_promotions: VecDeque::new(),
             debug_strs,
             debug_str_idx: 0,
             inferred_consts: HashMap::new(),
+            allocators: mt.allocators.clone(),
         })
     }
 
@@ -108,7 +135,8 @@ impl TraceBuilder {
                 }
             }
             TraceAction::UnmappableBBlock => None,
-            TraceAction::Promotion => todo!(),
+            // Promotions are removed from the trace before blocks are looked up.
+            TraceAction::Promotion(_) => unreachable!(),
         }
     }
 
@@ -147,6 +175,7 @@ impl TraceBuilder {
         for idx in 0..safepoint.lives.len() {
             let aot_op = &safepoint.lives[idx];
             let input_tyidx = self.handle_type(aot_op.type_(self.aot_mod))?;
+            self.check_live_tyidx(input_tyidx)?;
 
             // Get the location for this input variable.
             let var = &rec.live_vals[idx];
@@ -179,13 +208,21 @@ impl TraceBuilder {
                 aot_ir::Inst::Promote { tyidx, .. }
                 | aot_ir::Inst::IdempotentPromote { tyidx, .. } => {
                     // Consume the correct number of bytes from the promoted values array.
-                    self.promote_idx +=
-                        usize::try_from(self.aot_mod.type_(*tyidx).bytew()).unwrap();
+                    self.consume_promotion(
+                        bid,
+                        usize::try_from(self.aot_mod.type_(*tyidx).bytew()).unwrap(),
+                    )?;
                 }
                 aot_ir::Inst::DebugStr { .. } => {
                     // Skip this debug string.
                     self.debug_str_idx += 1;
                 }
+                aot_ir::Inst::Call { callee, .. }
+                    if self.aot_mod.func(*callee).name() == "yk_assume_constant" =>
+                {
+                    // Consume the recorded value of the quasi-constant.
+                    self.consume_promotion(bid, std::mem::size_of::<usize>())?;
+                }
                 _ => (),
             }
         }
@@ -238,7 +275,7 @@ impl TraceBuilder {
                     )
                 }
                 aot_ir::Inst::Store { tgt, val, volatile } => {
-                    self.handle_store(bid, iidx, tgt, val, *volatile)
+                    self.handle_store(tgt, val, *volatile)
                 }
                 aot_ir::Inst::PtrAdd {
                     ptr,
@@ -284,9 +321,9 @@ impl TraceBuilder {
                     case_dests,
                 ),
                 aot_ir::Inst::Phi {
+                    tyidx,
                     incoming_bbs,
                     incoming_vals,
-                    ..
                 } => {
                     assert_eq!(
                         prevbb.as_ref().map(|x| x.funcidx()),
@@ -298,6 +335,7 @@ impl TraceBuilder {
                     self.handle_phi(
                         bid,
                         iidx,
+                        *tyidx,
                         &prevbb.as_ref().unwrap().bbidx(),
                         incoming_bbs,
                         incoming_vals,
@@ -340,6 +378,12 @@ impl TraceBuilder {
                     let nextinst = blk.insts.last().unwrap();
                     self.handle_idempotent_promote(bid, iidx, val, nextinst)
                 }
+                aot_ir::Inst::InsertValue { .. } => {
+                    // The AOT IR doesn't record which field is being replaced.
+                    Err(CompilationError::General(
+                        "insertvalue is not supported".to_owned(),
+                    ))
+                }
                 _ => todo!("{:?}", inst),
             }?;
         }
@@ -400,34 +444,14 @@ impl TraceBuilder {
         let bytes = aot_const.bytes();
         match self.aot_mod.type_(aot_const.tyidx()) {
             aot_ir::Ty::Integer(x) => {
-                // FIXME: It would be better if the AOT IR had converted these integers in advance
-                // rather than doing this dance here.
-                let v = match x.bitw() {
-                    1 | 8 => {
-                        debug_assert_eq!(bytes.len(), 1);
-                        u64::from(bytes[0])
-                    }
-                    16 => {
-                        debug_assert_eq!(bytes.len(), 2);
-                        u64::from(u16::from_ne_bytes([bytes[0], bytes[1]]))
-                    }
-                    32 => {
-                        debug_assert_eq!(bytes.len(), 4);
-                        u64::from(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
-                    }
-                    64 => {
-                        debug_assert_eq!(bytes.len(), 8);
-                        u64::from_ne_bytes([
-                            bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6],
-                            bytes[7],
-                        ])
-                    }
-                    _ => todo!("{}", x.bitw()),
-                };
-                let jit_tyidx = self.jit_mod.insert_ty(jit_ir::Ty::Integer(x.bitw()))?;
+                let jit_tyidx = self.handle_type(self.aot_mod.type_(aot_const.tyidx()))?;
+                let jit_bitw = self.jit_mod.type_(jit_tyidx).bitw().unwrap();
+                // `handle_type` has already rejected integers wider than 128 bits, so the unwrap
+                // can't fail.
+                let v = aot_ir::const_int_bytes_to_u128(x.bitw(), bytes).unwrap();
                 Ok(jit_ir::Const::Int(
                     jit_tyidx,
-                    ArbBitInt::from_u64(x.bitw(), v),
+                    ArbBitInt::from_u128(jit_bitw, v),
                 ))
             }
             aot_ir::Ty::Float(fty) => {
@@ -487,18 +511,60 @@ impl TraceBuilder {
     fn handle_type(&mut self, aot_type: &aot_ir::Ty) -> Result<jit_ir::TyIdx, CompilationError> {
         let jit_ty = match aot_type {
             aot_ir::Ty::Void => jit_ir::Ty::Void,
-            aot_ir::Ty::Integer(x) => jit_ir::Ty::Integer(x.bitw()),
+            aot_ir::Ty::Integer(x) => {
+                jit_ir::Ty::Integer(jit_int_bitw(x.bitw()).ok_or_else(|| {
+                    CompilationError::General(format!("i{} integers are not supported", x.bitw()))
+                })?)
+            }
             aot_ir::Ty::Ptr => jit_ir::Ty::Ptr,
             aot_ir::Ty::Func(ft) => {
+                // Passing or returning other integer widths requires extending, splitting, or
+                // spilling values in ways that the code generator doesn't know about.
+                let ret_tyidxs = match self.aot_mod.type_(ft.ret_ty()) {
+                    aot_ir::Ty::Struct(st) => {
+                        (0..st.num_fields()).map(|i| st.field_tyidx(i)).collect()
+                    }
+                    _ => vec![ft.ret_ty()],
+                };
+                for tyidx in ft.arg_tyidxs().iter().chain(ret_tyidxs.iter()) {
+                    if let aot_ir::Ty::Integer(x) = self.aot_mod.type_(*tyidx)
+                        && !matches!(x.bitw(), 1 | 8 | 16 | 32 | 64)
+                    {
+                        return Err(CompilationError::General(format!(
+                            "calls with i{} arguments or return values are not supported",
+                            x.bitw()
+                        )));
+                    }
+                }
                 let mut jit_args = Vec::new();
                 for aot_arg_tyidx in ft.arg_tyidxs() {
                     let jit_ty = self.handle_type(self.aot_mod.type_(*aot_arg_tyidx))?;
                     jit_args.push(jit_ty);
                 }
-                let jit_retty = self.handle_type(self.aot_mod.type_(ft.ret_ty()))?;
+                let jit_retty = match self.aot_mod.type_(ft.ret_ty()) {
+                    // A call returning a two-field aggregate is scalarised into the call itself,
+                    // which returns the first field, and a [jit_ir::SecondRetValInst], which
+                    // returns the second field.
+                    aot_ir::Ty::Struct(st) if st.num_fields() == 2 => {
+                        self.handle_type(self.aot_mod.type_(st.field_tyidx(0)))?
+                    }
+                    aot_ir::Ty::Struct(st) => {
+                        return Err(CompilationError::General(format!(
+                            "calls returning {} are not supported",
+                            st.display(self.aot_mod)
+                        )));
+                    }
+                    x => self.handle_type(x)?,
+                };
                 jit_ir::Ty::Func(jit_ir::FuncTy::new(jit_args, jit_retty, ft.is_vararg()))
             }
-            aot_ir::Ty::Struct(_st) => todo!(),
+            aot_ir::Ty::Struct(st) => {
+                // Aggregates are scalarised into their fields, so there is no JIT IR struct type.
+                return Err(CompilationError::General(format!(
+                    "can't translate aggregate type {}",
+                    st.display(self.aot_mod)
+                )));
+            }
             aot_ir::Ty::Float(ft) => {
                 let inner = match ft {
                     aot_ir::FloatTy::Float => jit_ir::FloatTy::Float,
@@ -517,9 +583,11 @@ impl TraceBuilder {
         aot_idx: aot_ir::FuncIdx,
     ) -> Result<jit_ir::FuncDeclIdx, CompilationError> {
         let aot_func = self.aot_mod.func(aot_idx);
+        let is_allocator = self.allocators.iter().any(|x| x == aot_func.name());
         let jit_func = jit_ir::FuncDecl::new(
             aot_func.name().to_owned(),
             self.handle_type(self.aot_mod.type_(aot_func.tyidx()))?,
+            is_allocator,
         );
         self.jit_mod.insert_func_decl(jit_func)
     }
@@ -533,12 +601,123 @@ impl TraceBuilder {
         lhs: &aot_ir::Operand,
         rhs: &aot_ir::Operand,
     ) -> Result<(), CompilationError> {
-        let lhs = self.handle_operand(lhs)?;
-        let rhs = self.handle_operand(rhs)?;
-        let inst = jit_ir::BinOpInst::new(lhs, binop, rhs).into();
+        let jit_lhs = self.handle_operand(lhs)?;
+        let jit_rhs = self.handle_operand(rhs)?;
+        if matches!(binop, BinOp::SDiv | BinOp::SRem | BinOp::UDiv | BinOp::URem)
+            && jit_lhs.bitw(&self.jit_mod) > 64
+        {
+            return Err(CompilationError::General(format!(
+                "{binop} on integers wider than 64 bits is not supported"
+            )));
+        }
+        // Operations whose results depend on the undefined upper bits of their operands' JIT IR
+        // integers need those bits to be extended first.
+        let (jit_lhs, jit_rhs) = match binop {
+            BinOp::Shl => (jit_lhs, self.zext_in_place(rhs, jit_rhs)?),
+            BinOp::LShr | BinOp::UDiv | BinOp::URem => (
+                self.zext_in_place(lhs, jit_lhs)?,
+                self.zext_in_place(rhs, jit_rhs)?,
+            ),
+            BinOp::AShr => (
+                self.sext_in_place(lhs, jit_lhs)?,
+                self.zext_in_place(rhs, jit_rhs)?,
+            ),
+            BinOp::SDiv | BinOp::SRem => (
+                self.sext_in_place(lhs, jit_lhs)?,
+                self.sext_in_place(rhs, jit_rhs)?,
+            ),
+            _ => (jit_lhs, jit_rhs),
+        };
+        let inst = jit_ir::BinOpInst::new(jit_lhs, binop, jit_rhs).into();
         self.copy_inst(inst, bid, aot_inst_idx)
     }
 
+    /// If the AOT IR operand `aot_op` is an integer narrower than the JIT IR integer that
+    /// represents it, return a version of `jit_op` (the translation of `aot_op`) whose undefined
+    /// upper bits are zero extended. Otherwise return `jit_op` unchanged.
+    fn zext_in_place(
+        &mut self,
+        aot_op: &aot_ir::Operand,
+        jit_op: jit_ir::Operand,
+    ) -> Result<jit_ir::Operand, CompilationError> {
+        self.extend_in_place(aot_op, jit_op, false)
+    }
+
+    /// As [Self::zext_in_place], but sign extending.
+    fn sext_in_place(
+        &mut self,
+        aot_op: &aot_ir::Operand,
+        jit_op: jit_ir::Operand,
+    ) -> Result<jit_ir::Operand, CompilationError> {
+        self.extend_in_place(aot_op, jit_op, true)
+    }
+
+    fn extend_in_place(
+        &mut self,
+        aot_op: &aot_ir::Operand,
+        jit_op: jit_ir::Operand,
+        signed: bool,
+    ) -> Result<jit_ir::Operand, CompilationError> {
+        let aot_ir::Ty::Integer(x) = aot_op.type_(self.aot_mod) else {
+            return Ok(jit_op);
+        };
+        let aot_bitw = x.bitw();
+        let jit_bitw = jit_op.bitw(&self.jit_mod);
+        if aot_bitw == jit_bitw {
+            return Ok(jit_op);
+        }
+        let tyidx = jit_op.tyidx(&self.jit_mod);
+        match jit_op {
+            jit_ir::Operand::Const(cidx) => {
+                let jit_ir::Const::Int(_, v) = self.jit_mod.const_(cidx) else {
+                    panic!()
+                };
+                let v = v.truncate(aot_bitw);
+                let v = if signed {
+                    v.sign_extend(jit_bitw)
+                } else {
+                    v.zero_extend(jit_bitw)
+                };
+                Ok(jit_ir::Operand::Const(
+                    self.jit_mod.insert_const(jit_ir::Const::Int(tyidx, v))?,
+                ))
+            }
+            jit_ir::Operand::Var(_) if signed => {
+                // Shift the value's sign bit to the top of the JIT IR integer, then arithmetic
+                // shift it back down again.
+                let sh = jit_ir::Operand::Const(self.jit_mod.insert_const(jit_ir::Const::Int(
+                    tyidx,
+                    ArbBitInt::from_u64(jit_bitw, u64::from(jit_bitw - aot_bitw)),
+                ))?);
+                let shl = self.jit_mod.push_and_make_operand(
+                    jit_ir::BinOpInst::new(jit_op, BinOp::Shl, sh.clone()).into(),
+                )?;
+                self.jit_mod
+                    .push_and_make_operand(jit_ir::BinOpInst::new(shl, BinOp::AShr, sh).into())
+            }
+            jit_ir::Operand::Var(_) => {
+                let mask =
+                    jit_ir::Operand::Const(self.jit_mod.insert_const(jit_ir::Const::Int(
+                        tyidx,
+                        ArbBitInt::all_bits_set(aot_bitw).zero_extend(jit_bitw),
+                    ))?);
+                self.jit_mod
+                    .push_and_make_operand(jit_ir::BinOpInst::new(jit_op, BinOp::And, mask).into())
+            }
+        }
+    }
+
+    /// Deoptimisation can only handle values of at most 64 bits, so we can't compile traces where
+    /// wider values are live at a guard or at the start of the trace.
+    fn check_live_tyidx(&self, tyidx: jit_ir::TyIdx) -> Result<(), CompilationError> {
+        match self.jit_mod.type_(tyidx).bitw() {
+            Some(bitw) if bitw > 64 => Err(CompilationError::General(format!(
+                "live i{bitw} variables are not supported"
+            ))),
+            _ => Ok(()),
+        }
+    }
+
     /// Create a guard.
     ///
     /// The guard fails if `cond` is not `expect`.
@@ -574,6 +753,7 @@ impl TraceBuilder {
             for op in safepoint.lives.iter() {
                 match op {
                     aot_ir::Operand::Local(iid) => {
+                        self.check_live_tyidx(self.local_map[iid].tyidx(&self.jit_mod))?;
                         match self.local_map[iid] {
                             jit_ir::Operand::Var(liidx) => {
                                 // If, as often happens, a guard has in its live set the boolean
@@ -683,8 +863,13 @@ impl TraceBuilder {
         let frame = self.frames.pop().unwrap();
         if !self.frames.is_empty() {
             if let Some(val) = val {
-                let op = self.handle_operand(val)?;
-                self.local_map.insert(frame.callinst.unwrap(), op);
+                if let aot_ir::Ty::Struct(_) = val.type_(self.aot_mod) {
+                    let fields = self.handle_aggregate_operand(val)?;
+                    self.aggregates.insert(frame.callinst.unwrap(), fields);
+                } else {
+                    let op = self.handle_operand(val)?;
+                    self.local_map.insert(frame.callinst.unwrap(), op);
+                }
             }
             Ok(())
         } else {
@@ -704,9 +889,20 @@ impl TraceBuilder {
         pred: &aot_ir::Predicate,
         rhs: &aot_ir::Operand,
     ) -> Result<(), CompilationError> {
-        let inst =
-            jit_ir::ICmpInst::new(self.handle_operand(lhs)?, *pred, self.handle_operand(rhs)?)
-                .into();
+        let jit_lhs = self.handle_operand(lhs)?;
+        let jit_rhs = self.handle_operand(rhs)?;
+        let (jit_lhs, jit_rhs) = if pred.signed() {
+            (
+                self.sext_in_place(lhs, jit_lhs)?,
+                self.sext_in_place(rhs, jit_rhs)?,
+            )
+        } else {
+            (
+                self.zext_in_place(lhs, jit_lhs)?,
+                self.zext_in_place(rhs, jit_rhs)?,
+            )
+        };
+        let inst = jit_ir::ICmpInst::new(jit_lhs, *pred, jit_rhs).into();
         self.copy_inst(inst, bid, aot_inst_idx)
     }
 
@@ -733,13 +929,168 @@ impl TraceBuilder {
         tyidx: &aot_ir::TyIdx,
         volatile: bool,
     ) -> Result<(), CompilationError> {
-        let inst = jit_ir::LoadInst::new(
-            self.handle_operand(ptr)?,
-            self.handle_type(self.aot_mod.type_(*tyidx))?,
-            volatile,
+        if let aot_ir::Ty::Struct(st) = self.aot_mod.type_(*tyidx) {
+            // Load each field of the aggregate individually.
+            let jit_ptr = self.handle_operand(ptr)?;
+            let mut fields = Vec::with_capacity(st.num_fields());
+            for (i, field_ptr) in self.field_ptrs(jit_ptr, st)?.into_iter().enumerate() {
+                fields.push(self.load_scalar(
+                    field_ptr,
+                    self.aot_mod.type_(st.field_tyidx(i)),
+                    volatile,
+                )?);
+            }
+            let aot_iid = aot_ir::InstId::new(
+                bid.funcidx(),
+                bid.bbidx(),
+                aot_ir::BBlockInstIdx::new(aot_inst_idx),
+            );
+            self.aggregates.insert(aot_iid, fields);
+            return Ok(());
+        }
+        let jit_ptr = self.handle_operand(ptr)?;
+        let op = self.load_scalar(jit_ptr, self.aot_mod.type_(*tyidx), volatile)?;
+        let aot_iid = aot_ir::InstId::new(
+            bid.funcidx(),
+            bid.bbidx(),
+            aot_ir::BBlockInstIdx::new(aot_inst_idx),
+        );
+        self.local_map.insert(aot_iid, op);
+        Ok(())
+    }
+
+    /// Load a non-aggregate value of AOT IR type `aot_ty` from `ptr`.
+    fn load_scalar(
+        &mut self,
+        ptr: jit_ir::Operand,
+        aot_ty: &aot_ir::Ty,
+        volatile: bool,
+    ) -> Result<jit_ir::Operand, CompilationError> {
+        let tyidx = self.handle_type(aot_ty)?;
+        let aot_ir::Ty::Integer(x) = aot_ty else {
+            return self
+                .jit_mod
+                .push_and_make_operand(jit_ir::LoadInst::new(ptr, tyidx, volatile).into());
+        };
+        let bytew = usize::try_from(x.bytew()).unwrap();
+        if bytew == self.jit_mod.type_(tyidx).byte_size().unwrap() {
+            return self
+                .jit_mod
+                .push_and_make_operand(jit_ir::LoadInst::new(ptr, tyidx, volatile).into());
+        }
+        // Loading the whole JIT IR integer would read past the end of the AOT IR integer, so we
+        // load it piece by piece, and assemble the result with shifts and ors.
+        let jit_bitw = self.jit_mod.type_(tyidx).bitw().unwrap();
+        let mut val = None;
+        for (off, piece_bytew) in int_pieces(bytew) {
+            let piece_ptr = self.ptr_add(&ptr, off)?;
+            let piece_tyidx = self
+                .jit_mod
+                .insert_ty(jit_ir::Ty::Integer(u32::try_from(piece_bytew * 8).unwrap()))?;
+            let piece = self.jit_mod.push_and_make_operand(
+                jit_ir::LoadInst::new(piece_ptr, piece_tyidx, volatile).into(),
+            )?;
+            let mut piece = self
+                .jit_mod
+                .push_and_make_operand(jit_ir::ZExtInst::new(&piece, tyidx).into())?;
+            if off > 0 {
+                let sh = jit_ir::Operand::Const(self.jit_mod.insert_const(jit_ir::Const::Int(
+                    tyidx,
+                    ArbBitInt::from_u64(jit_bitw, u64::try_from(off * 8).unwrap()),
+                ))?);
+                piece = self
+                    .jit_mod
+                    .push_and_make_operand(jit_ir::BinOpInst::new(piece, BinOp::Shl, sh).into())?;
+            }
+            val = Some(match val {
+                None => piece,
+                Some(val) => self
+                    .jit_mod
+                    .push_and_make_operand(jit_ir::BinOpInst::new(val, BinOp::Or, piece).into())?,
+            });
+        }
+        // `int_pieces` always returns at least one piece.
+        Ok(val.unwrap())
+    }
+
+    /// Store the non-aggregate value `val` (of AOT IR type `aot_ty`) to `ptr`.
+    fn store_scalar(
+        &mut self,
+        ptr: jit_ir::Operand,
+        aot_ty: &aot_ir::Ty,
+        val: jit_ir::Operand,
+        volatile: bool,
+    ) -> Result<(), CompilationError> {
+        let bytew = match aot_ty {
+            aot_ir::Ty::Integer(x) => usize::try_from(x.bytew()).unwrap(),
+            _ => {
+                self.jit_mod
+                    .push(jit_ir::StoreInst::new(ptr, val, volatile).into())?;
+                return Ok(());
+            }
+        };
+        if bytew == val.byte_size(&self.jit_mod) {
+            self.jit_mod
+                .push(jit_ir::StoreInst::new(ptr, val, volatile).into())?;
+            return Ok(());
+        }
+        // Storing the whole JIT IR integer would write past the end of the AOT IR integer, so we
+        // store it piece by piece.
+        let jit_bitw = val.bitw(&self.jit_mod);
+        let tyidx = val.tyidx(&self.jit_mod);
+        for (off, piece_bytew) in int_pieces(bytew) {
+            let piece_bitw = u32::try_from(piece_bytew * 8).unwrap();
+            let piece_tyidx = self.jit_mod.insert_ty(jit_ir::Ty::Integer(piece_bitw))?;
+            let piece = match val {
+                jit_ir::Operand::Const(cidx) => {
+                    let jit_ir::Const::Int(_, v) = self.jit_mod.const_(cidx) else {
+                        panic!()
+                    };
+                    let v = v
+                        .checked_lshr(u32::try_from(off * 8).unwrap())
+                        .unwrap()
+                        .truncate(piece_bitw);
+                    jit_ir::Operand::Const(
+                        self.jit_mod
+                            .insert_const(jit_ir::Const::Int(piece_tyidx, v))?,
+                    )
+                }
+                jit_ir::Operand::Var(_) => {
+                    let mut piece = val.clone();
+                    if off > 0 {
+                        let sh = jit_ir::Operand::Const(self.jit_mod.insert_const(
+                            jit_ir::Const::Int(
+                                tyidx,
+                                ArbBitInt::from_u64(jit_bitw, u64::try_from(off * 8).unwrap()),
+                            ),
+                        )?);
+                        piece = self.jit_mod.push_and_make_operand(
+                            jit_ir::BinOpInst::new(piece, BinOp::LShr, sh).into(),
+                        )?;
+                    }
+                    self.jit_mod
+                        .push_and_make_operand(jit_ir::TruncInst::new(&piece, piece_tyidx).into())?
+                }
+            };
+            let piece_ptr = self.ptr_add(&ptr, off)?;
+            self.jit_mod
+                .push(jit_ir::StoreInst::new(piece_ptr, piece, volatile).into())?;
+        }
+        Ok(())
+    }
+
+    /// Return an operand pointing `off` bytes after `ptr`.
+    fn ptr_add(
+        &mut self,
+        ptr: &jit_ir::Operand,
+        off: usize,
+    ) -> Result<jit_ir::Operand, CompilationError> {
+        if off == 0 {
+            return Ok(ptr.clone());
+        }
+        self.jit_mod.push_and_make_operand(
+            jit_ir::PtrAddInst::new(ptr.clone(), i32::try_from(off).unwrap()).into(),
         )
-        .into();
-        self.copy_inst(inst, bid, aot_inst_idx)
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -799,7 +1150,8 @@ impl TraceBuilder {
         let inst =
             jit_ir::IndirectCallInst::new(&mut self.jit_mod, jit_tyidx, jit_callop, jit_args)?;
         let idx = self.jit_mod.push_indirect_call(inst)?;
-        self.copy_inst(jit_ir::Inst::IndirectCall(idx), bid, aot_inst_idx)
+        self.copy_inst(jit_ir::Inst::IndirectCall(idx), bid, aot_inst_idx)?;
+        self.scalarise_call_ret(bid, aot_inst_idx, *ftyidx)
     }
 
     /// Handle a *direct* call instruction.
@@ -829,6 +1181,10 @@ impl TraceBuilder {
             return Ok(());
         }
 
+        if AOT_MOD.func(*callee).name() == "yk_assume_constant" {
+            return self.handle_assume_constant(inst, bid, aot_inst_idx, nextinst);
+        }
+
         self.direct_call_impl(bid, aot_inst_idx, callee, args, safepoint, nextinst)
     }
 
@@ -898,25 +1254,70 @@ impl TraceBuilder {
                 None, // Note: the `idem_const` field may be populated later.
             )?
             .into();
-            self.copy_inst(inst, bid, aot_inst_idx)
+            self.copy_inst(inst, bid, aot_inst_idx)?;
+            self.scalarise_call_ret(bid, aot_inst_idx, func.tyidx())
         }
     }
 
-    fn handle_store(
+    /// If the function type `ftyidx` of the (outlined) call just translated returns an aggregate,
+    /// scalarise its return value: the call itself defines the first field, and we push a
+    /// [jit_ir::SecondRetValInst] for the second.
+    fn scalarise_call_ret(
         &mut self,
         bid: &aot_ir::BBlockId,
         aot_inst_idx: usize,
+        ftyidx: aot_ir::TyIdx,
+    ) -> Result<(), CompilationError> {
+        let aot_ir::Ty::Func(fty) = self.aot_mod.type_(ftyidx) else {
+            panic!()
+        };
+        // `handle_type` has already rejected aggregates that don't have exactly two fields.
+        let aot_ir::Ty::Struct(st) = self.aot_mod.type_(fty.ret_ty()) else {
+            return Ok(());
+        };
+        let first = jit_ir::Operand::Var(self.jit_mod.last_inst_idx());
+        let tyidx = self.handle_type(self.aot_mod.type_(st.field_tyidx(1)))?;
+        let second = self
+            .jit_mod
+            .push_and_make_operand(jit_ir::SecondRetValInst::new(tyidx).into())?;
+        let aot_iid = aot_ir::InstId::new(
+            bid.funcidx(),
+            bid.bbidx(),
+            aot_ir::BBlockInstIdx::new(aot_inst_idx),
+        );
+        self.local_map.remove(&aot_iid);
+        self.aggregates.insert(aot_iid, vec![first, second]);
+        Ok(())
+    }
+
+    fn handle_store(
+        &mut self,
         tgt: &aot_ir::Operand,
         val: &aot_ir::Operand,
         volatile: bool,
     ) -> Result<(), CompilationError> {
-        let inst = jit_ir::StoreInst::new(
-            self.handle_operand(tgt)?,
-            self.handle_operand(val)?,
-            volatile,
-        )
-        .into();
-        self.copy_inst(inst, bid, aot_inst_idx)
+        if let aot_ir::Ty::Struct(st) = val.type_(self.aot_mod) {
+            // Store each field of the aggregate individually.
+            let jit_tgt = self.handle_operand(tgt)?;
+            let fields = self.handle_aggregate_operand(val)?;
+            for (i, (field_ptr, field)) in self
+                .field_ptrs(jit_tgt, st)?
+                .into_iter()
+                .zip(fields)
+                .enumerate()
+            {
+                self.store_scalar(
+                    field_ptr,
+                    self.aot_mod.type_(st.field_tyidx(i)),
+                    field,
+                    volatile,
+                )?;
+            }
+            return Ok(());
+        }
+        let jit_tgt = self.handle_operand(tgt)?;
+        let jit_val = self.handle_operand(val)?;
+        self.store_scalar(jit_tgt, val.type_(self.aot_mod), jit_val, volatile)
     }
 
     fn handle_ptradd(
@@ -977,55 +1378,51 @@ impl TraceBuilder {
         val: &aot_ir::Operand,
         dest_tyidx: &aot_ir::TyIdx,
     ) -> Result<(), CompilationError> {
-        let inst = match cast_kind {
-            aot_ir::CastKind::SExt => jit_ir::SExtInst::new(
-                &self.handle_operand(val)?,
-                self.handle_type(self.aot_mod.type_(*dest_tyidx))?,
-            )
-            .into(),
-            aot_ir::CastKind::ZeroExtend => jit_ir::ZExtInst::new(
-                &self.handle_operand(val)?,
-                self.handle_type(self.aot_mod.type_(*dest_tyidx))?,
-            )
-            .into(),
-            aot_ir::CastKind::Trunc => jit_ir::TruncInst::new(
-                &self.handle_operand(val)?,
-                self.handle_type(self.aot_mod.type_(*dest_tyidx))?,
-            )
-            .into(),
-            aot_ir::CastKind::SIToFP => jit_ir::SIToFPInst::new(
-                &self.handle_operand(val)?,
-                self.handle_type(self.aot_mod.type_(*dest_tyidx))?,
-            )
-            .into(),
-            aot_ir::CastKind::FPExt => jit_ir::FPExtInst::new(
-                &self.handle_operand(val)?,
-                self.handle_type(self.aot_mod.type_(*dest_tyidx))?,
-            )
-            .into(),
-            aot_ir::CastKind::FPToSI => jit_ir::FPToSIInst::new(
-                &self.handle_operand(val)?,
-                self.handle_type(self.aot_mod.type_(*dest_tyidx))?,
-            )
-            .into(),
-            aot_ir::CastKind::BitCast => jit_ir::BitCastInst::new(
-                &self.handle_operand(val)?,
-                self.handle_type(self.aot_mod.type_(*dest_tyidx))?,
-            )
-            .into(),
-            aot_ir::CastKind::PtrToInt => jit_ir::PtrToIntInst::new(
-                &self.handle_operand(val)?,
-                self.handle_type(self.aot_mod.type_(*dest_tyidx))?,
-            )
-            .into(),
-            aot_ir::CastKind::IntToPtr => {
-                jit_ir::IntToPtrInst::new(&self.handle_operand(val)?).into()
+        let jit_tyidx = self.handle_type(self.aot_mod.type_(*dest_tyidx))?;
+        let op = self.handle_operand(val)?;
+        let op = match cast_kind {
+            aot_ir::CastKind::ZeroExtend
+            | aot_ir::CastKind::UIToFP
+            | aot_ir::CastKind::IntToPtr => self.zext_in_place(val, op)?,
+            aot_ir::CastKind::SExt | aot_ir::CastKind::SIToFP => self.sext_in_place(val, op)?,
+            _ => op,
+        };
+        match cast_kind {
+            aot_ir::CastKind::SExt | aot_ir::CastKind::ZeroExtend | aot_ir::CastKind::Trunc
+                if op.tyidx(&self.jit_mod) == jit_tyidx =>
+            {
+                // The source and destination types are represented by the same JIT IR integer, so
+                // extending in place (or, for a truncation, nothing) is all that's needed.
+                let aot_iid = aot_ir::InstId::new(
+                    bid.funcidx(),
+                    bid.bbidx(),
+                    aot_ir::BBlockInstIdx::new(aot_inst_idx),
+                );
+                self.local_map.insert(aot_iid, op);
+                return Ok(());
             }
-            aot_ir::CastKind::UIToFP => jit_ir::UIToFPInst::new(
-                &self.handle_operand(val)?,
-                self.handle_type(self.aot_mod.type_(*dest_tyidx))?,
-            )
-            .into(),
+            aot_ir::CastKind::SExt | aot_ir::CastKind::ZeroExtend | aot_ir::CastKind::Trunc => (),
+            _ => {
+                if op.bitw(&self.jit_mod) > 64
+                    || self.jit_mod.type_(jit_tyidx).bitw().is_some_and(|x| x > 64)
+                {
+                    return Err(CompilationError::General(format!(
+                        "{cast_kind:?} casts involving integers wider than 64 bits are not supported"
+                    )));
+                }
+            }
+        }
+        let inst = match cast_kind {
+            aot_ir::CastKind::SExt => jit_ir::SExtInst::new(&op, jit_tyidx).into(),
+            aot_ir::CastKind::ZeroExtend => jit_ir::ZExtInst::new(&op, jit_tyidx).into(),
+            aot_ir::CastKind::Trunc => jit_ir::TruncInst::new(&op, jit_tyidx).into(),
+            aot_ir::CastKind::SIToFP => jit_ir::SIToFPInst::new(&op, jit_tyidx).into(),
+            aot_ir::CastKind::FPExt => jit_ir::FPExtInst::new(&op, jit_tyidx).into(),
+            aot_ir::CastKind::FPToSI => jit_ir::FPToSIInst::new(&op, jit_tyidx).into(),
+            aot_ir::CastKind::BitCast => jit_ir::BitCastInst::new(&op, jit_tyidx).into(),
+            aot_ir::CastKind::PtrToInt => jit_ir::PtrToIntInst::new(&op, jit_tyidx).into(),
+            aot_ir::CastKind::IntToPtr => jit_ir::IntToPtrInst::new(&op).into(),
+            aot_ir::CastKind::UIToFP => jit_ir::UIToFPInst::new(&op, jit_tyidx).into(),
         };
         self.copy_inst(inst, bid, aot_inst_idx)
     }
@@ -1049,6 +1446,14 @@ impl TraceBuilder {
 
         let jit_tyidx = self.handle_type(test_val.type_(self.aot_mod))?;
         let bitw = self.jit_mod.type_(jit_tyidx).bitw().unwrap();
+        if bitw > 64 {
+            return Err(CompilationError::General(format!(
+                "switches on i{bitw} values are not supported"
+            )));
+        }
+        // The case values are zero extended, so the value we compare against them must be too.
+        let jit_test_val = self.handle_operand(test_val)?;
+        let jit_test_val = self.zext_in_place(test_val, jit_test_val)?;
 
         // Find out which case we traced.
         let guard = match case_dests.iter().position(|&cd| cd == next_bb.bbidx()) {
@@ -1062,7 +1467,6 @@ impl TraceBuilder {
                 let jit_const_opnd = jit_ir::Operand::Const(self.jit_mod.insert_const(jit_const)?);
 
                 // Perform the comparison.
-                let jit_test_val = self.handle_operand(test_val)?;
                 let cmp_inst =
                     jit_ir::ICmpInst::new(jit_test_val, jit_ir::Predicate::Equal, jit_const_opnd);
                 let jit_cond = self.jit_mod.push_and_make_operand(cmp_inst.into())?;
@@ -1098,9 +1502,8 @@ impl TraceBuilder {
                         jit_ir::Operand::Const(self.jit_mod.insert_const(jit_const)?);
 
                     // Do the comparison.
-                    let jit_test_val = self.handle_operand(test_val)?;
                     let cmp = jit_ir::ICmpInst::new(
-                        jit_test_val,
+                        jit_test_val.clone(),
                         jit_ir::Predicate::Equal,
                         jit_const_opnd,
                     );
@@ -1133,6 +1536,7 @@ impl TraceBuilder {
         &mut self,
         bid: &aot_ir::BBlockId,
         aot_inst_idx: usize,
+        tyidx: aot_ir::TyIdx,
         prev_bb: &aot_ir::BBlockIdx,
         incoming_bbs: &[aot_ir::BBlockIdx],
         incoming_vals: &[aot_ir::Operand],
@@ -1144,19 +1548,34 @@ impl TraceBuilder {
             bid.bbidx(),
             aot_ir::BBlockInstIdx::new(aot_inst_idx),
         );
-        let op = match self.handle_operand(chosen_val)? {
+        if let aot_ir::Ty::Struct(_) = self.aot_mod.type_(tyidx) {
+            let fields = self
+                .handle_aggregate_operand(chosen_val)?
+                .into_iter()
+                .map(|op| self.phi_operand(op))
+                .collect::<Result<Vec<_>, _>>()?;
+            self.aggregates.insert(aot_iit, fields);
+            return Ok(());
+        }
+        let op = self.handle_operand(chosen_val)?;
+        let op = self.phi_operand(op)?;
+        self.local_map.insert(aot_iit, op);
+        Ok(())
+    }
+
+    /// Return the operand a `Phi` maps to when `op` is its incoming value.
+    fn phi_operand(&mut self, op: jit_ir::Operand) -> Result<jit_ir::Operand, CompilationError> {
+        match op {
             jit_ir::Operand::Const(c) => {
                 // We don't want to do constant propagation here as it makes our life harder
                 // creating guards. Instead we simply create a `Const` instruction here and
                 // reference that.
                 let inst = jit_ir::Inst::Const(c);
                 self.jit_mod.push(inst)?;
-                jit_ir::Operand::Var(self.jit_mod.last_inst_idx())
+                Ok(jit_ir::Operand::Var(self.jit_mod.last_inst_idx()))
             }
-            op => op,
-        };
-        self.local_map.insert(aot_iit, op);
-        Ok(())
+            op => Ok(op),
+        }
     }
 
     fn handle_select(
@@ -1167,6 +1586,27 @@ impl TraceBuilder {
         trueval: &aot_ir::Operand,
         falseval: &aot_ir::Operand,
     ) -> Result<(), CompilationError> {
+        if let aot_ir::Ty::Struct(_) = trueval.type_(self.aot_mod) {
+            // Select each field of the aggregate individually.
+            let cond = self.handle_operand(cond)?;
+            let truevals = self.handle_aggregate_operand(trueval)?;
+            let falsevals = self.handle_aggregate_operand(falseval)?;
+            let mut fields = Vec::with_capacity(truevals.len());
+            for (t, f) in truevals.into_iter().zip(falsevals) {
+                fields.push(
+                    self.jit_mod.push_and_make_operand(
+                        jit_ir::SelectInst::new(cond.clone(), t, f).into(),
+                    )?,
+                );
+            }
+            let aot_iid = aot_ir::InstId::new(
+                bid.funcidx(),
+                bid.bbidx(),
+                aot_ir::BBlockInstIdx::new(aot_inst_idx),
+            );
+            self.aggregates.insert(aot_iid, fields);
+            return Ok(());
+        }
         let inst = jit_ir::SelectInst::new(
             self.handle_operand(cond)?,
             self.handle_operand(trueval)?,
@@ -1202,42 +1642,58 @@ impl TraceBuilder {
         }
     }
 
-    /// Consume bytes from `self.promotions` and build a constant from them of type `tyidx`.
+    /// Consume `bytew` bytes, promoted in the block `bid`, from `self.promotions`, returning the
+    /// index of the first byte consumed.
+    fn consume_promotion(
+        &mut self,
+        bid: &BBlockId,
+        bytew: usize,
+    ) -> Result<usize, CompilationError> {
+        // If the tracer recorded promotions inline, check that this promotion was recorded in the
+        // block we're processing, and has the size we expect.
+        if let Some((rec_bid, rec_bytew)) = self.inline_promotions.pop_front() {
+            if rec_bid.as_ref() != Some(bid) || rec_bytew != bytew {
+                return Err(CompilationError::General(format!(
+                    "promoted value recorded in {rec_bid:?} ({rec_bytew} bytes) consumed in \
+                     {bid:?} ({bytew} bytes)"
+                )));
+            }
+        }
+        if self.promote_idx + bytew > self.promotions.len() {
+            return Err(CompilationError::General(
+                "trace has fewer promoted values than expected".into(),
+            ));
+        }
+        let idx = self.promote_idx;
+        self.promote_idx += bytew;
+        Ok(idx)
+    }
+
+    /// Consume bytes, promoted in the block `bid`, from `self.promotions` and build a constant
+    /// from them of type `tyidx`.
     fn promote_bytes_to_const(
         &mut self,
+        bid: &BBlockId,
         tyidx: jit_ir::TyIdx,
     ) -> Result<jit_ir::ConstIdx, CompilationError> {
-        let ty = self.jit_mod.type_(tyidx);
+        let ty = self.jit_mod.type_(tyidx).clone();
         let c = match ty {
             jit_ir::Ty::Void => unreachable!(),
             jit_ir::Ty::Integer(bitw) => {
                 let bytew = ty.byte_size().unwrap();
-                let v = match *bitw {
-                    64 => u64::from_ne_bytes(
-                        self.promotions[self.promote_idx..self.promote_idx + bytew]
-                            .try_into()
-                            .unwrap(),
-                    ),
-                    32 => u64::from(u32::from_ne_bytes(
-                        self.promotions[self.promote_idx..self.promote_idx + bytew]
-                            .try_into()
-                            .unwrap(),
-                    )),
-                    x => todo!("{x}"),
-                };
-                self.promote_idx += bytew;
-                Const::Int(tyidx, ArbBitInt::from_u64(*bitw, v))
+                let idx = self.consume_promotion(bid, bytew)?;
+                // JIT IR integers are at most 128 bits wide, so the unwrap can't fail.
+                let v = aot_ir::const_int_bytes_to_u128(bitw, &self.promotions[idx..idx + bytew])
+                    .unwrap();
+                Const::Int(tyidx, ArbBitInt::from_u128(bitw, v))
             }
             jit_ir::Ty::Ptr => {
                 let bytew = ty.byte_size().unwrap();
                 assert_eq!(bytew, std::mem::size_of::<usize>());
-                let v = usize::from_ne_bytes(
-                    self.promotions[self.promote_idx..self.promote_idx + bytew]
-                        .try_into()
-                        .unwrap(),
-                );
-                self.promote_idx += bytew;
-                Const::Ptr(v)
+                let idx = self.consume_promotion(bid, bytew)?;
+                Const::Ptr(usize::from_ne_bytes(
+                    self.promotions[idx..idx + bytew].try_into().unwrap(),
+                ))
             }
             jit_ir::Ty::Func(_) => todo!(),
             jit_ir::Ty::Float(_) => todo!(),
@@ -1265,7 +1721,7 @@ impl TraceBuilder {
                 // same each time.
                 let tyidx = self.handle_type(self.aot_mod.type_(*tyidx))?;
                 // Create the constant from the runtime value.
-                let cidx = self.promote_bytes_to_const(tyidx)?;
+                let cidx = self.promote_bytes_to_const(bid, tyidx)?;
                 let cmp_instr = jit_ir::ICmpInst::new(
                     jit_ir::Operand::Var(self.jit_mod.last_inst_idx()),
                     aot_ir::Predicate::Equal,
@@ -1304,7 +1760,7 @@ impl TraceBuilder {
         let jit_ir::Inst::Call(ci) = call_inst else {
             todo!();
         };
-        let cidx = self.promote_bytes_to_const(call_inst.tyidx(&self.jit_mod))?;
+        let cidx = self.promote_bytes_to_const(bid, call_inst.tyidx(&self.jit_mod))?;
         let args = ci
             .iter_args_idx()
             .map(|i| self.jit_mod.arg(i).clone())
@@ -1324,6 +1780,109 @@ impl TraceBuilder {
         }
     }
 
+    /// Handle a call to `yk_assume_constant`. The value the quasi-constant had during trace
+    /// recording becomes a constant in the trace. Unlike [Self::handle_promote] we don't insert a
+    /// guard: instead the [MT] will invalidate this trace if the quasi-constant changes.
+    fn handle_assume_constant(
+        &mut self,
+        inst: &'static aot_ir::Inst,
+        bid: &aot_ir::BBlockId,
+        aot_inst_idx: usize,
+        nextinst: &'static aot_ir::Inst,
+    ) -> Result<(), CompilationError> {
+        self.outline_until_after_call(bid, nextinst);
+        let tyidx = self.handle_type(inst.def_type(self.aot_mod).unwrap())?;
+        let cidx = self.promote_bytes_to_const(bid, tyidx)?;
+        let aot_iid = aot_ir::InstId::new(
+            bid.funcidx(),
+            bid.bbidx(),
+            aot_ir::BBlockInstIdx::new(aot_inst_idx),
+        );
+        self.local_map.insert(aot_iid, jit_ir::Operand::Const(cidx));
+        Ok(())
+    }
+
+    /// Return pointers to each of the fields of the aggregate of type `st` which `ptr` points to.
+    fn field_ptrs(
+        &mut self,
+        ptr: jit_ir::Operand,
+        st: &aot_ir::StructTy,
+    ) -> Result<Vec<jit_ir::Operand>, CompilationError> {
+        let mut ptrs = Vec::with_capacity(st.num_fields());
+        for i in 0..st.num_fields() {
+            let bit_off = st.field_bit_off(i);
+            if bit_off % 8 != 0 {
+                return Err(CompilationError::General(format!(
+                    "aggregate field at non-byte-aligned bit offset {bit_off}"
+                )));
+            }
+            if bit_off == 0 {
+                ptrs.push(ptr.clone());
+            } else {
+                let off = i32::try_from(bit_off / 8).map_err(|_| {
+                    CompilationError::LimitExceeded(
+                        "aggregate field offset doesn't fit in i32".into(),
+                    )
+                })?;
+                ptrs.push(
+                    self.jit_mod
+                        .push_and_make_operand(jit_ir::PtrAddInst::new(ptr.clone(), off).into())?,
+                );
+            }
+        }
+        Ok(ptrs)
+    }
+
+    /// Translate an aggregate-typed operand into the JIT IR operands of each of its fields.
+    fn handle_aggregate_operand(
+        &mut self,
+        op: &aot_ir::Operand,
+    ) -> Result<Vec<jit_ir::Operand>, CompilationError> {
+        match op {
+            aot_ir::Operand::Local(iid) => Ok(self.aggregates[iid].clone()),
+            aot_ir::Operand::Const(cidx) => {
+                let aot_const = self.aot_mod.const_(*cidx);
+                let aot_ir::Ty::Struct(st) = self.aot_mod.type_(aot_const.tyidx()) else {
+                    panic!()
+                };
+                // We only support `undef`, `poison` and `zeroinitializer` aggregate constants:
+                // zero is a valid value for the fields of each.
+                match aot_const {
+                    aot_ir::Const::Unimplemented { llvm_const_str, .. }
+                        if ["undef", "poison", "zeroinitializer"]
+                            .iter()
+                            .any(|x| llvm_const_str.ends_with(x)) => {}
+                    _ => {
+                        return Err(CompilationError::General(format!(
+                            "unsupported aggregate constant {}",
+                            aot_const.display(self.aot_mod)
+                        )));
+                    }
+                }
+                let mut fields = Vec::with_capacity(st.num_fields());
+                for i in 0..st.num_fields() {
+                    let jit_tyidx = self.handle_type(self.aot_mod.type_(st.field_tyidx(i)))?;
+                    let c = match self.jit_mod.type_(jit_tyidx) {
+                        jit_ir::Ty::Integer(bitw) => {
+                            jit_ir::Const::Int(jit_tyidx, ArbBitInt::from_u64(*bitw, 0))
+                        }
+                        jit_ir::Ty::Float(_) => jit_ir::Const::Float(jit_tyidx, 0.0),
+                        jit_ir::Ty::Ptr => jit_ir::Const::Ptr(0),
+                        x => {
+                            return Err(CompilationError::General(format!(
+                                "unsupported aggregate field type {}",
+                                x.display(&self.jit_mod)
+                            )));
+                        }
+                    };
+                    fields.push(jit_ir::Operand::Const(self.jit_mod.insert_const(c)?));
+                }
+                Ok(fields)
+            }
+            aot_ir::Operand::Global(_) | aot_ir::Operand::Func(_) => panic!(),
+        }
+    }
+
     fn handle_fneg(
         &mut self,
         bid: &aot_ir::BBlockId,
@@ -1368,9 +1927,25 @@ impl TraceBuilder {
         // this information at AOT compile time and serialise it into the module (or block), so we
         // don't have to do this.
         mt.stats.timing_state(TimingState::TraceMapping);
-        let tas = ta_iter
-            .map(|x| x.map_err(|e| CompilationError::General(e.to_string())))
-            .collect::<Result<Vec<_>, _>>()?;
+        let mut tas = Vec::new();
+        // Promoted values recorded inline are moved to `self.promotions`, noting which block each
+        // was recorded in (i.e. the block that precedes it in the trace).
+        let mut inline_promotions = Vec::new();
+        for ta in ta_iter {
+            match ta.map_err(|e| CompilationError::General(e.to_string()))? {
+                TraceAction::Promotion(val) => {
+                    let bid = tas.last().and_then(|x| self.lookup_aot_block(x));
+                    self.inline_promotions.push_back((bid, val.len()));
+                    inline_promotions.extend_from_slice(&val);
+                }
+                ta => tas.push(ta),
+            }
+        }
+        if !self.inline_promotions.is_empty() {
+            // A tracer either records all promoted values inline or none of them.
+            assert!(self.promotions.is_empty());
+            self.promotions = inline_promotions.into_boxed_slice();
+        }
 
         // Peek to the last block (needed for side-tracing).
         let lastblk = match &tas.last() {
@@ -1490,7 +2065,7 @@ impl TraceBuilder {
                         }
                     }
                     TraceAction::UnmappableBBlock => panic!(),
-                    TraceAction::Promotion => todo!(),
+                    TraceAction::Promotion(_) => unreachable!(),
                 };
 
                 self.cp_block = self.lookup_aot_block(&prev);
@@ -1504,7 +2079,7 @@ impl TraceBuilder {
         }
 
         // FIXME: this section of code needs to be refactored.
-        #[cfg(tracer_hwt)]
+        let hwt = mt.tracer_kind() == TracerKind::Hardware;
         let mut last_blk_is_return = false;
         while let Some(b) = trace_iter.next() {
             match self.lookup_aot_block(&b) {
@@ -1514,8 +2089,7 @@ impl TraceBuilder {
                         // Due to the way HWT works, when you return from a call, you see the same
                         // basic block again. We skip it.
                         // FIXME: trace builder should be tracer agnostic.
-                        #[cfg(tracer_hwt)]
-                        if *prev_mbid == bid {
+                        if hwt && *prev_mbid == bid {
                             continue;
                         }
                         // Check the control flow is regular, bailing out if we detect e.g.
@@ -1564,8 +2138,7 @@ impl TraceBuilder {
                         }
                     } else {
                         // We are not outlining. Process blocks normally.
-                        #[cfg(tracer_hwt)]
-                        if last_blk_is_return {
+                        if hwt && last_blk_is_return {
                             // If the last block had a return terminator, we are returning
                             // from a call, which means the HWT will have recorded an
                             // additional caller block that we'll have to skip.
@@ -1576,8 +2149,7 @@ impl TraceBuilder {
                             prev_mappable_bid = Some(bid);
                             continue;
                         }
-                        #[cfg(tracer_hwt)]
-                        if self.aot_mod.bblock(&bid).is_return() {
+                        if hwt && self.aot_mod.bblock(&bid).is_return() {
                             last_blk_is_return = true;
                         }
                     }
@@ -1652,6 +2224,30 @@ struct InlinedFrame {
 }
 
 /// Create JIT IR from the (`aot_mod`, `ta_iter`) tuple.
+/// Return the bit width of the JIT IR integer that represents an AOT IR integer of `bitw` bits,
+/// or `None` if we can't represent such integers.
+fn jit_int_bitw(bitw: u32) -> Option<u32> {
+    match bitw {
+        1 => Some(1),
+        2..=128 => Some(bitw.next_power_of_two().max(8)),
+        _ => None,
+    }
+}
+
+/// Split an integer of `bytew` bytes into `(byte offset, byte width)` pieces, each of which is a
+/// power of two no bigger than 8 bytes.
+fn int_pieces(mut bytew: usize) -> Vec<(usize, usize)> {
+    let mut pieces = Vec::new();
+    let mut off = 0;
+    while bytew > 0 {
+        let piece = 1 << bytew.min(8).ilog2();
+        pieces.push((off, piece));
+        off += piece;
+        bytew -= piece;
+    }
+    pieces
+}
+
 pub(super) fn build(
     mt: &Arc<MT>,
     aot_mod: &'static Module,
@@ -0,0 +1,198 @@
+//! A persistent, on-disk, cache of optimised JIT IR modules.
+//!
+//! Normally every run of an interpreter has to build and optimise its traces from scratch, even
+//! though the AOT IR they are built from is identical between runs. If a cache directory is
+//! configured (see [crate::MTConfig::trace_cache]) then each root trace's optimised [Module] is
+//! stored in that directory, and a later run which records the same trace skips trace building and
+//! optimisation, needing only to run the code generator.
+//!
+//! A trace is identified by a [CacheKey] which consists of: the AOT IR (and the address it is
+//! loaded at, since traces can contain absolute addresses); whether traces are optimised and which
+//! functions the optimiser treats as allocators; and the trace's AOT block path, promoted values,
+//! and debug strings. Note that this means that a trace
+//! still has to be recorded before the cache can be consulted, since yk has no way of identifying a
+//! [Location](crate::location::Location) that is stable across process runs. It also means that
+//! the cache is of little use for interpreters which are loaded at a different address on each run
+//! (e.g. position independent executables with ASLR enabled).
+//!
+//! Only root traces which do not jump to other traces (i.e. [TraceKind::HeaderOnly] and
+//! [TraceKind::HeaderAndBody] traces) are cached.
+//!
+//! Each cache file contains its [CacheKey] in full, so a hash collision in file names cannot cause
+//! the wrong trace to be used. Cache files are written atomically, so multiple processes can share a
+//! cache directory. yk never removes anything from the cache directory: in particular, since the
+//! [CacheKey] does not identify the version of yk which created a trace, the cache directory must
+//! be emptied when yk is upgraded.
+//!
+//! [TraceKind::HeaderOnly]: jit_ir::TraceKind::HeaderOnly
+//! [TraceKind::HeaderAndBody]: jit_ir::TraceKind::HeaderAndBody
+
+use super::{aot_ir, jit_ir::Module, yk_ir_section, AOT_MOD};
+use crate::{
+    mt::TraceId,
+    trace::{AOTTraceIterator, AOTTraceIteratorError, TraceAction},
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::LazyLock,
+    vec,
+};
+use tempfile::NamedTempFile;
+
+/// The magic number at the start of every cache file.
+const MAGIC: &[u8; 4] = b"YKTC";
+
+/// The AOT safepoints, indexed by their stackmap ID.
+static AOT_SAFEPOINTS: LazyLock<HashMap<u64, &'static aot_ir::DeoptSafepoint>> =
+    LazyLock::new(|| AOT_MOD.safepoints().map(|x| (x.id, x)).collect());
+
+/// A hash of the AOT IR.
+pub(super) static AOT_IR_HASH: LazyLock<u64> = LazyLock::new(|| fnv1a(yk_ir_section().unwrap()));
+
+/// Identifies a trace in the cache.
+pub(super) struct CacheKey(Vec<u8>);
+
+impl CacheKey {
+    /// Create the key for the trace `trace`, with promoted values `promotions` and debug strings
+    /// `debug_strs`. `opt` is whether the trace will be optimised and `allocators` the functions
+    /// the optimiser treats as allocators.
+    pub(super) fn new(
+        trace: &[&TraceAction],
+        promotions: &[u8],
+        debug_strs: &[String],
+        opt: bool,
+        allocators: &[String],
+    ) -> Self {
+        let mut key = Vec::new();
+        let mut bytes = |x: &[u8]| {
+            key.extend_from_slice(&x.len().to_le_bytes());
+            key.extend_from_slice(x);
+        };
+        let ir = yk_ir_section().unwrap();
+        bytes(&AOT_IR_HASH.to_le_bytes());
+        bytes(&ir.as_ptr().addr().to_le_bytes());
+        bytes(&ir.len().to_le_bytes());
+        bytes(&[u8::from(opt)]);
+        let mut allocators = allocators.iter().collect::<Vec<_>>();
+        allocators.sort();
+        allocators.dedup();
+        bytes(&allocators.len().to_le_bytes());
+        for x in allocators {
+            bytes(x.as_bytes());
+        }
+        for ta in trace {
+            match ta {
+                TraceAction::MappedAOTBBlock { func_name, bb } => {
+                    bytes(&[0]);
+                    bytes(func_name.to_bytes());
+                    bytes(&bb.to_le_bytes());
+                }
+                TraceAction::UnmappableBBlock => bytes(&[1]),
+                TraceAction::Promotion(val) => {
+                    bytes(&[2]);
+                    bytes(val);
+                }
+            }
+        }
+        bytes(promotions);
+        for x in debug_strs {
+            bytes(x.as_bytes());
+        }
+        Self(key)
+    }
+
+    /// The path of the file in `dir` which this key's trace is stored in.
+    fn path(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("{:016x}.yktrace", fnv1a(&self.0)))
+    }
+
+    /// If a trace for this key is stored in `dir`, load it, giving it the ID `ctrid`. Returns
+    /// `Ok(None)` if there is no such trace.
+    pub(super) fn load(
+        &self,
+        dir: &Path,
+        ctrid: TraceId,
+    ) -> Result<Option<Module>, Box<dyn Error>> {
+        let path = self.path(dir);
+        let buf = match fs::read(&path) {
+            Ok(x) => x,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("{}: {e}", path.display()).into()),
+        };
+        let Some(buf) = buf.strip_prefix(MAGIC) else {
+            return Err(format!("{}: not a trace cache file", path.display()).into());
+        };
+        let Some((len, buf)) = buf.split_first_chunk::<8>() else {
+            return Err(format!("{}: truncated", path.display()).into());
+        };
+        let len = usize::try_from(u64::from_le_bytes(*len))?;
+        match buf.split_at_checked(len) {
+            Some((key, buf)) if key == self.0 => {
+                Module::deserialise(buf, ctrid, AOT_MOD.global_decls_len(), &|x| {
+                    AOT_SAFEPOINTS.get(&x).copied()
+                })
+                .map(Some)
+                .map_err(|e| format!("{}: {e}", path.display()).into())
+            }
+            // Another trace whose key hashes to the same value.
+            Some(_) => Ok(None),
+            None => Err(format!("{}: truncated", path.display()).into()),
+        }
+    }
+
+    /// Store `m`, which must be a trace that was created for this key, in `dir`.
+    pub(super) fn store(&self, dir: &Path, m: &Module) -> Result<(), Box<dyn Error>> {
+        let path = self.path(dir);
+        let mut f = NamedTempFile::new_in(dir)?;
+        f.write_all(MAGIC)?;
+        f.write_all(&u64::try_from(self.0.len())?.to_le_bytes())?;
+        f.write_all(&self.0)?;
+        f.write_all(&m.serialise())?;
+        f.persist(&path)
+            .map_err(|e| format!("{}: {}", path.display(), e.error))?;
+        Ok(())
+    }
+}
+
+/// A trace which has been read from an [AOTTraceIterator] into memory.
+pub(super) struct CollectedTrace(vec::IntoIter<Result<TraceAction, AOTTraceIteratorError>>);
+
+impl CollectedTrace {
+    pub(super) fn new(trace: Vec<Result<TraceAction, AOTTraceIteratorError>>) -> Self {
+        Self(trace.into_iter())
+    }
+}
+
+impl Iterator for CollectedTrace {
+    type Item = Result<TraceAction, AOTTraceIteratorError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+impl AOTTraceIterator for CollectedTrace {}
+
+/// Hash `bytes` with 64-bit FNV-1a. Unlike [std::hash::DefaultHasher], the result is guaranteed
+/// to be the same in every run of every build of yk.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |h, x| {
+        (h ^ u64::from(*x)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_vectors() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+}
@@ -0,0 +1,258 @@
+//! Dumping recorded traces to disk, and replaying them.
+//!
+//! If a dump directory is configured (see [crate::MTConfig::trace_dump]) then each recorded root
+//! trace -- its AOT block path, promoted values, and debug strings -- is written to a file in that
+//! directory before it is compiled. A dumped trace can later be replayed (see
+//! [crate::MT::replay_trace]), which feeds it through the trace builder, the optimiser, and
+//! the code generator exactly as if it had just been recorded, without needing to rerun the
+//! interpreter program (or a tracer) to get to the same point. This is mostly useful for
+//! reproducing, and then debugging, compiler bugs.
+//!
+//! A trace can only be replayed by the same interpreter binary that recorded it: this is checked
+//! when the dump is read. Only root traces which do not jump to other traces are dumped, since
+//! other traces can't be compiled without the traces they depend upon.
+
+use super::{trace_cache::CollectedTrace, trace_cache::AOT_IR_HASH, yk_ir_section};
+use crate::{
+    compile::CancellationToken,
+    location::{HotLocation, HotLocationKind},
+    mt::{TraceId, MT},
+    trace::TraceAction,
+};
+use parking_lot::Mutex;
+use std::{
+    error::Error,
+    ffi::{CStr, CString},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+};
+use tempfile::NamedTempFile;
+
+/// The magic number at the start of every dump file.
+const MAGIC: &[u8; 4] = b"YKTD";
+
+/// A recorded root trace.
+#[derive(Debug, PartialEq)]
+pub(crate) struct TraceDump {
+    /// The debug string of the [HotLocation] the trace was recorded from.
+    pub(crate) hl_debug_str: Option<String>,
+    pub(crate) trace: Vec<TraceAction>,
+    pub(crate) promotions: Box<[u8]>,
+    pub(crate) debug_strs: Vec<String>,
+}
+
+impl TraceDump {
+    /// Write the trace with ID `ctrid` to a new file in `dir`, returning the path of that file.
+    pub(super) fn write(
+        dir: &Path,
+        ctrid: TraceId,
+        hl_debug_str: Option<&str>,
+        trace: &[&TraceAction],
+        promotions: &[u8],
+        debug_strs: &[String],
+    ) -> Result<PathBuf, Box<dyn Error>> {
+        let path = dir.join(format!("{}-{ctrid}.yktd", process::id()));
+        let mut f = NamedTempFile::new_in(dir)?;
+        f.write_all(&Self::to_bytes(
+            *AOT_IR_HASH,
+            hl_debug_str,
+            trace,
+            promotions,
+            debug_strs,
+        ))?;
+        f.persist(&path)
+            .map_err(|e| format!("{}: {}", path.display(), e.error))?;
+        Ok(path)
+    }
+
+    /// Read the trace dump at `path`.
+    pub(crate) fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let buf = fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        // [AOT_IR_HASH] panics if this binary has no AOT IR, but we'd rather report an error.
+        yk_ir_section().map_err(|e| format!("this binary has no yk IR: {e}"))?;
+        Self::from_bytes(*AOT_IR_HASH, &buf).map_err(|e| format!("{}: {e}", path.display()).into())
+    }
+
+    /// Serialise a trace recorded by an interpreter binary whose AOT IR hashes to `ir_hash`.
+    fn to_bytes(
+        ir_hash: u64,
+        hl_debug_str: Option<&str>,
+        trace: &[&TraceAction],
+        promotions: &[u8],
+        debug_strs: &[String],
+    ) -> Vec<u8> {
+        let mut buf = MAGIC.to_vec();
+        buf.extend_from_slice(&ir_hash.to_le_bytes());
+        let mut bytes = |x: &[u8]| {
+            buf.extend_from_slice(&u64::try_from(x.len()).unwrap().to_le_bytes());
+            buf.extend_from_slice(x);
+        };
+        match hl_debug_str {
+            Some(x) => {
+                bytes(&[1]);
+                bytes(x.as_bytes());
+            }
+            None => bytes(&[0]),
+        }
+        bytes(&trace.len().to_le_bytes());
+        for ta in trace {
+            match ta {
+                TraceAction::MappedAOTBBlock { func_name, bb } => {
+                    bytes(&[0]);
+                    bytes(func_name.to_bytes());
+                    bytes(&bb.to_le_bytes());
+                }
+                TraceAction::UnmappableBBlock => bytes(&[1]),
+                TraceAction::Promotion(val) => {
+                    bytes(&[2]);
+                    bytes(val);
+                }
+            }
+        }
+        bytes(promotions);
+        bytes(&debug_strs.len().to_le_bytes());
+        for x in debug_strs {
+            bytes(x.as_bytes());
+        }
+        buf
+    }
+
+    /// Deserialise a trace, checking that it was recorded by an interpreter binary whose AOT IR
+    /// hashes to `ir_hash`.
+    fn from_bytes(ir_hash: u64, buf: &[u8]) -> Result<Self, Box<dyn Error>> {
+        /// Split a length-prefixed sequence of bytes off the front of `buf`.
+        fn take<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8], Box<dyn Error>> {
+            let (len, rest) = buf.split_first_chunk::<8>().ok_or("truncated")?;
+            let (x, rest) = rest
+                .split_at_checked(usize::try_from(u64::from_le_bytes(*len))?)
+                .ok_or("truncated")?;
+            *buf = rest;
+            Ok(x)
+        }
+        fn usize_(x: &[u8]) -> Result<usize, Box<dyn Error>> {
+            Ok(usize::from_le_bytes(x.try_into()?))
+        }
+
+        let buf = buf.strip_prefix(MAGIC).ok_or("not a trace dump file")?;
+        let (hash, mut buf) = buf.split_first_chunk::<8>().ok_or("truncated")?;
+        if u64::from_le_bytes(*hash) != ir_hash {
+            return Err("trace was recorded by a different interpreter binary".into());
+        }
+
+        let hl_debug_str = match take(&mut buf)? {
+            [0] => None,
+            [1] => Some(String::from_utf8(take(&mut buf)?.to_vec())?),
+            _ => return Err("corrupt".into()),
+        };
+        let mut trace = Vec::new();
+        for _ in 0..usize_(take(&mut buf)?)? {
+            trace.push(match take(&mut buf)? {
+                [0] => {
+                    // Replaying a trace is a one-off operation, so leaking function names (which
+                    // [TraceAction] requires to be `'static`) is harmless.
+                    let func_name: &'static CStr =
+                        Box::leak(CString::new(take(&mut buf)?)?.into_boxed_c_str());
+                    let bb = usize_(take(&mut buf)?)?;
+                    TraceAction::MappedAOTBBlock { func_name, bb }
+                }
+                [1] => TraceAction::UnmappableBBlock,
+                [2] => TraceAction::Promotion(take(&mut buf)?.into()),
+                _ => return Err("corrupt".into()),
+            });
+        }
+        let promotions = take(&mut buf)?.into();
+        let mut debug_strs = Vec::new();
+        for _ in 0..usize_(take(&mut buf)?)? {
+            debug_strs.push(String::from_utf8(take(&mut buf)?.to_vec())?);
+        }
+        if !buf.is_empty() {
+            return Err("trailing data".into());
+        }
+        Ok(Self {
+            hl_debug_str,
+            trace,
+            promotions,
+            debug_strs,
+        })
+    }
+
+    /// Compile this trace with `mt`'s compiler. The compiled trace is thrown away: it is not
+    /// linked into any [HotLocation] and can never be executed.
+    pub(crate) fn replay(self, mt: &Arc<MT>) -> Result<(), Box<dyn Error>> {
+        let ctrid = mt.next_trace_id();
+        let hl = Arc::new(Mutex::new(HotLocation {
+            kind: HotLocationKind::Compiling(ctrid, Default::default()),
+            tracecompilation_errors: 0,
+            debug_str: self.hl_debug_str,
+            invalidated: None,
+        }));
+        let trace = CollectedTrace::new(self.trace.into_iter().map(Ok).collect());
+        mt.compiler()
+            .root_compile(
+                Arc::clone(mt),
+                Box::new(trace),
+                ctrid,
+                hl,
+                self.promotions,
+                self.debug_strs,
+                None,
+                &CancellationToken::default(),
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let trace = [
+            TraceAction::MappedAOTBBlock {
+                func_name: c"main",
+                bb: 3,
+            },
+            TraceAction::UnmappableBBlock,
+            TraceAction::Promotion(Box::new([4, 5])),
+            TraceAction::MappedAOTBBlock {
+                func_name: c"f",
+                bb: 0,
+            },
+        ];
+        let buf = TraceDump::to_bytes(
+            42,
+            Some("loop"),
+            &trace.iter().collect::<Vec<_>>(),
+            &[1, 2, 3],
+            &["a".to_owned(), "bc".to_owned()],
+        );
+        assert_eq!(
+            TraceDump::from_bytes(42, &buf).unwrap(),
+            TraceDump {
+                hl_debug_str: Some("loop".to_owned()),
+                trace: trace.into_iter().collect(),
+                promotions: Box::new([1, 2, 3]),
+                debug_strs: vec!["a".to_owned(), "bc".to_owned()],
+            }
+        );
+    }
+
+    #[test]
+    fn corrupt() {
+        let buf = TraceDump::to_bytes(42, None, &[&TraceAction::UnmappableBBlock], &[], &[]);
+        assert!(TraceDump::from_bytes(42, &buf).is_ok());
+        // A different binary.
+        assert!(TraceDump::from_bytes(43, &buf).is_err());
+        for i in 0..buf.len() {
+            assert!(TraceDump::from_bytes(42, &buf[..i]).is_err());
+        }
+        let mut buf = buf;
+        buf.push(0);
+        assert!(TraceDump::from_bytes(42, &buf).is_err());
+    }
+}
@@ -9,7 +9,10 @@ use parking_lot::Mutex;
 use std::{
     error::Error,
     fmt,
-    sync::{Arc, Weak},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
 };
 
 pub(crate) mod guard;
@@ -32,6 +35,9 @@ pub(crate) enum CompilationError {
     /// Compilation failed because an external resource was exhausted: the end user running the
     /// interpreter probably wants to be informed of this.
     ResourceExhausted(Box<dyn Error>),
+    /// Compilation was cancelled via a [CancellationToken] (e.g. because the [MT] was shut down, or
+    /// because a trace that the trace being compiled depends upon was invalidated).
+    Cancelled,
 }
 
 impl fmt::Display for CompilationError {
@@ -41,13 +47,46 @@ impl fmt::Display for CompilationError {
             CompilationError::InternalError(s) => write!(f, "Internal error: {s}"),
             CompilationError::LimitExceeded(s) => write!(f, "Limit exceeded: {s}"),
             CompilationError::ResourceExhausted(e) => write!(f, "Resource exhausted: {e:}"),
+            CompilationError::Cancelled => write!(f, "Cancelled"),
         }
     }
 }
 
+/// Allows a compilation job to be cancelled. Cancellation is cooperative: a compiler checks the
+/// token with [Self::check] at convenient points (e.g. between compilation phases) and stops work
+/// if the token has been cancelled.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Cancel the compilation job(s) this token was given to.
+    pub(crate) fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Return `Err(CompilationError::Cancelled)` if this token has been cancelled.
+    pub(crate) fn check(&self) -> Result<(), CompilationError> {
+        if self.is_cancelled() {
+            Err(CompilationError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Are `self` and `other` clones of the same token?
+    pub(crate) fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
 /// The trait that every JIT compiler backend must implement.
 pub(crate) trait Compiler: Send + Sync {
-    /// Compile a mapped root trace into machine code.
+    /// Compile a mapped root trace into machine code. If `cancel` is cancelled, compilation may be
+    /// abandoned, returning [CompilationError::Cancelled].
     fn root_compile(
         &self,
         mt: Arc<MT>,
@@ -57,9 +96,11 @@ pub(crate) trait Compiler: Send + Sync {
         promotions: Box<[u8]>,
         debug_strs: Vec<String>,
         connector_ctr: Option<Arc<dyn CompiledTrace>>,
+        cancel: &CancellationToken,
     ) -> Result<Arc<dyn CompiledTrace>, CompilationError>;
 
-    /// Compile a guard trace into machine code.
+    /// Compile a guard trace into machine code. If `cancel` is cancelled, compilation may be
+    /// abandoned, returning [CompilationError::Cancelled].
     fn sidetrace_compile(
         &self,
         mt: Arc<MT>,
@@ -71,6 +112,7 @@ pub(crate) trait Compiler: Send + Sync {
         hl: Arc<Mutex<HotLocation>>,
         promotions: Box<[u8]>,
         debug_strs: Vec<String>,
+        cancel: &CancellationToken,
     ) -> Result<Arc<dyn CompiledTrace>, CompilationError>;
 }
 
@@ -88,6 +130,9 @@ pub(crate) trait CompiledTrace: fmt::Debug + Send + Sync {
     /// Return this trace's [TraceId].
     fn ctrid(&self) -> TraceId;
 
+    /// Return the [MT] instance this trace was compiled by.
+    fn mt(&self) -> &Arc<MT>;
+
     fn safepoint(&self) -> &Option<DeoptSafepoint>;
 
     /// Upcast this [CompiledTrace] to `Any`. This method is a hack that's only needed since trait
@@ -99,6 +144,17 @@ pub(crate) trait CompiledTrace: fmt::Debug + Send + Sync {
 
     fn patch_guard(&self, gidx: GuardIdx, target: *const std::ffi::c_void);
 
+    /// Undo a previous [Self::patch_guard] so that the guard `gidx` once again deoptimises when
+    /// it fails.
+    fn unpatch_guard(&self, gidx: GuardIdx);
+
+    /// Mark this trace as invalidated. An invalidated trace must not be entered by any thread, and
+    /// must not be linked to from any [HotLocation] or [Guard].
+    fn invalidate(&self);
+
+    /// Has this trace been invalidated?
+    fn is_invalidated(&self) -> bool;
+
     /// The pointer to this trace's executable code.
     fn entry(&self) -> *const c_void;
 
@@ -120,6 +176,7 @@ pub(crate) use compiled_trace_testing::*;
 #[cfg(test)]
 mod compiled_trace_testing {
     use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
 
     /// A [CompiledTrace] implementation suitable only for testing: when any of its methods are
     /// called it will `panic`.
@@ -137,6 +194,10 @@ mod compiled_trace_testing {
             TraceId::testing()
         }
 
+        fn mt(&self) -> &Arc<MT> {
+            panic!();
+        }
+
         fn safepoint(&self) -> &Option<DeoptSafepoint> {
             todo!()
         }
@@ -153,6 +214,18 @@ mod compiled_trace_testing {
             panic!();
         }
 
+        fn unpatch_guard(&self, _gidx: GuardIdx) {
+            panic!();
+        }
+
+        fn invalidate(&self) {
+            panic!();
+        }
+
+        fn is_invalidated(&self) -> bool {
+            panic!();
+        }
+
         fn entry(&self) -> *const c_void {
             panic!();
         }
@@ -170,26 +243,49 @@ mod compiled_trace_testing {
         }
     }
 
-    /// A [CompiledTrace] implementation suitable only for testing basic transitions. The `hl` method will return a
-    /// [HotLocation] but all other methods will `panic` if called.
+    /// A [CompiledTrace] implementation suitable only for testing basic transitions and
+    /// invalidation. The `hl` method will return a [HotLocation], and guards can be (un)patched
+    /// and the trace invalidated, but all other methods will `panic` if called.
     #[derive(Debug)]
     pub(crate) struct CompiledTraceTestingBasicTransitions {
+        ctrid: TraceId,
+        mt: Option<Arc<MT>>,
         guard: Guard,
         hl: Weak<Mutex<HotLocation>>,
+        invalidated: AtomicBool,
     }
 
     impl CompiledTraceTestingBasicTransitions {
         pub(crate) fn new(hl: Weak<Mutex<HotLocation>>) -> Self {
             Self {
+                ctrid: TraceId::testing(),
+                mt: None,
+                guard: Guard::new(),
+                hl,
+                invalidated: AtomicBool::new(false),
+            }
+        }
+
+        /// Create a trace with a fresh [TraceId] from `mt`. Unlike [Self::new], the [MT] can be
+        /// retrieved with [CompiledTrace::mt].
+        pub(crate) fn with_mt(mt: &Arc<MT>, hl: Weak<Mutex<HotLocation>>) -> Self {
+            Self {
+                ctrid: mt.next_trace_id(),
+                mt: Some(Arc::clone(mt)),
                 guard: Guard::new(),
                 hl,
+                invalidated: AtomicBool::new(false),
             }
         }
     }
 
     impl CompiledTrace for CompiledTraceTestingBasicTransitions {
         fn ctrid(&self) -> TraceId {
-            TraceId::testing()
+            self.ctrid
+        }
+
+        fn mt(&self) -> &Arc<MT> {
+            self.mt.as_ref().unwrap()
         }
 
         fn safepoint(&self) -> &Option<DeoptSafepoint> {
@@ -205,8 +301,20 @@ mod compiled_trace_testing {
             &self.guard
         }
 
-        fn patch_guard(&self, _gidx: GuardIdx, _target: *const std::ffi::c_void) {
-            panic!();
+        fn patch_guard(&self, gidx: GuardIdx, _target: *const std::ffi::c_void) {
+            assert_eq!(usize::from(gidx), 0);
+        }
+
+        fn unpatch_guard(&self, gidx: GuardIdx) {
+            assert_eq!(usize::from(gidx), 0);
+        }
+
+        fn invalidate(&self) {
+            self.invalidated.store(true, Ordering::SeqCst);
+        }
+
+        fn is_invalidated(&self) -> bool {
+            self.invalidated.load(Ordering::SeqCst)
         }
 
         fn entry(&self) -> *const c_void {
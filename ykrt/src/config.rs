@@ -0,0 +1,306 @@
+//! Per-[MT] run-time configuration.
+//!
+//! An [MTConfig] is created either with [MTConfig::default], which uses yk's built-in defaults, or
+//! with [MTConfig::from_env], which uses the `YK_*` / `YKD_*` environment variables (where set) in
+//! preference to the built-in defaults. Either way, each setting can then be overridden with the
+//! builder-style methods before the [MTConfig] is passed to [MT::with_config].
+//!
+//! [MT]: crate::MT
+//! [MT::with_config]: crate::MT::with_config
+
+use crate::mt::{HotThreshold, TraceCompilationErrorThreshold};
+use std::{cmp, env, error::Error, path::PathBuf};
+
+const DEFAULT_HOT_THRESHOLD: HotThreshold = 131;
+const DEFAULT_SIDETRACE_THRESHOLD: HotThreshold = 5;
+/// How often can a [HotLocation] or [Guard] lead to an error in tracing or compilation before we
+/// give up trying to trace (or compile...) it?
+///
+/// [HotLocation]: crate::location::HotLocation
+/// [Guard]: crate::compile::Guard
+const DEFAULT_TRACECOMPILATION_ERROR_THRESHOLD: TraceCompilationErrorThreshold = 5;
+/// The maximum number of basic blocks the software tracer will record in a single trace before
+/// giving up.
+const DEFAULT_SWT_MAX_TRACE_LEN: usize = 1_000_000;
+
+/// The configuration of an [MT](crate::MT) instance.
+#[derive(Clone, Debug)]
+pub struct MTConfig {
+    pub(crate) hot_threshold: HotThreshold,
+    pub(crate) sidetrace_threshold: HotThreshold,
+    pub(crate) trace_failure_threshold: TraceCompilationErrorThreshold,
+    /// The maximum number of compilation worker threads.
+    pub(crate) compiler_threads: usize,
+    /// Should traces be optimised?
+    pub(crate) opt: bool,
+    /// If `Some`, the name of the tracer to use (`hwt` or `swt`). If `None`, the hardware tracer is
+    /// used if it is available, and the software tracer otherwise.
+    pub(crate) tracer: Option<String>,
+    /// The maximum number of basic blocks the software tracer will record in a single trace.
+    pub(crate) swt_max_trace_len: usize,
+    /// The names of functions which the optimiser treats as allocators.
+    pub(crate) allocators: Vec<String>,
+    /// Where to log to (`-` for stderr) and the logging level (see `Verbosity`).
+    pub(crate) log: (String, u8),
+    /// Where to log IR to (`-` for stderr) and a comma-separated list of the IR phases to log. An
+    /// empty list of phases means that no IR is logged.
+    pub(crate) log_ir: (String, String),
+    /// If `Some`, where to output statistics to (`-` for stderr).
+    pub(crate) log_stats: Option<String>,
+    /// Should traces be compiled on the thread which recorded them, before the control point
+    /// returns?
+    pub(crate) foreground_compilation: bool,
+    /// If `Some`, the directory in which optimised traces are cached between process runs.
+    pub(crate) trace_cache: Option<PathBuf>,
+    /// If `Some`, the directory to which recorded traces are dumped.
+    pub(crate) trace_dump: Option<PathBuf>,
+    /// Should compilation block the thread which requested it?
+    #[cfg(feature = "yk_testing")]
+    pub(crate) serialise_compilation: bool,
+}
+
+impl Default for MTConfig {
+    fn default() -> Self {
+        Self {
+            hot_threshold: DEFAULT_HOT_THRESHOLD,
+            sidetrace_threshold: DEFAULT_SIDETRACE_THRESHOLD,
+            trace_failure_threshold: DEFAULT_TRACECOMPILATION_ERROR_THRESHOLD,
+            compiler_threads: cmp::max(1, num_cpus::get() - 1),
+            opt: true,
+            tracer: None,
+            swt_max_trace_len: DEFAULT_SWT_MAX_TRACE_LEN,
+            allocators: Vec::new(),
+            log: ("-".to_owned(), 1),
+            log_ir: ("-".to_owned(), "".to_owned()),
+            log_stats: None,
+            foreground_compilation: false,
+            trace_cache: None,
+            trace_dump: None,
+            #[cfg(feature = "yk_testing")]
+            serialise_compilation: false,
+        }
+    }
+}
+
+impl MTConfig {
+    /// Create a configuration from the `YK_*` and `YKD_*` environment variables, using the
+    /// built-in defaults for any that are not set.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let mut config = Self::default();
+        if let Ok(s) = env::var("YK_HOT_THRESHOLD") {
+            config.hot_threshold = s
+                .parse::<HotThreshold>()
+                .map_err(|e| format!("Invalid hot threshold '{s}': {e}"))?;
+        }
+        if let Ok(s) = env::var("YK_SIDETRACE_THRESHOLD") {
+            config.sidetrace_threshold = s
+                .parse::<HotThreshold>()
+                .map_err(|e| format!("Invalid sidetrace threshold '{s}': {e}"))?;
+        }
+        if let Ok(s) = env::var("YK_FOREGROUND_COMPILATION") {
+            config.foreground_compilation = s == "1";
+        }
+        if let Ok(s) = env::var("YK_TRACE_CACHE") {
+            config.trace_cache = Some(PathBuf::from(s));
+        }
+        if let Ok(s) = env::var("YK_TRACER") {
+            config.tracer = Some(s);
+        }
+        if let Ok(s) = env::var("YK_SWT_MAX_TRACE_LEN") {
+            let swt_max_trace_len = s
+                .parse::<usize>()
+                .map_err(|e| format!("Invalid software tracer maximum trace length '{s}': {e}"))?;
+            if swt_max_trace_len < 1 {
+                return Err("YK_SWT_MAX_TRACE_LEN must be >= 1".into());
+            }
+            config.swt_max_trace_len = swt_max_trace_len;
+        }
+        if let Ok(s) = env::var("YK_ALLOCATORS") {
+            config.allocators = s
+                .split(',')
+                .filter(|x| !x.is_empty())
+                .map(|x| x.to_owned())
+                .collect();
+        }
+        if let Ok(s) = env::var("YKD_OPT") {
+            config.opt = s != "0";
+        }
+        if let Ok(s) = env::var("YKD_LOG") {
+            let (path, level) = match s.split(':').collect::<Vec<_>>()[..] {
+                [path, level] => (path, level),
+                [level] => ("-", level),
+                [..] => return Err("YKD_LOG must be of the format `[<path|->:]<level>".into()),
+            };
+            let level = level
+                .parse::<u8>()
+                .map_err(|e| format!("Invalid YKD_LOG level '{s}': {e}"))?;
+            config.log = (path.to_owned(), level);
+        }
+        #[cfg(feature = "ykd")]
+        if let Ok(s) = env::var("YKD_LOG_IR") {
+            let (path, phases) =
+                match s.split(':').collect::<Vec<_>>()[..] {
+                    [path, phases] => (path, phases),
+                    [phases] => ("-", phases),
+                    _ => return Err(
+                        "YKD_LOG_IR must be of the format '[<path>:]<irstage_1>[,...,<irstage_n>]'"
+                            .into(),
+                    ),
+                };
+            config.log_ir = (path.to_owned(), phases.to_owned());
+        }
+        if let Ok(s) = env::var("YKD_LOG_STATS") {
+            config.log_stats = Some(s);
+        }
+        if let Ok(s) = env::var("YKD_TRACE_DUMP") {
+            config.trace_dump = Some(PathBuf::from(s));
+        }
+        #[cfg(feature = "yk_testing")]
+        if let Ok(s) = env::var("YKD_SERIALISE_COMPILATION") {
+            config.serialise_compilation = s == "1";
+        }
+        Ok(config)
+    }
+
+    /// Set the threshold at which `Location`'s are considered hot.
+    pub fn hot_threshold(mut self, hot_threshold: HotThreshold) -> Self {
+        self.hot_threshold = hot_threshold;
+        self
+    }
+
+    /// Set the threshold at which guard failures are considered hot and side-tracing should start.
+    pub fn sidetrace_threshold(mut self, sidetrace_threshold: HotThreshold) -> Self {
+        self.sidetrace_threshold = sidetrace_threshold;
+        self
+    }
+
+    /// Set the threshold at which a `Location` from which tracing has failed multiple times is
+    /// marked as "do not try tracing again".
+    ///
+    /// # Panics
+    ///
+    /// If `trace_failure_threshold` is 0.
+    pub fn trace_failure_threshold(
+        mut self,
+        trace_failure_threshold: TraceCompilationErrorThreshold,
+    ) -> Self {
+        if trace_failure_threshold < 1 {
+            panic!("Trace failure threshold must be >= 1.");
+        }
+        self.trace_failure_threshold = trace_failure_threshold;
+        self
+    }
+
+    /// Set the maximum number of threads that will be used to compile traces.
+    ///
+    /// # Panics
+    ///
+    /// If `compiler_threads` is 0.
+    pub fn compiler_threads(mut self, compiler_threads: usize) -> Self {
+        if compiler_threads < 1 {
+            panic!("The number of compiler threads must be >= 1.");
+        }
+        self.compiler_threads = compiler_threads;
+        self
+    }
+
+    /// Turn trace optimisation on or off.
+    pub fn optimise(mut self, opt: bool) -> Self {
+        self.opt = opt;
+        self
+    }
+
+    /// Record traces with the tracer `tracer` (`hwt` for the hardware tracer or `swt` for the
+    /// software tracer). If `tracer` is `None`, the hardware tracer is used if it is available, and
+    /// the software tracer otherwise. A tracer which is not available in this build of yk, or on
+    /// this machine, will cause [MT::with_config] to fail.
+    ///
+    /// [MT::with_config]: crate::MT::with_config
+    pub fn tracer(mut self, tracer: Option<&str>) -> Self {
+        self.tracer = tracer.map(|x| x.to_owned());
+        self
+    }
+
+    /// Set the maximum number of basic blocks the software tracer will record in a single trace.
+    /// Tracing is aborted if a trace grows longer than this. Has no effect on other tracers.
+    ///
+    /// # Panics
+    ///
+    /// If `swt_max_trace_len` is 0.
+    pub fn swt_max_trace_len(mut self, swt_max_trace_len: usize) -> Self {
+        if swt_max_trace_len < 1 {
+            panic!("The software tracer's maximum trace length must be >= 1.");
+        }
+        self.swt_max_trace_len = swt_max_trace_len;
+        self
+    }
+
+    /// Treat the function `name` as an allocator: it must take a single integer argument (a size
+    /// in bytes) and return a pointer to a fresh, never `NULL`, block of memory of that size.
+    /// Allocations which the optimiser can prove never escape a trace are then removed from it.
+    pub fn allocator(mut self, name: &str) -> Self {
+        self.allocators.push(name.to_owned());
+        self
+    }
+
+    /// Log general information to `path` (`-` for stderr) at verbosity `level` (with the same
+    /// meaning as in `YKD_LOG`). An invalid `level` will cause [MT::with_config] to fail.
+    ///
+    /// [MT::with_config]: crate::MT::with_config
+    pub fn log(mut self, path: &str, level: u8) -> Self {
+        self.log = (path.to_owned(), level);
+        self
+    }
+
+    /// Log the IR phases `phases` (a comma-separated list, with the same meaning as in
+    /// `YKD_LOG_IR`) to `path` (`-` for stderr). If `phases` is empty, no IR will be logged. An
+    /// invalid phase will cause [MT::with_config] to fail. Note that IR is only ever logged if the
+    /// `ykd` feature is enabled.
+    ///
+    /// [MT::with_config]: crate::MT::with_config
+    pub fn log_ir(mut self, path: &str, phases: &str) -> Self {
+        self.log_ir = (path.to_owned(), phases.to_owned());
+        self
+    }
+
+    /// Output statistics to `path` (`-` for stderr) when the [MT](crate::MT) is shut down. If
+    /// `path` is `None`, statistics will not be collected.
+    pub fn log_stats(mut self, path: Option<&str>) -> Self {
+        self.log_stats = path.map(|x| x.to_owned());
+        self
+    }
+
+    /// If `true`, traces are compiled on the thread which recorded them, before the control point
+    /// which stopped tracing returns. This makes it deterministic when a trace becomes available
+    /// for execution, at the cost of pausing the interpreter while compilation occurs.
+    pub fn foreground_compilation(mut self, foreground_compilation: bool) -> Self {
+        self.foreground_compilation = foreground_compilation;
+        self
+    }
+
+    /// Cache optimised traces in the directory `dir` (which will be created if it does not exist),
+    /// so that later runs of the interpreter using the same directory can reuse them rather than
+    /// building and optimising them again. If `dir` is `None`, traces are not cached.
+    pub fn trace_cache(mut self, dir: Option<&str>) -> Self {
+        self.trace_cache = dir.map(PathBuf::from);
+        self
+    }
+
+    /// Dump each recorded root trace to a new file in the directory `dir` (which will be created
+    /// if it does not exist), so that it can later be replayed with [MT::replay_trace]. If `dir`
+    /// is `None`, traces are not dumped.
+    ///
+    /// [MT::replay_trace]: crate::MT::replay_trace
+    pub fn trace_dump(mut self, dir: Option<&str>) -> Self {
+        self.trace_dump = dir.map(PathBuf::from);
+        self
+    }
+
+    /// If `true`, threads which request a trace to be compiled will block until compilation has
+    /// completed.
+    #[cfg(feature = "yk_testing")]
+    pub fn serialise_compilation(mut self, serialise_compilation: bool) -> Self {
+        self.serialise_compilation = serialise_compilation;
+        self
+    }
+}
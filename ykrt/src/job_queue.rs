@@ -1,20 +1,45 @@
 //! The job queue. This runs 1 or more worker threads and has them run compilation jobs as
 //! appropriate.
 
-use crate::mt::{TraceId, MT};
+use crate::{
+    compile::{CancellationToken, CompiledTrace},
+    config::MTConfig,
+    mt::{TraceId, MT},
+};
 use parking_lot::{Condvar, Mutex, MutexGuard};
-#[cfg(feature = "yk_testing")]
-use std::env;
 use std::{
-    cmp,
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
     thread::{self, JoinHandle},
 };
 
+/// How often the [Location] or [Guard] that a job was created for has been hit since the job's
+/// trace was recorded. This is shared between the job and the [HotLocation] / [Guard], so that the
+/// job's priority increases as it waits in the queue.
+///
+/// [Location]: crate::location::Location
+/// [HotLocation]: crate::location::HotLocation
+/// [Guard]: crate::compile::Guard
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Hotness(Arc<AtomicU64>);
+
+impl Hotness {
+    /// Record that the [Location] or [Guard] this job was created for has been hit again.
+    ///
+    /// [Location]: crate::location::Location
+    /// [Guard]: crate::compile::Guard
+    pub(crate) fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
 /// A job for the job queue.
 pub(crate) struct Job {
     /// The function we expect to run for this job when it is ready.
@@ -23,55 +48,115 @@ pub(crate) struct Job {
     connector_tid: Option<TraceId>,
     /// If `connector_tid` failed to compile, this function will be run.
     connector_failed: Box<dyn FnOnce() + Send>,
+    /// The priority of this job: of the jobs which are ready to run, the one with the highest
+    /// hotness is run first.
+    hotness: Hotness,
+    /// The traces that the trace compiled by this job will depend upon: if any of these are
+    /// invalidated, this job is cancelled.
+    deps: Vec<TraceId>,
+    /// Allows this job to be cancelled. `main` and `connector_failed` should check this token.
+    cancel: CancellationToken,
 }
 
 impl Job {
     /// Create a new job with a `main` method. If `connector_tid` is `Some`, `main` will only be
     /// run when `connector_tid` has compiled. If `connector_tid` fails to compile, then
-    /// `connector_failed` will be run (and `main` will not be run).
+    /// `connector_failed` will be run (and `main` will not be run). Jobs with a higher `hotness`
+    /// are run before those with a lower `hotness`. `cancel` is cancelled if the queue is shut
+    /// down or if any of the traces in `deps` are invalidated: note that cancelled jobs are still
+    /// run (via `main` or `connector_failed`), so that they can clean up after themselves.
     pub(crate) fn new(
         main: Box<dyn FnOnce() + Send>,
         connector_tid: Option<TraceId>,
         connector_failed: Box<dyn FnOnce() + Send>,
+        hotness: Hotness,
+        deps: Vec<TraceId>,
+        cancel: CancellationToken,
     ) -> Self {
         Self {
             main,
             connector_tid,
             connector_failed,
+            hotness,
+            deps,
+            cancel,
         }
     }
 }
 
+/// Return the index of the job in `queue` which should be run next: of those jobs which have no
+/// connector trace ID, or whose connector trace ID is in `compiled_traces`, this is the hottest
+/// one, with ties broken in favour of the job which was queued first.
+fn next_job(
+    queue: &VecDeque<Job>,
+    compiled_traces: &HashMap<TraceId, Arc<dyn CompiledTrace>>,
+) -> Option<usize> {
+    queue
+        .iter()
+        .enumerate()
+        .filter(|(_, job)| match &job.connector_tid {
+            Some(x) => compiled_traces.contains_key(x),
+            None => true,
+        })
+        .map(|(i, job)| (i, job.hotness.get()))
+        .max_by(|(i1, h1), (i2, h2)| h1.cmp(h2).then(i2.cmp(i1)))
+        .map(|(i, _)| i)
+}
+
 pub(crate) struct JobQueue {
-    /// The hard cap on the number of worker threads.
+    /// The cap on the number of worker threads. This can be changed at run-time: if it is reduced
+    /// below [Self::active_worker_threads], worker threads exit as soon as they are idle until the
+    /// cap is respected.
     max_worker_threads: AtomicUsize,
+    /// How many worker threads are running?
+    active_worker_threads: AtomicUsize,
     /// How many worker threads are waiting for work?
     idle_worker_threads: AtomicUsize,
-    /// [JoinHandle]s to each worker thread so that when an [MT] value is dropped, we can try
+    /// If `true`, compilation jobs are run on the thread which calls [Self::push], and are run to
+    /// completion before [Self::push] returns.
+    foreground_compilation: bool,
+    /// If `true`, compilation jobs are run (on a fresh thread) to completion before [Self::push]
+    /// returns.
+    #[cfg(feature = "yk_testing")]
+    serialise_compilation: bool,
+    /// [JoinHandle]s to each worker thread (including those which have exited because
+    /// [Self::max_worker_threads] was reduced) so that when an [MT] value is dropped, we can try
     /// joining each worker thread and see if it caused an error or not. If it did, we can
     /// percolate the error upwards, making it more likely that the main thread exits with an
     /// error. In other words, this [Vec] makes it harder for errors to be missed.
     worker_threads: Mutex<Vec<JoinHandle<()>>>,
-    /// The ordered queue of compilation worker functions, each a pair `(Option<connector_tid>,
-    /// job)`. Before `job` is run, `connector_tid`, if it is `Some`, must be present in
-    /// [MT::compiled_traces].
+    /// The queue of compilation jobs. Before a job is run, its `connector_tid`, if it is `Some`,
+    /// must be present in [MT::compiled_traces]. Jobs are not run in queue order: see [next_job].
     queue: Arc<(Condvar, Mutex<VecDeque<Job>>)>,
+    /// The `deps` and cancellation tokens of jobs which are currently running.
+    running: Mutex<Vec<(Vec<TraceId>, CancellationToken)>>,
+    /// Has [Self::shutdown] been called? If so, any newly pushed jobs are cancelled immediately.
+    shutdown: AtomicBool,
 }
 
 impl JobQueue {
-    pub(crate) fn new() -> Arc<Self> {
+    pub(crate) fn new(config: &MTConfig) -> Arc<Self> {
         Arc::new(Self {
             queue: Arc::new((Condvar::new(), Mutex::new(VecDeque::new()))),
-            max_worker_threads: AtomicUsize::new(cmp::max(1, num_cpus::get() - 1)),
+            max_worker_threads: AtomicUsize::new(config.compiler_threads),
+            active_worker_threads: AtomicUsize::new(0),
             worker_threads: Mutex::new(Vec::new()),
             idle_worker_threads: AtomicUsize::new(0),
+            foreground_compilation: config.foreground_compilation,
+            #[cfg(feature = "yk_testing")]
+            serialise_compilation: config.serialise_compilation,
+            running: Mutex::new(Vec::new()),
+            shutdown: AtomicBool::new(false),
         })
     }
 
-    /// Shut the queue down to the extent possible (running jobs cannot be cancelled and will
-    /// continue running). The main utility of this function is that it can detect worker threads
-    /// that have failed and, if we're lucky, get them to output what went wrong.
+    /// Shut the queue down to the extent possible. All queued and running jobs are cancelled,
+    /// though since cancellation is cooperative, running jobs may continue for a short while. The
+    /// other utility of this function is that it can detect worker threads that have failed and,
+    /// if we're lucky, get them to output what went wrong.
     pub(crate) fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        self.cancel_where(|_| true);
         let mut lk = self.worker_threads.lock();
         for hdl in lk.drain(..) {
             if hdl.is_finished()
@@ -85,39 +170,58 @@ impl JobQueue {
         }
     }
 
+    /// Cancel all jobs that depend upon the trace `ctrid`.
+    pub(crate) fn cancel_dependents(&self, ctrid: TraceId) {
+        self.cancel_where(|deps| deps.contains(&ctrid));
+    }
+
+    /// Cancel all queued and running jobs whose `deps` satisfy `f`.
+    fn cancel_where(&self, f: impl Fn(&[TraceId]) -> bool) {
+        let (cv, mtx) = &*self.queue;
+        let mut cancelled = false;
+        for job in mtx.lock().iter().filter(|job| f(&job.deps)) {
+            job.cancel.cancel();
+            cancelled = true;
+        }
+        for (_, cancel) in self.running.lock().iter().filter(|(deps, _)| f(deps)) {
+            cancel.cancel();
+        }
+        if cancelled {
+            // Wake worker threads up so that cancelled jobs can be cleaned up promptly.
+            cv.notify_all();
+        }
+    }
+
+    /// Run `job`'s main function by passing it to `f`, allowing `job` to be cancelled while it is
+    /// running.
+    fn run(&self, job: Job, f: impl FnOnce(Box<dyn FnOnce() + Send>)) {
+        let Job {
+            main, deps, cancel, ..
+        } = job;
+        self.running.lock().push((deps, cancel.clone()));
+        f(main);
+        let mut lk = self.running.lock();
+        let i = lk.iter().position(|(_, x)| x.ptr_eq(&cancel)).unwrap();
+        lk.swap_remove(i);
+    }
+
     /// Queue `job` to be run on a worker thread.
     pub(crate) fn push(self: &Arc<Self>, mt: &Arc<MT>, job: Job) {
+        if self.shutdown.load(Ordering::Relaxed) {
+            job.cancel.cancel();
+        }
+
         #[cfg(feature = "yk_testing")]
-        if let Ok(true) = env::var("YKD_SERIALISE_COMPILATION").map(|x| x.as_str() == "1") {
+        if self.serialise_compilation {
             // To ensure that we properly test that compilation can occur in another thread, we
             // spin up a new thread for each compilation. This is only acceptable because a)
             // `SERIALISE_COMPILATION` is an internal yk testing feature b) when we use it we're
             // checking correctness, not performance.
-            if let Some(tid) = job.connector_tid
-                && !mt.compiled_traces.lock().contains_key(&tid)
-            {
-                self.queue.1.lock().push_back(job);
-                return;
-            }
-            thread::spawn(job.main).join().unwrap();
-            loop {
-                let mut lk = self.queue.1.lock();
-                let cnd = {
-                    let ct_lk = mt.compiled_traces.lock();
-                    lk.iter().position(|x| match &x.connector_tid {
-                        Some(x) => ct_lk.contains_key(x),
-                        None => true,
-                    })
-                };
-                match cnd {
-                    Some(x) => {
-                        let job = lk.remove(x).unwrap();
-                        drop(lk);
-                        thread::spawn(job.main).join().unwrap();
-                    }
-                    None => break,
-                }
-            }
+            self.push_synchronous(mt, job, |main| thread::spawn(main).join().unwrap());
+            return;
+        }
+        if self.foreground_compilation {
+            self.push_synchronous(mt, job, |main| main());
             return;
         }
 
@@ -126,57 +230,161 @@ impl JobQueue {
         mtx.lock().push_back(job);
         cv.notify_one();
 
-        // Is there an idle worker thread that can take the job on?
+        // Is there an idle worker thread that can take the job on? If not, try spinning another up.
         if self.idle_worker_threads.load(Ordering::Relaxed) == 0 {
-            // Do we have enough active worker threads? If not, spin another up.
-            let mut lk = self.worker_threads.lock();
-            if lk.len() < self.max_worker_threads.load(Ordering::Relaxed) {
-                self.idle_worker_threads.fetch_add(1, Ordering::Relaxed);
-                // We only keep a weak reference alive to `MT`, as otherwise an active compiler job
-                // causes it to never be dropped.
-                let mt_wk = Arc::downgrade(mt);
-                let self_cl = Arc::clone(self);
-                let jq = Arc::clone(&self.queue);
-                let hdl = thread::spawn(move || {
-                    let (cv, mtx) = &*jq;
-                    let mut lk = mtx.lock();
-                    // If the strong count for `mt` is 0 then it has been dropped and there is no
-                    // point trying to do further work, even if there is work in the queue.
-                    while let Some(mt_st) = mt_wk.upgrade() {
-                        // Search through the queue looking for the first job we can compile (i.e.
-                        // there is no connector trace ID, or the connector trade ID has been
-                        // compiled).
-                        self_cl.idle_worker_threads.fetch_sub(1, Ordering::Relaxed);
-                        let cnd = {
-                            let ct_lk = mt_st.compiled_traces.lock();
-                            lk.iter().position(|x| match &x.connector_tid {
-                                Some(x) => ct_lk.contains_key(x),
-                                None => true,
-                            })
-                        };
-                        match cnd {
-                            Some(x) => {
-                                let job = lk.remove(x).unwrap();
-                                MutexGuard::unlocked(&mut lk, job.main);
-                                self_cl.idle_worker_threads.fetch_add(1, Ordering::Relaxed);
-                            }
-                            None => {
-                                self_cl.idle_worker_threads.fetch_add(1, Ordering::Relaxed);
-                                cv.wait(&mut lk);
-                            }
-                        }
+            self.spawn_worker(mt);
+        }
+    }
+
+    /// Are jobs run to completion before [Self::push] returns (rather than by worker threads)?
+    fn is_synchronous(&self) -> bool {
+        #[cfg(feature = "yk_testing")]
+        if self.serialise_compilation {
+            return true;
+        }
+        self.foreground_compilation
+    }
+
+    /// Run `job`, by passing its main function to `f`, before returning. If `job` is waiting for a
+    /// connector trace which hasn't yet compiled, `job` is instead queued, and will be run by a
+    /// later call to this function once the connector trace has compiled.
+    fn push_synchronous(&self, mt: &Arc<MT>, job: Job, f: impl Fn(Box<dyn FnOnce() + Send>)) {
+        if let Some(tid) = job.connector_tid
+            && !mt.compiled_traces.lock().contains_key(&tid)
+        {
+            self.queue.1.lock().push_back(job);
+            return;
+        }
+        self.run(job, &f);
+        loop {
+            let mut lk = self.queue.1.lock();
+            let cnd = next_job(&lk, &mt.compiled_traces.lock());
+            match cnd {
+                Some(x) => {
+                    let job = lk.remove(x).unwrap();
+                    drop(lk);
+                    self.run(job, &f);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Return the current cap on the number of worker threads.
+    pub(crate) fn max_worker_threads(&self) -> usize {
+        self.max_worker_threads.load(Ordering::Relaxed)
+    }
+
+    /// Set the cap on the number of worker threads to `max`. If `max` is lower than the number of
+    /// running worker threads, the excess threads will exit when they next become idle (i.e.
+    /// running jobs are not interrupted). If `max` is higher than the number of running worker
+    /// threads, new worker threads are spun up for any jobs waiting in the queue.
+    ///
+    /// # Panics
+    ///
+    /// If `max` is 0.
+    pub(crate) fn set_max_worker_threads(self: &Arc<Self>, mt: &Arc<MT>, max: usize) {
+        if max < 1 {
+            panic!("The number of compiler threads must be >= 1.");
+        }
+        self.max_worker_threads.store(max, Ordering::Relaxed);
+        if self.is_synchronous() {
+            return;
+        }
+        // Wake up idle worker threads so that any in excess of the new cap can exit.
+        self.queue.0.notify_all();
+        let waiting = self
+            .len()
+            .saturating_sub(self.idle_worker_threads.load(Ordering::Relaxed));
+        for _ in 0..waiting {
+            if !self.spawn_worker(mt) {
+                break;
+            }
+        }
+    }
+
+    /// How many jobs are waiting to be run? Note that this does not include jobs that are
+    /// currently being run.
+    pub(crate) fn len(&self) -> usize {
+        self.queue.1.lock().len()
+    }
+
+    /// How many worker threads are running?
+    #[cfg(test)]
+    pub(crate) fn active_worker_threads(&self) -> usize {
+        self.active_worker_threads.load(Ordering::Relaxed)
+    }
+
+    /// If the number of worker threads is below [Self::max_worker_threads], spin up a new worker
+    /// thread and return `true`; otherwise return `false`.
+    fn spawn_worker(self: &Arc<Self>, mt: &Arc<MT>) -> bool {
+        let mut lk = self.worker_threads.lock();
+        if self.active_worker_threads.load(Ordering::Relaxed)
+            >= self.max_worker_threads.load(Ordering::Relaxed)
+        {
+            return false;
+        }
+        self.active_worker_threads.fetch_add(1, Ordering::Relaxed);
+        self.idle_worker_threads.fetch_add(1, Ordering::Relaxed);
+        // We only keep a weak reference alive to `MT`, as otherwise an active compiler job causes
+        // it to never be dropped.
+        let mt_wk = Arc::downgrade(mt);
+        let self_cl = Arc::clone(self);
+        let jq = Arc::clone(&self.queue);
+        let hdl = thread::spawn(move || {
+            let (cv, mtx) = &*jq;
+            let mut lk = mtx.lock();
+            // If the strong count for `mt` is 0 then it has been dropped and there is no point
+            // trying to do further work, even if there is work in the queue.
+            while let Some(mt_st) = mt_wk.upgrade() {
+                self_cl.idle_worker_threads.fetch_sub(1, Ordering::Relaxed);
+                // If there are more worker threads than the cap allows, this thread exits.
+                if self_cl.retire_worker() {
+                    return;
+                }
+                // Search through the queue looking for the hottest job we can compile (i.e.
+                // there is no connector trace ID, or the connector trade ID has been compiled).
+                let cnd = next_job(&lk, &mt_st.compiled_traces.lock());
+                match cnd {
+                    Some(x) => {
+                        let job = lk.remove(x).unwrap();
+                        MutexGuard::unlocked(&mut lk, || self_cl.run(job, |main| main()));
+                        self_cl.idle_worker_threads.fetch_add(1, Ordering::Relaxed);
                     }
-                });
-                lk.push(hdl);
+                    None => {
+                        self_cl.idle_worker_threads.fetch_add(1, Ordering::Relaxed);
+                        cv.wait(&mut lk);
+                    }
+                }
+            }
+        });
+        lk.push(hdl);
+        true
+    }
+
+    /// If there are more running worker threads than [Self::max_worker_threads] allows, record
+    /// that the calling worker thread is about to exit and return `true`; otherwise return
+    /// `false`.
+    fn retire_worker(&self) -> bool {
+        let mut active = self.active_worker_threads.load(Ordering::Relaxed);
+        while active > self.max_worker_threads.load(Ordering::Relaxed) {
+            match self.active_worker_threads.compare_exchange_weak(
+                active,
+                active - 1,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(x) => active = x,
             }
         }
+        false
     }
 
     /// Notify the queue that `trid` has successfully completed. If there are other jobs waiting on
     /// `trid`, this function will try to have them run.
     pub(crate) fn notify_success(&self, trid: TraceId) {
-        #[cfg(feature = "yk_testing")]
-        if let Ok(true) = env::var("YKD_SERIALISE_COMPILATION").map(|x| x.as_str() == "1") {
+        if self.is_synchronous() {
             return;
         }
         // Since waking worker threads up is quite disruptive for the system, only send a wake-up
@@ -200,7 +408,7 @@ impl JobQueue {
 
     /// Notify the queue that `trid` has failed. If there are other jobs waiting on `trid`, this
     /// function will inform them that they cannot run.
-    pub(crate) fn notify_failure(self: &Arc<Self>, _mt: &Arc<MT>, trid: TraceId) {
+    pub(crate) fn notify_failure(self: &Arc<Self>, trid: TraceId) {
         let mut removed = Vec::new();
         let mut i = 0;
         let mut lk = self.queue.1.lock();
@@ -9,7 +9,9 @@
 #![allow(clippy::upper_case_acronyms)]
 
 pub(crate) mod aotsmp;
+mod assumptions;
 pub mod compile;
+mod config;
 mod job_queue;
 mod location;
 mod log;
@@ -18,7 +20,9 @@ pub mod promote;
 pub(crate) mod stack;
 pub(crate) mod thread_intercept;
 pub mod trace;
+mod trace_gc;
 
+pub use self::config::MTConfig;
 pub use self::location::Location;
 pub use self::mt::{HotThreshold, MTThread, MT};
 use std::ffi::{c_char, CStr};
@@ -10,6 +10,7 @@ use std::{
 
 use crate::{
     compile::CompiledTrace,
+    job_queue::Hotness,
     mt::{HotThreshold, TraceCompilationErrorThreshold, TraceId, MT},
 };
 use parking_lot::Mutex;
@@ -224,6 +225,7 @@ impl Location {
                     kind: HotLocationKind::Counting(count),
                     tracecompilation_errors: 0,
                     debug_str: Some(s),
+                    invalidated: None,
                 };
                 if self.count_to_hot_location(count, hl).is_none() {
                     // We clashed with another thread.
@@ -233,6 +235,30 @@ impl Location {
         }
     }
 
+    /// If `self` has a compiled trace, invalidate it (and any traces which depend on it), so that
+    /// future executions of the control point fall back to the interpreter and, in time, retrace
+    /// `self`. If a trace starting at `self` is currently being recorded or compiled, it is
+    /// discarded rather than being made available for execution. Otherwise this is a no-op.
+    ///
+    /// Note that threads which are currently executing the compiled trace will continue to do so
+    /// until they next leave JIT code (e.g. because a guard fails).
+    pub fn invalidate(&self) {
+        let Some(hl) = self.hot_location() else {
+            return;
+        };
+        let mut lk = hl.lock();
+        let ctr = match &lk.kind {
+            HotLocationKind::Compiled(ctr) => Arc::clone(ctr),
+            HotLocationKind::Compiling(trid, _) | HotLocationKind::Tracing(trid) => {
+                lk.invalidated = Some(*trid);
+                return;
+            }
+            _ => return,
+        };
+        drop(lk);
+        ctr.mt().invalidate_trace(ctr.ctrid());
+    }
+
     /// If `self` has a [HotLocation] return a reference to the `Mutex` that directly wraps it, or
     /// `None` otherwise.
     pub(crate) fn hot_location(&self) -> Option<&Mutex<HotLocation>> {
@@ -285,6 +311,9 @@ pub(crate) struct HotLocation {
     pub(crate) tracecompilation_errors: TraceCompilationErrorThreshold,
     /// An optional debug string for this hot location.
     pub(crate) debug_str: Option<String>,
+    /// If [Location::invalidate] was called while the trace with this ID was being recorded or
+    /// compiled, that trace must not be linked into this hot location.
+    pub(crate) invalidated: Option<TraceId>,
 }
 
 impl HotLocation {
@@ -306,8 +335,9 @@ pub(crate) enum HotLocationKind {
     /// HotLocation.
     Compiled(Arc<dyn CompiledTrace>),
     /// A trace for this HotLocation is being compiled in another trace. When compilation is
-    /// complete, the compiling thread will update the state of this HotLocation.
-    Compiling(TraceId),
+    /// complete, the compiling thread will update the state of this HotLocation. The [Hotness]
+    /// records how often this HotLocation is hit while waiting for compilation to complete.
+    Compiling(TraceId, Hotness),
     /// Because of a failure in compiling / tracing, we have reentered the `Counting` state. This
     /// can be seen as a way of implementing back-off in the face of errors.
     Counting(HotThreshold),
@@ -322,7 +352,7 @@ impl std::fmt::Debug for HotLocationKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Compiled(_) => write!(f, "Compiled"),
-            Self::Compiling(_) => write!(f, "Compiling"),
+            Self::Compiling(..) => write!(f, "Compiling"),
             Self::Counting(_) => write!(f, "Counting"),
             Self::DontTrace => write!(f, "DontTrace"),
             Self::Tracing(_) => write!(f, "Tracing"),
@@ -1,13 +1,17 @@
-//! The implementation of the `YKD_LOG_*` environment variables.
+//! The implementation of the logging configured by `YKD_LOG_*` environment variables (or the
+//! equivalent [MTConfig] settings).
 //!
 //! Note that some of these features are only meaningfully available when the `ykd` feature is
 //! available: otherwise we expose no-op functions.
 
 #[cfg(feature = "ykd")]
 use parking_lot::Mutex;
-use std::{env, error::Error, fs::File, io::Write, path::PathBuf};
+#[cfg(feature = "ykd")]
+use std::collections::HashSet;
+use std::{error::Error, fs::File, io::Write, path::PathBuf};
 use strum::{EnumCount, FromRepr};
 
+use crate::config::MTConfig;
 #[cfg(feature = "ykd")]
 use crate::location::HotLocation;
 
@@ -35,42 +39,40 @@ pub(crate) struct Log {
     /// The path to write to. A value of `None` should default to the platform specific standard
     /// for logging (e.g. stderr).
     path: Option<PathBuf>,
+    /// If `Some`, the path to write IR to (where `None` means stderr) and the IR phases to log.
+    #[cfg(feature = "ykd")]
+    log_ir: Option<(Option<PathBuf>, HashSet<IRPhase>)>,
 }
 
 impl Log {
-    pub(crate) fn new() -> Result<Self, Box<dyn Error>> {
-        match env::var("YKD_LOG") {
-            Ok(s) => {
-                let (path, level) = match s.split(':').collect::<Vec<_>>()[..] {
-                    [path, level] => {
-                        if path == "-" {
-                            (None, level)
-                        } else {
-                            let path = PathBuf::from(path);
-                            // If there's an existing log file, truncate (i.e. empty it), so that later
-                            // appends to the log aren't appending to a previous log run.
-                            File::create(&path).ok();
-                            (Some(path), level)
-                        }
-                    }
-                    [level] => (None, level),
-                    [..] => return Err("YKD_LOG must be of the format `[<path|->:]<level>".into()),
-                };
-                let level = level
-                    .parse::<u8>()
-                    .map_err(|e| format!("Invalid YKD_LOG level '{s}': {e}"))?;
-                // This unwrap can only fail dynamically if we've got the types wrong statically
-                // (i.e. it'll fail as soon as this code is executed for the first time).
-                let max_level = u8::try_from(Verbosity::COUNT).unwrap() - 1;
-                let level = Verbosity::from_repr(level)
-                    .ok_or_else(|| format!("YKD_LOG level {level} exceeds maximum {max_level}"))?;
-                Ok(Self { path, level })
+    pub(crate) fn new(config: &MTConfig) -> Result<Self, Box<dyn Error>> {
+        let (path, level) = &config.log;
+        // This unwrap can only fail dynamically if we've got the types wrong statically (i.e.
+        // it'll fail as soon as this code is executed for the first time).
+        let max_level = u8::try_from(Verbosity::COUNT).unwrap() - 1;
+        let level = Verbosity::from_repr(*level)
+            .ok_or_else(|| format!("YKD_LOG level {level} exceeds maximum {max_level}"))?;
+
+        #[cfg(feature = "ykd")]
+        let log_ir = {
+            let (path, phases) = &config.log_ir;
+            if phases.is_empty() {
+                None
+            } else {
+                let phases = phases
+                    .split(',')
+                    .map(IRPhase::from_str)
+                    .collect::<Result<HashSet<_>, _>>()?;
+                Some((log_path(path), phases))
             }
-            Err(_) => Ok(Self {
-                path: None,
-                level: Verbosity::Error,
-            }),
-        }
+        };
+
+        Ok(Self {
+            path: log_path(path),
+            level,
+            #[cfg(feature = "ykd")]
+            log_ir,
+        })
     }
 
     #[cfg(feature = "ykd")]
@@ -133,78 +135,71 @@ pub(crate) enum IRPhase {
     AsmFull,
 }
 
-#[cfg(not(feature = "ykd"))]
-mod internals {
-    use super::IRPhase;
-    pub(crate) fn should_log_ir(_: IRPhase) -> bool {
-        false
-    }
-    pub(crate) fn log_ir(_: &str) {}
-}
-
 #[cfg(feature = "ykd")]
-mod internals {
-    use super::IRPhase;
-    use std::{collections::HashSet, env, error::Error, fs::File, io::Write, sync::LazyLock};
-
-    static LOG_IR: LazyLock<Option<(String, HashSet<IRPhase>)>> = LazyLock::new(|| {
-        let mut log_phases = HashSet::new();
-        if let Ok(x) = env::var("YKD_LOG_IR") {
-            let (path, phases) = match x.split(':').collect::<Vec<_>>().as_slice() {
-                [path, phases] => (*path, *phases),
-                [phases] => ("-", *phases),
-                _ => panic!(
-                    "YKD_LOG_IR must be of the format '[<path>:]<irstage_1>[,...,<irstage_n>]'"
-                ),
-            };
-            for x in phases.split(',') {
-                log_phases.insert(IRPhase::from_str(x).unwrap());
-            }
-            if path != "-" {
-                // If there's an existing log file, truncate (i.e. empty it), so that later
-                // appends to the log aren't appending to a previous log run.
-                File::create(path).ok();
-            }
-            Some((path.to_string(), log_phases))
-        } else {
-            None
-        }
-    });
-
-    impl IRPhase {
-        fn from_str(s: &str) -> Result<Self, Box<dyn Error>> {
-            match s {
-                "aot" => Ok(Self::AOT),
-                "debugstrs" => Ok(Self::DebugStrs),
-                "jit-pre-opt" => Ok(Self::PreOpt),
-                "jit-post-opt" => Ok(Self::PostOpt),
-                "jit-asm" => Ok(Self::Asm),
-                "jit-asm-full" => Ok(Self::AsmFull),
-                _ => Err(format!("Invalid YKD_LOG_IR value: {s}").into()),
-            }
+impl IRPhase {
+    fn from_str(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s {
+            "aot" => Ok(Self::AOT),
+            "debugstrs" => Ok(Self::DebugStrs),
+            "jit-pre-opt" => Ok(Self::PreOpt),
+            "jit-post-opt" => Ok(Self::PostOpt),
+            "jit-asm" => Ok(Self::Asm),
+            "jit-asm-full" => Ok(Self::AsmFull),
+            _ => Err(format!("Invalid YKD_LOG_IR value: {s}").into()),
         }
     }
+}
 
-    pub(crate) fn should_log_ir(phase: IRPhase) -> bool {
-        if let Some(true) = LOG_IR.as_ref().map(|(_, phases)| phases.contains(&phase)) {
+impl Log {
+    /// Should IR for phase `phase` be logged?
+    #[cfg(feature = "ykd")]
+    pub(crate) fn should_log_ir(&self, phase: IRPhase) -> bool {
+        if let Some(true) = self
+            .log_ir
+            .as_ref()
+            .map(|(_, phases)| phases.contains(&phase))
+        {
             return true;
         }
         false
     }
 
-    pub(crate) fn log_ir(s: &str) {
-        match LOG_IR.as_ref().map(|(p, _)| p.as_str()) {
-            Some("-") => eprint!("{s}"),
-            Some(x) => {
+    /// Should IR for phase `phase` be logged?
+    #[cfg(not(feature = "ykd"))]
+    pub(crate) fn should_log_ir(&self, _: IRPhase) -> bool {
+        false
+    }
+
+    /// Log the IR `s`. This should only be called if [Self::should_log_ir] returned `true`.
+    #[cfg(feature = "ykd")]
+    pub(crate) fn log_ir(&self, s: &str) {
+        match self.log_ir.as_ref().map(|(p, _)| p) {
+            Some(None) => eprint!("{s}"),
+            Some(Some(p)) => {
                 File::options()
                     .append(true)
-                    .open(x)
+                    .open(p)
                     .map(|mut x| x.write(s.as_bytes()))
                     .ok();
             }
             None => (),
         }
     }
+
+    /// Log the IR `s`. This should only be called if [Self::should_log_ir] returned `true`.
+    #[cfg(not(feature = "ykd"))]
+    pub(crate) fn log_ir(&self, _: &str) {}
 }
 
-pub(crate) use internals::{log_ir, should_log_ir};
+/// Convert the logging path `path` into a [PathBuf], where `-` (i.e. stderr) is converted to
+/// `None`. If there's an existing file at `path`, it is truncated (i.e. emptied), so that later
+/// appends to the log aren't appending to a previous log run.
+fn log_path(path: &str) -> Option<PathBuf> {
+    if path == "-" {
+        None
+    } else {
+        let path = PathBuf::from(path);
+        File::create(&path).ok();
+        Some(path)
+    }
+}
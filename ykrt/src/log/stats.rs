@@ -4,7 +4,7 @@
 //! territory -- but it's better than nothing!
 
 #[cfg(not(test))]
-use std::env;
+use crate::config::MTConfig;
 #[cfg(feature = "yk_testing")]
 use std::sync::Condvar;
 use std::{
@@ -17,7 +17,8 @@ use std::{
 use strum::{Display, EnumCount, EnumIter, IntoEnumIterator};
 
 /// Record yk statistics if enabled. In non-testing mode, this is only enabled if the end user
-/// defines the environment variable `YKD_LOG_STATS`. In testing mode, this is always enabled, with
+/// defines the environment variable `YKD_LOG_STATS` (or the equivalent [MTConfig] setting). In
+/// testing mode, this is always enabled, with
 /// output being sent to `stderr`.
 pub(crate) struct Stats {
     // On most runs of yk we anticipate that the end user won't want to be recording JIT
@@ -42,6 +43,10 @@ struct StatsInner {
     traces_compiled_ok: u64,
     /// How many traces were compiled unsuccessfully?
     traces_compiled_err: u64,
+    /// How many trace compilations were cancelled?
+    traces_compiled_cancelled: u64,
+    /// How many compiled traces have been invalidated?
+    traces_invalidated: u64,
     /// How many times have traces been executed? Note that the same trace can count arbitrarily
     /// many times to this.
     trace_executions: u64,
@@ -51,10 +56,10 @@ struct StatsInner {
 
 impl Stats {
     #[cfg(not(test))]
-    pub fn new() -> Self {
-        if let Ok(p) = env::var("YKD_LOG_STATS") {
+    pub fn new(config: &MTConfig) -> Self {
+        if let Some(p) = &config.log_stats {
             Self {
-                inner: Some(Mutex::new(StatsInner::new(p))),
+                inner: Some(Mutex::new(StatsInner::new(p.clone()))),
                 #[cfg(feature = "yk_testing")]
                 wait_until_condvar: Some(Condvar::new()),
             }
@@ -68,7 +73,7 @@ impl Stats {
     }
 
     #[cfg(test)]
-    pub fn new() -> Self {
+    pub fn new(_config: &crate::config::MTConfig) -> Self {
         Self {
             inner: Some(Mutex::new(StatsInner::new("-".to_string()))),
             #[cfg(feature = "yk_testing")]
@@ -143,6 +148,16 @@ impl Stats {
         self.update_with(|inner| inner.traces_compiled_err += 1);
     }
 
+    /// Increment the "a trace compilation has been cancelled" count.
+    pub fn trace_compiled_cancelled(&self) {
+        self.update_with(|inner| inner.traces_compiled_cancelled += 1);
+    }
+
+    /// Increment the "a compiled trace has been invalidated" count.
+    pub fn trace_invalidated(&self) {
+        self.update_with(|inner| inner.traces_invalidated += 1);
+    }
+
     /// Increment the "a compiled trace has started execution" count.
     pub fn trace_executed(&self) {
         self.update_with(|inner| inner.trace_executions += 1);
@@ -173,6 +188,8 @@ impl StatsInner {
             traces_recorded_err: 0,
             traces_compiled_ok: 0,
             traces_compiled_err: 0,
+            traces_compiled_cancelled: 0,
+            traces_invalidated: 0,
             trace_executions: 0,
             durations: [Duration::new(0, 0); TimingState::COUNT],
         }
@@ -212,6 +229,14 @@ impl StatsInner {
                 "traces_compiled_err".to_owned(),
                 self.traces_compiled_err.to_string(),
             ),
+            (
+                "traces_compiled_cancelled".to_owned(),
+                self.traces_compiled_cancelled.to_string(),
+            ),
+            (
+                "traces_invalidated".to_owned(),
+                self.traces_invalidated.to_string(),
+            ),
             (
                 "trace_executions".to_owned(),
                 self.trace_executions.to_string(),
@@ -290,6 +315,8 @@ mod yk_testing {
         traces_recorded_err: u64,
         traces_compiled_ok: u64,
         traces_compiled_err: u64,
+        traces_compiled_cancelled: u64,
+        traces_invalidated: u64,
         trace_executions: u64,
     }
 
@@ -308,6 +335,8 @@ mod yk_testing {
                 traces_recorded_err: inner.traces_recorded_err,
                 traces_compiled_ok: inner.traces_compiled_ok,
                 traces_compiled_err: inner.traces_compiled_err,
+                traces_compiled_cancelled: inner.traces_compiled_cancelled,
+                traces_invalidated: inner.traces_invalidated,
                 trace_executions: inner.trace_executions,
             };
             unsafe { test(cstats) }
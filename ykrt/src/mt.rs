@@ -4,10 +4,11 @@ use std::{
     assert_matches::debug_assert_matches,
     cell::RefCell,
     collections::{HashMap, HashSet},
-    env,
     error::Error,
     ffi::c_void,
+    fs,
     marker::PhantomData,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicU64, Ordering},
         Arc,
@@ -19,16 +20,23 @@ use parking_lot::Mutex;
 #[cfg(not(all(feature = "yk_testing", not(test))))]
 use parking_lot_core::SpinWait;
 
+#[cfg(jitc_yk)]
+use crate::compile::jitc_yk::trace_dump::TraceDump;
 use crate::{
     aotsmp::{load_aot_stackmaps, AOT_STACKMAPS},
-    compile::{default_compiler, CompilationError, CompiledTrace, Compiler, GuardIdx},
-    job_queue::{Job, JobQueue},
+    assumptions::{Assumptions, RecordingEpoch},
+    compile::{
+        default_compiler, CancellationToken, CompilationError, CompiledTrace, Compiler, GuardIdx,
+    },
+    config::MTConfig,
+    job_queue::{Hotness, Job, JobQueue},
     location::{HotLocation, HotLocationKind, Location, TraceFailed},
     log::{
         stats::{Stats, TimingState},
         Log, Verbosity,
     },
-    trace::{default_tracer, AOTTraceIterator, TraceRecorder, Tracer},
+    trace::{default_tracer, AOTTraceIterator, TraceRecorder, Tracer, TracerKind},
+    trace_gc::TraceGC,
 };
 
 // Emit a log entry with hot location debug information if present and support is compiled in.
@@ -59,11 +67,6 @@ type AtomicHotThreshold = AtomicU32;
 pub type TraceCompilationErrorThreshold = u16;
 pub type AtomicTraceCompilationErrorThreshold = AtomicU16;
 
-const DEFAULT_HOT_THRESHOLD: HotThreshold = 131;
-const DEFAULT_SIDETRACE_THRESHOLD: HotThreshold = 5;
-/// How often can a [HotLocation] or [Guard] lead to an error in tracing or compilation before we
-/// give up trying to trace (or compile...) it?
-const DEFAULT_TRACECOMPILATION_ERROR_THRESHOLD: TraceCompilationErrorThreshold = 5;
 static REG64_SIZE: usize = 8;
 
 thread_local! {
@@ -101,9 +104,22 @@ pub struct MT {
     /// A monotonically increasing integer that uniquely identifies each compiled trace.
     compiled_trace_id: AtomicU64,
     /// The currently available compiled traces. This is a [HashMap] because it is potentially a
-    /// sparse mapping due to (1) (one day!) we might garbage collect traces (2) some
-    /// [TraceId]s that we hand out are "lost" because a trace failed to compile.
+    /// sparse mapping due to (1) traces being invalidated (2) some [TraceId]s that we hand out
+    /// are "lost" because a trace failed to compile.
     pub(crate) compiled_traces: Mutex<HashMap<TraceId, Arc<dyn CompiledTrace>>>,
+    /// Keeps track of which traces depend on each other, and of invalidated traces which may
+    /// still be executing.
+    trace_gc: TraceGC,
+    /// The quasi-constant memory cells that compiled traces depend upon.
+    assumptions: Arc<Assumptions>,
+    /// Should traces be optimised?
+    pub(crate) opt: bool,
+    /// The names of functions to be treated as allocators.
+    pub(crate) allocators: Vec<String>,
+    /// If `Some`, the directory in which optimised traces are cached between process runs.
+    pub(crate) trace_cache: Option<PathBuf>,
+    /// If `Some`, the directory to which recorded traces are dumped.
+    pub(crate) trace_dump: Option<PathBuf>,
     pub(crate) log: Log,
     pub(crate) stats: Stats,
 }
@@ -115,37 +131,77 @@ impl std::fmt::Debug for MT {
 }
 
 impl MT {
-    // Create a new meta-tracer instance. Arbitrarily many of these can be created, though there
-    // are no guarantees as to whether they will share resources effectively or fairly.
+    // Create a new meta-tracer instance configured by the `YK_*` / `YKD_*` environment variables.
+    // Arbitrarily many of these can be created, though there are no guarantees as to whether they
+    // will share resources effectively or fairly.
     pub fn new() -> Result<Arc<Self>, Box<dyn Error>> {
+        Self::with_config(MTConfig::from_env()?)
+    }
+
+    /// Create a new meta-tracer instance configured by `config`. Note that environment variables
+    /// are not consulted: to use them as defaults, create `config` with [MTConfig::from_env].
+    pub fn with_config(config: MTConfig) -> Result<Arc<Self>, Box<dyn Error>> {
         load_aot_stackmaps();
-        let hot_threshold = match env::var("YK_HOT_THRESHOLD") {
-            Ok(s) => s
-                .parse::<HotThreshold>()
-                .map_err(|e| format!("Invalid hot threshold '{s}': {e}"))?,
-            Err(_) => DEFAULT_HOT_THRESHOLD,
-        };
-        let sidetrace_threshold = match env::var("YK_SIDETRACE_THRESHOLD") {
-            Ok(s) => s
-                .parse::<HotThreshold>()
-                .map_err(|e| format!("Invalid sidetrace threshold '{s}': {e}"))?,
-            Err(_) => DEFAULT_SIDETRACE_THRESHOLD,
-        };
-        Ok(Arc::new(Self {
+        if let Some(dir) = &config.trace_cache {
+            fs::create_dir_all(dir).map_err(|e| {
+                format!("Can't create trace cache directory {}: {e}", dir.display())
+            })?;
+        }
+        if let Some(dir) = &config.trace_dump {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Can't create trace dump directory {}: {e}", dir.display()))?;
+        }
+        let mt = Arc::new(Self {
             shutdown: AtomicBool::new(false),
-            hot_threshold: AtomicHotThreshold::new(hot_threshold),
-            sidetrace_threshold: AtomicHotThreshold::new(sidetrace_threshold),
+            hot_threshold: AtomicHotThreshold::new(config.hot_threshold),
+            sidetrace_threshold: AtomicHotThreshold::new(config.sidetrace_threshold),
             trace_failure_threshold: AtomicTraceCompilationErrorThreshold::new(
-                DEFAULT_TRACECOMPILATION_ERROR_THRESHOLD,
+                config.trace_failure_threshold,
             ),
-            job_queue: JobQueue::new(),
-            tracer: Mutex::new(default_tracer()?),
+            job_queue: JobQueue::new(&config),
+            tracer: Mutex::new(default_tracer(&config)?),
             compiler: Mutex::new(default_compiler()?),
             compiled_trace_id: AtomicU64::new(0),
             compiled_traces: Mutex::new(HashMap::new()),
-            log: Log::new()?,
-            stats: Stats::new(),
-        }))
+            trace_gc: TraceGC::new(),
+            assumptions: Arc::new(Assumptions::new()),
+            opt: config.opt,
+            allocators: config.allocators.clone(),
+            trace_cache: config.trace_cache.clone(),
+            trace_dump: config.trace_dump.clone(),
+            log: Log::new(&config)?,
+            stats: Stats::new(&config),
+        });
+        Ok(mt)
+    }
+
+    /// Build, optimise, and generate code for the trace previously dumped (see
+    /// [MTConfig::trace_dump]) to `path`, exactly as if it had just been recorded. The compiled
+    /// trace is then thrown away. The dumped trace must have been recorded by the interpreter
+    /// binary this `MT` is running in.
+    pub fn replay_trace(self: &Arc<Self>, path: &Path) -> Result<(), Box<dyn Error>> {
+        #[cfg(jitc_yk)]
+        {
+            TraceDump::read(path)?.replay(self)
+        }
+        #[cfg(not(jitc_yk))]
+        {
+            Err(format!(
+                "{}: trace replay requires the yk JIT compiler",
+                path.display()
+            )
+            .into())
+        }
+    }
+
+    /// Return the [Compiler] that will be used for compiling future traces.
+    pub(crate) fn compiler(&self) -> Arc<dyn Compiler> {
+        Arc::clone(&*self.compiler.lock())
+    }
+
+    /// Return the kind of [Tracer] that will be used for recording future traces.
+    pub(crate) fn tracer_kind(&self) -> TracerKind {
+        self.tracer.lock().kind()
     }
 
     /// Put this meta-tracer into shutdown mode, panicking if any problems are discovered. This
@@ -153,8 +209,9 @@ impl MT {
     /// threads have caused an error. The best place to do this is likely to be on the main thread,
     /// though this is not mandatory.
     ///
-    /// Note: this method does not stop all of the meta-tracer's activities. For example, -- but
-    /// not only! -- other threads will continue compiling and executing traces.
+    /// Queued and running compilation jobs are cancelled (as are any jobs queued after this call),
+    /// but this method does not stop all of the meta-tracer's activities. For example, -- but not
+    /// only! -- other threads will continue executing traces.
     ///
     /// Only the first call of this method performs meaningful actions: any subsequent calls will
     /// note the previous shutdown and immediately return.
@@ -208,6 +265,32 @@ impl MT {
             .store(trace_failure_threshold, Ordering::Relaxed);
     }
 
+    /// Return the maximum number of threads that this `MT` instance will use to compile traces.
+    /// Notice that this value can be changed by other threads and is thus potentially stale as
+    /// soon as it is read.
+    pub fn compiler_threads(self: &Arc<Self>) -> usize {
+        self.job_queue.max_worker_threads()
+    }
+
+    /// Set the maximum number of threads that will be used to compile traces. If this is lower
+    /// than the number of compiler threads currently running, the excess threads will exit once
+    /// they have finished their current compilation job.
+    ///
+    /// # Panics
+    ///
+    /// If `compiler_threads` is 0.
+    pub fn set_compiler_threads(self: &Arc<Self>, compiler_threads: usize) {
+        self.job_queue
+            .set_max_worker_threads(self, compiler_threads);
+    }
+
+    /// Return the number of compilation jobs which are waiting for a compiler thread. Notice that
+    /// this value can be changed by other threads and is thus potentially stale as soon as it is
+    /// read.
+    pub fn compile_queue_len(self: &Arc<Self>) -> usize {
+        self.job_queue.len()
+    }
+
     /// Return the unique ID for the next trace.
     pub(crate) fn next_trace_id(self: &Arc<Self>) -> TraceId {
         // Note: fetch_add is documented to wrap on overflow.
@@ -220,21 +303,169 @@ impl MT {
         TraceId(ctr_id)
     }
 
+    /// Invalidate all compiled traces. Traces currently being recorded or compiled are discarded
+    /// rather than being made available for execution. Future executions of the control point
+    /// will fall back to the interpreter, and new traces will be recorded and compiled as
+    /// [Location]s become hot again.
+    ///
+    /// Note that threads which are currently executing compiled traces will continue to do so
+    /// until they next leave JIT code (e.g. because a guard fails).
+    pub fn invalidate_all(self: &Arc<Self>) {
+        let ctrids = {
+            // [Self::add_compiled_trace] registers assumptions while holding this lock, so every
+            // trace is either already in `compiled_traces` or will be rejected by
+            // [Assumptions::register].
+            let lk = self.compiled_traces.lock();
+            self.assumptions.invalidate_all();
+            lk.keys().cloned().collect::<Vec<_>>()
+        };
+        for ctrid in ctrids {
+            self.invalidate_trace(ctrid);
+        }
+    }
+
+    /// Inform this meta-tracer that the quasi-constant memory cell `ptr` (see
+    /// `yk_assume_constant`) has been written to. All compiled traces which assumed that `ptr`
+    /// would not change are invalidated.
+    ///
+    /// Note that threads which are currently executing such traces will continue to do so until
+    /// they next leave JIT code (e.g. because a guard fails).
+    pub fn assumption_changed(self: &Arc<Self>, ptr: *const c_void) {
+        for ctrid in self.assumptions.changed(ptr as usize) {
+            self.invalidate_trace(ctrid);
+        }
+    }
+
+    /// Make the newly compiled trace `ctr` available to other threads, recording that its machine
+    /// code can jump to each of the traces in `deps` and, if it is a side-trace, that it will be
+    /// linked from the guard `parent`. `assumptions` are the quasi-constant memory cells `ctr`
+    /// assumes have not changed since `assumptions_epoch`. Returns `false` (and does not add
+    /// `ctr`) if any of `deps` has been invalidated, any of `assumptions` has changed, or
+    /// [Self::invalidate_all] has been called, in the interim, in which case `ctr` must be
+    /// discarded.
+    fn add_compiled_trace(
+        &self,
+        ctr: &Arc<dyn CompiledTrace>,
+        deps: &[TraceId],
+        parent: Option<(TraceId, GuardIdx)>,
+        assumptions: &[usize],
+        assumptions_epoch: &RecordingEpoch,
+    ) -> bool {
+        let mut lk = self.compiled_traces.lock();
+        if !deps
+            .iter()
+            .all(|x| lk.get(x).map(|x| !x.is_invalidated()).unwrap_or(false))
+        {
+            return false;
+        }
+        // We must register the assumptions while holding the `compiled_traces` lock: otherwise
+        // [Self::assumption_changed] could try (and fail) to invalidate `ctr` before we have added
+        // it to `compiled_traces`.
+        if !self
+            .assumptions
+            .register(ctr.ctrid(), assumptions, assumptions_epoch)
+        {
+            return false;
+        }
+        self.trace_gc.register(ctr.ctrid(), deps, parent);
+        lk.insert(ctr.ctrid(), Arc::clone(ctr));
+        true
+    }
+
+    /// Link the newly compiled root trace `ctr` into `hl`, which must be in the
+    /// [HotLocationKind::Compiling] state. If `ctr` has been invalidated since it was added to
+    /// `compiled_traces`, or `hl`'s [Location] was invalidated while `ctr` was being recorded or
+    /// compiled, `ctr` is discarded instead.
+    fn link_root_trace(self: &Arc<Self>, hl: &Mutex<HotLocation>, ctr: Arc<dyn CompiledTrace>) {
+        let mut lk = hl.lock();
+        debug_assert_matches!(lk.kind, HotLocationKind::Compiling(..));
+        if ctr.is_invalidated() {
+            lk.kind = HotLocationKind::Counting(0);
+        } else if lk.invalidated == Some(ctr.ctrid()) {
+            lk.kind = HotLocationKind::Counting(0);
+            drop(lk);
+            self.invalidate_trace(ctr.ctrid());
+        } else {
+            lk.kind = HotLocationKind::Compiled(ctr);
+        }
+    }
+
+    /// Invalidate the compiled trace `ctrid` and, transitively, all of the traces which can jump
+    /// to it. Invalidated traces will no longer be entered, and are freed once no thread is
+    /// executing JIT code. Returns how many traces were invalidated: if `ctrid` does not exist,
+    /// or has already been invalidated, this will be 0.
+    pub(crate) fn invalidate_trace(self: &Arc<Self>, ctrid: TraceId) -> usize {
+        let mut num = 0;
+        let mut todo = vec![ctrid];
+        while let Some(ctrid) = todo.pop() {
+            let ctr = {
+                let lk = self.compiled_traces.lock();
+                match lk.get(&ctrid) {
+                    Some(ctr) if !ctr.is_invalidated() => {
+                        // We must mark the trace as invalidated before unlinking it, so that a
+                        // thread which is concurrently linking it (see [Guard::set_ctr] and the
+                        // root trace compilation job) will notice and not link it.
+                        ctr.invalidate();
+                        Arc::clone(ctr)
+                    }
+                    _ => continue,
+                }
+            };
+
+            // Unlink the trace from everywhere that new threads might start executing it.
+            if let Some(hl) = ctr.hl().upgrade() {
+                let mut lk = hl.lock();
+                if let HotLocationKind::Compiled(hl_ctr) = &lk.kind
+                    && hl_ctr.ctrid() == ctrid
+                {
+                    lk.kind = HotLocationKind::Counting(0);
+                }
+            }
+            if let Some((parent_id, gidx)) = self.trace_gc.parent(ctrid) {
+                let parent = self.compiled_traces.lock().get(&parent_id).cloned();
+                if let Some(parent) = parent {
+                    parent.guard(gidx).unset_ctr(&parent, gidx);
+                }
+            }
+
+            let deps = {
+                let mut lk = self.compiled_traces.lock();
+                lk.remove(&ctrid);
+                self.assumptions.remove(ctrid);
+                self.trace_gc.retire(ctr)
+            };
+            self.job_queue.cancel_dependents(ctrid);
+            self.job_queue.notify_failure(ctrid);
+            self.stats.trace_invalidated();
+            self.log
+                .log(Verbosity::Tracing, &format!("trace-invalidated: {ctrid}"));
+            num += 1;
+            todo.extend(deps);
+        }
+        self.trace_gc.collect();
+        num
+    }
+
     /// Add a compilation job for a root trace where:
     ///   * `hl_arc` is the [HotLocation] this compilation job is related to.
     ///   * `ctrid` is the trace ID to be given to the new compiled trace.
     ///   * `connector_tid` is the optional trace ID of the trace the new compiled trace will
     ///     connect to.
+    ///   * `assumptions` are the quasi-constant memory cells the trace depends upon, and the
+    ///     epoch at which the trace started being recorded.
     fn queue_root_compile_job(
         self: &Arc<Self>,
         trace_iter: (Box<dyn AOTTraceIterator>, Box<[u8]>, Vec<String>),
         hl_arc: Arc<Mutex<HotLocation>>,
         trid: TraceId,
         connector_tid: Option<TraceId>,
+        assumptions: (Vec<usize>, RecordingEpoch),
     ) {
         self.stats.trace_recorded_ok();
 
         let hl_arc_cl = Arc::clone(&hl_arc);
+        let cancel = CancellationToken::default();
+        let cancel_cl = cancel.clone();
         let mt = Arc::clone(self);
         let main = move || {
             let compiler = {
@@ -242,31 +473,65 @@ impl MT {
                 Arc::clone(&*lk)
             };
             mt.stats.timing_state(TimingState::Compiling);
-            let connector_ctr = connector_tid.map(|x| Arc::clone(&mt.compiled_traces.lock()[&x]));
-            match compiler.root_compile(
-                Arc::clone(&mt),
-                trace_iter.0,
-                trid,
-                Arc::clone(&hl_arc),
-                trace_iter.1,
-                trace_iter.2,
-                connector_ctr,
-            ) {
-                Ok(ctr) => {
+            let r = cancel
+                .check()
+                .and_then(|_| match connector_tid {
+                    Some(x) => match mt.compiled_traces.lock().get(&x) {
+                        Some(ctr) => Ok(Some(Arc::clone(ctr))),
+                        // The connector trace has been invalidated. This isn't the fault of the
+                        // trace being compiled, so it mustn't count as a failure.
+                        None => Err(CompilationError::Cancelled),
+                    },
+                    None => Ok(None),
+                })
+                .and_then(|connector_ctr| {
+                    compiler.root_compile(
+                        Arc::clone(&mt),
+                        trace_iter.0,
+                        trid,
+                        Arc::clone(&hl_arc),
+                        trace_iter.1,
+                        trace_iter.2,
+                        connector_ctr,
+                        &cancel,
+                    )
+                })
+                .and_then(|ctr| {
                     assert_eq!(ctr.ctrid(), trid);
-                    mt.compiled_traces
-                        .lock()
-                        .insert(ctr.ctrid(), Arc::clone(&ctr));
-                    let mut hl = hl_arc.lock();
-                    debug_assert_matches!(hl.kind, HotLocationKind::Compiling(_));
-                    hl.kind = HotLocationKind::Compiled(ctr);
+                    if mt.add_compiled_trace(
+                        &ctr,
+                        connector_tid.as_slice(),
+                        None,
+                        &assumptions.0,
+                        &assumptions.1,
+                    ) {
+                        Ok(ctr)
+                    } else {
+                        // The connector trace or an assumption was invalidated during
+                        // compilation.
+                        Err(CompilationError::Cancelled)
+                    }
+                });
+            match r {
+                Ok(ctr) => {
+                    mt.link_root_trace(&hl_arc, ctr);
                     mt.stats.trace_compiled_ok();
                     mt.job_queue.notify_success(trid);
                 }
+                Err(CompilationError::Cancelled) => {
+                    mt.stats.trace_compiled_cancelled();
+                    let mut hl = hl_arc.lock();
+                    debug_assert_matches!(hl.kind, HotLocationKind::Compiling(..));
+                    hl.kind = HotLocationKind::Counting(0);
+                    drop(hl);
+                    mt.log
+                        .log(Verbosity::Tracing, "trace-compilation-cancelled");
+                    mt.job_queue.notify_failure(trid);
+                }
                 Err(e) => {
                     mt.stats.trace_compiled_err();
                     let mut hl = hl_arc.lock();
-                    debug_assert_matches!(hl.kind, HotLocationKind::Compiling(_));
+                    debug_assert_matches!(hl.kind, HotLocationKind::Compiling(..));
                     if let TraceFailed::DontTrace = hl.tracecompilation_error(&mt) {
                         hl.kind = HotLocationKind::DontTrace;
                     } else {
@@ -294,29 +559,45 @@ impl MT {
                             mt.log
                                 .log(Verbosity::Error, &format!("trace-compilation-aborted: {e}"));
                         }
+                        CompilationError::Cancelled => unreachable!(),
                     }
-                    mt.job_queue.notify_failure(&mt, trid);
+                    mt.job_queue.notify_failure(trid);
                 }
             }
 
             mt.stats.timing_state(TimingState::None);
         };
 
+        let hotness = match &hl_arc_cl.lock().kind {
+            HotLocationKind::Compiling(_, hotness) => hotness.clone(),
+            _ => unreachable!(),
+        };
+        let cancel_failed = cancel_cl.clone();
         let mt = Arc::clone(self);
         let failure = move || {
             let mut hl = hl_arc_cl.lock();
-            debug_assert_matches!(hl.kind, HotLocationKind::Compiling(_));
-            if let TraceFailed::DontTrace = hl.tracecompilation_error(&mt) {
+            debug_assert_matches!(hl.kind, HotLocationKind::Compiling(..));
+            if cancel_failed.is_cancelled() {
+                mt.stats.trace_compiled_cancelled();
+                hl.kind = HotLocationKind::Counting(0);
+            } else if let TraceFailed::DontTrace = hl.tracecompilation_error(&mt) {
                 hl.kind = HotLocationKind::DontTrace;
             } else {
                 hl.kind = HotLocationKind::Counting(0);
             }
-            mt.job_queue.notify_failure(&mt, trid);
+            mt.job_queue.notify_failure(trid);
         };
 
         self.job_queue.push(
             self,
-            Job::new(Box::new(main), connector_tid, Box::new(failure)),
+            Job::new(
+                Box::new(main),
+                connector_tid,
+                Box::new(failure),
+                hotness,
+                connector_tid.into_iter().collect(),
+                cancel_cl,
+            ),
         );
     }
 
@@ -329,6 +610,8 @@ impl MT {
     ///   * `guardid` is the ID of the guard in `parent_ctr` which failed.
     ///   * `connector_tid` is the optional trace ID of the trace the new compiled trace will
     ///     connect to.
+    ///   * `assumptions` are the quasi-constant memory cells the trace depends upon, and the
+    ///     epoch at which the trace started being recorded.
     fn queue_sidetrace_compile_job(
         self: &Arc<Self>,
         trace_iter: (Box<dyn AOTTraceIterator>, Box<[u8]>, Vec<String>),
@@ -337,8 +620,12 @@ impl MT {
         parent_ctr: Arc<dyn CompiledTrace>,
         gidx: GuardIdx,
         connector_tid: TraceId,
+        assumptions: (Vec<usize>, RecordingEpoch),
     ) {
         self.stats.trace_recorded_ok();
+        let deps = vec![connector_tid, parent_ctr.ctrid()];
+        let cancel = CancellationToken::default();
+        let cancel_cl = cancel.clone();
         let mt = Arc::clone(self);
         let parent_ctr_cl = Arc::clone(&parent_ctr);
         let main = move || {
@@ -347,29 +634,57 @@ impl MT {
                 Arc::clone(&*lk)
             };
             mt.stats.timing_state(TimingState::Compiling);
-            let target_ctr = Arc::clone(&mt.compiled_traces.lock()[&connector_tid]);
+            let target_ctr = mt.compiled_traces.lock().get(&connector_tid).cloned();
             // FIXME: Can we pass in the root trace address, root trace entry variable locations,
             // and the base stack-size from here, rather than spreading them out via
             // DeoptInfo/SideTraceInfo, and CompiledTrace?
-            match compiler.sidetrace_compile(
-                Arc::clone(&mt),
-                trace_iter.0,
-                trid,
-                Arc::clone(&parent_ctr),
-                gidx,
-                target_ctr,
-                Arc::clone(&hl_arc),
-                trace_iter.1,
-                trace_iter.2,
-            ) {
-                Ok(ctr) => {
+            let r = cancel
+                .check()
+                .and_then(|_| match target_ctr {
+                    Some(target_ctr) => compiler.sidetrace_compile(
+                        Arc::clone(&mt),
+                        trace_iter.0,
+                        trid,
+                        Arc::clone(&parent_ctr),
+                        gidx,
+                        target_ctr,
+                        Arc::clone(&hl_arc),
+                        trace_iter.1,
+                        trace_iter.2,
+                        &cancel,
+                    ),
+                    // The target trace has been invalidated. This isn't the fault of the sidetrace
+                    // being compiled, so it mustn't count as a failure.
+                    None => Err(CompilationError::Cancelled),
+                })
+                .and_then(|ctr| {
                     assert_eq!(ctr.ctrid(), trid);
-                    mt.compiled_traces
-                        .lock()
-                        .insert(ctr.ctrid(), Arc::clone(&ctr));
+                    let deps = [connector_tid, parent_ctr.ctrid()];
+                    if mt.add_compiled_trace(
+                        &ctr,
+                        &deps,
+                        Some((parent_ctr.ctrid(), gidx)),
+                        &assumptions.0,
+                        &assumptions.1,
+                    ) {
+                        Ok(ctr)
+                    } else {
+                        // The target trace, parent trace, or an assumption was invalidated during
+                        // compilation.
+                        Err(CompilationError::Cancelled)
+                    }
+                });
+            match r {
+                Ok(ctr) => {
                     parent_ctr.guard(gidx).set_ctr(ctr, &parent_ctr, gidx);
                     mt.stats.trace_compiled_ok();
                 }
+                Err(CompilationError::Cancelled) => {
+                    parent_ctr.guard(gidx).trace_or_compile_cancelled();
+                    mt.stats.trace_compiled_cancelled();
+                    mt.log
+                        .log(Verbosity::Tracing, "sidetrace-compilation-cancelled");
+                }
                 Err(e) => {
                     parent_ctr.guard(gidx).trace_or_compile_failed(&mt);
                     mt.stats.trace_compiled_err();
@@ -397,6 +712,7 @@ impl MT {
                                 &format!("sidetrace-compilation-aborted: {e}"),
                             );
                         }
+                        CompilationError::Cancelled => unreachable!(),
                     }
                 }
             }
@@ -404,13 +720,27 @@ impl MT {
             mt.stats.timing_state(TimingState::None);
         };
 
+        let hotness = parent_ctr_cl.guard(gidx).hotness();
         let mt = Arc::clone(self);
+        let cancel_failed = cancel_cl.clone();
         let failure = move || {
-            parent_ctr_cl.guard(gidx).trace_or_compile_failed(&mt);
+            if cancel_failed.is_cancelled() {
+                parent_ctr_cl.guard(gidx).trace_or_compile_cancelled();
+                mt.stats.trace_compiled_cancelled();
+            } else {
+                parent_ctr_cl.guard(gidx).trace_or_compile_failed(&mt);
+            }
         };
         self.job_queue.push(
             self,
-            Job::new(Box::new(main), Some(connector_tid), Box::new(failure)),
+            Job::new(
+                Box::new(main),
+                Some(connector_tid),
+                Box::new(failure),
+                hotness,
+                deps,
+                cancel_cl,
+            ),
         );
     }
 
@@ -434,6 +764,10 @@ impl MT {
                 self.stats.timing_state(TimingState::OutsideYk);
             }
             TransitionControlPoint::Execute(ctr) => {
+                if !self.trace_gc.enter(&*ctr) {
+                    // `ctr` has been invalidated since we read it from the [HotLocation].
+                    return;
+                }
                 yklog!(
                     self.log,
                     Verbosity::Execution,
@@ -452,6 +786,9 @@ impl MT {
                     rsp = unsafe { rsp.byte_add(REG64_SIZE) };
                 }
                 let trace_addr = ctr.entry();
+                // `__yk_exec_trace` never returns, so we must drop our reference to `ctr` now. It
+                // won't be freed while we're executing it: see [TraceGC::enter].
+                drop(ctr);
                 MTThread::with_borrow_mut(|mtt| {
                     mtt.push_tstate(MTThreadState::Executing {
                         mt: Arc::clone(self),
@@ -478,7 +815,7 @@ impl MT {
             } => {
                 // Assuming no bugs elsewhere, the `unwrap`s cannot fail, because
                 // `StartSideTracing` will have put a `Some` in the `Rc`.
-                let (hl, thread_tracer, promotions, debug_strs) =
+                let (hl, thread_tracer, promotions, debug_strs, assumptions) =
                     MTThread::with_borrow_mut(|mtt| match mtt.pop_tstate() {
                         MTThreadState::Tracing {
                             trid: _,
@@ -486,12 +823,20 @@ impl MT {
                             thread_tracer,
                             promotions,
                             debug_strs,
+                            assumptions,
+                            assumptions_epoch,
                             frameaddr: tracing_frameaddr,
                             seen_hls: _,
                             gtrace: _,
                         } => {
                             assert_eq!(frameaddr, tracing_frameaddr);
-                            (hl, thread_tracer, promotions, debug_strs)
+                            (
+                                hl,
+                                thread_tracer,
+                                promotions,
+                                debug_strs,
+                                (assumptions, assumptions_epoch),
+                            )
                         }
                         _ => unreachable!(),
                     });
@@ -513,6 +858,7 @@ impl MT {
                             parent_ctr,
                             gidx,
                             connector_tid,
+                            assumptions,
                         );
                         if start {
                             self.start_tracing(
@@ -525,7 +871,7 @@ impl MT {
                     }
                     Err(e) => {
                         MTThread::set_tracing(IsTracing::None);
-                        self.job_queue.notify_failure(self, trid);
+                        self.job_queue.notify_failure(trid);
                         parent_ctr.guard(gidx).trace_or_compile_failed(self);
                         self.stats.trace_recorded_err();
                         yklog!(
@@ -563,6 +909,7 @@ impl MT {
             let lk = self.tracer.lock();
             Arc::clone(&*lk)
         };
+        let assumptions_epoch = self.assumptions.start_recording();
         MTThread::set_tracing(IsTracing::Loop);
         MTThread::with_borrow_mut(|mtt| {
             match Arc::clone(&tracer).start_recorder() {
@@ -573,6 +920,8 @@ impl MT {
                         thread_tracer: tt,
                         promotions: Vec::new(),
                         debug_strs: Vec::new(),
+                        assumptions: Vec::new(),
+                        assumptions_epoch,
                         frameaddr,
                         seen_hls: HashSet::new(),
                         gtrace: None,
@@ -622,7 +971,7 @@ impl MT {
     ) {
         // Assuming no bugs elsewhere, the `unwrap`s cannot fail, because `StartTracing`
         // will have put a `Some` in the `Rc`.
-        let (hl, thread_tracer, promotions, debug_strs) =
+        let (hl, thread_tracer, promotions, debug_strs, assumptions) =
             MTThread::with_borrow_mut(|mtt| match mtt.pop_tstate() {
                 MTThreadState::Tracing {
                     trid: _,
@@ -630,6 +979,8 @@ impl MT {
                     thread_tracer,
                     promotions,
                     debug_strs,
+                    assumptions,
+                    assumptions_epoch,
                     frameaddr: tracing_frameaddr,
                     seen_hls: _,
                     gtrace: _,
@@ -637,7 +988,13 @@ impl MT {
                     // If this assert fails then the code in `transition_control_point`,
                     // which rejects traces that end in another frame, didn't work.
                     assert_eq!(frameaddr, tracing_frameaddr);
-                    (hl, thread_tracer, promotions, debug_strs)
+                    (
+                        hl,
+                        thread_tracer,
+                        promotions,
+                        debug_strs,
+                        (assumptions, assumptions_epoch),
+                    )
                 }
                 _ => unreachable!(),
             });
@@ -656,11 +1013,12 @@ impl MT {
                     hl,
                     trid,
                     connector_tid,
+                    assumptions,
                 );
             }
             Err(e) => {
                 MTThread::set_tracing(IsTracing::None);
-                self.job_queue.notify_failure(self, trid);
+                self.job_queue.notify_failure(trid);
                 self.stats.timing_state(TimingState::None);
                 self.stats.trace_recorded_err();
                 yklog!(
@@ -725,7 +1083,10 @@ impl MT {
                     HotLocationKind::Compiled(ref ctr) => {
                         TransitionControlPoint::Execute(Arc::clone(ctr))
                     }
-                    HotLocationKind::Compiling(_) => TransitionControlPoint::NoAction,
+                    HotLocationKind::Compiling(_, ref hotness) => {
+                        hotness.inc();
+                        TransitionControlPoint::NoAction
+                    }
                     HotLocationKind::Counting(c) => {
                         if c < self.hot_threshold() {
                             lk.kind = HotLocationKind::Counting(c + 1);
@@ -745,7 +1106,7 @@ impl MT {
                         if Arc::strong_count(&hl) == 2 {
                             // Another thread was tracing this location but it's terminated.
                             self.stats.trace_recorded_err();
-                            self.job_queue.notify_failure(self, trid);
+                            self.job_queue.notify_failure(trid);
                             match lk.tracecompilation_error(self) {
                                 TraceFailed::KeepTrying => {
                                     let trid = self.next_trace_id();
@@ -778,6 +1139,7 @@ impl MT {
                                 kind: HotLocationKind::Tracing(trid),
                                 tracecompilation_errors: 0,
                                 debug_str: None,
+                                invalidated: None,
                             };
                             if let Some(hl) = loc.count_to_hot_location(x, hl) {
                                 TransitionControlPoint::StartTracing(hl, trid)
@@ -839,7 +1201,7 @@ impl MT {
                     let mut lk = tracing_hl.lock();
                     match &lk.kind {
                         HotLocationKind::Compiled(_)
-                        | HotLocationKind::Compiling(_)
+                        | HotLocationKind::Compiling(..)
                         | HotLocationKind::Counting(_)
                         | HotLocationKind::DontTrace => (),
                         HotLocationKind::Tracing(trid) => {
@@ -852,7 +1214,7 @@ impl MT {
                                     lk.kind = HotLocationKind::DontTrace;
                                 }
                             }
-                            self.job_queue.notify_failure(self, trid);
+                            self.job_queue.notify_failure(trid);
                         }
                     }
 
@@ -862,16 +1224,16 @@ impl MT {
                 let mut lk = hl.lock();
 
                 match lk.kind {
-                    HotLocationKind::Compiled(_) | HotLocationKind::Compiling(_) => {
+                    HotLocationKind::Compiled(_) | HotLocationKind::Compiling(..) => {
                         let compiled_trid = match lk.kind {
                             HotLocationKind::Compiled(ref ctr) => ctr.ctrid(),
-                            HotLocationKind::Compiling(ref ctrid) => *ctrid,
+                            HotLocationKind::Compiling(ref ctrid, _) => *ctrid,
                             _ => unreachable!(),
                         };
                         drop(lk);
                         let mut lk = tracing_hl.lock();
                         // let trid = self.next_trace_id();
-                        lk.kind = HotLocationKind::Compiling(*tracing_trid);
+                        lk.kind = HotLocationKind::Compiling(*tracing_trid, Hotness::default());
                         TransitionControlPoint::StopTracing(*tracing_trid, Some(compiled_trid))
                     }
                     HotLocationKind::Counting(_) => TransitionControlPoint::NoAction,
@@ -882,7 +1244,7 @@ impl MT {
                             TransitionControlPoint::NoAction
                         } else {
                             // ...and it's this location...
-                            lk.kind = HotLocationKind::Compiling(hl_trid);
+                            lk.kind = HotLocationKind::Compiling(hl_trid, Hotness::default());
                             TransitionControlPoint::StopTracing(hl_trid, None)
                         }
                     }
@@ -896,6 +1258,7 @@ impl MT {
                             kind: HotLocationKind::Counting(count),
                             tracecompilation_errors: 0,
                             debug_str: None,
+                            invalidated: None,
                         };
                         loc.count_to_hot_location(count, hl)
                             .map(|x| Arc::as_ptr(&x))
@@ -947,7 +1310,7 @@ impl MT {
                     let mut lk = tracing_hl.lock();
                     match &lk.kind {
                         HotLocationKind::Compiled(_)
-                        | HotLocationKind::Compiling(_)
+                        | HotLocationKind::Compiling(..)
                         | HotLocationKind::Counting(_)
                         | HotLocationKind::DontTrace => (),
                         HotLocationKind::Tracing(trid) => {
@@ -960,7 +1323,7 @@ impl MT {
                                     lk.kind = HotLocationKind::DontTrace;
                                 }
                             }
-                            self.job_queue.notify_failure(self, trid);
+                            self.job_queue.notify_failure(trid);
                         }
                     }
 
@@ -970,11 +1333,11 @@ impl MT {
                 let mut lk = hl.lock();
                 match lk.kind {
                     HotLocationKind::Compiled(_)
-                    | HotLocationKind::Compiling(_)
+                    | HotLocationKind::Compiling(..)
                     | HotLocationKind::Tracing(_) => {
                         let connector_tid = match lk.kind {
                             HotLocationKind::Compiled(ref ctr) => ctr.ctrid(),
-                            HotLocationKind::Compiling(ref ctrid) => *ctrid,
+                            HotLocationKind::Compiling(ref ctrid, _) => *ctrid,
                             HotLocationKind::Tracing(tid) => tid,
                             _ => unreachable!(),
                         };
@@ -1019,6 +1382,7 @@ impl MT {
                             kind: HotLocationKind::Counting(count),
                             tracecompilation_errors: 0,
                             debug_str: None,
+                            invalidated: None,
                         };
                         loc.count_to_hot_location(count, hl)
                             .map(|x| Arc::as_ptr(&x))
@@ -1075,7 +1439,7 @@ impl MT {
                     let mut lk = hl.lock();
                     match &lk.kind {
                         HotLocationKind::Compiled(_) => todo!(),
-                        HotLocationKind::Compiling(_) => todo!(),
+                        HotLocationKind::Compiling(..) => todo!(),
                         HotLocationKind::Counting(_) => todo!(),
                         HotLocationKind::DontTrace => todo!(),
                         HotLocationKind::Tracing(trid) => {
@@ -1088,7 +1452,7 @@ impl MT {
                                     lk.kind = HotLocationKind::DontTrace;
                                 }
                             }
-                            self.job_queue.notify_failure(self, trid);
+                            self.job_queue.notify_failure(trid);
                         }
                     }
                     drop(lk);
@@ -1101,7 +1465,10 @@ impl MT {
                         Some(&*hl)
                     );
                 }
-                MTThreadState::Executing { .. } => return,
+                MTThreadState::Executing { .. } => {
+                    self.trace_gc.exit();
+                    return;
+                }
             }
         }
     }
@@ -1135,6 +1502,7 @@ impl MT {
                     let lk = self.tracer.lock();
                     Arc::clone(&*lk)
                 };
+                let assumptions_epoch = self.assumptions.start_recording();
                 MTThread::set_tracing(IsTracing::Guard);
                 MTThread::with_borrow_mut(|mtt| match Arc::clone(&tracer).start_recorder() {
                     Ok(tt) => mtt.push_tstate(MTThreadState::Tracing {
@@ -1143,6 +1511,8 @@ impl MT {
                         thread_tracer: tt,
                         promotions: Vec::new(),
                         debug_strs: Vec::new(),
+                        assumptions: Vec::new(),
+                        assumptions_epoch,
                         frameaddr,
                         seen_hls: HashSet::new(),
                         gtrace: Some((parent, gidx)),
@@ -1223,6 +1593,11 @@ enum MTThreadState {
         promotions: Vec<u8>,
         /// Records the content of data recorded via `yk_debug_str`.
         debug_strs: Vec<String>,
+        /// The addresses of the quasi-constant memory cells recorded via `yk_assume_constant`.
+        /// Their values are recorded in `promotions`.
+        assumptions: Vec<usize>,
+        /// The [Assumptions] epoch when tracing started.
+        assumptions_epoch: RecordingEpoch,
         /// The `frameaddr` when tracing started. This allows us to tell if we're finishing tracing
         /// at the same point that we started.
         frameaddr: *mut c_void,
@@ -1311,7 +1686,12 @@ impl MTThread {
     pub(crate) fn compiled_trace(&self, ctrid: TraceId) -> Arc<dyn CompiledTrace> {
         for tstate in self.tstate.iter().rev() {
             if let MTThreadState::Executing { mt } = tstate {
-                return Arc::clone(&mt.compiled_traces.lock()[&ctrid]);
+                // If the trace has been invalidated, this thread may still be executing it.
+                let lk = mt.compiled_traces.lock();
+                return match lk.get(&ctrid) {
+                    Some(ctr) => Arc::clone(ctr),
+                    None => mt.trace_gc.retired(ctrid).unwrap(),
+                };
             }
         }
         panic!();
@@ -1355,12 +1735,7 @@ impl MTThread {
     /// If the stack is empty. There should always be at least one element on the stack, so a panic
     /// here means that something has gone wrong elsewhere.
     pub(crate) fn promote_i32(&mut self, val: i32) -> bool {
-        if let MTThreadState::Tracing {
-            ref mut promotions, ..
-        } = self.peek_mut_tstate()
-        {
-            promotions.extend_from_slice(&val.to_ne_bytes());
-        }
+        self.push_promotion(&val.to_ne_bytes());
         true
     }
 
@@ -1376,12 +1751,7 @@ impl MTThread {
     /// If the stack is empty. There should always be at least one element on the stack, so a panic
     /// here means that something has gone wrong elsewhere.
     pub(crate) fn promote_u32(&mut self, val: u32) -> bool {
-        if let MTThreadState::Tracing {
-            ref mut promotions, ..
-        } = self.peek_mut_tstate()
-        {
-            promotions.extend_from_slice(&val.to_ne_bytes());
-        }
+        self.push_promotion(&val.to_ne_bytes());
         true
     }
 
@@ -1397,12 +1767,7 @@ impl MTThread {
     /// If the stack is empty. There should always be at least one element on the stack, so a panic
     /// here means that something has gone wrong elsewhere.
     pub(crate) fn promote_i64(&mut self, val: i64) -> bool {
-        if let MTThreadState::Tracing {
-            ref mut promotions, ..
-        } = self.peek_mut_tstate()
-        {
-            promotions.extend_from_slice(&val.to_ne_bytes());
-        }
+        self.push_promotion(&val.to_ne_bytes());
         true
     }
 
@@ -1418,13 +1783,46 @@ impl MTThread {
     /// If the stack is empty. There should always be at least one element on the stack, so a panic
     /// here means that something has gone wrong elsewhere.
     pub(crate) fn promote_usize(&mut self, val: usize) -> bool {
+        self.push_promotion(&val.to_ne_bytes());
+        true
+    }
+
+    /// Records `val` as the value of the quasi-constant memory cell at `ptr`.
+    ///
+    /// # Panics
+    ///
+    /// If the stack is empty. There should always be at least one element on the stack, so a panic
+    /// here means that something has gone wrong elsewhere.
+    pub(crate) fn assume_constant(&mut self, ptr: usize, val: usize) {
+        self.push_promotion(&val.to_ne_bytes());
         if let MTThreadState::Tracing {
-            ref mut promotions, ..
+            ref mut assumptions,
+            ..
         } = self.peek_mut_tstate()
         {
-            promotions.extend_from_slice(&val.to_ne_bytes());
+            assumptions.push(ptr);
+        }
+    }
+
+    /// If a trace is being recorded, record the promoted value `val` (a native-endian sequence of
+    /// bytes) in it: inline, if the trace recorder supports that, or otherwise in the side buffer
+    /// of promoted values.
+    ///
+    /// # Panics
+    ///
+    /// If the stack is empty. There should always be at least one element on the stack, so a panic
+    /// here means that something has gone wrong elsewhere.
+    fn push_promotion(&mut self, val: &[u8]) {
+        if let MTThreadState::Tracing {
+            ref thread_tracer,
+            ref mut promotions,
+            ..
+        } = self.peek_mut_tstate()
+        {
+            if !thread_tracer.record_promotion(val) {
+                promotions.extend_from_slice(val);
+            }
         }
-        true
     }
 
     /// Record a debug string.
@@ -1541,9 +1939,15 @@ mod tests {
     use super::*;
     use crate::{
         compile::{CompiledTraceTestingBasicTransitions, CompiledTraceTestingMinimal},
+        job_queue::Job,
         trace::TraceRecorderError,
     };
-    use std::{assert_matches::assert_matches, hint::black_box, ptr, thread};
+    use std::{
+        assert_matches::assert_matches,
+        hint::black_box,
+        ptr, thread,
+        time::{Duration, Instant},
+    };
     use test::bench::Bencher;
 
     // We only implement enough of the equality function for the tests we have.
@@ -1586,6 +1990,8 @@ mod tests {
                 thread_tracer: Box::new(DummyTraceRecorder),
                 promotions: Vec::new(),
                 debug_strs: Vec::new(),
+                assumptions: Vec::new(),
+                assumptions_epoch: mt.assumptions.start_recording(),
                 frameaddr: ptr::null_mut(),
                 seen_hls: HashSet::new(),
                 gtrace: None,
@@ -1620,6 +2026,8 @@ mod tests {
                 thread_tracer: Box::new(DummyTraceRecorder),
                 promotions: Vec::new(),
                 debug_strs: Vec::new(),
+                assumptions: Vec::new(),
+                assumptions_epoch: mt.assumptions.start_recording(),
                 frameaddr: ptr::null_mut(),
                 seen_hls: HashSet::new(),
                 gtrace: Some((ctr, GuardIdx::from(0))),
@@ -1649,7 +2057,7 @@ mod tests {
         expect_stop_tracing(&mt, &loc);
         assert!(matches!(
             loc.hot_location().unwrap().lock().kind,
-            HotLocationKind::Compiling(_)
+            HotLocationKind::Compiling(..)
         ));
         let ctr = Arc::new(CompiledTraceTestingBasicTransitions::new(Arc::downgrade(
             &loc.hot_location_arc_clone().unwrap(),
@@ -1749,6 +2157,8 @@ mod tests {
                             thread_tracer: Box::new(DummyTraceRecorder),
                             promotions: Vec::new(),
                             debug_strs: Vec::new(),
+                            assumptions: Vec::new(),
+                            assumptions_epoch: mt.assumptions.start_recording(),
                             frameaddr: ptr::null_mut(),
                             seen_hls: HashSet::new(),
                             gtrace: None,
@@ -1869,7 +2279,7 @@ mod tests {
         // If tracing succeeded, we'll now be in the Compiling state.
         assert!(matches!(
             loc.hot_location().unwrap().lock().kind,
-            HotLocationKind::Compiling(_)
+            HotLocationKind::Compiling(..)
         ));
     }
 
@@ -1948,7 +2358,7 @@ mod tests {
         expect_stop_tracing(&mt, &loc1);
         assert!(matches!(
             loc1.hot_location().unwrap().lock().kind,
-            HotLocationKind::Compiling(_)
+            HotLocationKind::Compiling(..)
         ));
         expect_start_tracing(&mt, &loc2);
         expect_stop_tracing(&mt, &loc2);
@@ -1988,6 +2398,8 @@ mod tests {
                                     thread_tracer: Box::new(DummyTraceRecorder),
                                     promotions: Vec::new(),
                                     debug_strs: Vec::new(),
+                                    assumptions: Vec::new(),
+                                    assumptions_epoch: mt.assumptions.start_recording(),
                                     frameaddr: ptr::null_mut(),
                                     seen_hls: HashSet::new(),
                                     gtrace: None,
@@ -2000,7 +2412,7 @@ mod tests {
                             expect_stop_tracing(&mt, &loc);
                             assert!(matches!(
                                 loc.hot_location().unwrap().lock().kind,
-                                HotLocationKind::Compiling(_)
+                                HotLocationKind::Compiling(..)
                             ));
                             assert_eq!(
                                 mt.transition_control_point(&loc, ptr::null_mut()),
@@ -2008,7 +2420,7 @@ mod tests {
                             );
                             assert!(matches!(
                                 loc.hot_location().unwrap().lock().kind,
-                                HotLocationKind::Compiling(_)
+                                HotLocationKind::Compiling(..)
                             ));
                             loc.hot_location().unwrap().lock().kind = HotLocationKind::Compiled(
                                 Arc::new(CompiledTraceTestingMinimal::new()),
@@ -2132,4 +2544,522 @@ mod tests {
             TransitionControlPoint::Execute(_)
         );
     }
+
+    /// Create a [HotLocation] for `loc` which is linked to a new compiled trace.
+    fn compiled_location(mt: &Arc<MT>, loc: &Location) -> Arc<dyn CompiledTrace> {
+        mt.set_hot_threshold(0);
+        expect_start_tracing(mt, loc);
+        expect_stop_tracing(mt, loc);
+        let ctr: Arc<dyn CompiledTrace> = Arc::new(CompiledTraceTestingBasicTransitions::with_mt(
+            mt,
+            Arc::downgrade(&loc.hot_location_arc_clone().unwrap()),
+        ));
+        assert!(mt.add_compiled_trace(&ctr, &[], None, &[], &mt.assumptions.start_recording()));
+        loc.hot_location().unwrap().lock().kind = HotLocationKind::Compiled(Arc::clone(&ctr));
+        ctr
+    }
+
+    #[test]
+    fn invalidate_root_trace() {
+        let mt = MT::new().unwrap();
+        let loc = Location::new();
+        let ctr = compiled_location(&mt, &loc);
+        assert_matches!(
+            mt.transition_control_point(&loc, ptr::null_mut()),
+            TransitionControlPoint::Execute(_)
+        );
+
+        assert_eq!(mt.invalidate_trace(ctr.ctrid()), 1);
+        assert!(ctr.is_invalidated());
+        assert_matches!(
+            loc.hot_location().unwrap().lock().kind,
+            HotLocationKind::Counting(0)
+        );
+        assert!(!mt.compiled_traces.lock().contains_key(&ctr.ctrid()));
+        // No thread was executing JIT code, so the trace should have been freed.
+        assert_eq!(mt.trace_gc.num_retired(), 0);
+        assert_eq!(Arc::strong_count(&ctr), 1);
+        // Invalidating a trace twice is harmless.
+        assert_eq!(mt.invalidate_trace(ctr.ctrid()), 0);
+
+        // The location can be traced and compiled again.
+        let ctr2 = compiled_location(&mt, &loc);
+        assert_ne!(ctr.ctrid(), ctr2.ctrid());
+    }
+
+    #[test]
+    fn invalidated_traces_not_freed_while_executing() {
+        let mt = MT::new().unwrap();
+        let loc = Location::new();
+        let ctr = compiled_location(&mt, &loc);
+
+        assert!(mt.trace_gc.enter(&*ctr));
+        assert_eq!(mt.invalidate_trace(ctr.ctrid()), 1);
+        assert_eq!(mt.trace_gc.num_retired(), 1);
+        assert!(mt.trace_gc.retired(ctr.ctrid()).is_some());
+        // A thread which has not yet started executing the trace must not do so.
+        assert!(!mt.trace_gc.enter(&*ctr));
+        assert_eq!(mt.trace_gc.num_retired(), 1);
+
+        mt.trace_gc.exit();
+        assert_eq!(mt.trace_gc.num_retired(), 0);
+        assert!(mt.trace_gc.retired(ctr.ctrid()).is_none());
+        assert_eq!(Arc::strong_count(&ctr), 1);
+    }
+
+    #[test]
+    fn invalidation_cascades_to_dependents() {
+        let mt = MT::new().unwrap();
+        let loc1 = Location::new();
+        let loc2 = Location::new();
+        let loc3 = Location::new();
+        let ctr1 = compiled_location(&mt, &loc1);
+        let ctr2 = compiled_location(&mt, &loc2);
+
+        // A connector trace from `loc3` to `loc1`.
+        mt.set_hot_threshold(0);
+        expect_start_tracing(&mt, &loc3);
+        expect_stop_tracing(&mt, &loc3);
+        let ctr3: Arc<dyn CompiledTrace> = Arc::new(CompiledTraceTestingBasicTransitions::with_mt(
+            &mt,
+            Arc::downgrade(&loc3.hot_location_arc_clone().unwrap()),
+        ));
+        assert!(mt.add_compiled_trace(
+            &ctr3,
+            &[ctr1.ctrid()],
+            None,
+            &[],
+            &mt.assumptions.start_recording()
+        ));
+        loc3.hot_location().unwrap().lock().kind = HotLocationKind::Compiled(Arc::clone(&ctr3));
+
+        assert_eq!(mt.invalidate_trace(ctr1.ctrid()), 2);
+        assert!(ctr1.is_invalidated());
+        assert!(!ctr2.is_invalidated());
+        assert!(ctr3.is_invalidated());
+        assert_matches!(
+            loc3.hot_location().unwrap().lock().kind,
+            HotLocationKind::Counting(0)
+        );
+        assert_matches!(
+            loc2.hot_location().unwrap().lock().kind,
+            HotLocationKind::Compiled(_)
+        );
+
+        // A trace which depends on an invalidated trace cannot be added.
+        let ctr4: Arc<dyn CompiledTrace> = Arc::new(CompiledTraceTestingBasicTransitions::with_mt(
+            &mt,
+            Arc::downgrade(&loc2.hot_location_arc_clone().unwrap()),
+        ));
+        assert!(!mt.add_compiled_trace(
+            &ctr4,
+            &[ctr1.ctrid()],
+            None,
+            &[],
+            &mt.assumptions.start_recording()
+        ));
+        assert!(mt.add_compiled_trace(
+            &ctr4,
+            &[ctr2.ctrid()],
+            None,
+            &[],
+            &mt.assumptions.start_recording()
+        ));
+    }
+
+    #[test]
+    fn invalidate_location() {
+        let mt = MT::new().unwrap();
+        let loc1 = Location::new();
+        let loc2 = Location::new();
+        let ctr1 = compiled_location(&mt, &loc1);
+        let ctr2 = compiled_location(&mt, &loc2);
+
+        loc1.invalidate();
+        assert!(ctr1.is_invalidated());
+        assert!(!ctr2.is_invalidated());
+        assert_matches!(
+            loc1.hot_location().unwrap().lock().kind,
+            HotLocationKind::Counting(0)
+        );
+        // Invalidating a location without a compiled trace is a no-op.
+        loc1.invalidate();
+        Location::new().invalidate();
+
+        expect_start_tracing(&mt, &loc1);
+    }
+
+    #[test]
+    fn invalidate_all() {
+        let mt = MT::new().unwrap();
+        let locs = (0..4).map(|_| Location::new()).collect::<Vec<_>>();
+        let ctrs = locs
+            .iter()
+            .map(|loc| compiled_location(&mt, loc))
+            .collect::<Vec<_>>();
+
+        mt.invalidate_all();
+        assert!(mt.compiled_traces.lock().is_empty());
+        for (loc, ctr) in locs.iter().zip(ctrs) {
+            assert!(ctr.is_invalidated());
+            assert_matches!(
+                loc.hot_location().unwrap().lock().kind,
+                HotLocationKind::Counting(0)
+            );
+        }
+    }
+
+    #[test]
+    fn invalidate_in_flight() {
+        let mt = MT::new().unwrap();
+        mt.set_hot_threshold(0);
+        let loc = Location::new();
+
+        // Invalidating a location while its trace is being compiled discards that trace.
+        let epoch = mt.assumptions.start_recording();
+        expect_start_tracing(&mt, &loc);
+        expect_stop_tracing(&mt, &loc);
+        let hl = loc.hot_location_arc_clone().unwrap();
+        let ctr: Arc<dyn CompiledTrace> = Arc::new(CompiledTraceTestingBasicTransitions::with_mt(
+            &mt,
+            Arc::downgrade(&hl),
+        ));
+        hl.lock().kind = HotLocationKind::Compiling(ctr.ctrid(), Hotness::default());
+        loc.invalidate();
+        assert!(mt.add_compiled_trace(&ctr, &[], None, &[], &epoch));
+        mt.link_root_trace(&hl, Arc::clone(&ctr));
+        assert!(ctr.is_invalidated());
+        assert_matches!(hl.lock().kind, HotLocationKind::Counting(0));
+        assert!(!mt.compiled_traces.lock().contains_key(&ctr.ctrid()));
+
+        // The next trace for the location is unaffected.
+        expect_start_tracing(&mt, &loc);
+        expect_stop_tracing(&mt, &loc);
+        let ctr: Arc<dyn CompiledTrace> = Arc::new(CompiledTraceTestingBasicTransitions::with_mt(
+            &mt,
+            Arc::downgrade(&hl),
+        ));
+        hl.lock().kind = HotLocationKind::Compiling(ctr.ctrid(), Hotness::default());
+        assert!(mt.add_compiled_trace(&ctr, &[], None, &[], &mt.assumptions.start_recording()));
+        mt.link_root_trace(&hl, Arc::clone(&ctr));
+        assert!(!ctr.is_invalidated());
+        assert_matches!(hl.lock().kind, HotLocationKind::Compiled(_));
+
+        // A trace whose recording started before all traces were invalidated cannot be added...
+        let epoch = mt.assumptions.start_recording();
+        let ctr: Arc<dyn CompiledTrace> = Arc::new(CompiledTraceTestingBasicTransitions::with_mt(
+            &mt,
+            Arc::downgrade(&hl),
+        ));
+        mt.invalidate_all();
+        assert!(!mt.add_compiled_trace(&ctr, &[], None, &[], &epoch));
+        // ...but one which started afterwards can be.
+        assert!(mt.add_compiled_trace(&ctr, &[], None, &[], &mt.assumptions.start_recording()));
+    }
+
+    #[test]
+    fn assumption_changed() {
+        let mt = MT::new().unwrap();
+        let loc1 = Location::new();
+        let loc2 = Location::new();
+        mt.set_hot_threshold(0);
+        let cell1 = 0usize;
+        let cell2 = 0usize;
+        let ptr1 = &cell1 as *const usize;
+        let ptr2 = &cell2 as *const usize;
+
+        let mut ctrs = Vec::new();
+        for (loc, ptr) in [(&loc1, ptr1), (&loc2, ptr2)] {
+            let epoch = mt.assumptions.start_recording();
+            expect_start_tracing(&mt, loc);
+            expect_stop_tracing(&mt, loc);
+            let ctr: Arc<dyn CompiledTrace> =
+                Arc::new(CompiledTraceTestingBasicTransitions::with_mt(
+                    &mt,
+                    Arc::downgrade(&loc.hot_location_arc_clone().unwrap()),
+                ));
+            assert!(mt.add_compiled_trace(&ctr, &[], None, &[ptr as usize], &epoch));
+            loc.hot_location().unwrap().lock().kind = HotLocationKind::Compiled(Arc::clone(&ctr));
+            ctrs.push(ctr);
+        }
+
+        mt.assumption_changed(ptr1 as *const c_void);
+        assert!(ctrs[0].is_invalidated());
+        assert!(!ctrs[1].is_invalidated());
+        assert_matches!(
+            loc1.hot_location().unwrap().lock().kind,
+            HotLocationKind::Counting(0)
+        );
+
+        // A trace whose recording started before an assumption changed cannot be added.
+        let epoch = mt.assumptions.start_recording();
+        let ctr: Arc<dyn CompiledTrace> = Arc::new(CompiledTraceTestingBasicTransitions::with_mt(
+            &mt,
+            Arc::downgrade(&loc1.hot_location_arc_clone().unwrap()),
+        ));
+        mt.assumption_changed(ptr1 as *const c_void);
+        assert!(!mt.add_compiled_trace(&ctr, &[], None, &[ptr1 as usize], &epoch));
+        // ...but one which doesn't depend on the changed assumption can be.
+        assert!(mt.add_compiled_trace(&ctr, &[], None, &[ptr2 as usize], &epoch));
+        mt.assumption_changed(ptr2 as *const c_void);
+        assert!(ctr.is_invalidated());
+        assert!(ctrs[1].is_invalidated());
+    }
+
+    #[test]
+    fn assumptions_pruned() {
+        let mt = MT::new().unwrap();
+        let loc = Location::new();
+        mt.set_hot_threshold(0);
+        let cell = 0usize;
+        let ptr = &cell as *const usize;
+
+        // Changes are only remembered while a trace which started before them is in flight.
+        mt.assumption_changed(ptr as *const c_void);
+        assert_eq!(mt.assumptions.num_changed(), 0);
+        let epoch = mt.assumptions.start_recording();
+        mt.assumption_changed(ptr as *const c_void);
+        assert_eq!(mt.assumptions.num_changed(), 1);
+        drop(epoch);
+        assert_eq!(mt.assumptions.num_changed(), 0);
+
+        // A trace's assumptions are forgotten when it is invalidated for another reason.
+        expect_start_tracing(&mt, &loc);
+        expect_stop_tracing(&mt, &loc);
+        let ctr: Arc<dyn CompiledTrace> = Arc::new(CompiledTraceTestingBasicTransitions::with_mt(
+            &mt,
+            Arc::downgrade(&loc.hot_location_arc_clone().unwrap()),
+        ));
+        assert!(mt.add_compiled_trace(
+            &ctr,
+            &[],
+            None,
+            &[ptr as usize],
+            &mt.assumptions.start_recording()
+        ));
+        assert_eq!(mt.assumptions.num_dependents(), 1);
+        assert_eq!(mt.invalidate_trace(ctr.ctrid()), 1);
+        assert_eq!(mt.assumptions.num_dependents(), 0);
+    }
+
+    #[test]
+    fn with_config() {
+        let mt = MT::with_config(
+            MTConfig::default()
+                .hot_threshold(7)
+                .sidetrace_threshold(3)
+                .optimise(false),
+        )
+        .unwrap();
+        assert_eq!(mt.hot_threshold(), 7);
+        assert_eq!(mt.sidetrace_threshold(), 3);
+        assert!(!mt.opt);
+
+        assert!(MT::with_config(MTConfig::default().log("-", u8::MAX)).is_err());
+    }
+
+    #[test]
+    fn tracer_config() {
+        #[cfg(tracer_swt)]
+        {
+            let mt = MT::with_config(MTConfig::default().tracer(Some("swt"))).unwrap();
+            assert_eq!(mt.tracer_kind(), TracerKind::Software);
+        }
+        assert!(MT::with_config(MTConfig::default().tracer(Some("nonsense"))).is_err());
+    }
+
+    /// Wait (for a bounded period) until `f` returns `true`.
+    fn wait_until(f: impl Fn() -> bool) {
+        let start = Instant::now();
+        while !f() {
+            assert!(start.elapsed() < Duration::from_secs(10), "Timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn resize_compiler_threads() {
+        let mt = MT::with_config(MTConfig::default().compiler_threads(1)).unwrap();
+        assert_eq!(mt.compiler_threads(), 1);
+
+        // Queue jobs which block until `release` is set: with one compiler thread, only one job
+        // can run at a time.
+        let started = Arc::new(AtomicU64::new(0));
+        let release = Arc::new(AtomicBool::new(false));
+        for _ in 0..3 {
+            let started = Arc::clone(&started);
+            let release = Arc::clone(&release);
+            mt.job_queue.push(
+                &mt,
+                Job::new(
+                    Box::new(move || {
+                        started.fetch_add(1, Ordering::Relaxed);
+                        while !release.load(Ordering::Relaxed) {
+                            thread::sleep(Duration::from_millis(1));
+                        }
+                    }),
+                    None,
+                    Box::new(|| ()),
+                    Hotness::default(),
+                    Vec::new(),
+                    CancellationToken::default(),
+                ),
+            );
+        }
+        wait_until(|| started.load(Ordering::Relaxed) == 1);
+        assert_eq!(mt.compile_queue_len(), 2);
+        assert_eq!(mt.job_queue.active_worker_threads(), 1);
+
+        // Increasing the cap spins up threads for the waiting jobs.
+        mt.set_compiler_threads(4);
+        assert_eq!(mt.compiler_threads(), 4);
+        wait_until(|| started.load(Ordering::Relaxed) == 3);
+        assert_eq!(mt.compile_queue_len(), 0);
+        assert_eq!(mt.job_queue.active_worker_threads(), 3);
+
+        // Decreasing the cap causes threads to exit once they are idle.
+        mt.set_compiler_threads(1);
+        assert_eq!(mt.job_queue.active_worker_threads(), 3);
+        release.store(true, Ordering::Relaxed);
+        wait_until(|| mt.job_queue.active_worker_threads() == 1);
+    }
+
+    #[test]
+    fn hottest_job_compiled_first() {
+        let mt = MT::with_config(MTConfig::default().compiler_threads(1)).unwrap();
+        let release = Arc::new(AtomicBool::new(false));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let job = |name: &'static str, connector_tid, hotness: &Hotness| {
+            let release = Arc::clone(&release);
+            let order = Arc::clone(&order);
+            Job::new(
+                Box::new(move || {
+                    while !release.load(Ordering::Relaxed) {
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                    order.lock().push(name);
+                }),
+                connector_tid,
+                Box::new(|| ()),
+                hotness.clone(),
+                Vec::new(),
+                CancellationToken::default(),
+            )
+        };
+
+        // Block the single compiler thread so that the other jobs have to wait in the queue.
+        mt.job_queue
+            .push(&mt, job("blocker", None, &Hotness::default()));
+        wait_until(|| mt.compile_queue_len() == 0);
+        let cold = Hotness::default();
+        let warm = Hotness::default();
+        let hot = Hotness::default();
+        mt.job_queue.push(&mt, job("cold", None, &cold));
+        mt.job_queue.push(&mt, job("warm", None, &warm));
+        // The hottest job can't run until its connector trace has compiled.
+        mt.job_queue
+            .push(&mt, job("hot", Some(TraceId(u64::MAX)), &hot));
+        for _ in 0..2 {
+            warm.inc();
+        }
+        for _ in 0..3 {
+            hot.inc();
+        }
+        release.store(true, Ordering::Relaxed);
+        wait_until(|| order.lock().len() == 3);
+        assert_eq!(*order.lock(), vec!["blocker", "warm", "cold"]);
+        assert_eq!(mt.compile_queue_len(), 1);
+    }
+
+    #[test]
+    fn cancel_jobs() {
+        let mt = MT::with_config(MTConfig::default().compiler_threads(1)).unwrap();
+        let loc = Location::new();
+        let ctr = compiled_location(&mt, &loc);
+
+        // A job which runs until it is cancelled, and which depends on `ctr`.
+        let running = CancellationToken::default();
+        let running_cl = running.clone();
+        let started = Arc::new(AtomicBool::new(false));
+        let started_cl = Arc::clone(&started);
+        mt.job_queue.push(
+            &mt,
+            Job::new(
+                Box::new(move || {
+                    started_cl.store(true, Ordering::Relaxed);
+                    while !running_cl.is_cancelled() {
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                }),
+                None,
+                Box::new(|| ()),
+                Hotness::default(),
+                vec![ctr.ctrid()],
+                running.clone(),
+            ),
+        );
+        wait_until(|| started.load(Ordering::Relaxed));
+
+        // Queued jobs: one which depends on `ctr` and one which doesn't.
+        let queued = [CancellationToken::default(), CancellationToken::default()];
+        for (cancel, deps) in queued.iter().zip([vec![ctr.ctrid()], Vec::new()]) {
+            mt.job_queue.push(
+                &mt,
+                Job::new(
+                    Box::new(|| ()),
+                    None,
+                    Box::new(|| ()),
+                    Hotness::default(),
+                    deps,
+                    cancel.clone(),
+                ),
+            );
+        }
+
+        mt.invalidate_trace(ctr.ctrid());
+        assert!(running.is_cancelled());
+        assert!(queued[0].is_cancelled());
+        assert!(!queued[1].is_cancelled());
+        wait_until(|| mt.compile_queue_len() == 0);
+
+        // After shutdown, all jobs are cancelled, including those queued after shutdown.
+        mt.shutdown();
+        let cancel = CancellationToken::default();
+        mt.job_queue.push(
+            &mt,
+            Job::new(
+                Box::new(|| ()),
+                None,
+                Box::new(|| ()),
+                Hotness::default(),
+                Vec::new(),
+                cancel.clone(),
+            ),
+        );
+        assert!(cancel.is_cancelled());
+    }
+
+    #[test]
+    fn foreground_compilation() {
+        let mt = MT::with_config(MTConfig::default().foreground_compilation(true)).unwrap();
+        let tid = thread::current().id();
+        let ran = Arc::new(AtomicBool::new(false));
+        let ran_cl = Arc::clone(&ran);
+        mt.job_queue.push(
+            &mt,
+            Job::new(
+                Box::new(move || {
+                    assert_eq!(thread::current().id(), tid);
+                    ran_cl.store(true, Ordering::Relaxed);
+                }),
+                None,
+                Box::new(|| ()),
+                Hotness::default(),
+                Vec::new(),
+                CancellationToken::default(),
+            ),
+        );
+        assert!(ran.load(Ordering::Relaxed));
+        assert_eq!(mt.job_queue.active_worker_threads(), 0);
+    }
 }
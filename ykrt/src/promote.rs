@@ -92,3 +92,19 @@ pub extern "C" fn __yk_idempotent_promote_i32(val: i32) -> i32 {
     }
     val
 }
+
+/// Read the quasi-constant memory cell `ptr`. During trace recording, the value read is recorded
+/// so that the trace can treat it as a constant without a guard: if the cell is later written to,
+/// the interpreter must call `yk_assumption_changed` to invalidate the traces which depend on it.
+///
+/// # Safety
+///
+/// `ptr` must be a valid, aligned, pointer to a `usize`.
+#[no_mangle]
+pub unsafe extern "C" fn yk_assume_constant(ptr: *const usize) -> usize {
+    let val = unsafe { *ptr };
+    if MTThread::is_tracing() {
+        MTThread::with_borrow_mut(|mtt| mtt.assume_constant(ptr as usize, val));
+    }
+    val
+}
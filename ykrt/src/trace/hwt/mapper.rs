@@ -1,14 +1,7 @@
 //! The mapper translates a hwtracer trace into an IR trace.
 
 use crate::trace::{AOTTraceIterator, AOTTraceIteratorError, TraceAction, TraceRecorderError};
-use hwtracer::{
-    llvm_blockmap::LLVM_BLOCK_MAP, Block, BlockIteratorError, HWTracerError, TemporaryErrorKind,
-    Trace,
-};
-use ykaddr::{
-    addr::{vaddr_to_obj_and_off, vaddr_to_sym_and_obj},
-    obj::SELF_BIN_PATH,
-};
+use hwtracer::{image::Image, Block, BlockIteratorError, HWTracerError, TemporaryErrorKind, Trace};
 
 /// Map the *machine* basic blocks of the specified trace into LLVM IR basic blocks.
 ///
@@ -17,6 +10,8 @@ use ykaddr::{
 /// code spanning at least one machine block).
 pub(crate) struct HWTTraceIterator {
     hwt_iter: Box<dyn Iterator<Item = Result<Block, BlockIteratorError>> + Send>,
+    /// The code that the trace was recorded from.
+    image: &'static dyn Image,
     /// The next [TraceAction]`s we will produce when `next` is called. We need this intermediary
     /// to allow us to deduplicate mapped/unmapped basic blocks. This will be empty on the first
     /// iteration and from then on will always have at least one [TraceAction] in it at all times,
@@ -32,8 +27,10 @@ impl AOTTraceIterator for HWTTraceIterator {}
 
 impl HWTTraceIterator {
     pub fn new(trace: Box<dyn Trace>) -> Result<Self, TraceRecorderError> {
+        let image = trace.image();
         Ok(Self {
             hwt_iter: trace.iter_blocks(),
+            image,
             upcoming: Vec::new(),
             tas_generated: 0,
         })
@@ -87,8 +84,6 @@ impl HWTTraceIterator {
         }
         let (block_vaddr, block_last_inst) = b_rng.unwrap();
 
-        let (obj_name, _block_off) = vaddr_to_obj_and_off(block_vaddr).unwrap();
-
         // Currently we only read in a block map and IR for the currently running binary (and not
         // for dynamically linked shared objects). Thus, if we see code from another object, we
         // can't map it.
@@ -96,13 +91,15 @@ impl HWTTraceIterator {
         // FIXME: https://github.com/ykjit/yk/issues/413
         // In the future we could inline code from shared objects if they were built for use with
         // yk (i.e. they have a blockmap and IR embedded).
-        if obj_name != *SELF_BIN_PATH {
+        if !self.image.in_main_obj(block_vaddr).unwrap() {
             self.push_upcoming(TraceAction::new_unmappable_block());
             return;
         }
 
         let block_len = block_last_inst - block_vaddr;
-        let mut ents = LLVM_BLOCK_MAP
+        let mut ents = self
+            .image
+            .blockmap()
             .query(block_vaddr, block_vaddr + block_len)
             .collect::<Vec<_>>();
 
@@ -121,12 +118,7 @@ impl HWTTraceIterator {
                 // same function, and a block X has a start address between basic blocks A and B,
                 // then X must also belong to the same function and there's no need to query the
                 // linker.
-                let sio = vaddr_to_sym_and_obj(block_vaddr).unwrap();
-                debug_assert_eq!(
-                    obj_name.to_str().unwrap(),
-                    sio.dli_fname().unwrap().to_str().unwrap()
-                );
-                if let Some(sym_name) = sio.dli_sname() {
+                if let Some(sym_name) = self.image.sym_name(block_vaddr) {
                     for bb in ent.value.corr_bbs() {
                         self.push_upcoming(TraceAction::new_mapped_aot_block(
                             sym_name,
@@ -1,6 +1,6 @@
 //! Hardware tracing via hwtracer.
 
-use super::{AOTTraceIterator, TraceRecorder, TraceRecorderError};
+use super::{AOTTraceIterator, TraceRecorder, TraceRecorderError, TracerKind};
 use hwtracer::{HWTracerError, TemporaryErrorKind};
 use std::{error::Error, sync::Arc};
 
@@ -13,7 +13,7 @@ pub(crate) struct HWTracer {
 }
 
 impl HWTracer {
-    pub fn new() -> Result<Self, Box<dyn Error>> {
+    pub fn new() -> Result<Self, HWTracerError> {
         Ok(HWTracer {
             backend: hwtracer::TracerBuilder::new().build()?,
         })
@@ -26,6 +26,10 @@ impl super::Tracer for HWTracer {
             thread_tracer: Arc::clone(&self.backend).start_collector()?,
         }))
     }
+
+    fn kind(&self) -> TracerKind {
+        TracerKind::Hardware
+    }
 }
 
 /// Hardware thread tracer.
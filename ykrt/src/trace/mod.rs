@@ -12,6 +12,7 @@
 //!
 //! This module thus contains tracing backends which can record and process traces.
 
+use crate::config::MTConfig;
 use std::{error::Error, ffi::CStr, fmt, sync::Arc};
 use thiserror::Error;
 
@@ -20,28 +21,63 @@ pub(crate) mod hwt;
 #[cfg(tracer_swt)]
 pub(crate) mod swt;
 
+/// Map the blocks of a hardware trace to the [TraceAction]s that the trace builder would see.
+///
+/// This is mostly useful for decoding traces saved by a previous run (see `hwtracer::offline`).
+#[cfg(tracer_hwt)]
+pub fn map_hwt_trace(trace: Box<dyn hwtracer::Trace>) -> Result<Vec<TraceAction>, Box<dyn Error>> {
+    Ok(hwt::HWTTraceIterator::new(trace)?.collect::<Result<Vec<_>, _>>()?)
+}
+
 /// A `Tracer` is a front-end to a tracer backend (e.g. hardware or software tracing). The tracer
 /// backend may have its own configuration options, which is why `Tracer` does not have a `new`
 /// method.
 pub(crate) trait Tracer: Send + Sync {
     /// Start recording a trace of the current thread.
     fn start_recorder(self: Arc<Self>) -> Result<Box<dyn TraceRecorder>, Box<dyn Error>>;
+
+    /// What kind of tracer backend is this?
+    fn kind(&self) -> TracerKind;
 }
 
-/// Return a [Tracer] instance or `Err` if none can be found. The [Tracer] returned will be
-/// selected on a combination of what the platform can support and other (possibly run-time) user
-/// configuration.
-pub(crate) fn default_tracer() -> Result<Arc<dyn Tracer>, Box<dyn Error>> {
-    #[cfg(tracer_hwt)]
-    {
-        return Ok(Arc::new(hwt::HWTracer::new()?));
-    }
-    #[cfg(tracer_swt)]
-    {
-        return Ok(Arc::new(swt::SWTracer::new()?));
+/// The kinds of tracer backend.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum TracerKind {
+    Hardware,
+    #[cfg_attr(not(tracer_swt), allow(dead_code))]
+    Software,
+}
+
+/// Return a [Tracer] instance or `Err` if none can be found. If `config` names a tracer, that
+/// tracer is returned (or `Err` if it is not available). Otherwise, the hardware tracer is
+/// returned if it is compiled in and the platform supports it, falling back to the software tracer
+/// (if it is compiled in).
+pub(crate) fn default_tracer(config: &MTConfig) -> Result<Arc<dyn Tracer>, Box<dyn Error>> {
+    match config.tracer.as_deref() {
+        #[cfg(tracer_hwt)]
+        Some("hwt") => Ok(Arc::new(hwt::HWTracer::new()?)),
+        #[cfg(tracer_swt)]
+        Some("swt") => Ok(Arc::new(swt::SWTracer::new(config.swt_max_trace_len)?)),
+        #[allow(unreachable_patterns)]
+        Some(x @ ("hwt" | "swt")) => {
+            Err(format!("Tracer '{x}' is not available in this build of yk").into())
+        }
+        Some(x) => Err(format!("Unknown tracer '{x}'").into()),
+        None => {
+            #[cfg(tracer_hwt)]
+            match hwt::HWTracer::new() {
+                Ok(x) => return Ok(Arc::new(x)),
+                // The platform doesn't support hardware tracing: fall back to the software tracer.
+                #[cfg(tracer_swt)]
+                Err(hwtracer::HWTracerError::ConfigError(_)) => (),
+                Err(e) => return Err(e.into()),
+            }
+            #[cfg(tracer_swt)]
+            return Ok(Arc::new(swt::SWTracer::new(config.swt_max_trace_len)?));
+            #[allow(unreachable_code)]
+            Err("No tracing backend for this platform/configuration.".into())
+        }
     }
-    #[allow(unreachable_code)]
-    Err("No tracing backend for this platform/configuration.".into())
 }
 
 /// An instance of a [Tracer] which is currently recording a trace of the current thread.
@@ -49,6 +85,14 @@ pub(crate) trait TraceRecorder: fmt::Debug {
     /// Stop recording a trace of the current thread and return an iterator which successively
     /// produces [TraceAction]s.
     fn stop(self: Box<Self>) -> Result<Box<dyn AOTTraceIterator>, TraceRecorderError>;
+
+    /// Record the promoted value `val` (a native-endian sequence of bytes) inline in the
+    /// trace, returning `true` if the recorder did so: the value will then be produced as a
+    /// [TraceAction::Promotion] by the trace's iterator. If `false` is returned, the caller must
+    /// record `val` in the side buffer of promoted values instead.
+    fn record_promotion(&self, _val: &[u8]) -> bool {
+        false
+    }
 }
 
 /// When a trace recorder stops, it may immediately realise that a problem occurred and return an
@@ -75,8 +119,8 @@ pub enum TraceRecorderError {
 ///    after the call to the control point. Note that the (almost certainly unmappable, though that
 ///    depends on the backend) block representing the control point's body must not be returned by
 ///    the iterator.
-/// 2. Consecutive `TraceAction`s must not compare equal (i.e. the iterator must have deduplicated
-///    consecutive `TraceAction`s).
+/// 2. Consecutive `TraceAction`s, other than [TraceAction::Promotion]s, must not compare equal
+///    (i.e. the iterator must have deduplicated consecutive blocks).
 /// 3. The call to the "stop tracing" function must not appear at the tail of the trace.
 pub(crate) trait AOTTraceIterator:
     Iterator<Item = Result<TraceAction, AOTTraceIteratorError>> + Send
@@ -120,13 +164,14 @@ pub enum TraceAction {
     ///
     /// This usually means that the basic blocks were compiled outside of ykllvm.
     UnmappableBBlock,
-    /// A value promoted and recorded within the low-level trace (e.g. `PTWRITE`). In essence these
-    /// are calls to `yk_promote` that have been inlined so that the tracer backend can handle them
-    /// rather than being handled by yk's generic run-time support for `yk_promote`.
+    /// A value promoted and recorded within the low-level trace (see
+    /// [TraceRecorder::record_promotion]), as a native-endian sequence of bytes. A promotion
+    /// immediately follows the block which promoted the value, which allows the trace builder to
+    /// check that it consumes promoted values in the order, and in the blocks, they were recorded.
     ///
-    /// While no tracer backend currently uses this variant, it's present to remind us that this a
-    /// useful possibility.
-    Promotion,
+    /// Tracer backends which don't record promotions inline never produce this variant: their
+    /// promoted values are recorded in a separate buffer instead.
+    Promotion(Box<[u8]>),
 }
 
 impl TraceAction {
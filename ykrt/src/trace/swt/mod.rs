@@ -1,50 +1,166 @@
 //! Software tracer.
+//!
+//! Each thread records its trace into a compact byte buffer. Every entry starts with an unsigned
+//! LEB128 header whose low [TAG_BITS] bits say what kind of entry it is:
+//!
+//! * [TAG_BBLOCK]: a basic block in the same function as the previous basic block. The rest of the
+//!   header is the zig-zag encoded difference between the block's index and the previous block's.
+//! * [TAG_FUNC_BBLOCK]: a basic block in a different function to the previous basic block (or the
+//!   first basic block in the trace). The rest of the header is the function's index, and is
+//!   followed by the block's index as a further LEB128 value.
+//! * [TAG_PROMOTION]: a promoted value. The rest of the header is the value's size in bytes, and is
+//!   followed by the value's native-endian bytes.
+//!
+//! Since most basic blocks are close to their predecessor in the same function, most basic blocks
+//! are thus recorded in a single byte.
 
 use super::{
-    AOTTraceIterator, AOTTraceIteratorError, TraceAction, TraceRecorder, TraceRecorderError, Tracer,
+    AOTTraceIterator, AOTTraceIteratorError, TraceAction, TraceRecorder, TraceRecorderError,
+    Tracer, TracerKind,
 };
-use crate::mt::MTThread;
-use std::{
-    cell::RefCell,
-    collections::HashMap,
-    error::Error,
-    ffi::CString,
-    sync::{Arc, LazyLock},
+use crate::{
+    compile::jitc_yk::{aot_ir::FuncIdx, AOT_MOD},
+    mt::MTThread,
 };
+use std::{cell::RefCell, error::Error, mem, sync::Arc};
 
-#[derive(Debug, Eq, PartialEq, Clone)]
-struct TracingBBlock {
-    function_index: usize,
-    block_index: usize,
+/// The number of bits at the bottom of an entry's header which identify the kind of entry.
+const TAG_BITS: u32 = 2;
+/// A basic block in the same function as the previous basic block.
+const TAG_BBLOCK: usize = 0;
+/// A basic block in a different function to the previous basic block.
+const TAG_FUNC_BBLOCK: usize = 1;
+/// A promoted value.
+const TAG_PROMOTION: usize = 2;
+
+/// The trace being recorded by the current thread.
+struct TraceBuf {
+    /// The encoded entries traced so far.
+    bytes: Vec<u8>,
+    /// The number of entries in `bytes`.
+    len: usize,
+    /// Is the software tracer recording a trace for this thread? This can be `false` even when
+    /// the thread is tracing if another tracer backend is in use.
+    recording: bool,
+    /// The maximum number of entries that may be recorded in `bytes`.
+    max_len: usize,
+    /// Did the trace try to grow beyond `max_len` entries?
+    overflowed: bool,
+    /// The function and block index of the last basic block recorded, if there is one.
+    last_bblock: Option<(usize, usize)>,
 }
 
-/// Mapping of function indices to function names.
-///
-/// FIXME: We shouldn't be reaching into codegen-backend-specific stuff here. There should probably
-/// be some kind of generic codegen interface that offers this information up.
-///
-/// FIXME: We also probably don't need a whole hashmap caching owned copies of all of the function
-/// names. Looking at the sole use-site of `FUNC_NAMES`, I reckon that (once the LLVM backend has
-/// been deleted) it would be sufficient to expose a thin wrapper around `Module::func_()` and use
-/// that for querying function names from indices.
-#[cfg(jitc_yk)]
-static FUNC_NAMES: LazyLock<HashMap<usize, CString>> = LazyLock::new(|| {
-    crate::compile::jitc_yk::AOT_MOD
-        .funcs()
-        .iter_enumerated()
-        .map(|(funcidx, func)| {
-            // unwrap cannot fail assuming that all symbols are UTF-8.
-            (usize::from(funcidx), CString::new(func.name()).unwrap())
-        })
-        .collect::<HashMap<_, _>>()
-});
+impl TraceBuf {
+    /// Account for a new entry, returning `true` if it should be recorded.
+    fn reserve(&mut self) -> bool {
+        if !self.recording {
+            false
+        } else if self.len < self.max_len {
+            self.len += 1;
+            true
+        } else {
+            self.overflowed = true;
+            false
+        }
+    }
+
+    /// Record the basic block `block_index` in the function `function_index`.
+    fn push_bblock(&mut self, function_index: usize, block_index: usize) {
+        if !self.reserve() {
+            return;
+        }
+        match self.last_bblock {
+            Some((last_func, last_block)) if last_func == function_index => {
+                let delta = zigzag(block_index.wrapping_sub(last_block) as isize);
+                push_header(&mut self.bytes, delta, TAG_BBLOCK);
+            }
+            _ => {
+                push_header(&mut self.bytes, function_index, TAG_FUNC_BBLOCK);
+                push_uleb128(&mut self.bytes, block_index);
+            }
+        }
+        self.last_bblock = Some((function_index, block_index));
+    }
+
+    /// Record the promoted value `val`.
+    fn push_promotion(&mut self, val: &[u8]) {
+        if !self.reserve() {
+            return;
+        }
+        push_header(&mut self.bytes, val.len(), TAG_PROMOTION);
+        self.bytes.extend_from_slice(val);
+    }
+
+    /// Reset this buffer ready for the next trace, returning the encoded entries and whether the
+    /// trace overflowed.
+    fn take(&mut self) -> (Vec<u8>, bool) {
+        self.recording = false;
+        self.len = 0;
+        self.last_bblock = None;
+        (
+            mem::take(&mut self.bytes),
+            mem::replace(&mut self.overflowed, false),
+        )
+    }
+}
 
 thread_local! {
-    // Collection of traced basic blocks.
-    static BASIC_BLOCKS: RefCell<Vec<TracingBBlock>> = const { RefCell::new(vec![]) };
+    // The trace being recorded by this thread.
+    static TRACE_BUF: RefCell<TraceBuf> = const {
+        RefCell::new(TraceBuf {
+            bytes: Vec::new(),
+            len: 0,
+            recording: false,
+            max_len: 0,
+            overflowed: false,
+            last_bblock: None,
+        })
+    };
+}
+
+/// Map a signed integer to an unsigned integer such that integers close to zero map to small
+/// unsigned integers.
+fn zigzag(x: isize) -> usize {
+    ((x << 1) ^ (x >> (isize::BITS - 1))) as usize
+}
+
+/// The inverse of [zigzag].
+fn unzigzag(x: usize) -> isize {
+    ((x >> 1) as isize) ^ -((x & 1) as isize)
+}
+
+/// Append `x` to `bytes` as an unsigned LEB128 value.
+fn push_uleb128(bytes: &mut Vec<u8>, mut x: usize) {
+    while x >= 0x80 {
+        bytes.push((x as u8) | 0x80);
+        x >>= 7;
+    }
+    bytes.push(x as u8);
+}
+
+/// Append an entry header with the payload `x` and the tag `tag` to `bytes`.
+fn push_header(bytes: &mut Vec<u8>, x: usize, tag: usize) {
+    debug_assert_eq!(x >> (usize::BITS - TAG_BITS), 0);
+    push_uleb128(bytes, (x << TAG_BITS) | tag);
 }
 
-/// Inserts LLVM IR basicblock metadata into a thread-local BASIC_BLOCKS vector.
+/// Read an unsigned LEB128 value from `bytes` starting at `*off`, updating `*off` to point
+/// immediately after it.
+fn read_uleb128(bytes: &[u8], off: &mut usize) -> usize {
+    let mut x = 0;
+    let mut shift = 0;
+    loop {
+        let b = bytes[*off];
+        *off += 1;
+        x |= usize::from(b & 0x7f) << shift;
+        if b & 0x80 == 0 {
+            return x;
+        }
+        shift += 7;
+    }
+}
+
+/// Inserts LLVM IR basicblock metadata into this thread's trace buffer.
 ///
 /// # Arguments
 /// * `function_index` - The index of the function to which the basic block belongs.
@@ -53,28 +169,35 @@ thread_local! {
 #[no_mangle]
 pub extern "C" fn __yk_trace_basicblock(function_index: usize, block_index: usize) {
     if MTThread::is_tracing() {
-        BASIC_BLOCKS.with(|v| {
-            v.borrow_mut().push(TracingBBlock {
-                function_index,
-                block_index,
-            });
-        })
+        TRACE_BUF.with_borrow_mut(|tb| tb.push_bblock(function_index, block_index));
     }
 }
 
-pub(crate) struct SWTracer {}
+pub(crate) struct SWTracer {
+    /// The maximum number of entries (basic blocks and promoted values) that may be recorded in a
+    /// single trace.
+    max_trace_len: usize,
+}
 
 impl SWTracer {
-    pub fn new() -> Result<Self, Box<dyn Error>> {
-        Ok(SWTracer {})
+    pub fn new(max_trace_len: usize) -> Result<Self, Box<dyn Error>> {
+        Ok(SWTracer { max_trace_len })
     }
 }
 
 impl Tracer for SWTracer {
     fn start_recorder(self: Arc<Self>) -> Result<Box<dyn TraceRecorder>, Box<dyn Error>> {
-        debug_assert!(BASIC_BLOCKS.with(|bbs| bbs.borrow().is_empty()));
+        TRACE_BUF.with_borrow_mut(|tb| {
+            debug_assert!(tb.bytes.is_empty() && !tb.overflowed);
+            tb.recording = true;
+            tb.max_len = self.max_trace_len;
+        });
         Ok(Box::new(SWTTraceRecorder {}))
     }
+
+    fn kind(&self) -> TracerKind {
+        TracerKind::Software
+    }
 }
 
 #[derive(Debug)]
@@ -82,24 +205,99 @@ struct SWTTraceRecorder {}
 
 impl TraceRecorder for SWTTraceRecorder {
     fn stop(self: Box<Self>) -> Result<Box<dyn AOTTraceIterator>, TraceRecorderError> {
-        let bbs = BASIC_BLOCKS.with(|tb| tb.replace(Vec::new()));
-        if bbs.is_empty() {
-            // FIXME: who should handle an empty trace?
-            panic!();
+        let (bytes, overflowed) = TRACE_BUF.with_borrow_mut(|tb| tb.take());
+        if overflowed {
+            Err(TraceRecorderError::TraceTooLong)
+        } else if bytes.is_empty() {
+            Err(TraceRecorderError::TraceEmpty)
         } else {
-            Ok(Box::new(SWTraceIterator::new(bbs)))
+            Ok(Box::new(SWTraceIterator::new(bytes)))
+        }
+    }
+
+    fn record_promotion(&self, val: &[u8]) -> bool {
+        TRACE_BUF.with_borrow_mut(|tb| tb.push_promotion(val));
+        true
+    }
+}
+
+/// A decoded entry in a software trace.
+#[derive(Debug, Eq, PartialEq)]
+enum TraceEntry {
+    /// A basic block.
+    BBlock {
+        function_index: usize,
+        block_index: usize,
+    },
+    /// A promoted value, as a native-endian sequence of bytes.
+    Promotion(Box<[u8]>),
+}
+
+/// Decodes the entries in an encoded trace buffer.
+struct TraceDecoder {
+    bytes: Vec<u8>,
+    /// The offset in `bytes` of the next entry.
+    off: usize,
+    /// The function and block index of the last basic block decoded, if there is one.
+    last_bblock: Option<(usize, usize)>,
+}
+
+impl TraceDecoder {
+    fn new(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            off: 0,
+            last_bblock: None,
+        }
+    }
+}
+
+impl Iterator for TraceDecoder {
+    type Item = TraceEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.off == self.bytes.len() {
+            return None;
+        }
+        let hdr = read_uleb128(&self.bytes, &mut self.off);
+        let x = hdr >> TAG_BITS;
+        match hdr & ((1 << TAG_BITS) - 1) {
+            TAG_BBLOCK => {
+                // The encoder never produces this tag for the first basic block.
+                let (function_index, last_block) = self.last_bblock.unwrap();
+                let block_index = last_block.wrapping_add(unzigzag(x) as usize);
+                self.last_bblock = Some((function_index, block_index));
+                Some(TraceEntry::BBlock {
+                    function_index,
+                    block_index,
+                })
+            }
+            TAG_FUNC_BBLOCK => {
+                let block_index = read_uleb128(&self.bytes, &mut self.off);
+                self.last_bblock = Some((x, block_index));
+                Some(TraceEntry::BBlock {
+                    function_index: x,
+                    block_index,
+                })
+            }
+            TAG_PROMOTION => {
+                let val = self.bytes[self.off..self.off + x].into();
+                self.off += x;
+                Some(TraceEntry::Promotion(val))
+            }
+            _ => unreachable!(),
         }
     }
 }
 
 struct SWTraceIterator {
-    bbs: std::vec::IntoIter<TracingBBlock>,
+    entries: TraceDecoder,
 }
 
 impl SWTraceIterator {
-    fn new(bbs: Vec<TracingBBlock>) -> SWTraceIterator {
+    fn new(bytes: Vec<u8>) -> SWTraceIterator {
         SWTraceIterator {
-            bbs: bbs.into_iter(),
+            entries: TraceDecoder::new(bytes),
         }
     }
 }
@@ -108,19 +306,124 @@ impl Iterator for SWTraceIterator {
     type Item = Result<TraceAction, AOTTraceIteratorError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.bbs
-            .next()
-            .map(|tb| match FUNC_NAMES.get(&tb.function_index) {
-                Some(name) => Ok(TraceAction::MappedAOTBBlock {
-                    func_name: name.as_c_str(),
-                    bb: tb.block_index,
-                }),
-                _ => panic!(
-                    "Failed to get function name by index {:?}",
-                    tb.function_index
-                ),
-            })
+        self.entries.next().map(|e| match e {
+            TraceEntry::BBlock {
+                function_index,
+                block_index,
+            } => Ok(TraceAction::MappedAOTBBlock {
+                func_name: AOT_MOD.func(FuncIdx::new(function_index)).name_cstr(),
+                bb: block_index,
+            }),
+            TraceEntry::Promotion(val) => Ok(TraceAction::Promotion(val)),
+        })
     }
 }
 
 impl AOTTraceIterator for SWTraceIterator {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_trace() {
+        let tracer = Arc::new(SWTracer::new(2).unwrap());
+        let tr = tracer.start_recorder().unwrap();
+        assert!(matches!(tr.stop(), Err(TraceRecorderError::TraceEmpty)));
+    }
+
+    #[test]
+    fn not_recording() {
+        // When another tracer is in use, blocks and promotions must not be recorded.
+        TRACE_BUF.with_borrow_mut(|tb| {
+            tb.push_bblock(0, 0);
+            tb.push_promotion(&[1, 2]);
+            assert!(tb.bytes.is_empty() && !tb.overflowed);
+        });
+    }
+
+    #[test]
+    fn trace_too_long() {
+        let tracer = Arc::new(SWTracer::new(2).unwrap());
+        let tr = Arc::clone(&tracer).start_recorder().unwrap();
+        for block_index in 0..5 {
+            TRACE_BUF.with_borrow_mut(|tb| tb.push_bblock(0, block_index));
+        }
+        assert_eq!(TRACE_BUF.with_borrow(|tb| tb.len), 2);
+        assert!(matches!(tr.stop(), Err(TraceRecorderError::TraceTooLong)));
+
+        // The buffer must be reset so that the next trace can be recorded.
+        let tr = tracer.start_recorder().unwrap();
+        assert!(tr.record_promotion(&[1, 2]));
+        assert!(tr.record_promotion(&[3, 4, 5, 6]));
+        assert!(tr.stop().is_ok());
+    }
+
+    #[test]
+    fn inline_promotions() {
+        let tracer = Arc::new(SWTracer::new(10).unwrap());
+        let tr = tracer.start_recorder().unwrap();
+        assert!(tr.record_promotion(&[1, 2, 3, 4]));
+        assert!(tr.record_promotion(&7usize.to_ne_bytes()));
+        let actions = tr.stop().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            actions,
+            [
+                TraceAction::Promotion(Box::new([1, 2, 3, 4])),
+                TraceAction::Promotion(7usize.to_ne_bytes().into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn zigzag_roundtrip() {
+        for x in [0, 1, -1, 2, -2, 63, -64, isize::MAX, isize::MIN] {
+            assert_eq!(unzigzag(zigzag(x)), x);
+        }
+        assert_eq!(zigzag(0), 0);
+        assert_eq!(zigzag(-1), 1);
+        assert_eq!(zigzag(1), 2);
+    }
+
+    #[test]
+    fn encode_decode() {
+        let mut tb = TraceBuf {
+            bytes: Vec::new(),
+            len: 0,
+            recording: true,
+            max_len: usize::MAX,
+            overflowed: false,
+            last_bblock: None,
+        };
+        let bblock = |function_index, block_index| TraceEntry::BBlock {
+            function_index,
+            block_index,
+        };
+        let entries = [
+            bblock(3, 1),
+            bblock(3, 2),
+            bblock(3, 2),
+            bblock(3, 0),
+            TraceEntry::Promotion(Box::new([1, 2, 3])),
+            bblock(1000, 1),
+            bblock(3, 100000),
+            bblock(3, 5),
+            TraceEntry::Promotion(Box::new([])),
+            bblock(3, 6),
+        ];
+        for e in &entries {
+            match e {
+                TraceEntry::BBlock {
+                    function_index,
+                    block_index,
+                } => tb.push_bblock(*function_index, *block_index),
+                TraceEntry::Promotion(val) => tb.push_promotion(val),
+            }
+        }
+        let (bytes, overflowed) = tb.take();
+        assert!(!overflowed);
+        // Blocks close to their predecessor in the same function need only a single byte.
+        assert_eq!(bytes[2..5], [2 << TAG_BITS, 0, 3 << TAG_BITS]);
+        assert_eq!(TraceDecoder::new(bytes).collect::<Vec<_>>(), entries);
+    }
+}
@@ -0,0 +1,159 @@
+//! Invalidation and garbage collection of compiled traces.
+//!
+//! A [CompiledTrace] can be reached from several places: a [HotLocation] in the
+//! [HotLocationKind::Compiled] state; a [Guard] in a parent trace that has been patched to jump to
+//! a side-trace; and the machine code of other traces which jump to it (side-traces jump to their
+//! target trace when they finish, and connector traces jump to the trace they connect to).
+//! Invalidating a trace means unlinking it from all of these places so that no thread can newly
+//! start executing it, and then invalidating every trace which can jump to it.
+//!
+//! However, when a trace is invalidated, other threads may already be executing its machine code,
+//! so we cannot free it straight away: instead it is "retired". Since we do not track which trace
+//! each thread is executing (a thread can pass through arbitrarily many traces before
+//! deoptimising), retired traces are only freed when we observe that no thread is executing *any*
+//! JIT code for the [MT] in question. Note that this means that if at least one thread is always
+//! executing JIT code, retired traces will never be freed.
+//!
+//! [HotLocation]: crate::location::HotLocation
+//! [HotLocationKind::Compiled]: crate::location::HotLocationKind::Compiled
+//! [Guard]: crate::compile::Guard
+//! [MT]: crate::mt::MT
+
+use crate::{
+    compile::{CompiledTrace, GuardIdx},
+    mt::TraceId,
+};
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    mem,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+pub(crate) struct TraceGC {
+    /// How many threads are currently executing JIT code? Note that a thread which executes a
+    /// trace which calls back into the interpreter which then executes a trace will count twice
+    /// towards this.
+    executing: AtomicUsize,
+    /// How many traces are in [TraceGCInner::retired]? This allows us to cheaply check whether
+    /// there is any garbage to collect without taking a lock.
+    num_retired: AtomicUsize,
+    inner: Mutex<TraceGCInner>,
+}
+
+struct TraceGCInner {
+    /// Maps a [TraceId] to the [TraceId]s of the traces whose machine code can jump to it. If a
+    /// trace is invalidated, all of its dependents must also be invalidated.
+    dependents: HashMap<TraceId, Vec<TraceId>>,
+    /// For each side-trace, the [TraceId] of its parent and the [GuardIdx] of the guard in the
+    /// parent which has been (or will be) patched to jump to the side-trace.
+    parents: HashMap<TraceId, (TraceId, GuardIdx)>,
+    /// Traces which have been invalidated but which may still be being executed by a thread.
+    retired: HashMap<TraceId, Arc<dyn CompiledTrace>>,
+}
+
+impl TraceGC {
+    pub(crate) fn new() -> Self {
+        Self {
+            executing: AtomicUsize::new(0),
+            num_retired: AtomicUsize::new(0),
+            inner: Mutex::new(TraceGCInner {
+                dependents: HashMap::new(),
+                parents: HashMap::new(),
+                retired: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Record that the trace `ctrid` can jump to each of the traces in `deps` and, if `ctrid` is a
+    /// side-trace, that it is linked from the guard `parent`.
+    pub(crate) fn register(
+        &self,
+        ctrid: TraceId,
+        deps: &[TraceId],
+        parent: Option<(TraceId, GuardIdx)>,
+    ) {
+        let mut lk = self.inner.lock();
+        for dep in deps {
+            lk.dependents.entry(*dep).or_default().push(ctrid);
+        }
+        if let Some(parent) = parent {
+            lk.parents.insert(ctrid, parent);
+        }
+    }
+
+    /// If `ctrid` is a side-trace, return its parent's [TraceId] and the [GuardIdx] of the guard
+    /// in the parent that jumps to it.
+    pub(crate) fn parent(&self, ctrid: TraceId) -> Option<(TraceId, GuardIdx)> {
+        self.inner.lock().parents.get(&ctrid).cloned()
+    }
+
+    /// Retire the (already invalidated and unlinked) trace `ctr`, returning the [TraceId]s of the
+    /// traces which can jump to it (and which therefore also need to be invalidated).
+    pub(crate) fn retire(&self, ctr: Arc<dyn CompiledTrace>) -> Vec<TraceId> {
+        debug_assert!(ctr.is_invalidated());
+        let ctrid = ctr.ctrid();
+        let mut lk = self.inner.lock();
+        lk.parents.remove(&ctrid);
+        let deps = lk.dependents.remove(&ctrid).unwrap_or_default();
+        lk.retired.insert(ctrid, ctr);
+        self.num_retired.store(lk.retired.len(), Ordering::Relaxed);
+        deps
+    }
+
+    /// If `ctrid` is a retired (but not yet freed) trace, return it.
+    pub(crate) fn retired(&self, ctrid: TraceId) -> Option<Arc<dyn CompiledTrace>> {
+        self.inner.lock().retired.get(&ctrid).cloned()
+    }
+
+    /// Record that this thread is about to start executing `ctr`'s machine code. Returns `false`
+    /// if `ctr` has been invalidated, in which case the thread must not execute it.
+    ///
+    /// The caller must hold a reference to `ctr` when calling this function, but, if `true` is
+    /// returned, can drop that reference before executing `ctr`.
+    pub(crate) fn enter(&self, ctr: &dyn CompiledTrace) -> bool {
+        // The ordering here is subtle: `retire` is only called after a trace has been marked as
+        // invalidated, and `collect` only frees traces if it sees `executing == 0`. Since both
+        // this increment and the check of `is_invalidated` are sequentially consistent, either
+        // `collect` will see our increment (and not free anything), or we will see that `ctr` has
+        // been invalidated.
+        self.executing.fetch_add(1, Ordering::SeqCst);
+        if ctr.is_invalidated() {
+            self.exit();
+            return false;
+        }
+        true
+    }
+
+    /// Record that this thread has stopped executing JIT code.
+    pub(crate) fn exit(&self) {
+        let prev = self.executing.fetch_sub(1, Ordering::SeqCst);
+        debug_assert!(prev > 0);
+        if prev == 1 && self.num_retired.load(Ordering::Relaxed) > 0 {
+            self.collect();
+        }
+    }
+
+    /// If no thread is executing JIT code, free all retired traces, returning how many were
+    /// freed. Note that a freed trace's memory is only released when the last reference to it is
+    /// dropped (e.g. a compilation job may still hold a reference to it).
+    pub(crate) fn collect(&self) -> usize {
+        let mut lk = self.inner.lock();
+        if lk.retired.is_empty() || self.executing.load(Ordering::SeqCst) != 0 {
+            return 0;
+        }
+        let retired = mem::take(&mut lk.retired);
+        self.num_retired.store(0, Ordering::Relaxed);
+        drop(lk);
+        retired.len()
+    }
+
+    /// How many retired traces are waiting to be freed?
+    #[cfg(test)]
+    pub(crate) fn num_retired(&self) -> usize {
+        self.num_retired.load(Ordering::Relaxed)
+    }
+}